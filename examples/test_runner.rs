@@ -1,5 +1,7 @@
 use std::path::Path;
 
+use rusty_samplers::{ConvertOptions, Registry};
+
 fn main() {
     println!("🧪 Testing rusty-samplers conversion with test file...\n");
     
@@ -10,9 +12,12 @@ fn main() {
         return;
     }
     
+    let registry = Registry::default();
+    let options = ConvertOptions::default();
+
     // Test SFZ conversion
     println!("🔄 Testing SFZ conversion...");
-    match rusty_samplers::convert_file(test_file, rusty_samplers::OutputFormat::Sfz) {
+    match rusty_samplers::convert_file(&registry, test_file, "sfz", &options) {
         Ok(content) => {
             println!("✅ SFZ conversion successful!");
             println!("📄 Output preview:\n{}\n", &content[..content.len().min(200)]);
@@ -24,7 +29,7 @@ fn main() {
     
     // Test Decent Sampler conversion  
     println!("🔄 Testing Decent Sampler conversion...");
-    match rusty_samplers::convert_file(test_file, rusty_samplers::OutputFormat::DecentSampler) {
+    match rusty_samplers::convert_file(&registry, test_file, "ds", &options) {
         Ok(content) => {
             println!("✅ Decent Sampler conversion successful!");
             println!("📄 Output preview:\n{}\n", &content[..content.len().min(300)]);
@@ -1,10 +1,10 @@
 use eframe::egui;
 use rfd::FileDialog;
 use std::path::PathBuf;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
 
-use rusty_samplers::{OutputFormat, CopyConfig, copy_samples};
+use rusty_samplers::{ConvertOptions, CopyConfig, ParseObserver, ParseWarning, ProgramWriter, Registry, copy_samples};
 
 // Color palette
 const ACCENT: egui::Color32 = egui::Color32::from_rgb(90, 140, 255);
@@ -20,7 +20,11 @@ const DROP_HOVER_BG: egui::Color32 = egui::Color32::from_rgb(35, 40, 60);
 pub struct RustySamplersApp {
     // File selection
     selected_files: Vec<PathBuf>,
-    output_format: OutputFormat,
+    registry: Arc<Registry>,
+    /// Index into `text_writers()`.
+    output_format: usize,
+    /// Passed to the writer as `ConvertOptions::drum_kit_layout`.
+    drum_kit_layout: bool,
 
     // UI state
     conversion_status: String,
@@ -57,6 +61,23 @@ pub struct ConversionResult {
     pub success: bool,
     pub message: String,
     pub sample_summary: Option<String>,
+    /// What the parser skipped, shown under the file.
+    pub warnings: Vec<String>,
+}
+
+/// Keeps the parser's warnings for a file so they show in the results.
+#[derive(Default)]
+struct WarningCollector(Vec<String>);
+
+impl ParseObserver for WarningCollector {
+    fn warning(&mut self, warning: ParseWarning) {
+        self.0.push(warning.to_string());
+    }
+}
+
+/// The registry's writers with text output, which the GUI can write.
+fn text_writers(registry: &Registry) -> Vec<&dyn ProgramWriter> {
+    registry.writers().filter(|w| w.capabilities().text).collect()
 }
 
 fn section_heading(ui: &mut egui::Ui, text: &str) {
@@ -99,7 +120,7 @@ impl eframe::App for RustySamplersApp {
         ctx.input(|i| {
             for file in &i.raw.dropped_files {
                 if let Some(path) = &file.path {
-                    if path.extension().and_then(|e| e.to_str()) == Some("akp")
+                    if self.registry.detect(path).is_some()
                         && !self.selected_files.contains(path)
                     {
                         self.selected_files.push(path.clone());
@@ -116,7 +137,7 @@ impl eframe::App for RustySamplersApp {
             ui.add_space(2.0);
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
-                    if ui.button("Open AKP Files...").clicked() {
+                    if ui.button("Open Program Files...").clicked() {
                         self.open_file_dialog();
                         ui.close_menu();
                     }
@@ -231,7 +252,7 @@ impl eframe::App for RustySamplersApp {
                 ui.painter().text(
                     center - egui::Vec2::new(0.0, 10.0),
                     egui::Align2::CENTER_CENTER,
-                    "Drop .akp files here",
+                    "Drop program files here",
                     egui::FontId::proportional(16.0),
                     MUTED,
                 );
@@ -266,7 +287,8 @@ impl eframe::App for RustySamplersApp {
                     .show(ui, |ui| {
                         for (i, file) in self.selected_files.iter().enumerate() {
                             ui.horizontal(|ui| {
-                                ui.label(egui::RichText::new("AKP").color(ACCENT_DIM).size(10.0).strong());
+                                let extension = file.extension().unwrap_or_default().to_string_lossy().to_uppercase();
+                                ui.label(egui::RichText::new(extension).color(ACCENT_DIM).size(10.0).strong());
                                 ui.add_space(4.0);
                                 ui.label(file.file_name().unwrap_or(file.as_os_str()).to_string_lossy());
                                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
@@ -287,21 +309,25 @@ impl eframe::App for RustySamplersApp {
             // ── Format selection ──
             section_heading(ui, "OUTPUT FORMAT");
 
+            let registry = Arc::clone(&self.registry);
+            let writers = text_writers(&registry);
             ui.horizontal(|ui| {
-                ui.add_space(4.0);
-                ui.radio_value(&mut self.output_format, OutputFormat::Sfz, "SFZ");
-                ui.add_space(12.0);
-                ui.radio_value(&mut self.output_format, OutputFormat::DecentSampler, "Decent Sampler");
+                for (index, writer) in writers.iter().enumerate() {
+                    ui.add_space(if index == 0 { 4.0 } else { 12.0 });
+                    ui.radio_value(&mut self.output_format, index, writer.info().name);
+                }
             });
 
             ui.add_space(4.0);
-            let desc = match self.output_format {
-                OutputFormat::Sfz => "Standard sampler format — compatible with most samplers",
-                OutputFormat::DecentSampler => "Decent Sampler XML — includes UI controls and effects",
-            };
+            let desc = writers.get(self.output_format).map_or("", |w| w.info().description);
             ui.label(egui::RichText::new(desc).color(MUTED).size(12.0));
 
             ui.add_space(4.0);
+            ui.horizontal(|ui| {
+                ui.add_space(4.0);
+                ui.checkbox(&mut self.drum_kit_layout, egui::RichText::new("Lay out drum kits by pad").color(MUTED));
+            });
+
             ui.horizontal(|ui| {
                 ui.add_space(4.0);
                 ui.checkbox(&mut self.batch_mode, egui::RichText::new("Custom output directory").color(MUTED));
@@ -398,6 +424,13 @@ impl eframe::App for RustySamplersApp {
                                 ui.label(egui::RichText::new(format!("Samples: {summary}")).color(MUTED).size(11.0));
                             });
                         }
+                        if !result.warnings.is_empty() {
+                            ui.indent("warning_msg", |ui| {
+                                for warning in &result.warnings {
+                                    ui.label(egui::RichText::new(format!("Warning: {warning}")).color(MUTED).size(11.0));
+                                }
+                            });
+                        }
                     }
                 });
             }
@@ -421,10 +454,12 @@ impl eframe::App for RustySamplersApp {
                     ui.separator();
                     ui.add_space(8.0);
 
-                    ui.label("Converts Akai AKP sampler programs to:");
+                    ui.label("Converts sampler programs to:");
                     ui.add_space(4.0);
-                    ui.label(egui::RichText::new("  SFZ — universal sampler format").size(12.0));
-                    ui.label(egui::RichText::new("  Decent Sampler — XML with UI and effects").size(12.0));
+                    for writer in text_writers(&self.registry) {
+                        let info = writer.info();
+                        ui.label(egui::RichText::new(format!("  {} — {}", info.name, info.description)).size(12.0));
+                    }
 
                     ui.add_space(12.0);
                     ui.vertical_centered(|ui| {
@@ -441,8 +476,8 @@ impl eframe::App for RustySamplersApp {
 impl RustySamplersApp {
     fn open_file_dialog(&mut self) {
         if let Some(files) = FileDialog::new()
-            .add_filter("AKP Files", &["akp"])
-            .set_title("Select AKP Files")
+            .add_filter("Program Files", &self.registry.input_extensions())
+            .set_title("Select Program Files")
             .pick_files()
         {
             for file in files {
@@ -463,15 +498,19 @@ impl RustySamplersApp {
     }
 
     fn start_conversion(&mut self) {
+        let Some(info) = text_writers(&self.registry).get(self.output_format).map(|w| *w.info()) else {
+            return;
+        };
         let (tx, rx) = mpsc::channel();
         self.progress_receiver = Some(rx);
         self.conversion_results.clear();
 
         let files = self.selected_files.clone();
-        let format = self.output_format;
+        let registry = Arc::clone(&self.registry);
         let output_dir = self.output_directory.clone();
         let do_copy_samples = self.copy_samples;
         let sample_source_dir = self.sample_source_dir.clone();
+        let options = ConvertOptions { drum_kit_layout: self.drum_kit_layout, ..Default::default() };
 
         thread::spawn(move || {
             let _ = tx.send(ConversionProgress::Started("Starting conversion...".to_string()));
@@ -485,32 +524,42 @@ impl RustySamplersApp {
                 );
                 let _ = tx.send(ConversionProgress::Progress(progress_msg, i as f32 / files.len() as f32));
 
-                let conversion_result = rusty_samplers::convert_file_with_program(file_path, format);
+                let mut warnings = WarningCollector::default();
+                let conversion_result = registry.convert_file_with_program(file_path, info.id, &options, &mut warnings)
+                    .map_err(|e| e.to_string());
 
                 let result = match conversion_result {
                     Ok((content, program)) => {
                         let output_file = if let Some(dir) = &output_dir {
                             let filename = file_path.file_stem().unwrap_or(file_path.as_os_str());
-                            let extension = match format {
-                                OutputFormat::Sfz => "sfz",
-                                OutputFormat::DecentSampler => "dspreset",
-                            };
-                            dir.join(format!("{}.{}", filename.to_string_lossy(), extension))
+                            dir.join(format!("{}.{}", filename.to_string_lossy(), info.extension()))
                         } else {
-                            match format {
-                                OutputFormat::Sfz => file_path.with_extension("sfz"),
-                                OutputFormat::DecentSampler => file_path.with_extension("dspreset"),
-                            }
+                            file_path.with_extension(info.extension())
                         };
 
                         let write_result = std::fs::write(&output_file, content).map_err(|e| e.to_string());
 
                         let final_success = write_result.is_ok();
 
-                        let sample_summary = if final_success && do_copy_samples {
-                            let search = sample_source_dir.as_deref()
-                                .unwrap_or_else(|| file_path.parent().unwrap_or(std::path::Path::new(".")));
-                            let out = output_file.parent().unwrap_or(std::path::Path::new("."));
+                        let search = sample_source_dir.as_deref()
+                            .unwrap_or_else(|| file_path.parent().unwrap_or(std::path::Path::new(".")));
+                        let out = output_file.parent().unwrap_or(std::path::Path::new("."));
+
+                        // Samples that aren't WAVs (S1000/S3000, SF2) are extracted next to the output
+                        let extracted = if final_success {
+                            registry.detect(file_path)
+                                .map(|reader| reader.extract_samples(file_path, search, out))
+                                .transpose()
+                                .map(Option::flatten)
+                        } else {
+                            Ok(None)
+                        };
+
+                        let sample_summary = if let Err(e) = &extracted {
+                            Some(format!("Could not extract samples: {e}"))
+                        } else if let Ok(Some(extracted)) = &extracted {
+                            Some(format!("{} extracted as WAV, {} missing", extracted.written, extracted.missing_samples.len()))
+                        } else if final_success && do_copy_samples {
                             let sample_paths = program.sample_paths();
                             let path_refs: Vec<&str> = sample_paths.to_vec();
                             let config = CopyConfig {
@@ -525,10 +574,7 @@ impl RustySamplersApp {
                         };
 
                         let message = if final_success {
-                            format!("Converted to {} format", match format {
-                                OutputFormat::Sfz => "SFZ",
-                                OutputFormat::DecentSampler => "Decent Sampler",
-                            })
+                            format!("Converted to {} format", info.name)
                         } else {
                             write_result.err().unwrap_or_else(|| "Unknown error".to_string())
                         };
@@ -539,6 +585,7 @@ impl RustySamplersApp {
                             success: final_success,
                             message,
                             sample_summary,
+                            warnings: warnings.0,
                         }
                     }
                     Err(e) => {
@@ -548,6 +595,7 @@ impl RustySamplersApp {
                             success: false,
                             message: e,
                             sample_summary: None,
+                            warnings: warnings.0,
                         }
                     }
                 };
@@ -0,0 +1,463 @@
+//! Ableton Live Sampler preset (`.adv`) output: gzipped XML describing one
+//! Sampler device, with a multisample part per zone.
+//!
+//! Sampler has a single amp envelope, filter and LFO for the whole device,
+//! where an AKP has them per keygroup, so they're taken from the first
+//! keygroup that has each. Samples aren't embedded; parts point at the WAVs
+//! by absolute path, read through `samples.rs` for their length and loops.
+
+use std::fmt::Display;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::dspreset::xml_escape;
+use crate::error::Result;
+use crate::instrument::EnvelopeTiming;
+use crate::samples::{find_wav_info, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions, Filter};
+
+/// Sampler filter types.
+const FILTER_LOWPASS: u8 = 0;
+const FILTER_HIGHPASS: u8 = 1;
+const FILTER_BANDPASS: u8 = 2;
+const FILTER_NOTCH: u8 = 3;
+
+/// Sampler loop modes.
+const LOOP_OFF: u8 = 0;
+const LOOP_FORWARD: u8 = 1;
+/// Release loop mode that keeps playing the sustain loop after note-off.
+const RELEASE_LOOP_SUSTAIN: u8 = 3;
+
+/// An `.adv` file and the samples it couldn't reference.
+#[derive(Debug, Clone)]
+pub struct AdvOutput {
+    pub bytes: Vec<u8>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample found on disk, as a multisample part refers to it.
+struct SampleRef {
+    path: String,
+    info: WavInfo,
+}
+
+impl AkaiProgram {
+    /// Build a Sampler preset, reading samples from `sample_dir`. Zones whose
+    /// sample can't be read are left out and listed in the output; if none
+    /// can be read this fails.
+    pub fn to_adv(&self, sample_dir: &Path, options: &ConvertOptions) -> Result<AdvOutput> {
+        let mut samples: LoadedSamples<SampleRef> = LoadedSamples::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+
+        let mut xml = XmlWriter::default();
+        xml.line("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        xml.open("Ableton MajorVersion=\"5\" MinorVersion=\"11.0_433\" SchemaChangeCount=\"3\" Creator=\"Rusty Samplers\" Revision=\"\"");
+        xml.open("MultiSampler");
+        xml.value("LomId", 0);
+        xml.value("IsExpanded", true);
+        xml.param("On", true);
+        xml.value("UserName", "");
+        xml.value("Annotation", "");
+
+        xml.open("Player");
+        xml.open("MultiSampleMap");
+        xml.open("SampleParts");
+
+        // Per-key detune has no Sampler equivalent, so those keygroups split per key
+        let mut part_id = 0;
+        for range in self.key_layout(true, options.drum_kit_layout) {
+            let keygroup = range.keygroup;
+            for (zone, xf) in keygroup.zones.iter().zip(keygroup.velocity_crossfades()) {
+                if zone.sample_name.is_empty() {
+                    continue;
+                }
+                let sample = samples.load(&zone.sample_name, |_| find_sample_ref(sample_dir, &zone.sample_name));
+                let Some(sample) = sample else { continue };
+
+                // Whole semitones move the root key; Detune covers the rest (±50 cents)
+                let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                    + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                    + zone.root_tune as i32 + range.detune as i32;
+                let semitones = (cents as f32 / 100.0).round() as i32;
+                let root_key = zone.root_key.or(sample.info.unity_note).unwrap_or(DEFAULT_ROOT_KEY) as i32;
+
+                let name = Path::new(&zone.sample_name).file_stem().unwrap_or_default().to_string_lossy();
+                xml.open(&format!("MultiSamplePart Id=\"{part_id}\""));
+                part_id += 1;
+                xml.value("LomId", 0);
+                xml.value("Name", &name);
+                xml.value("IsActive", true);
+                xml.range("KeyRange", range.low_key, range.high_key, range.low_key, range.high_key);
+                // Sampler velocities start at 1; fades run inside the layer's range
+                let low_vel = xf.low_vel.max(1);
+                let fade_in_end = xf.fade_in.map_or(low_vel, |(_, to)| to.max(1));
+                let fade_out_start = xf.fade_out.map_or(xf.high_vel, |(from, _)| from);
+                xml.range("VelocityRange", low_vel, xf.high_vel, fade_in_end, fade_out_start);
+                xml.range("SelectorRange", 0, 127, 0, 127);
+                xml.value("RootKey", (root_key - semitones).clamp(0, 127));
+                xml.value("Detune", cents - semitones * 100);
+                xml.value("TuneScale", if zone.keyboard_track == 0 { 0 } else { 100 });
+                xml.value("Panorama", format!("{:.2}", (zone.pan as f32 / 50.0).clamp(-1.0, 1.0)));
+                xml.value("Volume", format!("{:.4}", 10.0f32.powf(zone.level as f32 / 20.0)));
+                xml.value("Link", false);
+                xml.value("SampleStart", 0);
+                xml.value("SampleEnd", sample.info.frames);
+
+                // LOOP carries on looping through the release; LOOP UNTIL RELEASE plays out
+                let sample_loop = zone.sample_loop.or(sample.info.sample_loop).filter(|_| zone.loop_enabled());
+                let (start, end, crossfade) = sample_loop.map_or((0, sample.info.frames, 0), |l| (l.start, l.end + 1, l.crossfade));
+                let sustain_mode = if sample_loop.is_some() { LOOP_FORWARD } else { LOOP_OFF };
+                let release_mode = if sample_loop.is_some() && zone.playback != 3 { RELEASE_LOOP_SUSTAIN } else { LOOP_OFF };
+                for (element, mode) in [("SustainLoop", sustain_mode), ("ReleaseLoop", release_mode)] {
+                    xml.open(element);
+                    xml.value("Start", start);
+                    xml.value("End", end);
+                    xml.value("Mode", mode);
+                    xml.value("Crossfade", crossfade);
+                    xml.value("Detune", 0);
+                    xml.close(element);
+                }
+
+                xml.open("SampleRef");
+                xml.open("FileRef");
+                xml.value("RelativePathType", 0);
+                xml.value("RelativePath", "");
+                xml.value("Path", &sample.path);
+                xml.value("Type", 1);
+                xml.close("FileRef");
+                xml.value("DefaultDuration", sample.info.frames);
+                xml.value("DefaultSampleRate", sample.info.sample_rate);
+                xml.close("SampleRef");
+                xml.close("MultiSamplePart");
+            }
+        }
+
+        xml.close("SampleParts");
+        xml.close("MultiSampleMap");
+        xml.close("Player");
+
+        let (_, missing_samples) = samples.finish(sample_dir)?;
+
+        self.write_device_sections(&mut xml);
+        xml.close("MultiSampler");
+        xml.close("Ableton");
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(xml.text.as_bytes())?;
+        Ok(AdvOutput { bytes: encoder.finish()?, missing_samples })
+    }
+
+    /// Write the program to an `.adv` file, returning the samples that
+    /// couldn't be referenced (see `to_adv`).
+    pub fn write_adv(&self, path: &Path, sample_dir: &Path, options: &ConvertOptions) -> Result<MissingSamples> {
+        let output = self.to_adv(sample_dir, options)?;
+        fs::write(path, output.bytes)?;
+        Ok(output.missing_samples)
+    }
+
+    /// The device-wide pitch LFO amount, filter, amp envelope and LFO.
+    fn write_device_sections(&self, xml: &mut XmlWriter) {
+        let lfo = self.lfo1.as_ref().filter(|lfo| lfo.depth > 0);
+
+        xml.open("Pitch");
+        xml.param("PitchLfoAmount", format!("{:.2}", lfo.map_or(0.0, |lfo| lfo.depth_normalized())));
+        xml.close("Pitch");
+
+        // Peak, morphing, phaser and voweliser types have no Sampler filter
+        let filter = self.keygroups.iter()
+            .find_map(|kg| kg.filter.as_ref())
+            .and_then(|filter| sampler_filter_type(filter).map(|(kind, steep)| (filter, kind, steep)));
+        let filter_env = self.keygroups.iter().find_map(|kg| kg.filter_env.as_ref()).filter(|env| env.depth != 0);
+        xml.open("Filter");
+        xml.param("IsOn", filter.is_some());
+        if let Some((filter, kind, steep)) = filter {
+            xml.open("Slot");
+            xml.open("Value");
+            xml.open("SimplerFilter Id=\"0\"");
+            xml.param("Type", kind);
+            xml.param("Slope", steep);
+            xml.param("Freq", format!("{:.1}", filter.cutoff_hz().clamp(30.0, 22000.0)));
+            xml.param("Res", format!("{:.3}", filter.resonance as f32 / 12.0));
+            xml.open("Envelope");
+            xml.param("IsOn", filter_env.is_some());
+            if let Some(env) = filter_env {
+                // Depth ±100 spans ±8 octaves, as for SFZ fileg_depth
+                xml.param("Amount", format!("{:.1}", (env.depth as f32 * 0.96).clamp(-72.0, 72.0)));
+                write_envelope_times(xml, env);
+            }
+            xml.close("Envelope");
+            xml.close("SimplerFilter");
+            xml.close("Value");
+            xml.close("Slot");
+        }
+        xml.close("Filter");
+
+        let output_db = self.output.as_ref().map_or(0.0, |o| o.volume_db());
+        let velocity = self.output.as_ref().map_or(0, |o| o.velocity_sensitivity.clamp(0, 100));
+        xml.open("VolumeAndPan");
+        xml.param("Volume", format!("{output_db:.2}"));
+        xml.param("VolumeVelScale", format!("{:.2}", velocity as f32 / 100.0));
+        xml.open("Envelope");
+        if let Some(env) = self.keygroups.iter().find_map(|kg| kg.amp_env.as_ref()) {
+            write_envelope_times(xml, env);
+        }
+        xml.close("Envelope");
+        xml.close("VolumeAndPan");
+
+        xml.open("Lfo");
+        xml.param("IsOn", lfo.is_some());
+        if let Some(lfo) = lfo {
+            xml.open("Slot");
+            xml.open("Value");
+            xml.open("SimplerLfo Id=\"0\"");
+            xml.param("Type", sampler_lfo_type(lfo.waveform));
+            xml.param("Frequency", format!("{:.2}", lfo.rate_hz()));
+            // LFO delay (0-100) is up to 10 s, as for SFZ lfo1_delay
+            xml.param("Attack", format!("{:.0}", lfo.delay as f32 * 100.0));
+            xml.param("IsKeyTriggered", lfo.retrigger != 0);
+            xml.close("SimplerLfo");
+            xml.close("Value");
+            xml.close("Slot");
+        }
+        xml.close("Lfo");
+    }
+}
+
+/// Sampler filter type and whether it's the 24 dB slope, for the AKP filter
+/// types Sampler has.
+fn sampler_filter_type(filter: &Filter) -> Option<(u8, bool)> {
+    match filter.filter_type {
+        0 | 2 => Some((FILTER_LOWPASS, false)),
+        1 => Some((FILTER_LOWPASS, true)),
+        3 | 5 => Some((FILTER_BANDPASS, false)),
+        4 => Some((FILTER_BANDPASS, true)),
+        6..=8 => Some((FILTER_HIGHPASS, false)),
+        12..=16 => Some((FILTER_NOTCH, false)),
+        _ => None,
+    }
+}
+
+/// Sampler LFO waveform for an AKP one (0=SINE .. 8=RANDOM).
+fn sampler_lfo_type(waveform: u8) -> u8 {
+    match waveform {
+        1 => 2,        // triangle
+        2..=4 => 1,    // square
+        5 | 6 => 4,    // saw up
+        7 => 3,        // saw down
+        8 => 5,        // sample & hold
+        _ => 0,        // sine
+    }
+}
+
+/// Attack, decay and release in ms and linear sustain, within Sampler's ranges.
+fn write_envelope_times(xml: &mut XmlWriter, env: &impl EnvelopeTiming) {
+    xml.param("AttackTime", format!("{:.1}", (env.attack_time() * 1000.0).clamp(0.1, 20000.0)));
+    xml.param("DecayTime", format!("{:.1}", (env.decay_time() * 1000.0).clamp(1.0, 60000.0)));
+    xml.param("SustainLevel", format!("{:.3}", env.sustain_normalized()));
+    xml.param("ReleaseTime", format!("{:.1}", (env.release_time() * 1000.0).clamp(1.0, 60000.0)));
+}
+
+/// Find a sample and read its header, with the absolute path Live needs.
+fn find_sample_ref(sample_dir: &Path, sample_name: &str) -> std::result::Result<SampleRef, String> {
+    let (path, info) = find_wav_info(sample_dir, sample_name)?;
+    let path = fs::canonicalize(&path).map_err(|e| e.to_string())?;
+    Ok(SampleRef { path: path.to_string_lossy().into_owned(), info })
+}
+
+/// Tab-indented XML in Live's layout, where every value is a `Value`
+/// attribute and automatable parameters wrap theirs in `Manual`.
+#[derive(Default)]
+struct XmlWriter {
+    text: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn line(&mut self, line: &str) {
+        self.text.extend(std::iter::repeat_n('\t', self.depth));
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Open an element; `tag` may carry attributes.
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{tag}>"));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.line(&format!("</{name}>"));
+    }
+
+    fn value(&mut self, name: &str, value: impl Display) {
+        self.line(&format!("<{name} Value=\"{}\" />", xml_escape(&value.to_string())));
+    }
+
+    fn param(&mut self, name: &str, value: impl Display) {
+        self.open(name);
+        self.value("LomId", 0);
+        self.value("Manual", value);
+        self.close(name);
+    }
+
+    /// A key, velocity or selector range with its crossfade points.
+    fn range(&mut self, name: &str, min: u8, max: u8, crossfade_min: u8, crossfade_max: u8) {
+        self.open(name);
+        self.value("Min", min);
+        self.value("Max", max);
+        self.value("CrossfadeMin", crossfade_min);
+        self.value("CrossfadeMax", crossfade_max);
+        self.close(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::WavAudio;
+    use crate::types::{Envelope, FilterEnvelope, Keygroup, Lfo, ProgramOutput, SampleLoop, Zone};
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels: vec![vec![0; frames]] }.to_wav_bytes()
+    }
+
+    fn unzip(bytes: &[u8]) -> String {
+        let mut xml = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    /// The `Manual` value of a parameter, searching from `section`.
+    fn manual<'a>(xml: &'a str, section: &str, param: &str) -> &'a str {
+        let from = &xml[xml.find(&format!("<{section}")).unwrap()..];
+        let from = &from[from.find(&format!("<{param}>")).unwrap()..];
+        let start = from.find("<Manual Value=\"").unwrap() + 15;
+        &from[start..start + from[start..].find('"').unwrap()]
+    }
+
+    #[test]
+    fn test_sampler_parts() {
+        let dir = TempDir::new().unwrap();
+        let looped = SampleLoop { start: 10, end: 29, crossfade: 5 };
+        fs::write(dir.path().join("piano.wav"), wav(40, Some(48), Some(looped))).unwrap();
+
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                semitone_tune: 1,
+                zones: vec![
+                    Zone { sample_name: "PIANO".to_string(), high_vel: 100, level: -6, fine_tune: 70, pan: 25, sample_loop: Some(looped), playback: 3, ..Default::default() },
+                    Zone { sample_name: "MISSING".to_string(), low_vel: 101, ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_adv(dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+        let xml = unzip(&output.bytes);
+        assert_eq!(xml.matches("<MultiSamplePart ").count(), 1);
+
+        // Consecutive elements, ignoring indentation
+        let flat = xml.replace('\t', "");
+        let part = |lines: &[&str]| assert!(flat.contains(&lines.join("\n")), "missing {lines:?}");
+        part(&["<KeyRange>", "<Min Value=\"36\" />", "<Max Value=\"59\" />", "<CrossfadeMin Value=\"36\" />", "<CrossfadeMax Value=\"59\" />"]);
+        part(&["<VelocityRange>", "<Min Value=\"1\" />", "<Max Value=\"100\" />"]);
+        // 1 semitone + 70 cents: the root moves down 2 and Detune takes -30
+        part(&["<RootKey Value=\"46\" />", "<Detune Value=\"-30\" />", "<TuneScale Value=\"100\" />", "<Panorama Value=\"0.50\" />", "<Volume Value=\"0.5012\" />"]);
+        // LOOP UNTIL RELEASE: no release loop
+        part(&["<SustainLoop>", "<Start Value=\"10\" />", "<End Value=\"30\" />", "<Mode Value=\"1\" />", "<Crossfade Value=\"5\" />"]);
+        part(&["<ReleaseLoop>", "<Start Value=\"10\" />", "<End Value=\"30\" />", "<Mode Value=\"0\" />"]);
+        let path = fs::canonicalize(dir.path().join("piano.wav")).unwrap();
+        assert!(xml.contains(&format!("<Path Value=\"{}\" />", xml_escape(&path.to_string_lossy()))));
+    }
+
+    #[test]
+    fn test_device_envelopes_filter_and_lfo() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("pad.wav"), wav(8, None, None)).unwrap();
+        let zones = vec![Zone { sample_name: "pad.wav".to_string(), ..Default::default() }];
+
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, velocity_sensitivity: 40, ..Default::default() }),
+            lfo1: Some(Lfo { waveform: 1, rate: 50, depth: 40, delay: 20, ..Default::default() }),
+            keygroups: vec![
+                Keygroup { zones: zones.clone(), ..Default::default() },
+                Keygroup {
+                    zones: zones.clone(),
+                    amp_env: Some(Envelope { attack: 0, decay: 50, sustain: 50, release: 100, ..Default::default() }),
+                    filter: Some(Filter { filter_type: 1, cutoff: 50, resonance: 6, ..Default::default() }),
+                    filter_env: Some(FilterEnvelope { attack: 25, depth: -50, ..Default::default() }),
+                    ..Default::default()
+                },
+                Keygroup {
+                    zones,
+                    filter: Some(Filter { filter_type: 6, ..Default::default() }),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let xml = unzip(&program.to_adv(dir.path(), &ConvertOptions::default()).unwrap().bytes);
+        assert_eq!(manual(&xml, "VolumeAndPan", "Volume"), "-6.02");
+        assert_eq!(manual(&xml, "VolumeAndPan", "VolumeVelScale"), "0.40");
+        assert_eq!(manual(&xml, "VolumeAndPan", "AttackTime"), "0.1");
+        assert_eq!(manual(&xml, "VolumeAndPan", "DecayTime"), "7.4");
+        assert_eq!(manual(&xml, "VolumeAndPan", "SustainLevel"), "0.500");
+        assert_eq!(manual(&xml, "VolumeAndPan", "ReleaseTime"), "148.4");
+
+        // The first keygroup with a filter sets it: 4-pole lowpass
+        assert_eq!(manual(&xml, "Filter", "IsOn"), "true");
+        assert_eq!(manual(&xml, "SimplerFilter", "Type"), "0");
+        assert_eq!(manual(&xml, "SimplerFilter", "Slope"), "true");
+        assert_eq!(manual(&xml, "SimplerFilter", "Freq"), "632.5");
+        assert_eq!(manual(&xml, "SimplerFilter", "Res"), "0.500");
+        assert_eq!(manual(&xml, "SimplerFilter", "Amount"), "-48.0");
+        assert_eq!(manual(&xml, "SimplerFilter", "AttackTime"), "2.7");
+
+        assert_eq!(manual(&xml, "Pitch", "PitchLfoAmount"), "0.40");
+        assert_eq!(manual(&xml, "SimplerLfo", "Type"), "2");
+        assert_eq!(manual(&xml, "SimplerLfo", "Frequency"), "1.73");
+        assert_eq!(manual(&xml, "SimplerLfo", "Attack"), "2000");
+    }
+
+    #[test]
+    fn test_unsupported_filter_is_off() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("pad.wav"), wav(8, None, None)).unwrap();
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "pad.wav".to_string(), ..Default::default() }],
+                filter: Some(Filter { filter_type: 17, ..Default::default() }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let xml = unzip(&program.to_adv(dir.path(), &ConvertOptions::default()).unwrap().bytes);
+        assert_eq!(manual(&xml, "Filter", "IsOn"), "false");
+        assert!(!xml.contains("<SimplerFilter"));
+        assert_eq!(manual(&xml, "Lfo", "IsOn"), "false");
+    }
+}
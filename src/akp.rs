@@ -0,0 +1,776 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{AkpError, Result};
+use crate::samples::{read_wav_info, MissingSamples, DEFAULT_ROOT_KEY};
+use crate::types::*;
+
+/// Marker byte at offset 0 of every chunk.
+const CHUNK_MARKER: u8 = 1;
+const MAX_KEYGROUPS: usize = 99;
+const MAX_SAMPLE_NAME: usize = 20;
+/// Chunk ID written for MPC4000 pad assignments. The real ID is
+/// undocumented; the parser recognises the chunk by position and size.
+const PAD_CHUNK_ID: &[u8; 4] = b"pads";
+
+/// A sample file behind the zones of a program imported from another format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleFile {
+    /// Name the zones use: the file stem plus `.WAV`, as the AKP parser
+    /// reports names. The AKP itself stores just the stem.
+    pub name: String,
+    /// Path the source format gave, relative to the source file.
+    pub path: String,
+}
+
+/// AKP name for a sample path: `Samples/Piano C3.wav` → `Piano C3.WAV`.
+pub fn akp_sample_name(path: &str) -> String {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+    format!("{stem}.WAV")
+}
+
+/// `akp_sample_name` for `path`, numbered from 2 if `samples` already uses
+/// it, and shortened to keep the number within the AKP name length.
+fn unique_sample_name(path: &str, samples: &[SampleFile]) -> String {
+    let taken = |name: &str| samples.iter().any(|s| s.name.eq_ignore_ascii_case(name));
+    let name = akp_sample_name(path);
+    if !taken(&name) {
+        return name;
+    }
+    let stem = name.strip_suffix(".WAV").unwrap_or(&name);
+    (2..)
+        .map(|n| {
+            let suffix = format!(" {n}");
+            let stem: String = stem.chars().take(MAX_SAMPLE_NAME.saturating_sub(suffix.len())).collect();
+            format!("{}{suffix}.WAV", stem.trim_end())
+        })
+        .find(|name| !taken(name))
+        .expect("a free number")
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+fn new_chunk(size: usize) -> Vec<u8> {
+    let mut data = vec![0u8; size];
+    data[0] = CHUNK_MARKER;
+    data
+}
+
+impl AkaiProgram {
+    /// Serialize as an AKP (RIFF/APRG) file, using the same byte offsets the
+    /// parser reads. Missing optional chunks are written with their defaults,
+    /// and every keygroup gets three envelopes and four zone chunks.
+    ///
+    /// Zone fields that come from WAV headers (`root_key`, `root_tune`,
+    /// `sample_loop`) have no place in AKP and aren't written.
+    pub fn to_akp_bytes(&self) -> Result<Vec<u8>> {
+        if self.keygroups.len() > MAX_KEYGROUPS {
+            return Err(AkpError::UnwritableProgram(format!(
+                "{} keygroups (maximum {MAX_KEYGROUPS})", self.keygroups.len(),
+            )));
+        }
+
+        let mut body = b"APRG".to_vec();
+
+        if let Some(pads) = &self.pads {
+            let mut data = new_chunk(1 + pads.notes.len());
+            for (i, note) in pads.notes.iter().enumerate() {
+                data[1 + i] = note.unwrap_or(0xFF);
+            }
+            push_chunk(&mut body, PAD_CHUNK_ID, &data);
+        }
+
+        let mut data = new_chunk(6);
+        data[1] = self.header.as_ref().map_or(0, |h| h.midi_program_number);
+        data[2] = self.keygroups.len() as u8;
+        push_chunk(&mut body, b"prg ", &data);
+
+        push_chunk(&mut body, b"out ", &out_chunk(&self.output.clone().unwrap_or_default()));
+        push_chunk(&mut body, b"tune", &tune_chunk(&self.tuning.clone().unwrap_or_default()));
+        push_chunk(&mut body, b"lfo ", &lfo1_chunk(self.lfo1.as_ref().unwrap_or(&Lfo::default())));
+        push_chunk(&mut body, b"lfo ", &lfo2_chunk(self.lfo2.as_ref().unwrap_or(&Lfo::default())));
+        push_chunk(&mut body, b"mods", &mods_chunk(&self.modulation.clone().unwrap_or_default()));
+
+        for keygroup in &self.keygroups {
+            push_chunk(&mut body, b"kgrp", &keygroup_chunks(keygroup)?);
+        }
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        Ok(bytes)
+    }
+
+    /// Write the program to an `.akp` file.
+    pub fn write_akp(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_akp_bytes()?)?;
+        Ok(())
+    }
+
+    /// Rename every zone's sample from a path (as SFZ or Decent Sampler give
+    /// it) to its AKP name. The S5000/S6000 finds samples by name in the
+    /// program's folder, so the files need copying there under these names.
+    /// Different paths that would share a name (`a/Piano.wav`, `b/Piano.wav`)
+    /// get a numbered one instead (`Piano.WAV`, `Piano 2.WAV`), ignoring case
+    /// as the sampler does. Returns each sample once, in the order zones
+    /// first use it.
+    pub fn use_akp_sample_names(&mut self) -> Vec<SampleFile> {
+        let mut samples: Vec<SampleFile> = Vec::new();
+        for zone in self.keygroups.iter_mut().flat_map(|k| k.zones.iter_mut()) {
+            let name = match samples.iter().find(|s| s.path == zone.sample_name) {
+                Some(sample) => sample.name.clone(),
+                None => {
+                    let name = unique_sample_name(&zone.sample_name, &samples);
+                    samples.push(SampleFile { name: name.clone(), path: zone.sample_name.clone() });
+                    name
+                }
+            };
+            zone.sample_name = name;
+        }
+        samples
+    }
+
+    /// Match each zone's pitch to its sample's own root note.
+    ///
+    /// Other formats play a sample relative to a root key set in the preset
+    /// (`root_key` here); the S5000 plays it relative to the unity note in
+    /// the WAV's `smpl` chunk, or C3 without one. The difference is added to
+    /// the zone's semitone tune and `root_key` is set to the WAV's note.
+    /// Samples are read from `dir`; the paths of samples that couldn't be
+    /// read are returned and their zones left as is.
+    pub fn match_sample_roots(&mut self, samples: &[SampleFile], dir: &Path) -> Vec<String> {
+        let mut roots: HashMap<&str, Option<u8>> = HashMap::new();
+        let mut unreadable = Vec::new();
+        for sample in samples {
+            let root = match read_wav_info(&dir.join(&sample.path)) {
+                Ok(info) => Some(info.unity_note.unwrap_or(DEFAULT_ROOT_KEY)),
+                Err(_) => {
+                    unreadable.push(sample.path.clone());
+                    None
+                }
+            };
+            roots.entry(&sample.name).or_insert(root);
+        }
+
+        for zone in self.keygroups.iter_mut().flat_map(|k| k.zones.iter_mut()) {
+            let Some(&Some(wav_root)) = roots.get(zone.sample_name.as_str()) else {
+                continue;
+            };
+            if let Some(key_center) = zone.root_key {
+                let semitone = zone.semitone_tune as i16 + wav_root as i16 - key_center as i16;
+                zone.semitone_tune = semitone.clamp(-36, 36) as i8;
+            }
+            zone.root_key = Some(wav_root);
+        }
+
+        unreadable
+    }
+
+    /// Write the program to `path` the way the S5000/S6000 loads it, for
+    /// programs from other formats: samples get their AKP names, zones are
+    /// retuned to the WAVs' own root notes (see `match_sample_roots`), and
+    /// each sample is copied from `sample_dir` next to the program under
+    /// its AKP name unless it's there already. Returns (sample, reason) for
+    /// each sample that couldn't be read or copied.
+    pub fn write_akp_with_samples(&self, path: &Path, sample_dir: &Path) -> Result<MissingSamples> {
+        let mut program = self.clone();
+        let samples = program.use_akp_sample_names();
+        let unreadable = program.match_sample_roots(&samples, sample_dir);
+        program.write_akp(path)?;
+
+        let output_dir = path.parent().unwrap_or(Path::new("."));
+        let mut missing = MissingSamples::new();
+        for sample in &samples {
+            if unreadable.contains(&sample.path) {
+                missing.push((sample.path.clone(), format!("could not read it from '{}'", sample_dir.display())));
+                continue;
+            }
+            let stem = sample.name.strip_suffix(".WAV").unwrap_or(&sample.name);
+            let extension = Path::new(&sample.path).extension().map_or("wav".into(), |e| e.to_string_lossy());
+            let dest = output_dir.join(format!("{stem}.{extension}"));
+            if dest.exists() {
+                continue;
+            }
+            if let Err(e) = fs::copy(sample_dir.join(&sample.path), &dest) {
+                missing.push((sample.path.clone(), e.to_string()));
+            }
+        }
+        Ok(missing)
+    }
+}
+
+fn out_chunk(output: &ProgramOutput) -> Vec<u8> {
+    let mut data = new_chunk(8);
+    data[1] = output.loudness;
+    data[2] = output.amp_mod_1;
+    data[3] = output.amp_mod_2;
+    data[4] = output.pan_mod_1;
+    data[5] = output.pan_mod_2;
+    data[6] = output.pan_mod_3;
+    data[7] = output.velocity_sensitivity as u8;
+    data
+}
+
+fn tune_chunk(tuning: &ProgramTuning) -> Vec<u8> {
+    let mut data = new_chunk(22);
+    data[1] = tuning.semitone as u8;
+    data[2] = tuning.fine as u8;
+    for (i, &d) in tuning.detune.iter().enumerate() {
+        data[3 + i] = d as u8;
+    }
+    data[15] = tuning.pitchbend_up;
+    data[16] = tuning.pitchbend_down;
+    data[17] = tuning.bend_mode;
+    data[18] = tuning.aftertouch as u8;
+    data
+}
+
+fn lfo1_chunk(lfo: &Lfo) -> Vec<u8> {
+    let mut data = new_chunk(12);
+    data[1] = lfo.waveform;
+    data[2] = lfo.rate;
+    data[3] = lfo.delay;
+    data[4] = lfo.depth;
+    data[5] = lfo.sync;
+    data[7] = lfo.modwheel;
+    data[8] = lfo.aftertouch;
+    data[9] = lfo.rate_mod as u8;
+    data[10] = lfo.delay_mod as u8;
+    data[11] = lfo.depth_mod as u8;
+    data
+}
+
+fn lfo2_chunk(lfo: &Lfo) -> Vec<u8> {
+    let mut data = new_chunk(12);
+    data[1] = lfo.waveform;
+    data[2] = lfo.rate;
+    data[3] = lfo.delay;
+    data[4] = lfo.depth;
+    data[6] = lfo.retrigger;
+    data[9] = lfo.rate_mod as u8;
+    data[10] = lfo.delay_mod as u8;
+    data[11] = lfo.depth_mod as u8;
+    data
+}
+
+fn mods_chunk(modulation: &ProgramModulation) -> Vec<u8> {
+    let sources = [
+        modulation.amp_mod_1_source,
+        modulation.amp_mod_2_source,
+        modulation.pan_mod_1_source,
+        modulation.pan_mod_2_source,
+        modulation.pan_mod_3_source,
+        modulation.lfo1_rate_mod_source,
+        modulation.lfo1_delay_mod_source,
+        modulation.lfo1_depth_mod_source,
+        modulation.lfo2_rate_mod_source,
+        modulation.lfo2_delay_mod_source,
+        modulation.lfo2_depth_mod_source,
+        modulation.pitch_mod_1_source,
+        modulation.pitch_mod_2_source,
+        modulation.amp_mod_source,
+        modulation.filter_mod_1_source,
+        modulation.filter_mod_2_source,
+        modulation.filter_mod_3_source,
+    ];
+    // Source bytes at odd offsets 5-37
+    let mut data = new_chunk(38);
+    for (i, source) in sources.into_iter().enumerate() {
+        data[5 + i * 2] = source;
+    }
+    data
+}
+
+fn keygroup_chunks(keygroup: &Keygroup) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    let mut kloc = new_chunk(16);
+    kloc[4] = keygroup.low_key;
+    kloc[5] = keygroup.high_key;
+    kloc[6] = keygroup.semitone_tune as u8;
+    kloc[7] = keygroup.fine_tune as u8;
+    kloc[8] = keygroup.override_fx;
+    kloc[9] = keygroup.fx_send_level;
+    kloc[10] = keygroup.pitch_mod_1 as u8;
+    kloc[11] = keygroup.pitch_mod_2 as u8;
+    kloc[12] = keygroup.amp_mod as u8;
+    kloc[13] = keygroup.zone_crossfade;
+    kloc[14] = keygroup.mute_group;
+    push_chunk(&mut out, b"kloc", &kloc);
+
+    let amp = keygroup.amp_env.clone().unwrap_or_default();
+    let mut env = new_chunk(18);
+    env[1] = amp.attack;
+    env[3] = amp.decay;
+    env[4] = amp.release;
+    env[7] = amp.sustain;
+    env[10] = amp.velocity_attack as u8;
+    env[12] = amp.keyscale as u8;
+    env[14] = amp.on_vel_release as u8;
+    env[15] = amp.off_vel_release as u8;
+    push_chunk(&mut out, b"env ", &env);
+
+    let filter_env = keygroup.filter_env.clone().unwrap_or_default();
+    let mut env = new_chunk(18);
+    env[1] = filter_env.attack;
+    env[3] = filter_env.decay;
+    env[4] = filter_env.release;
+    env[7] = filter_env.sustain;
+    env[9] = filter_env.depth as u8;
+    env[10] = filter_env.velocity_attack as u8;
+    env[12] = filter_env.keyscale as u8;
+    env[14] = filter_env.on_vel_release as u8;
+    env[15] = filter_env.off_vel_release as u8;
+    push_chunk(&mut out, b"env ", &env);
+
+    let aux = keygroup.aux_env.clone().unwrap_or_default();
+    let mut env = new_chunk(18);
+    env[1..5].copy_from_slice(&[aux.rate_1, aux.rate_2, aux.rate_3, aux.rate_4]);
+    env[5..9].copy_from_slice(&[aux.level_1, aux.level_2, aux.level_3, aux.level_4]);
+    env[10] = aux.vel_rate_1 as u8;
+    env[12] = aux.key_rate_2_4 as u8;
+    env[14] = aux.vel_rate_4 as u8;
+    env[15] = aux.off_vel_rate_4 as u8;
+    env[16] = aux.vel_output_level as u8;
+    push_chunk(&mut out, b"env ", &env);
+
+    // Z4/Z8 programs carry the filter routing after the S5000 filter fields
+    let z_series = keygroup.filter2.is_some()
+        || keygroup.filter3.is_some()
+        || keygroup.filter_routing == FilterRouting::Parallel;
+    let mut filt = filt_chunk(keygroup.filter.as_ref().unwrap_or(&Filter::default()));
+    if z_series {
+        filt.push(match keygroup.filter_routing {
+            FilterRouting::Series => 0,
+            FilterRouting::Parallel => 1,
+        });
+    }
+    push_chunk(&mut out, b"filt", &filt);
+    for filter in [&keygroup.filter2, &keygroup.filter3].into_iter().flatten() {
+        push_chunk(&mut out, b"filt", &filt_chunk(filter));
+    }
+
+    for i in 0..4 {
+        push_chunk(&mut out, b"zone", &zone_chunk(keygroup.zones.get(i))?);
+    }
+
+    Ok(out)
+}
+
+fn filt_chunk(filter: &Filter) -> Vec<u8> {
+    let mut data = new_chunk(10);
+    data[1] = filter.filter_type;
+    data[2] = filter.cutoff;
+    data[3] = filter.resonance;
+    data[4] = filter.keyboard_track as u8;
+    data[5] = filter.mod_input_1 as u8;
+    data[6] = filter.mod_input_2 as u8;
+    data[7] = filter.mod_input_3 as u8;
+    data[8] = filter.headroom;
+    data
+}
+
+/// An empty zone (`None`) is written with a zero-length name.
+fn zone_chunk(zone: Option<&Zone>) -> Result<Vec<u8>> {
+    let mut data = new_chunk(46);
+    let Some(zone) = zone else {
+        return Ok(data);
+    };
+
+    // The parser appends .WAV to extensionless names; strip it back off
+    let name = zone.sample_name.strip_suffix(".WAV").unwrap_or(&zone.sample_name).replace('/', "\\");
+    if name.is_empty() || name.len() > MAX_SAMPLE_NAME {
+        return Err(AkpError::UnwritableProgram(format!(
+            "sample name '{name}' must be 1-{MAX_SAMPLE_NAME} bytes",
+        )));
+    }
+
+    data[1] = name.len() as u8;
+    data[2..2 + name.len()].copy_from_slice(name.as_bytes());
+    data[34] = zone.low_vel;
+    data[35] = zone.high_vel;
+    data[36] = zone.fine_tune as u8;
+    data[37] = zone.semitone_tune as u8;
+    data[38] = zone.filter as u8;
+    data[39] = zone.pan as u8;
+    data[40] = zone.playback;
+    data[41] = zone.output;
+    data[42] = zone.level as u8;
+    data[43] = zone.keyboard_track;
+    data[44..46].copy_from_slice(&zone.vel_to_start.to_le_bytes());
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_akp_bytes;
+
+    fn reparse(bytes: &[u8]) -> AkaiProgram {
+        parse_akp_bytes(bytes).unwrap()
+    }
+
+    /// Every field set to a non-default value so a dropped or misplaced
+    /// byte shows up as a mismatch.
+    fn s5000_program() -> AkaiProgram {
+        let keygroup = Keygroup {
+            low_key: 36,
+            high_key: 59,
+            semitone_tune: -3,
+            fine_tune: 12,
+            override_fx: 2,
+            fx_send_level: 40,
+            pitch_mod_1: -20,
+            pitch_mod_2: 15,
+            amp_mod: 60,
+            zone_crossfade: 1,
+            mute_group: 4,
+            zones: vec![
+                Zone {
+                    sample_name: "STR C2 PP.WAV".to_string(),
+                    low_vel: 0,
+                    high_vel: 63,
+                    fine_tune: -7,
+                    semitone_tune: 1,
+                    filter: -12,
+                    pan: 30,
+                    playback: 2,
+                    output: 3,
+                    level: -6,
+                    keyboard_track: 0,
+                    vel_to_start: -250,
+                    ..Default::default()
+                },
+                Zone { sample_name: "Strings/C2 FF.wav".to_string(), low_vel: 64, high_vel: 127, ..Default::default() },
+            ],
+            amp_env: Some(Envelope {
+                attack: 5, decay: 60, release: 35, sustain: 90,
+                velocity_attack: -10, keyscale: 20, on_vel_release: 5, off_vel_release: -5,
+            }),
+            filter_env: Some(FilterEnvelope {
+                attack: 1, decay: 2, release: 3, sustain: 4, depth: -50,
+                velocity_attack: 6, keyscale: 7, on_vel_release: 8, off_vel_release: 9,
+            }),
+            aux_env: Some(AuxEnvelope {
+                rate_1: 10, rate_2: 20, rate_3: 30, rate_4: 40,
+                level_1: 100, level_2: 80, level_3: 60, level_4: 0,
+                vel_rate_1: -1, key_rate_2_4: 2, vel_rate_4: -3, off_vel_rate_4: 4, vel_output_level: 50,
+            }),
+            filter: Some(Filter {
+                filter_type: 7, cutoff: 64, resonance: 9, keyboard_track: -25,
+                mod_input_1: 11, mod_input_2: -22, mod_input_3: 33, headroom: 2,
+            }),
+            ..Default::default()
+        };
+
+        AkaiProgram {
+            header: Some(ProgramHeader { midi_program_number: 12, number_of_keygroups: 2 }),
+            output: Some(ProgramOutput {
+                loudness: 77, amp_mod_1: 1, amp_mod_2: 2, pan_mod_1: 3, pan_mod_2: 4, pan_mod_3: 5,
+                velocity_sensitivity: -30,
+            }),
+            tuning: Some(ProgramTuning {
+                semitone: -2, fine: 17, detune: [0, 1, -2, 3, -4, 5, -6, 7, -8, 9, -10, 11],
+                pitchbend_up: 7, pitchbend_down: 12, bend_mode: 1, aftertouch: -4,
+            }),
+            lfo1: Some(Lfo {
+                waveform: 3, rate: 44, delay: 5, depth: 66, sync: 1, retrigger: 0,
+                modwheel: 70, aftertouch: 30, rate_mod: -5, delay_mod: 6, depth_mod: -7,
+            }),
+            lfo2: Some(Lfo {
+                waveform: 8, rate: 12, delay: 3, depth: 45, sync: 0, retrigger: 1,
+                modwheel: 0, aftertouch: 0, rate_mod: 9, delay_mod: -10, depth_mod: 11,
+            }),
+            modulation: Some(ProgramModulation {
+                amp_mod_1_source: 1, amp_mod_2_source: 2, pan_mod_1_source: 3, pan_mod_2_source: 4,
+                pan_mod_3_source: 5, lfo1_rate_mod_source: 6, lfo1_delay_mod_source: 7,
+                lfo1_depth_mod_source: 8, lfo2_rate_mod_source: 9, lfo2_delay_mod_source: 10,
+                lfo2_depth_mod_source: 11, pitch_mod_1_source: 12, pitch_mod_2_source: 13,
+                amp_mod_source: 14, filter_mod_1_source: 0, filter_mod_2_source: 5, filter_mod_3_source: 9,
+            }),
+            keygroups: vec![keygroup, Keygroup {
+                low_key: 60,
+                high_key: 127,
+                zones: vec![Zone { sample_name: "STR C4.WAV".to_string(), ..Default::default() }],
+                amp_env: Some(Envelope::default()),
+                filter_env: Some(FilterEnvelope::default()),
+                aux_env: Some(AuxEnvelope::default()),
+                filter: Some(Filter::default()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn z8_program() -> AkaiProgram {
+        let mut program = s5000_program();
+        program.model = ProgramModel::ZSeries;
+        let keygroup = &mut program.keygroups[0];
+        keygroup.filter2 = Some(Filter { filter_type: 12, cutoff: 50, resonance: 3, ..Default::default() });
+        keygroup.filter3 = Some(Filter { filter_type: 17, cutoff: 80, ..Default::default() });
+        keygroup.filter_routing = FilterRouting::Parallel;
+        program
+    }
+
+    fn mpc4000_program() -> AkaiProgram {
+        let mut program = s5000_program();
+        program.model = ProgramModel::Mpc4000;
+        let mut pads = PadAssignments::default();
+        pads.notes[0] = Some(36);
+        pads.notes[1] = Some(38);
+        pads.notes[63] = Some(0);
+        program.pads = Some(pads);
+        program
+    }
+
+    #[test]
+    fn test_round_trip_fixtures() {
+        for (name, program) in [("S5000", s5000_program()), ("Z8", z8_program()), ("MPC4000", mpc4000_program())] {
+            let bytes = program.to_akp_bytes().unwrap();
+            let reparsed = reparse(&bytes);
+            assert_eq!(reparsed, program, "{name} program changed in round trip");
+            assert_eq!(reparsed.to_akp_bytes().unwrap(), bytes, "{name} bytes changed in round trip");
+        }
+    }
+
+    fn chunk(id: &[u8; 4], data: Vec<u8>) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend(data);
+        out
+    }
+
+    /// The file create_test_akp.py generates: zero marker bytes and a
+    /// 48-byte zone, as some third-party tools write them.
+    fn generated_fixture() -> Vec<u8> {
+        let with = |size: usize, values: &[(usize, u8)]| {
+            let mut data = vec![0u8; size];
+            for &(offset, value) in values {
+                data[offset] = value;
+            }
+            data
+        };
+        let mut zone = with(48, &[(34, 1), (35, 127), (40, 4), (43, 1)]);
+        zone[1] = 8;
+        zone[2..10].copy_from_slice(b"Piano_C3");
+
+        let mut kgrp = chunk(b"kloc", with(16, &[(4, 36), (5, 96), (10, 50), (11, 30), (12, 80)]));
+        kgrp.extend(chunk(b"env ", with(18, &[(1, 10), (3, 50), (4, 30), (7, 80), (10, 20), (12, 15), (14, 10), (15, 5)])));
+        kgrp.extend(chunk(b"env ", with(18, &[(1, 5), (3, 60), (4, 40), (7, 70), (9, 50)])));
+        kgrp.extend(chunk(b"env ", with(18, &[(1, 10), (5, 100)])));
+        kgrp.extend(chunk(b"filt", with(10, &[(2, 75), (3, 6), (4, 10), (5, 40), (6, 30), (7, 20)])));
+        kgrp.extend(chunk(b"zone", zone));
+
+        let mut body = b"APRG".to_vec();
+        body.extend(chunk(b"prg ", with(6, &[(1, 1), (2, 1)])));
+        body.extend(chunk(b"out ", with(8, &[(1, 85), (2, 30), (3, 20), (4, 40), (5, 25), (6, 50), (7, 25)])));
+        body.extend(chunk(b"tune", with(22, &[(15, 2), (16, 2), (18, 3)])));
+        body.extend(chunk(b"lfo ", with(12, &[(1, 1), (2, 30), (4, 50), (7, 60), (8, 40), (9, 15), (10, 10), (11, 20)])));
+        body.extend(chunk(b"lfo ", with(12, &[])));
+        body.extend(chunk(b"mods", with(38, &[(5, 6), (7, 3), (9, 8), (11, 6), (13, 1), (15, 6), (27, 7), (29, 11), (31, 5), (33, 5), (35, 8), (37, 9)])));
+        body.extend(chunk(b"kgrp", kgrp));
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip_generated_fixture() {
+        let program = reparse(&generated_fixture());
+        assert_eq!(reparse(&program.to_akp_bytes().unwrap()), program);
+    }
+
+    #[test]
+    fn test_round_trip_mpc4000_kit_fixture() {
+        let program = reparse(&crate::fixtures::mpc4000_kit());
+        let bytes = program.to_akp_bytes().unwrap();
+        assert_eq!(&bytes[12..16], PAD_CHUNK_ID);
+        assert_eq!(reparse(&bytes), program);
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        let bytes = s5000_program().to_akp_bytes().unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"APRG");
+        assert_eq!(&bytes[12..16], b"prg ");
+        assert_eq!(bytes[20..26], [CHUNK_MARKER, 12, 2, 0, 0, 0]);
+
+        // prg, out, tune, lfo, lfo, mods, then keygroups of
+        // kloc + 3 env + filt + 4 zones
+        let kgrp = 12 + (8 + 6) + (8 + 8) + (8 + 22) + 2 * (8 + 12) + (8 + 38);
+        assert_eq!(&bytes[kgrp..kgrp + 4], b"kgrp");
+        let kgrp_size = u32::from_le_bytes(bytes[kgrp + 4..kgrp + 8].try_into().unwrap());
+        assert_eq!(kgrp_size, (8 + 16) + 3 * (8 + 18) + (8 + 10) + 4 * (8 + 46));
+    }
+
+    #[test]
+    fn test_missing_chunks_written_with_defaults() {
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "KICK.WAV".to_string(), ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let reparsed = reparse(&program.to_akp_bytes().unwrap());
+        assert_eq!(reparsed.output, Some(ProgramOutput::default()));
+        assert_eq!(reparsed.tuning, Some(ProgramTuning::default()));
+        assert_eq!(reparsed.keygroups[0].filter, Some(Filter::default()));
+        assert_eq!(reparsed.keygroups[0].zones, program.keygroups[0].zones);
+    }
+
+    #[test]
+    fn test_sample_name_too_long() {
+        let mut program = s5000_program();
+        program.keygroups[0].zones[0].sample_name = "A VERY LONG SAMPLE NAME.WAV".to_string();
+        assert!(matches!(program.to_akp_bytes(), Err(AkpError::UnwritableProgram(_))));
+    }
+
+    #[test]
+    fn test_write_akp() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("strings.akp");
+        s5000_program().write_akp(&path).unwrap();
+        assert_eq!(reparse(&fs::read(&path).unwrap()), s5000_program());
+    }
+
+    #[test]
+    fn test_use_akp_sample_names() {
+        let mut program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    Zone { sample_name: "Samples/Piano C3.wav".to_string(), ..Default::default() },
+                    Zone { sample_name: "Samples/Piano C3.wav".to_string(), ..Default::default() },
+                    Zone { sample_name: "kick".to_string(), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let samples = program.use_akp_sample_names();
+        assert_eq!(samples, [
+            SampleFile { name: "Piano C3.WAV".to_string(), path: "Samples/Piano C3.wav".to_string() },
+            SampleFile { name: "kick.WAV".to_string(), path: "kick".to_string() },
+        ]);
+        let names: Vec<&str> = program.keygroups[0].zones.iter().map(|z| z.sample_name.as_str()).collect();
+        assert_eq!(names, ["Piano C3.WAV", "Piano C3.WAV", "kick.WAV"]);
+    }
+
+    #[test]
+    fn test_use_akp_sample_names_numbers_clashes() {
+        let zone = |path: &str| Zone { sample_name: path.to_string(), ..Default::default() };
+        let mut program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    zone("soft/Piano C3.wav"),
+                    zone("loud/Piano C3.wav"),
+                    zone("PIANO C3.aif"),
+                    zone("loud/Piano C3.wav"),
+                ],
+                ..Default::default()
+            }, Keygroup {
+                zones: vec![zone("a/Grand Piano Forte C3.wav"), zone("b/Grand Piano Forte C3.wav")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let samples = program.use_akp_sample_names();
+        let names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Piano C3.WAV", "Piano C3 2.WAV", "PIANO C3 3.WAV", "Grand Piano Forte C3.WAV", "Grand Piano Forte 2.WAV"]);
+        let zones: Vec<&str> = program.keygroups[0].zones.iter().map(|z| z.sample_name.as_str()).collect();
+        assert_eq!(zones, ["Piano C3.WAV", "Piano C3 2.WAV", "PIANO C3 3.WAV", "Piano C3 2.WAV"]);
+
+        // Both layers reach the program's folder
+        let source = tempfile::TempDir::new().unwrap();
+        let output = tempfile::TempDir::new().unwrap();
+        for (dir, note) in [("soft", 60), ("loud", 62)] {
+            fs::create_dir(source.path().join(dir)).unwrap();
+            fs::write(source.path().join(dir).join("Piano C3.wav"), test_wav(note)).unwrap();
+        }
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup { zones: vec![zone("soft/Piano C3.wav"), zone("loud/Piano C3.wav")], ..Default::default() }],
+            ..Default::default()
+        };
+        let missing = program.write_akp_with_samples(&output.path().join("Piano.akp"), source.path()).unwrap();
+        assert!(missing.is_empty(), "{missing:?}");
+        assert_eq!(fs::read(output.path().join("Piano C3.wav")).unwrap(), test_wav(60));
+        assert_eq!(fs::read(output.path().join("Piano C3 2.wav")).unwrap(), test_wav(62));
+    }
+
+    /// Mono 16-bit WAV with a smpl chunk at `unity_note`.
+    fn test_wav(unity_note: u32) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\0\0\0\x01\0\x01\0\x44\xac\0\0\x88\x58\x01\0\x02\0\x10\0");
+        let mut smpl = vec![0u8; 36];
+        smpl[12..16].copy_from_slice(&unity_note.to_le_bytes());
+        wav.extend_from_slice(b"smpl");
+        wav.extend_from_slice(&36u32.to_le_bytes());
+        wav.extend(smpl);
+        wav.extend_from_slice(b"data\x04\0\0\0\0\0\0\0");
+        let riff_size = (wav.len() - 8) as u32;
+        wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        wav
+    }
+
+    #[test]
+    fn test_match_sample_roots() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("a.wav"), test_wav(55)).unwrap();
+
+        let mut program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    Zone { sample_name: "a.wav".to_string(), root_key: Some(60), ..Default::default() },
+                    Zone { sample_name: "missing.wav".to_string(), root_key: Some(60), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let samples = program.use_akp_sample_names();
+        assert_eq!(program.match_sample_roots(&samples, dir.path()), ["missing.wav"]);
+        let zones = &program.keygroups[0].zones;
+        assert_eq!((zones[0].root_key, zones[0].semitone_tune), (Some(55), -5));
+        assert_eq!((zones[1].root_key, zones[1].semitone_tune), (Some(60), 0));
+    }
+
+    #[test]
+    fn test_write_akp_with_samples() {
+        let source = tempfile::TempDir::new().unwrap();
+        let output = tempfile::TempDir::new().unwrap();
+        fs::create_dir(source.path().join("Samples")).unwrap();
+        fs::write(source.path().join("Samples/Piano C3.wav"), test_wav(55)).unwrap();
+
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    Zone { sample_name: "Samples/Piano C3.wav".to_string(), root_key: Some(60), ..Default::default() },
+                    Zone { sample_name: "missing.wav".to_string(), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let path = output.path().join("Piano.akp");
+        let missing = program.write_akp_with_samples(&path, source.path()).unwrap();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, "missing.wav");
+
+        let written = reparse(&fs::read(&path).unwrap());
+        assert_eq!(written.keygroups[0].zones[0].sample_name, "Piano C3.WAV");
+        assert_eq!(written.keygroups[0].zones[0].semitone_tune, -5);
+        assert!(output.path().join("Piano C3.wav").exists());
+        // The program itself is left as it was
+        assert_eq!(program.keygroups[0].zones[0].sample_name, "Samples/Piano C3.wav");
+    }
+}
@@ -1,26 +1,36 @@
-use std::fs::{self, File};
+use std::collections::BTreeMap;
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use rusty_samplers::{AkpError, AkaiProgram, OutputFormat, Result, CopyConfig, copy_samples};
-use rusty_samplers::parser::{validate_riff_header, parse_top_level_chunks};
+use rusty_samplers::{AkpError, AkaiProgram, ConvertOptions, DetuneMode, Result, CopyConfig, copy_samples, copy_samples_from};
+use rusty_samplers::registry::{ProgramWriter, Registry, WriteTarget};
+use rusty_samplers::diskimage::{self, DiskImage, ImageEntry};
+use rusty_samplers::parser::{parse_akp_bytes, ParseObserver, ParseWarning};
+use rusty_samplers::s1000;
 
 #[derive(Parser)]
 #[command(name = "rusty-samplers-cli")]
 #[command(about = "Multi-Format Sampler Converter — converts Akai AKP files to SFZ and Decent Sampler formats")]
 #[command(version)]
 struct Cli {
-    /// Input AKP file or directory (with --batch)
-    input: PathBuf,
+    /// Input program in any format from --list-formats, disk image (.iso/.img), or directory (with --batch)
+    #[arg(required_unless_present = "list_formats")]
+    input: Option<PathBuf>,
 
-    /// Output format: sfz, ds
-    #[arg(short, long, default_value = "sfz", value_parser = parse_format)]
-    format: OutputFormat,
+    /// Output format: sfz, ds, akp, sf2, bitwig, ableton, mpc, exs, renoise, deluge, blackbox (see --list-formats).
+    /// Defaults to sfz, or akp for SFZ input
+    #[arg(short, long, value_parser = parse_format)]
+    format: Option<&'static str>,
 
-    /// Batch convert all .akp files in a directory
+    /// List the input and output formats and exit
+    #[arg(long)]
+    list_formats: bool,
+
+    /// Batch convert all .akp (and S1000/S3000 .p/.p3) files in a directory
     #[arg(short, long)]
     batch: bool,
 
@@ -31,23 +41,61 @@ struct Cli {
     /// Directory to search for source sample files (default: same as input)
     #[arg(long)]
     sample_dir: Option<PathBuf>,
+
+    /// How to write per-note detune tables: scala (.scl file), split (per-key regions)
+    #[arg(long, default_value = "scala", value_parser = parse_detune_mode)]
+    detune: DetuneMode,
+
+    /// Lay out MPC4000 programs as a drum kit by pad (labels like "Pad A01")
+    #[arg(long)]
+    drum_kit: bool,
+}
+
+/// Resolve a format name or alias to its writer id.
+fn parse_format(s: &str) -> std::result::Result<&'static str, String> {
+    let registry = Registry::default();
+    registry.writer(s)
+        .map(|writer| writer.info().id)
+        .ok_or_else(|| format!("Unknown format '{s}'. Valid formats: {}", registry.writer_ids()))
 }
 
-fn parse_format(s: &str) -> std::result::Result<OutputFormat, String> {
+fn parse_detune_mode(s: &str) -> std::result::Result<DetuneMode, String> {
     match s.to_lowercase().as_str() {
-        "sfz" => Ok(OutputFormat::Sfz),
-        "ds" | "dspreset" | "decent" | "decentsampler" => Ok(OutputFormat::DecentSampler),
-        other => Err(format!("Unknown format '{other}'. Valid formats: sfz, ds")),
+        "scala" | "scl" => Ok(DetuneMode::Scala),
+        "split" => Ok(DetuneMode::SplitRegions),
+        other => Err(format!("Unknown detune mode '{other}'. Valid modes: scala, split")),
     }
 }
 
 fn main() {
     let cli = Cli::parse();
+    let registry = Registry::default();
+
+    if cli.list_formats {
+        print_formats(&registry);
+        return;
+    }
+    let input = cli.input.as_deref().expect("clap requires an input without --list-formats");
+
+    // Without --format, the input's reader picks the output
+    let default_format = (!cli.batch && !diskimage::is_image_file(input))
+        .then(|| registry.detect(input))
+        .flatten()
+        .map_or("sfz", |reader| reader.default_output());
+    let writer = registry.writer(cli.format.unwrap_or(default_format)).expect("checked by parse_format");
+
+    let options = ConvertOptions {
+        detune: cli.detune,
+        drum_kit_layout: cli.drum_kit,
+        ..Default::default()
+    };
 
-    let result = if cli.batch {
-        run_batch_conversion(&cli.input, cli.format, cli.copy_samples, cli.sample_dir.as_deref())
+    let result = if diskimage::is_image_file(input) {
+        run_image_conversion(&registry, input, writer, &options)
+    } else if cli.batch {
+        run_batch_conversion(&registry, input, writer, &options, cli.copy_samples, cli.sample_dir.as_deref())
     } else {
-        run_conversion(&cli.input, cli.format, cli.copy_samples, cli.sample_dir.as_deref())
+        run_conversion(&registry, input, writer, &options, cli.copy_samples, cli.sample_dir.as_deref())
     };
 
     if let Err(e) = result {
@@ -56,7 +104,34 @@ fn main() {
     }
 }
 
-fn run_batch_conversion(directory: &Path, format: OutputFormat, do_copy_samples: bool, sample_dir: Option<&Path>) -> Result<()> {
+fn print_formats(registry: &Registry) {
+    println!("Input formats:");
+    for reader in registry.readers() {
+        let info = reader.info();
+        println!("   {:<10} {} (.{})", info.id, info.name, info.extensions.join(", ."));
+    }
+    println!();
+    println!("Output formats (--format):");
+    for writer in registry.writers() {
+        let info = writer.info();
+        let capabilities = writer.capabilities();
+        let mut notes = Vec::new();
+        if capabilities.bundles_samples {
+            notes.push("bundles samples");
+        }
+        if capabilities.card_layout {
+            notes.push("card layout");
+        }
+        if capabilities.scala_tuning {
+            notes.push("Scala tuning");
+        }
+        let aliases = if info.aliases.is_empty() { String::new() } else { format!(" [{}]", info.aliases.join(", ")) };
+        let notes = if notes.is_empty() { String::new() } else { format!(" — {}", notes.join(", ")) };
+        println!("   {:<10} .{:<12} {}{aliases}{notes}", info.id, info.extension(), info.description);
+    }
+}
+
+fn run_batch_conversion(registry: &Registry, directory: &Path, writer: &dyn ProgramWriter, options: &ConvertOptions, do_copy_samples: bool, sample_dir: Option<&Path>) -> Result<()> {
     if !directory.exists() {
         return Err(AkpError::Io(io::Error::new(
             io::ErrorKind::NotFound,
@@ -72,11 +147,11 @@ fn run_batch_conversion(directory: &Path, format: OutputFormat, do_copy_samples:
     }
 
     let mut akp_files = Vec::new();
-    collect_akp_files(directory, &mut akp_files)?;
+    collect_program_files(directory, &mut akp_files)?;
     akp_files.sort();
 
     if akp_files.is_empty() {
-        println!("No .akp files found in directory: {}", directory.display());
+        println!("No program files found in directory: {}", directory.display());
         return Ok(());
     }
 
@@ -100,16 +175,15 @@ fn run_batch_conversion(directory: &Path, format: OutputFormat, do_copy_samples:
         let file_name = akp_file.file_name().unwrap_or(akp_file.as_os_str()).to_string_lossy();
         batch_progress.set_message(format!("Processing {file_name}"));
 
-        match run_conversion(akp_file, format, do_copy_samples, sample_dir) {
+        match run_conversion(registry, akp_file, writer, options, do_copy_samples, sample_dir) {
             Ok(()) => {
                 success_count += 1;
                 batch_progress.println(format!("OK: {file_name}"));
             }
             Err(e) => {
                 error_count += 1;
-                let error_msg = format!("{file_name}: {e}");
-                errors.push(error_msg.clone());
-                batch_progress.println(format!("FAIL: {error_msg}"));
+                batch_progress.println(format!("FAIL: {file_name}: {e}"));
+                errors.push((file_name.into_owned(), e));
             }
         }
 
@@ -124,26 +198,142 @@ fn run_batch_conversion(directory: &Path, format: OutputFormat, do_copy_samples:
     println!("   Failed:     {error_count}");
     println!("   Total:      {}", akp_files.len());
 
-    if !errors.is_empty() {
-        println!();
-        println!("ERRORS:");
-        for error in &errors {
-            println!("   - {error}");
+    print_errors(&errors);
+    Ok(())
+}
+
+/// List failures grouped by error code, so that files failing the same
+/// way end up together.
+fn print_errors(errors: &[(String, AkpError)]) {
+    if errors.is_empty() {
+        return;
+    }
+    let mut by_code: BTreeMap<&str, Vec<&(String, AkpError)>> = BTreeMap::new();
+    for error in errors {
+        by_code.entry(error.1.code()).or_default().push(error);
+    }
+
+    println!();
+    println!("ERRORS:");
+    for (code, errors) in by_code {
+        println!("   {code} ({}):", errors.len());
+        for (name, error) in errors {
+            println!("      - {name}: {error}");
         }
     }
+}
+
+/// Convert every program on a disk image. Programs are extracted into a
+/// directory named after the image, mirroring the image's layout, with
+/// their samples alongside.
+fn run_image_conversion(registry: &Registry, image_path: &Path, writer: &dyn ProgramWriter, options: &ConvertOptions) -> Result<()> {
+    if !image_path.exists() {
+        return Err(AkpError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File '{}' not found", image_path.display()),
+        )));
+    }
+
+    let image = DiskImage::open(image_path)?;
+    let programs = image.programs();
+    println!("{} image: {} files, {} programs", image.format(), image.entries().len(), programs.len());
+
+    if programs.is_empty() {
+        return Ok(());
+    }
 
+    let stem = image_path.file_stem().unwrap_or_default();
+    let output_root = image_path.with_file_name(stem);
+    println!("Extracting to {}", output_root.display());
+    println!();
+
+    let mut success_count = 0;
+    let mut errors = Vec::new();
+
+    for entry in &programs {
+        match convert_image_program(registry, &image, entry, &output_root, writer, options) {
+            Ok(()) => {
+                success_count += 1;
+                println!("OK: {}", entry.path);
+            }
+            Err(e) => {
+                println!("FAIL: {}: {e}", entry.path);
+                errors.push((entry.path.clone(), e));
+            }
+        }
+    }
+
+    println!();
+    println!("IMAGE SUMMARY:");
+    println!("   Successful: {success_count}");
+    println!("   Failed:     {}", errors.len());
+    println!("   Total:      {}", programs.len());
+
+    print_errors(&errors);
     Ok(())
 }
 
-fn collect_akp_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+fn convert_image_program(registry: &Registry, image: &DiskImage, entry: &ImageEntry, output_root: &Path, writer: &dyn ProgramWriter, options: &ConvertOptions) -> Result<()> {
+    let program_path = output_root.join(&entry.path);
+    let output_dir = program_path.parent().unwrap_or(output_root);
+    image.extract(entry, &program_path)?;
+
+    if s1000::is_program_file(&program_path) {
+        // S1000/S3000 samples are extracted as-is; run_conversion converts them to WAV
+        let program = s1000::parse_s1000_program(&image.read(entry)?)?;
+        for sample_path in program.sample_paths() {
+            let name = sample_path.trim_end_matches(".WAV");
+            let sample = image.entries().iter().find(|e| {
+                e.dir() == entry.dir()
+                    && e.name().rsplit_once('.').is_some_and(|(stem, ext)| {
+                        stem.eq_ignore_ascii_case(name)
+                            && s1000::SAMPLE_EXTENSIONS.iter().any(|s| s.eq_ignore_ascii_case(ext))
+                    })
+            });
+            if let Some(sample) = sample {
+                image.extract(sample, &output_dir.join(sample.name()))?;
+            }
+        }
+    } else {
+        let program = parse_akp_bytes(&image.read(entry)?)?;
+        let sample_paths = program.sample_paths();
+        let report = copy_samples_from(&image.dir_source(entry.dir()), output_dir, &sample_paths);
+        println!("Samples: {}", report.summary());
+    }
+
+    run_conversion(registry, &program_path, writer, options, false, None)
+}
+
+/// Write `program` as `name` into `output_dir`, reading samples from
+/// `sample_dir`, and warn about any samples left out. Returns the preset
+/// file written.
+fn write_program(
+    program: &AkaiProgram,
+    writer: &dyn ProgramWriter,
+    output_dir: &Path,
+    name: &str,
+    sample_dir: &Path,
+    options: &ConvertOptions,
+    progress: &ProgressBar,
+) -> Result<PathBuf> {
+    let target = WriteTarget { output_dir, name, sample_dir };
+    let outcome = writer.write(program, &target, options)?;
+    for (sample, reason) in &outcome.missing_samples {
+        progress.suspend(|| println!("Warning: sample '{sample}' left out of the {} output ({reason})", writer.info().name));
+    }
+    Ok(outcome.path)
+}
+
+fn collect_program_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
-            collect_akp_files(&path, files)?;
+            collect_program_files(&path, files)?;
         } else if path.extension()
             .and_then(|s| s.to_str())
             .is_some_and(|ext| ext.eq_ignore_ascii_case("akp"))
+            || s1000::is_program_file(&path)
         {
             files.push(path);
         }
@@ -151,13 +341,24 @@ fn collect_akp_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
     Ok(())
 }
 
-fn run_conversion(file_path: &Path, format: OutputFormat, do_copy_samples: bool, sample_dir: Option<&Path>) -> Result<()> {
+/// Convert one file: read it with the registry's reader for its format,
+/// extract its samples if they aren't WAVs already, and write each of its
+/// programs next to it.
+fn run_conversion(registry: &Registry, file_path: &Path, writer: &dyn ProgramWriter, options: &ConvertOptions, do_copy_samples: bool, sample_dir: Option<&Path>) -> Result<()> {
     if !file_path.exists() {
         return Err(AkpError::Io(io::Error::new(
             io::ErrorKind::NotFound,
             format!("File '{}' not found", file_path.display()),
         )));
     }
+    let reader = registry.detect(file_path).ok_or_else(|| {
+        AkpError::UnsupportedFormat(format!("'{}' isn't a format that can be read (see --list-formats)", file_path.display()))
+    })?;
+    if reader.info().id == writer.info().id {
+        return Err(AkpError::UnsupportedFormat(format!(
+            "'{}' is already {}; choose another --format", file_path.display(), writer.info().name,
+        )));
+    }
 
     let progress = ProgressBar::new(100);
     progress.set_style(
@@ -168,60 +369,115 @@ fn run_conversion(file_path: &Path, format: OutputFormat, do_copy_samples: bool,
         .progress_chars("##-"),
     );
 
-    progress.set_message("Opening file...");
+    progress.set_message(format!("Reading {}...", reader.info().name));
     progress.inc(10);
+    let mut programs = reader.read(file_path, &mut ProgressObserver(&progress))?;
+    if programs.is_empty() {
+        return Err(AkpError::InvalidPreset(reader.info().name.to_string(), "no programs found".to_string()));
+    }
+    progress.set_position(50);
+
+    let search = sample_dir
+        .unwrap_or_else(|| file_path.parent().unwrap_or(Path::new(".")));
+    let output_dir = file_path.parent().unwrap_or(Path::new("."));
+
+    // Samples that aren't WAVs (S1000/S3000, SF2) are written as WAVs next
+    // to the output, and read from there
+    progress.set_message("Extracting samples...");
+    let extracted = reader.extract_samples(file_path, search, output_dir)?;
+    let wav_dir = if extracted.is_some() { output_dir } else { search };
+
+    progress.set_message("Reading sample headers...");
+    let sample_info: Vec<_> = programs.iter_mut()
+        .filter_map(|read| reader.apply_sample_info(&mut read.program, wav_dir))
+        .collect();
+
+    let capabilities = writer.capabilities();
+
+    // Extracted samples are already alongside the output, and bundling
+    // writers carry their own. Copying comes first so an .adv or .exs can
+    // point at the copies.
+    let copy_reports: Vec<_> = if do_copy_samples && extracted.is_none() && !capabilities.bundles_samples {
+        progress.set_message("Copying samples...");
+        programs.iter().map(|read| {
+            let sample_paths = read.program.sample_paths();
+            let config = CopyConfig {
+                search_dir: search,
+                output_dir,
+                sample_paths: &sample_paths,
+            };
+            copy_samples(&config)
+        }).collect()
+    } else {
+        Vec::new()
+    };
+    let sample_root = if copy_reports.is_empty() { wav_dir } else { output_dir };
+
+    progress.set_message(format!("Writing {} output...", writer.info().name));
+    let mut written = Vec::new();
+    for read in &programs {
+        // Detune table goes to a .scl next to the SFZ unless regions are split
+        let mut options = options.clone();
+        let has_detune = read.program.tuning.as_ref().is_some_and(|t| t.has_detune());
+        if capabilities.scala_tuning && options.detune == DetuneMode::Scala && has_detune {
+            let scala_path = output_dir.join(format!("{}.scl", read.name));
+            fs::write(&scala_path, read.program.to_scala_string(&format!("{} detune table", read.name)))?;
+            options.scala_file = scala_path.file_name().map(|n| n.to_string_lossy().into_owned());
+        }
 
-    let mut file = File::open(file_path)?;
-
-    progress.set_message("Validating RIFF header...");
-    progress.inc(10);
-    validate_riff_header(&mut file)?;
-
-    progress.set_message("Parsing chunks...");
-    progress.inc(20);
-    let mut program = AkaiProgram::default();
-    let file_len = file.metadata()?.len();
-    parse_top_level_chunks(&mut file, file_len, &mut program, &progress)?;
+        // Card layouts go next to the program, named after it
+        written.push(write_program(&read.program, writer, output_dir, &read.name, sample_root, &options, &progress)?);
+    }
 
-    progress.set_message("Validating structure...");
-    progress.inc(10);
+    progress.finish_with_message(match written.as_slice() {
+        [path] => format!("Created {}", path.display()),
+        paths => format!("Created {} {} programs", paths.len(), writer.info().name),
+    });
+    if written.len() > 1 {
+        for path in &written {
+            println!("   {}", path.display());
+        }
+    }
 
-    if program.keygroups.is_empty() {
-        return Err(AkpError::MissingRequiredChunk("keygroup".to_string()));
+    if let Some(extracted) = &extracted {
+        println!("Samples: {} extracted as WAV to {}", extracted.written, output_dir.display());
+        for (sample, reason) in &extracted.missing_samples {
+            println!("Warning: could not extract sample '{sample}' ({reason})");
+        }
     }
 
-    let format_name = match format {
-        OutputFormat::Sfz => "SFZ",
-        OutputFormat::DecentSampler => "Decent Sampler",
-    };
+    for report in &sample_info {
+        println!("Root keys: {}", report.summary());
+        for name in report.unresolved() {
+            println!("Warning: no root key for '{name}' (sample not found and no note in name)");
+        }
+    }
 
-    progress.set_message(format!("Generating {format_name} output..."));
-    let (output_content, file_extension) = match format {
-        OutputFormat::Sfz => (program.to_sfz_string(), "sfz"),
-        OutputFormat::DecentSampler => (program.to_dspreset_string(), "dspreset"),
-    };
+    for report in &copy_reports {
+        println!("Samples: {}", report.summary());
+    }
 
-    let output_path = file_path.with_extension(file_extension);
+    // Multi-program formats repeat the file's skipped list on each program
+    if let Some(read) = programs.first().filter(|read| !read.skipped.is_empty()) {
+        println!("{} settings couldn't be represented:", read.skipped.len());
+        for skipped in &read.skipped {
+            println!("   - {skipped}");
+        }
+    }
 
-    progress.set_message("Writing output...");
-    fs::write(&output_path, output_content)?;
+    Ok(())
+}
 
-    progress.finish_with_message(format!("Created {}", output_path.display()));
+/// Shows parsing on the progress bar, which it moves from 20 to 50, and
+/// prints the parser's warnings above it.
+struct ProgressObserver<'a>(&'a ProgressBar);
 
-    if do_copy_samples {
-        let search = sample_dir
-            .unwrap_or_else(|| file_path.parent().unwrap_or(Path::new(".")));
-        let output_dir = output_path.parent().unwrap_or(Path::new("."));
-        let sample_paths = program.sample_paths();
-        let path_refs: Vec<&str> = sample_paths.to_vec();
-        let config = CopyConfig {
-            search_dir: search,
-            output_dir,
-            sample_paths: &path_refs,
-        };
-        let report = copy_samples(&config);
-        println!("Samples: {}", report.summary());
+impl ParseObserver for ProgressObserver<'_> {
+    fn progress(&mut self, fraction: f32) {
+        self.0.set_position(20 + (fraction * 30.0) as u64);
     }
 
-    Ok(())
+    fn warning(&mut self, warning: ParseWarning) {
+        self.0.println(format!("Warning: {warning}"));
+    }
 }
@@ -0,0 +1,332 @@
+//! Bitwig Studio `.multisample` output: a zip holding `multisample.xml` and
+//! the WAVs it plays, which Bitwig's Sampler device loads directly.
+//!
+//! The format only describes the sample mapping (key and velocity ranges
+//! with their fades, root key, tuning, gain and loop); envelopes, filters
+//! and modulation are Sampler settings rather than part of the file. Samples
+//! are found through `samples.rs` and stored as they are, uncompressed,
+//! since WAV data barely deflates.
+
+use std::fs;
+use std::path::Path;
+
+use crate::dspreset::xml_escape;
+use crate::error::{AkpError, Result};
+use crate::samples::{find_wav_info, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions};
+
+/// A `.multisample` file and the samples that couldn't go in it.
+#[derive(Debug, Clone)]
+pub struct MultisampleOutput {
+    pub bytes: Vec<u8>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample stored in the zip.
+struct BundledSample {
+    file_name: String,
+    bytes: Vec<u8>,
+    info: WavInfo,
+}
+
+impl AkaiProgram {
+    /// Build a `.multisample` named `name`, reading samples from
+    /// `sample_dir`. Zones whose sample can't be read are left out and listed
+    /// in the output; if none can be read this fails.
+    pub fn to_multisample(&self, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<MultisampleOutput> {
+        let mut bundled: LoadedSamples<BundledSample> = LoadedSamples::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+        let program_db = self.output.as_ref().map_or(0.0, |o| o.volume_db());
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<multisample name=\"{}\">\n", xml_escape(name)));
+        xml.push_str("  <generator>Rusty Samplers</generator>\n");
+        xml.push_str("  <category/>\n");
+        xml.push_str("  <creator/>\n");
+        xml.push_str("  <description>Converted from an Akai program</description>\n");
+        xml.push_str("  <keywords/>\n");
+        xml.push_str("  <layer name=\"Default\">\n");
+
+        // Per-key detune has no Bitwig equivalent, so those keygroups split per key
+        for range in self.key_layout(true, options.drum_kit_layout) {
+            let keygroup = range.keygroup;
+            for (zone, xf) in keygroup.zones.iter().zip(keygroup.velocity_crossfades()) {
+                if zone.sample_name.is_empty() {
+                    continue;
+                }
+                let sample = bundled.load(&zone.sample_name, |bundled| bundle_sample(sample_dir, &zone.sample_name, bundled));
+                let Some(sample) = sample else { continue };
+
+                let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                    + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                    + zone.root_tune as i32 + range.detune as i32;
+                let gain = program_db + zone.level as f32;
+                xml.push_str(&format!(
+                    "    <sample file=\"{}\" gain=\"{gain:.2}\" sample-start=\"0.000\" sample-stop=\"{:.3}\" tune=\"{:.2}\" reverse=\"false\" zone-logic=\"always-play\">\n",
+                    xml_escape(&sample.file_name), sample.info.frames as f32, cents as f32 / 100.0,
+                ));
+
+                let root_key = zone.root_key.or(sample.info.unity_note).unwrap_or(DEFAULT_ROOT_KEY);
+                let track = if zone.keyboard_track == 0 { "0.0" } else { "1.0" };
+                xml.push_str(&format!(
+                    "      <key low=\"{}\" high=\"{}\" root=\"{root_key}\" track=\"{track}\"/>\n",
+                    range.low_key, range.high_key,
+                ));
+
+                // Fades run from the widened range edge to the layer's own range
+                let fade_low = xf.fade_in.map_or(0, |(from, to)| to - from);
+                let fade_high = xf.fade_out.map_or(0, |(from, to)| to - from);
+                xml.push_str(&format!(
+                    "      <velocity low=\"{}\" high=\"{}\" fade-low=\"{fade_low}\" fade-high=\"{fade_high}\"/>\n",
+                    xf.low_vel, xf.high_vel,
+                ));
+
+                // Bitwig's loop end is exclusive and its fade a fraction of the loop
+                match zone.sample_loop.or(sample.info.sample_loop).filter(|_| zone.loop_enabled()) {
+                    Some(sample_loop) => {
+                        let length = (sample_loop.end + 1).saturating_sub(sample_loop.start).max(1);
+                        xml.push_str(&format!(
+                            "      <loop mode=\"loop\" start=\"{:.3}\" stop=\"{:.3}\" fade=\"{:.2}\"/>\n",
+                            sample_loop.start as f32, (sample_loop.end + 1) as f32,
+                            (sample_loop.crossfade as f32 / length as f32).min(1.0),
+                        ));
+                    }
+                    None => xml.push_str("      <loop mode=\"off\"/>\n"),
+                }
+                xml.push_str("    </sample>\n");
+            }
+        }
+
+        xml.push_str("  </layer>\n");
+        xml.push_str("</multisample>\n");
+
+        let (bundled, missing_samples) = bundled.finish(sample_dir)?;
+        let mut files: Vec<(&str, &[u8])> = vec![("multisample.xml", xml.as_bytes())];
+        files.extend(bundled.iter().map(|s| (s.file_name.as_str(), s.bytes.as_slice())));
+        let bytes = zip_stored(&files)?;
+        Ok(MultisampleOutput { bytes, missing_samples })
+    }
+
+    /// Write the program to a `.multisample` file named after it, returning
+    /// the samples that couldn't be included (see `to_multisample`).
+    pub fn write_multisample(&self, path: &Path, sample_dir: &Path, options: &ConvertOptions) -> Result<MissingSamples> {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let output = self.to_multisample(&name, sample_dir, options)?;
+        fs::write(path, output.bytes)?;
+        Ok(output.missing_samples)
+    }
+}
+
+/// Read a sample for the zip, under its file name (numbered if an earlier
+/// sample from another folder has the same one).
+fn bundle_sample(sample_dir: &Path, sample_name: &str, bundled: &[BundledSample]) -> std::result::Result<BundledSample, String> {
+    let (path, info) = find_wav_info(sample_dir, sample_name)?;
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let (stem, extension) = file_name.rsplit_once('.').unwrap_or((&file_name, "wav"));
+    let taken = |name: &str| bundled.iter().any(|s| s.file_name.eq_ignore_ascii_case(name));
+    let mut unique = file_name.clone();
+    let mut number = 2;
+    while taken(&unique) {
+        unique = format!("{stem} {number}.{extension}");
+        number += 1;
+    }
+    Ok(BundledSample { file_name: unique, bytes, info })
+}
+
+/// A zip archive with every file stored (method 0). Timestamps are fixed at
+/// 1980-01-01 so the same program always gives the same bytes.
+pub(crate) fn zip_stored(files: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+    const VERSION: u16 = 20;
+    const UTF8_NAMES: u16 = 1 << 11;
+    const DOS_DATE: u16 = (1 << 5) | 1;
+
+    let too_large = || AkpError::UnwritableProgram("samples exceed the 4 GB zip limit".to_string());
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let crc = crc32(data);
+
+        // Fields shared by the local and central headers, from "version needed"
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&VERSION.to_le_bytes());
+        fields.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes()); // stored
+        fields.extend_from_slice(&0u16.to_le_bytes()); // time
+        fields.extend_from_slice(&DOS_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&fields);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&VERSION.to_le_bytes()); // version made by
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&[0; 10]); // comment length, disk, internal and external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    Ok(out)
+}
+
+/// CRC-32 (IEEE), as zip uses.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &byte| TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::samples::WavAudio;
+    use crate::types::{Keygroup, ProgramOutput, SampleLoop, Zone};
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels: vec![(0..frames as i16).collect()] }.to_wav_bytes()
+    }
+
+    /// (name, data) for each local file entry, checking its CRC.
+    pub(crate) fn zip_entries(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while u32_at(offset) == 0x0403_4b50 {
+            assert_eq!(u16_at(offset + 8), 0, "entries are stored");
+            let size = u32_at(offset + 18) as usize;
+            let name_start = offset + 30;
+            let data_start = name_start + u16_at(offset + 26) + u16_at(offset + 28);
+            let data = bytes[data_start..data_start + size].to_vec();
+            assert_eq!(u32_at(offset + 14), crc32(&data));
+            entries.push((String::from_utf8(bytes[name_start..name_start + u16_at(offset + 26)].to_vec()).unwrap(), data));
+            offset = data_start + size;
+        }
+
+        // The end record points back at a central directory with an entry per file
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), entries.len());
+        assert_eq!(u32_at(end + 16) as usize, offset);
+        assert_eq!(u32_at(offset), 0x0201_4b50);
+        entries
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_multisample_zip_and_mapping() {
+        let dir = TempDir::new().unwrap();
+        let looped = SampleLoop { start: 10, end: 29, crossfade: 5 };
+        let piano = wav(40, Some(48), Some(looped));
+        fs::write(dir.path().join("piano.wav"), &piano).unwrap();
+
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                semitone_tune: 1,
+                zones: vec![
+                    Zone { sample_name: "PIANO".to_string(), high_vel: 100, level: -3, fine_tune: -30, root_key: Some(48), sample_loop: Some(looped), playback: 2, ..Default::default() },
+                    Zone { sample_name: "MISSING".to_string(), low_vel: 101, ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_multisample("Piano & Co", dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+
+        let entries = zip_entries(&output.bytes);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["multisample.xml", "piano.wav"]);
+        assert_eq!(entries[1].1, piano);
+
+        let xml = String::from_utf8(entries[0].1.clone()).unwrap();
+        assert!(xml.contains("<multisample name=\"Piano &amp; Co\">"));
+        assert!(xml.contains("<sample file=\"piano.wav\" gain=\"-9.02\" sample-start=\"0.000\" sample-stop=\"40.000\" tune=\"0.70\""));
+        assert!(xml.contains("<key low=\"36\" high=\"59\" root=\"48\" track=\"1.0\"/>"));
+        assert!(xml.contains("<velocity low=\"0\" high=\"100\" fade-low=\"0\" fade-high=\"0\"/>"));
+        assert!(xml.contains("<loop mode=\"loop\" start=\"10.000\" stop=\"30.000\" fade=\"0.25\"/>"));
+        assert_eq!(xml.matches("<sample ").count(), 1);
+    }
+
+    #[test]
+    fn test_same_file_name_from_two_folders() {
+        let dir = TempDir::new().unwrap();
+        for folder in ["a", "b"] {
+            fs::create_dir(dir.path().join(folder)).unwrap();
+            fs::write(dir.path().join(folder).join("hit.wav"), wav(4, None, None)).unwrap();
+        }
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    Zone { sample_name: "a/hit.wav".to_string(), high_vel: 63, ..Default::default() },
+                    Zone { sample_name: "b/hit.wav".to_string(), low_vel: 64, ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_multisample("Hits", dir.path(), &ConvertOptions::default()).unwrap();
+        let entries = zip_entries(&output.bytes);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["multisample.xml", "hit.wav", "hit 2.wav"]);
+        let xml = String::from_utf8(entries[0].1.clone()).unwrap();
+        assert!(xml.contains("file=\"hit 2.wav\""));
+        assert!(xml.contains("root=\"60\""));
+        assert!(xml.contains("<loop mode=\"off\"/>"));
+    }
+}
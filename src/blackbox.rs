@@ -0,0 +1,219 @@
+//! 1010music Blackbox preset output: a `preset.xml` whose first pad is a
+//! multisample cell, written into the card layout the Blackbox reads
+//! (`Presets/<name>/preset.xml`, with the samples in the same folder).
+//!
+//! The pad cell holds the playback settings (gain, envelope, filter,
+//! looping) and each AKP zone becomes an asset cell on it with its key and
+//! velocity range and root note. The pad's settings come from the first
+//! keygroup that has each, as the Blackbox has one set per pad.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dspreset::xml_escape;
+use crate::error::Result;
+use crate::instrument::EnvelopeTiming;
+use crate::samples::{card_file_name, card_name, find_wav_info, CardExport, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions};
+
+/// Longest preset and sample name written, to fit the Blackbox's browser.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// A Blackbox preset and the samples it plays.
+#[derive(Debug, Clone)]
+pub struct BlackboxOutput {
+    pub xml: String,
+    /// (source file, file name in the preset folder) for each sample to copy.
+    pub samples: Vec<(PathBuf, String)>,
+    pub missing_samples: MissingSamples,
+}
+
+impl AkaiProgram {
+    /// Build a Blackbox preset reading samples from `sample_dir`. Zones
+    /// whose sample can't be read are left out and listed in the output; if
+    /// none can be read this fails.
+    pub fn to_blackbox(&self, sample_dir: &Path, options: &ConvertOptions) -> Result<BlackboxOutput> {
+        let mut found: LoadedSamples<(PathBuf, String, WavInfo)> = LoadedSamples::new();
+        let mut file_names: Vec<String> = Vec::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+
+        let mut assets = String::new();
+        // The first zone with a sample decides whether the pad loops
+        let mut loop_mode = None;
+        for range in self.key_layout(false, options.drum_kit_layout) {
+            let keygroup = range.keygroup;
+            for zone in &keygroup.zones {
+                if zone.sample_name.is_empty() {
+                    continue;
+                }
+                let sample = found.load(&zone.sample_name, |_| {
+                    let (path, info) = find_wav_info(sample_dir, &zone.sample_name)?;
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let file_name = card_file_name(&stem, "wav", MAX_NAME_LEN, &file_names);
+                    file_names.push(file_name.clone());
+                    Ok((path, file_name, info))
+                });
+                let Some((_, file_name, info)) = sample else { continue };
+                loop_mode.get_or_insert(u8::from(zone.loop_enabled()));
+
+                // Assets have no fine tune, so tuning moves the root note by whole semitones
+                let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                    + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                    + zone.root_tune as i32;
+                let root_key = zone.root_key.or(info.unity_note).unwrap_or(DEFAULT_ROOT_KEY) as i32;
+                let root_note = (root_key - (cents as f32 / 100.0).round() as i32).clamp(0, 127);
+
+                assets.push_str(&format!(
+                    "    <cell row=\"0\" column=\"0\" layer=\"1\" filename=\".\\{}\" type=\"asset\">\n",
+                    xml_escape(file_name),
+                ));
+                assets.push_str(&format!(
+                    "      <params rootnote=\"{root_note}\" keyrangebottom=\"{}\" keyrangetop=\"{}\" velrangebottom=\"{}\" velrangetop=\"{}\" asssrcrow=\"0\" asssrccol=\"0\"/>\n",
+                    range.low_key, range.high_key, zone.low_vel, zone.high_vel.min(127),
+                ));
+                assets.push_str("    </cell>\n");
+            }
+        }
+
+        let (found, missing_samples) = found.finish(sample_dir)?;
+        let first_sample = &found[0].1;
+
+        let gain_db = self.output.as_ref().map_or(0.0, |o| o.volume_db());
+        let (attack, decay, sustain, release) = match self.keygroups.iter().find_map(|kg| kg.amp_env.as_ref()) {
+            Some(env) => (
+                (env.attack_time() * 1000.0).round() as i32,
+                (env.decay_time() * 1000.0).round() as i32,
+                (env.sustain_normalized() * 1000.0).round() as i32,
+                (env.release_time() * 1000.0).round() as i32,
+            ),
+            None => (0, 0, 1000, 0),
+        };
+
+        // One knob for both filters: negative is lowpass closing from the top,
+        // positive highpass opening from the bottom
+        let (cutoff, resonance) = match self.keygroups.iter().find_map(|kg| kg.filter.as_ref()) {
+            Some(filter) if filter.filter_type <= 2 => (-(100 - filter.cutoff.min(100) as i32) * 10, filter.resonance as i32 * 1000 / 12),
+            Some(filter) if (6..=8).contains(&filter.filter_type) => (filter.cutoff.min(100) as i32 * 10, filter.resonance as i32 * 1000 / 12),
+            _ => (0, 0),
+        };
+        let loop_mode = loop_mode.unwrap_or(0);
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<document version=\"2\">\n");
+        xml.push_str("  <session version=\"2\">\n");
+        xml.push_str(&format!(
+            "    <cell row=\"0\" column=\"0\" layer=\"0\" filename=\".\\{}\" type=\"sample\">\n",
+            xml_escape(first_sample),
+        ));
+        xml.push_str(&format!(
+            "      <params gaindb=\"{:.0}\" pitch=\"0\" panpos=\"0\" samtrigtype=\"0\" loopmode=\"{loop_mode}\" cellmode=\"0\" multisammode=\"1\" polymode=\"2\" envattack=\"{attack}\" envdecay=\"{decay}\" envsus=\"{sustain}\" envrel=\"{release}\" dualfilcutoff=\"{cutoff}\" res=\"{resonance}\" samstart=\"0\" samlen=\"0\" loopstart=\"0\" loopend=\"0\"/>\n",
+            gain_db.max(-60.0),
+        ));
+        xml.push_str("    </cell>\n");
+        xml.push_str(&assets);
+        xml.push_str("  </session>\n");
+        xml.push_str("</document>\n");
+
+        let mut samples: Vec<(PathBuf, String)> = found.into_iter().map(|(path, file_name, _)| (path, file_name)).collect();
+        samples.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(BlackboxOutput { xml, samples, missing_samples })
+    }
+
+    /// Write the preset to `Presets/<name>/preset.xml` under `card_root`
+    /// with its samples beside it, ready to copy onto the card.
+    pub fn write_blackbox(&self, card_root: &Path, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<CardExport> {
+        let output = self.to_blackbox(sample_dir, options)?;
+        let preset_dir = card_root.join("Presets").join(card_name(name, MAX_NAME_LEN));
+        fs::create_dir_all(&preset_dir)?;
+        for (source, file_name) in &output.samples {
+            fs::copy(source, preset_dir.join(file_name))?;
+        }
+        let preset_path = preset_dir.join("preset.xml");
+        fs::write(&preset_path, output.xml)?;
+        Ok(CardExport { preset_path, missing_samples: output.missing_samples })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::WavAudio;
+    use crate::types::{Envelope, Filter, Keygroup, Zone};
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop: None,
+        };
+        WavAudio { info, channels: vec![vec![0; frames]] }.to_wav_bytes()
+    }
+
+    #[test]
+    fn test_blackbox_cells() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("Piano C3 soft.wav"), wav(30, Some(60))).unwrap();
+        fs::write(dir.path().join("Piano C3 loud.wav"), wav(30, None)).unwrap();
+
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                low_key: 48,
+                high_key: 71,
+                semitone_tune: -2,
+                amp_env: Some(Envelope { attack: 0, sustain: 50, ..Default::default() }),
+                filter: Some(Filter { filter_type: 0, cutoff: 70, resonance: 6, ..Default::default() }),
+                zones: vec![
+                    Zone { sample_name: "Piano C3 soft".to_string(), high_vel: 80, playback: 2, ..Default::default() },
+                    Zone { sample_name: "Piano C3 loud".to_string(), low_vel: 81, root_key: Some(62), ..Default::default() },
+                    Zone { sample_name: "MISSING".to_string(), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_blackbox(dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+        let names: Vec<&str> = output.samples.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, ["Piano C3 loud.wav", "Piano C3 soft.wav"]);
+
+        let xml = &output.xml;
+        assert!(xml.contains("<cell row=\"0\" column=\"0\" layer=\"0\" filename=\".\\Piano C3 soft.wav\" type=\"sample\">"));
+        assert!(xml.contains("loopmode=\"1\" cellmode=\"0\" multisammode=\"1\""));
+        assert!(xml.contains("envattack=\"0\""));
+        assert!(xml.contains("envsus=\"500\""));
+        assert!(xml.contains("dualfilcutoff=\"-300\" res=\"500\""));
+        // Tuned down 2 semitones, so the root notes move up
+        assert!(xml.contains("<params rootnote=\"62\" keyrangebottom=\"48\" keyrangetop=\"71\" velrangebottom=\"0\" velrangetop=\"80\""));
+        assert!(xml.contains("<params rootnote=\"64\" keyrangebottom=\"48\" keyrangetop=\"71\" velrangebottom=\"81\" velrangetop=\"127\""));
+        assert_eq!(xml.matches("type=\"asset\"").count(), 2);
+    }
+
+    #[test]
+    fn test_blackbox_card_layout() {
+        let src = TempDir::new().unwrap();
+        let card = TempDir::new().unwrap();
+        fs::write(src.path().join("Kick: Ünïcode.wav"), wav(10, None)).unwrap();
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "Kick: Ünïcode.wav".to_string(), ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let export = program.write_blackbox(card.path(), "A very long program name for the Blackbox", src.path(), &ConvertOptions::default()).unwrap();
+        let preset_dir = card.path().join("Presets/A very long program name for the");
+        assert_eq!(export.preset_path, preset_dir.join("preset.xml"));
+        assert!(preset_dir.join("Kick_ _n_code.wav").exists());
+        assert!(fs::read_to_string(&export.preset_path).unwrap().contains("filename=\".\\Kick_ _n_code.wav\""));
+    }
+}
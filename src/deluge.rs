@@ -0,0 +1,375 @@
+//! Synthstrom Deluge synth preset output: an XML `<sound>` whose first
+//! oscillator plays the program as a multisample, written into the card
+//! layout the Deluge reads (`SYNTHS/<name>.XML`, with the samples under
+//! `SAMPLES/<name>/`).
+//!
+//! Deluge multisamples are split by key only, so each keygroup becomes a
+//! sample range playing its top velocity layer. Envelopes and filters are
+//! preset-wide; they come from the first keygroup that has each. Deluge
+//! parameters are 32-bit values stored as hex, from `0x80000000` (knob at
+//! 0) to `0x7FFFFFFF` (knob at 50), and are written as the same fraction
+//! of the AKP's 0-100 ranges.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dspreset::xml_escape;
+use crate::error::Result;
+use crate::samples::{card_file_name, card_name, find_wav_info, CardExport, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions, Filter, Lfo, Zone};
+
+/// Note the Deluge plays a sample range at its recorded pitch, before the
+/// range's transpose.
+const DELUGE_BASE_NOTE: i32 = 60;
+
+/// Longest preset and sample name written, to keep the Deluge's scrolling
+/// display readable.
+pub const MAX_NAME_LEN: usize = 40;
+
+const FIRMWARE_VERSION: &str = "4.1.0";
+
+/// A Deluge synth preset and the samples it plays.
+#[derive(Debug, Clone)]
+pub struct DelugeOutput {
+    pub xml: String,
+    /// (source file, path under the card root) for each sample to copy.
+    pub samples: Vec<(PathBuf, String)>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample range: the keygroup's top velocity layer and its sample.
+struct SampleRange<'a> {
+    low_key: u8,
+    high_key: u8,
+    zone: &'a Zone,
+    card_path: String,
+    info: WavInfo,
+    cents: i32,
+}
+
+impl AkaiProgram {
+    /// Build a Deluge synth preset named `name`, reading samples from
+    /// `sample_dir`. Keygroups whose sample can't be read are left out and
+    /// listed in the output; if none can be read this fails.
+    pub fn to_deluge(&self, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<DelugeOutput> {
+        let folder = format!("SAMPLES/{}", card_name(name, MAX_NAME_LEN));
+        let mut found: LoadedSamples<(PathBuf, String, WavInfo)> = LoadedSamples::new();
+        let mut file_names: Vec<String> = Vec::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+
+        let mut layout = self.key_layout(false, options.drum_kit_layout);
+        layout.sort_by_key(|range| (range.low_key, std::cmp::Reverse(range.high_key)));
+
+        let mut ranges: Vec<SampleRange> = Vec::new();
+        for range in &layout {
+            // Ranges can't overlap, so a keygroup inside the one before is skipped
+            if ranges.last().is_some_and(|last| range.high_key <= last.high_key) {
+                continue;
+            }
+            let keygroup = range.keygroup;
+            let Some(zone) = keygroup.zones.iter()
+                .filter(|zone| !zone.sample_name.is_empty())
+                .max_by_key(|zone| (zone.high_vel, zone.low_vel)) else { continue };
+
+            let sample = found.load(&zone.sample_name, |_| {
+                let (path, info) = find_wav_info(sample_dir, &zone.sample_name)?;
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let file_name = card_file_name(&stem, "wav", MAX_NAME_LEN, &file_names);
+                file_names.push(file_name.clone());
+                Ok((path, format!("{folder}/{file_name}"), info))
+            });
+            let Some((_, card_path, info)) = sample else { continue };
+
+            let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                + zone.root_tune as i32;
+            ranges.push(SampleRange { low_key: range.low_key, high_key: range.high_key, zone, card_path: card_path.clone(), info: info.clone(), cents });
+        }
+
+        let (found, missing_samples) = found.finish(sample_dir)?;
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<sound firmwareVersion=\"{FIRMWARE_VERSION}\" earliestCompatibleFirmware=\"4.0.0\" polyphonic=\"poly\" voicePriority=\"1\" mode=\"subtractive\" lpfMode=\"{}\" modFXType=\"none\">\n",
+            if self.keygroups.iter().find_map(|kg| kg.filter.as_ref()).is_some_and(|f| f.filter_type == 1) { "24dB" } else { "12dB" },
+        ));
+
+        // Loop mode is per oscillator: 0 cuts at note-off, 1 plays once, 2 loops
+        let loop_mode = match ranges[0].zone.playback {
+            1 => 1,
+            _ if ranges[0].zone.loop_enabled() => 2,
+            _ => 0,
+        };
+        xml.push_str(&format!("\t<osc1 type=\"sample\" transpose=\"0\" cents=\"0\" loopMode=\"{loop_mode}\" reversed=\"0\" timeStretchEnable=\"0\" timeStretchAmount=\"0\">\n"));
+        xml.push_str("\t\t<sampleRanges>\n");
+        for (index, range) in ranges.iter().enumerate() {
+            // Each range reaches up to the next one, filling any gap between keygroups
+            let top = ranges.get(index + 1).map(|next| format!(" rangeTopNote=\"{}\"", next.low_key - 1)).unwrap_or_default();
+            let root_key = range.zone.root_key.or(range.info.unity_note).unwrap_or(DEFAULT_ROOT_KEY) as i32;
+            let cents = (DELUGE_BASE_NOTE - root_key) * 100 + range.cents;
+            let transpose = (cents as f32 / 100.0).round() as i32;
+            xml.push_str(&format!(
+                "\t\t\t<sampleRange{top} fileName=\"{}\" transpose=\"{transpose}\" cents=\"{}\">\n",
+                xml_escape(&range.card_path), cents - transpose * 100,
+            ));
+            let loop_points = range.zone.sample_loop.or(range.info.sample_loop)
+                .filter(|_| range.zone.loop_enabled())
+                .map(|l| format!(" startLoopPos=\"{}\" endLoopPos=\"{}\"", l.start, l.end + 1))
+                .unwrap_or_default();
+            xml.push_str(&format!(
+                "\t\t\t\t<zone startSamplePos=\"0\" endSamplePos=\"{}\"{loop_points} />\n",
+                range.info.frames,
+            ));
+            xml.push_str("\t\t\t</sampleRange>\n");
+        }
+        xml.push_str("\t\t</sampleRanges>\n");
+        xml.push_str("\t</osc1>\n");
+        xml.push_str("\t<osc2 type=\"square\" transpose=\"0\" cents=\"0\" />\n");
+
+        let lfo = self.lfo1.as_ref().filter(|lfo| lfo.depth > 0);
+        xml.push_str(&format!("\t<lfo1 type=\"{}\" syncLevel=\"0\" />\n", lfo.map_or("triangle", deluge_lfo_type)));
+        xml.push_str("\t<lfo2 type=\"triangle\" />\n");
+        self.write_default_params(&mut xml, lfo);
+        xml.push_str("</sound>\n");
+
+        let mut samples: Vec<(PathBuf, String)> = found.into_iter().map(|(path, card_path, _)| (path, card_path)).collect();
+        samples.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(DelugeOutput { xml, samples, missing_samples })
+    }
+
+    /// Write the preset to `SYNTHS/<name>.XML` under `card_root` and copy its
+    /// samples to `SAMPLES/<name>/`, ready to copy onto the card.
+    pub fn write_deluge(&self, card_root: &Path, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<CardExport> {
+        let output = self.to_deluge(name, sample_dir, options)?;
+        for (source, card_path) in &output.samples {
+            let dest = card_root.join(card_path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(source, dest)?;
+        }
+        let preset_path = card_root.join("SYNTHS").join(format!("{}.XML", card_name(name, MAX_NAME_LEN)));
+        fs::create_dir_all(card_root.join("SYNTHS"))?;
+        fs::write(&preset_path, output.xml)?;
+        Ok(CardExport { preset_path, missing_samples: output.missing_samples })
+    }
+
+    /// Volume, filters, envelopes, LFO rate and the patch cables routing
+    /// velocity, the filter envelope and the LFO.
+    fn write_default_params(&self, xml: &mut String, lfo: Option<&Lfo>) {
+        let filter = self.keygroups.iter().find_map(|kg| kg.filter.as_ref());
+        let filter_env = self.keygroups.iter().find_map(|kg| kg.filter_env.as_ref()).filter(|env| env.depth != 0);
+        let volume = self.output.as_ref().map_or(1.0, |o| o.loudness as f32 / 100.0);
+        let resonance = |f: &Filter| f.resonance as f32 / 12.0;
+
+        xml.push_str("\t<defaultParams>\n");
+        param(xml, "volume", volume);
+        param(xml, "oscAVolume", 1.0);
+        param(xml, "oscBVolume", 0.0);
+
+        // Lowpass types use the LPF and highpass types the HPF; the rest play unfiltered
+        let destination = match filter {
+            Some(filter) if filter.filter_type <= 2 => {
+                param(xml, "lpfFrequency", filter.cutoff as f32 / 100.0);
+                param(xml, "lpfResonance", resonance(filter));
+                param(xml, "hpfFrequency", 0.0);
+                Some("lpfFrequency")
+            }
+            Some(filter) if (6..=8).contains(&filter.filter_type) => {
+                param(xml, "lpfFrequency", 1.0);
+                param(xml, "hpfFrequency", filter.cutoff as f32 / 100.0);
+                param(xml, "hpfResonance", resonance(filter));
+                Some("hpfFrequency")
+            }
+            _ => {
+                param(xml, "lpfFrequency", 1.0);
+                param(xml, "hpfFrequency", 0.0);
+                None
+            }
+        };
+
+        xml.push_str("\t\t<envelope1>\n");
+        if let Some(env) = self.keygroups.iter().find_map(|kg| kg.amp_env.as_ref()) {
+            envelope_params(xml, [env.attack, env.decay, env.sustain, env.release]);
+        } else {
+            envelope_params(xml, [0, 0, 100, 0]);
+        }
+        xml.push_str("\t\t</envelope1>\n");
+        if let Some(env) = filter_env {
+            xml.push_str("\t\t<envelope2>\n");
+            envelope_params(xml, [env.attack, env.decay, env.sustain, env.release]);
+            xml.push_str("\t\t</envelope2>\n");
+        }
+        if let Some(lfo) = lfo {
+            param(xml, "lfo1Rate", lfo.rate as f32 / 100.0);
+        }
+
+        xml.push_str("\t\t<patchCables>\n");
+        let velocity = self.output.as_ref().map_or(0, |o| o.velocity_sensitivity.clamp(0, 100));
+        if velocity > 0 {
+            patch_cable(xml, "velocity", "volume", velocity as f32 / 100.0);
+        }
+        if let (Some(env), Some(destination)) = (filter_env, destination) {
+            patch_cable(xml, "envelope2", destination, env.depth as f32 / 100.0);
+        }
+        if let Some(lfo) = lfo {
+            patch_cable(xml, "lfo1", "pitch", lfo.depth_normalized());
+        }
+        xml.push_str("\t\t</patchCables>\n");
+        xml.push_str("\t</defaultParams>\n");
+    }
+}
+
+fn envelope_params(xml: &mut String, [attack, decay, sustain, release]: [u8; 4]) {
+    for (name, value) in [("attack", attack), ("decay", decay), ("sustain", sustain), ("release", release)] {
+        xml.push_str(&format!("\t\t\t<{name}>{}</{name}>\n", unipolar_hex(value as f32 / 100.0)));
+    }
+}
+
+fn param(xml: &mut String, name: &str, fraction: f32) {
+    xml.push_str(&format!("\t\t<{name}>{}</{name}>\n", unipolar_hex(fraction)));
+}
+
+fn patch_cable(xml: &mut String, source: &str, destination: &str, amount: f32) {
+    xml.push_str(&format!(
+        "\t\t\t<patchCable source=\"{source}\" destination=\"{destination}\" amount=\"{}\" />\n",
+        bipolar_hex(amount),
+    ));
+}
+
+/// A knob position (0-1 of its range) as the Deluge stores it.
+fn unipolar_hex(fraction: f32) -> String {
+    let value = (fraction.clamp(0.0, 1.0) as f64 * u32::MAX as f64).round() as i64 - (1i64 << 31);
+    format!("0x{:08X}", value as i32 as u32)
+}
+
+/// A patch cable amount (-1 to 1), full scale at ±`i32::MAX`.
+fn bipolar_hex(amount: f32) -> String {
+    let value = (amount.clamp(-1.0, 1.0) as f64 * i32::MAX as f64).round() as i32;
+    format!("0x{:08X}", value as u32)
+}
+
+/// Deluge LFO type for an AKP waveform (0=SINE .. 8=RANDOM).
+fn deluge_lfo_type(lfo: &Lfo) -> &'static str {
+    match lfo.waveform {
+        1 => "triangle",
+        2..=4 => "square",
+        5..=7 => "saw",
+        8 => "sampleAndHold",
+        _ => "sine",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::WavAudio;
+    use crate::types::{Envelope, FilterEnvelope, Keygroup, ProgramOutput, SampleLoop};
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels: vec![vec![0; frames]] }.to_wav_bytes()
+    }
+
+    #[test]
+    fn test_parameter_hex() {
+        assert_eq!(unipolar_hex(0.0), "0x80000000");
+        assert_eq!(unipolar_hex(0.5), "0x00000000");
+        assert_eq!(unipolar_hex(1.0), "0x7FFFFFFF");
+        assert_eq!(bipolar_hex(0.5), "0x40000000");
+        assert_eq!(bipolar_hex(-1.0), "0x80000001");
+    }
+
+    #[test]
+    fn test_deluge_ranges() {
+        let dir = TempDir::new().unwrap();
+        let looped = SampleLoop { start: 10, end: 29, crossfade: 0 };
+        fs::write(dir.path().join("Pno C2.wav"), wav(40, Some(48), Some(looped))).unwrap();
+        fs::write(dir.path().join("Pno C3 soft.wav"), wav(30, None, None)).unwrap();
+        fs::write(dir.path().join("Pno C3 loud.wav"), wav(50, None, None)).unwrap();
+
+        let program = AkaiProgram {
+            keygroups: vec![
+                Keygroup {
+                    low_key: 60,
+                    high_key: 72,
+                    zones: vec![
+                        Zone { sample_name: "Pno C3 soft".to_string(), high_vel: 80, ..Default::default() },
+                        Zone { sample_name: "Pno C3 loud".to_string(), low_vel: 81, fine_tune: 20, ..Default::default() },
+                    ],
+                    ..Default::default()
+                },
+                Keygroup {
+                    low_key: 36,
+                    high_key: 55,
+                    zones: vec![Zone { sample_name: "Pno C2".to_string(), playback: 2, ..Default::default() }],
+                    ..Default::default()
+                },
+                Keygroup {
+                    low_key: 73,
+                    high_key: 96,
+                    zones: vec![Zone { sample_name: "MISSING".to_string(), ..Default::default() }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let output = program.to_deluge("Felt/Piano", dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+        let card_paths: Vec<&str> = output.samples.iter().map(|(_, path)| path.as_str()).collect();
+        assert_eq!(card_paths, ["SAMPLES/Felt_Piano/Pno C2.wav", "SAMPLES/Felt_Piano/Pno C3 loud.wav"]);
+
+        // Ranges go up by key, each reaching the next; the top layer plays
+        let xml = &output.xml;
+        assert!(xml.contains("loopMode=\"2\""));
+        assert!(xml.contains("<sampleRange rangeTopNote=\"59\" fileName=\"SAMPLES/Felt_Piano/Pno C2.wav\" transpose=\"12\" cents=\"0\">"));
+        assert!(xml.contains("<zone startSamplePos=\"0\" endSamplePos=\"40\" startLoopPos=\"10\" endLoopPos=\"30\" />"));
+        assert!(xml.contains("<sampleRange fileName=\"SAMPLES/Felt_Piano/Pno C3 loud.wav\" transpose=\"0\" cents=\"20\">"));
+        assert!(!xml.contains("soft"));
+    }
+
+    #[test]
+    fn test_deluge_params_and_card_layout() {
+        let src = TempDir::new().unwrap();
+        let card = TempDir::new().unwrap();
+        fs::write(src.path().join("hit.wav"), wav(20, None, None)).unwrap();
+
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, velocity_sensitivity: 50, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "hit".to_string(), ..Default::default() }],
+                amp_env: Some(Envelope { attack: 0, decay: 50, sustain: 100, release: 25, ..Default::default() }),
+                filter: Some(Filter { filter_type: 7, cutoff: 50, resonance: 12, ..Default::default() }),
+                filter_env: Some(FilterEnvelope { depth: -50, ..Default::default() }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let export = program.write_deluge(card.path(), "Hit", src.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(export.preset_path, card.path().join("SYNTHS/Hit.XML"));
+        assert!(card.path().join("SAMPLES/Hit/hit.wav").exists());
+
+        let xml = fs::read_to_string(&export.preset_path).unwrap();
+        assert!(xml.contains("<volume>0x00000000</volume>"));
+        assert!(xml.contains("<lpfFrequency>0x7FFFFFFF</lpfFrequency>"));
+        assert!(xml.contains("<hpfFrequency>0x00000000</hpfFrequency>"));
+        assert!(xml.contains("<hpfResonance>0x7FFFFFFF</hpfResonance>"));
+        assert!(xml.contains("<envelope1>\n\t\t\t<attack>0x80000000</attack>\n\t\t\t<decay>0x00000000</decay>"));
+        assert!(xml.contains("<patchCable source=\"velocity\" destination=\"volume\" amount=\"0x40000000\" />"));
+        assert!(xml.contains("<patchCable source=\"envelope2\" destination=\"hpfFrequency\" amount=\"0xC0000000\" />"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Akai S1000/S3000 native hard disk and CD-ROM layout.
+//!
+//! The disk is a run of partitions (A, B, …), each made of 8 KB blocks.
+//! A partition starts with a header holding its size, a table of up to
+//! 100 volumes and a block allocation table; each volume's directory lists
+//! up to 126 (S1000) or 510 (S3000) files. Layout per the akaitools S3000
+//! disk format notes linked from docs/akp-format-reference.md. Floppy
+//! images use a different, smaller layout and aren't supported.
+
+use std::io::{Read, Seek};
+
+use super::{read_at, safe_name, u16_le, ImageEntry};
+use crate::error::Result;
+use crate::s1000::akai_to_ascii;
+
+const BLOCK_SIZE: u64 = 0x2000;
+const MAX_PARTITIONS: usize = 18;
+const VOLUME_TABLE_OFFSET: usize = 0x0CA;
+const VOLUME_ENTRY_SIZE: usize = 16;
+const MAX_VOLUMES: usize = 100;
+const FAT_OFFSET: usize = 0x070A;
+const FILE_ENTRY_SIZE: usize = 24;
+const S1000_VOLUME_FILES: usize = 126;
+const S3000_VOLUME_FILES: usize = 510;
+/// Header blocks at the start of each partition (size, volume table, FAT).
+const HEADER_BLOCKS: u64 = 3;
+
+struct Partition {
+    /// Byte offset of the partition in the image.
+    base: u64,
+    blocks: u16,
+    header: Vec<u8>,
+}
+
+impl Partition {
+    fn fat_entry(&self, block: u16) -> u16 {
+        let offset = FAT_OFFSET + block as usize * 2;
+        if offset + 2 > self.header.len() { 0 } else { u16_le(&self.header, offset) }
+    }
+
+    /// Byte ranges of a block chain. High bits in a FAT entry mark end of
+    /// file or reserved blocks; `max_blocks` guards against loops.
+    fn chain_extents(&self, first: u16, max_blocks: u64) -> Vec<(u64, u64)> {
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        let mut block = first;
+        while block < self.blocks && (extents.len() as u64) < max_blocks {
+            let offset = self.base + block as u64 * BLOCK_SIZE;
+            match extents.last_mut() {
+                Some(last) if last.0 + last.1 == offset => last.1 += BLOCK_SIZE,
+                _ => extents.push((offset, BLOCK_SIZE)),
+            }
+            let next = self.fat_entry(block);
+            if next == 0 || next & 0xC000 != 0 {
+                break;
+            }
+            block = next;
+        }
+        extents
+    }
+}
+
+/// List an Akai disk's files as `<partition>/<volume>/<file>.<ext>`, or
+/// `None` if the image doesn't start with a plausible Akai partition.
+pub(super) fn list<R: Read + Seek>(reader: &mut R, image_len: u64) -> Result<Option<Vec<ImageEntry>>> {
+    let mut entries = Vec::new();
+    let mut base = 0u64;
+
+    for index in 0..MAX_PARTITIONS {
+        let header_len = (HEADER_BLOCKS * BLOCK_SIZE).min(image_len.saturating_sub(base)) as usize;
+        if header_len < FAT_OFFSET {
+            if index == 0 {
+                return Ok(None);
+            }
+            break;
+        }
+        let header = read_at(reader, base, header_len)?;
+        let blocks = u16_le(&header, 0);
+        if (blocks as u64) <= HEADER_BLOCKS || base + blocks as u64 * BLOCK_SIZE > image_len {
+            if index == 0 {
+                return Ok(None);
+            }
+            break;
+        }
+
+        let partition = Partition { base, blocks, header };
+        let volumes = volume_table(&partition);
+        // The first partition decides whether this is an Akai disk at all
+        if index == 0 && volumes.is_empty() {
+            return Ok(None);
+        }
+
+        let letter = (b'A' + index as u8) as char;
+        for (name, s3000, start) in volumes {
+            let max_files = if s3000 { S3000_VOLUME_FILES } else { S1000_VOLUME_FILES };
+            let mut directory = Vec::new();
+            for (offset, length) in partition.chain_extents(start, 2) {
+                directory.extend(read_at(reader, offset, length as usize)?);
+            }
+            for entry in directory.chunks_exact(FILE_ENTRY_SIZE).take(max_files) {
+                if let Some(file) = file_entry(&partition, entry, &format!("{letter}/{name}")) {
+                    entries.push(file);
+                }
+            }
+        }
+
+        base += blocks as u64 * BLOCK_SIZE;
+    }
+
+    Ok(Some(entries))
+}
+
+/// Active volumes as (name, is S3000, directory block).
+fn volume_table(partition: &Partition) -> Vec<(String, bool, u16)> {
+    (0..MAX_VOLUMES)
+        .filter_map(|i| {
+            let entry = &partition.header[VOLUME_TABLE_OFFSET + i * VOLUME_ENTRY_SIZE..][..VOLUME_ENTRY_SIZE];
+            let start = u16_le(entry, 14);
+            let valid_name = entry[..12].iter().all(|&c| c <= 40);
+            // Volume types: 1 = S1000, 3 = S3000
+            match entry[12] {
+                1 | 3 if valid_name && start >= HEADER_BLOCKS as u16 && start < partition.blocks => {
+                    Some((safe_name(&akai_to_ascii(&entry[..12])), entry[12] == 3, start))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A directory entry: name, file type at 16, size (24-bit) at 17, start block at 20.
+fn file_entry(partition: &Partition, entry: &[u8], dir: &str) -> Option<ImageEntry> {
+    let file_type = entry[16];
+    if file_type == 0 || entry[..12].iter().any(|&c| c > 40) {
+        return None;
+    }
+    let size = u32::from_le_bytes([entry[17], entry[18], entry[19], 0]) as u64;
+    let start = u16_le(entry, 20);
+
+    // Type is an ASCII letter; the high bit marks S3000 files ('p' → .p, 0xF0 → .p3)
+    let letter = (file_type & 0x7F) as char;
+    let extension = if file_type & 0x80 != 0 { format!("{letter}3") } else { letter.to_string() };
+
+    Some(ImageEntry {
+        path: format!("{dir}/{}.{extension}", safe_name(&akai_to_ascii(&entry[..12]))),
+        size,
+        extents: partition.chain_extents(start, size.div_ceil(BLOCK_SIZE)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn akai_name(name: &str) -> [u8; 12] {
+        let mut bytes = [10u8; 12];
+        for (i, c) in name.bytes().enumerate() {
+            bytes[i] = match c {
+                b'0'..=b'9' => c - b'0',
+                b'A'..=b'Z' => c - b'A' + 11,
+                _ => 10,
+            };
+        }
+        bytes
+    }
+
+    fn set_fat(image: &mut [u8], base: usize, block: usize, value: u16) {
+        let offset = base + FAT_OFFSET + block * 2;
+        image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// One S3000 partition of 8 blocks: volume "STRINGS" with its directory
+    /// at block 3, program "VIOLIN" (block 4) and a sample spanning blocks 5 → 7.
+    fn make_partition(blocks: u16) -> Vec<u8> {
+        let block = BLOCK_SIZE as usize;
+        let mut image = vec![0u8; blocks as usize * block];
+        image[0..2].copy_from_slice(&blocks.to_le_bytes());
+
+        let volume = &mut image[VOLUME_TABLE_OFFSET..VOLUME_TABLE_OFFSET + VOLUME_ENTRY_SIZE];
+        volume[..12].copy_from_slice(&akai_name("STRINGS"));
+        volume[12] = 3;
+        volume[14..16].copy_from_slice(&3u16.to_le_bytes());
+
+        for b in 0..3 {
+            set_fat(&mut image, 0, b, 0x8000);
+        }
+        set_fat(&mut image, 0, 3, 0xC000);
+        set_fat(&mut image, 0, 4, 0xC000);
+        set_fat(&mut image, 0, 5, 7);
+        set_fat(&mut image, 0, 7, 0xC000);
+
+        let files: [(&str, u8, u32, u16); 2] = [("VIOLIN", 0xF0, 300, 4), ("VIOLIN C3", 0xF3, 9000, 5)];
+        for (i, (name, file_type, size, start)) in files.into_iter().enumerate() {
+            let entry = &mut image[3 * block + i * FILE_ENTRY_SIZE..][..FILE_ENTRY_SIZE];
+            entry[..12].copy_from_slice(&akai_name(name));
+            entry[16] = file_type;
+            entry[17..20].copy_from_slice(&size.to_le_bytes()[..3]);
+            entry[20..22].copy_from_slice(&start.to_le_bytes());
+        }
+
+        image[4 * block] = 1;
+        image[5 * block..6 * block].fill(b'a');
+        image[7 * block..8 * block].fill(b'b');
+        image
+    }
+
+    #[test]
+    fn test_list_akai_volume() {
+        let mut image = Cursor::new(make_partition(8));
+        let len = image.get_ref().len() as u64;
+        let entries = list(&mut image, len).unwrap().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "A/STRINGS/VIOLIN.p3");
+        assert_eq!(entries[1].path, "A/STRINGS/VIOLIN C3.s3");
+
+        let program = super::super::read_entry(&mut image, len, &entries[0]).unwrap();
+        assert_eq!(program.len(), 300);
+        assert_eq!(program[0], 1);
+
+        let sample = super::super::read_entry(&mut image, len, &entries[1]).unwrap();
+        assert_eq!(sample.len(), 9000);
+        assert!(sample[..8192].iter().all(|&b| b == b'a'));
+        assert!(sample[8192..].iter().all(|&b| b == b'b'));
+    }
+
+    #[test]
+    fn test_second_partition() {
+        let mut image = make_partition(8);
+        image.extend(make_partition(8));
+        let len = image.len() as u64;
+        let entries = list(&mut Cursor::new(image), len).unwrap().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[2].path, "B/STRINGS/VIOLIN.p3");
+    }
+
+    #[test]
+    fn test_not_akai() {
+        let mut image = make_partition(8);
+        image[VOLUME_TABLE_OFFSET + 12] = 0;
+        let len = image.len() as u64;
+        assert!(list(&mut Cursor::new(image), len).unwrap().is_none());
+    }
+}
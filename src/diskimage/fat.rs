@@ -0,0 +1,405 @@
+//! FAT12/16/32 reader with VFAT long names. Handles a bare volume or an
+//! MBR with up to four FAT partitions; with several partitions each one's
+//! files are prefixed `1/`, `2/`, ….
+
+use std::io::{Read, Seek};
+
+use super::{in_image, read_at, safe_name, u16_le, u32_le, ImageEntry};
+use crate::error::{AkpError, Result};
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+#[derive(Debug)]
+struct Volume {
+    fat_type: FatType,
+    cluster_size: u64,
+    fat_offset: u64,
+    fat_size: u64,
+    /// FAT12/16 fixed root directory (offset, length); FAT32 uses `root_cluster`.
+    root_dir: (u64, u64),
+    root_cluster: u32,
+    data_offset: u64,
+    cluster_count: u32,
+    /// Length of the whole image, which directory chains must fit in.
+    image_len: u64,
+}
+
+/// List a FAT volume's files, or `None` if the image isn't FAT.
+pub(super) fn list<R: Read + Seek>(reader: &mut R, image_len: u64) -> Result<Option<Vec<ImageEntry>>> {
+    let sector = match read_at(reader, 0, 512) {
+        Ok(sector) => sector,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let volumes = if let Some(volume) = parse_boot_sector(&sector, 0, image_len) {
+        vec![volume]
+    } else if sector[510..512] == [0x55, 0xAA] {
+        partition_volumes(reader, &sector, image_len)?
+    } else {
+        Vec::new()
+    };
+
+    if volumes.is_empty() {
+        return Ok(None);
+    }
+
+    let multiple = volumes.len() > 1;
+    let mut entries = Vec::new();
+    for (index, volume) in volumes.iter().enumerate() {
+        let prefix = if multiple { (index + 1).to_string() } else { String::new() };
+        if !in_image(image_len, volume.fat_offset, volume.fat_size) {
+            return Err(AkpError::InvalidDiskImage("FAT extends past the end of the image".to_string()));
+        }
+        let fat = read_at(reader, volume.fat_offset, volume.fat_size as usize)?;
+        let root = match volume.fat_type {
+            FatType::Fat32 => read_chain(reader, volume, &fat, volume.root_cluster)?,
+            _ => {
+                if !in_image(image_len, volume.root_dir.0, volume.root_dir.1) {
+                    return Err(AkpError::InvalidDiskImage("root directory extends past the end of the image".to_string()));
+                }
+                read_at(reader, volume.root_dir.0, volume.root_dir.1 as usize)?
+            }
+        };
+        list_directory(reader, volume, &fat, &root, &prefix, 0, &mut entries)?;
+    }
+    Ok(Some(entries))
+}
+
+fn partition_volumes<R: Read + Seek>(reader: &mut R, mbr: &[u8], image_len: u64) -> Result<Vec<Volume>> {
+    let mut volumes = Vec::new();
+    for i in 0..4 {
+        let entry = &mbr[446 + i * 16..446 + (i + 1) * 16];
+        if !FAT_PARTITION_TYPES.contains(&entry[4]) {
+            continue;
+        }
+        let base = u32_le(entry, 8) as u64 * 512;
+        if let Ok(sector) = read_at(reader, base, 512) {
+            volumes.extend(parse_boot_sector(&sector, base, image_len));
+        }
+    }
+    Ok(volumes)
+}
+
+/// Parse the BIOS parameter block, rejecting anything that isn't plausibly FAT.
+fn parse_boot_sector(sector: &[u8], base: u64, image_len: u64) -> Option<Volume> {
+    let bytes_per_sector = u16_le(sector, 11) as u64;
+    let sectors_per_cluster = sector[13] as u64;
+    let reserved = u16_le(sector, 14) as u64;
+    let fat_count = sector[16] as u64;
+    let root_entries = u16_le(sector, 17) as u64;
+    let total_sectors = match u16_le(sector, 19) {
+        0 => u32_le(sector, 32) as u64,
+        n => n as u64,
+    };
+    let fat_sectors = match u16_le(sector, 22) {
+        0 => u32_le(sector, 36) as u64,
+        n => n as u64,
+    };
+
+    if ![512, 1024, 2048, 4096].contains(&bytes_per_sector)
+        || !sectors_per_cluster.is_power_of_two()
+        || reserved == 0
+        || !(1..=2).contains(&fat_count)
+        || fat_sectors == 0
+        || total_sectors == 0
+    {
+        return None;
+    }
+
+    let root_dir_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+    let data_sector = reserved + fat_count * fat_sectors + root_dir_sectors;
+    let cluster_count = total_sectors.checked_sub(data_sector)? / sectors_per_cluster;
+    let fat_type = match cluster_count {
+        0..=4084 => FatType::Fat12,
+        4085..=65524 => FatType::Fat16,
+        _ => FatType::Fat32,
+    };
+    if fat_type != FatType::Fat32 && root_entries == 0 {
+        return None;
+    }
+
+    Some(Volume {
+        fat_type,
+        cluster_size: bytes_per_sector * sectors_per_cluster,
+        fat_offset: base + reserved * bytes_per_sector,
+        fat_size: fat_sectors * bytes_per_sector,
+        root_dir: (
+            base + (reserved + fat_count * fat_sectors) * bytes_per_sector,
+            root_entries * DIR_ENTRY_SIZE as u64,
+        ),
+        root_cluster: u32_le(sector, 44),
+        data_offset: base + data_sector * bytes_per_sector,
+        cluster_count: cluster_count as u32,
+        image_len,
+    })
+}
+
+impl Volume {
+    fn next_cluster(&self, fat: &[u8], cluster: u32) -> Option<u32> {
+        let index = cluster as usize;
+        let (offset, width) = match self.fat_type {
+            FatType::Fat12 => (index + index / 2, 2),
+            FatType::Fat16 => (index * 2, 2),
+            FatType::Fat32 => (index * 4, 4),
+        };
+        if offset + width > fat.len() {
+            return None;
+        }
+        let next = match self.fat_type {
+            FatType::Fat12 => {
+                let pair = u16_le(fat, offset) as u32;
+                if index & 1 == 0 { pair & 0x0FFF } else { pair >> 4 }
+            }
+            FatType::Fat16 => u16_le(fat, offset) as u32,
+            FatType::Fat32 => u32_le(fat, offset) & 0x0FFF_FFFF,
+        };
+        (next >= 2 && next < self.cluster_count + 2).then_some(next)
+    }
+
+    /// Byte ranges of a cluster chain; stops at end-of-chain, bad values or loops.
+    fn chain_extents(&self, fat: &[u8], first: u32) -> Vec<(u64, u64)> {
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        let mut cluster = Some(first).filter(|&c| c >= 2 && c < self.cluster_count + 2);
+        let max_entries = (fat.len() as u64 * 2 / 3) as usize;
+        while let Some(c) = cluster {
+            if extents.len() > max_entries {
+                break;
+            }
+            let offset = self.data_offset + (c as u64 - 2) * self.cluster_size;
+            // Merge contiguous clusters into one extent
+            match extents.last_mut() {
+                Some(last) if last.0 + last.1 == offset => last.1 += self.cluster_size,
+                _ => extents.push((offset, self.cluster_size)),
+            }
+            cluster = self.next_cluster(fat, c);
+        }
+        extents
+    }
+}
+
+/// Read a directory's cluster chain. A chain can't hold more than the
+/// whole image, which also stops loops from piling up data.
+fn read_chain<R: Read + Seek>(reader: &mut R, volume: &Volume, fat: &[u8], first: u32) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for (offset, length) in volume.chain_extents(fat, first) {
+        if !in_image(volume.image_len, offset, length) || data.len() as u64 + length > volume.image_len {
+            return Err(AkpError::InvalidDiskImage("directory extends past the end of the image".to_string()));
+        }
+        data.extend(read_at(reader, offset, length as usize)?);
+    }
+    Ok(data)
+}
+
+fn list_directory<R: Read + Seek>(
+    reader: &mut R,
+    volume: &Volume,
+    fat: &[u8],
+    dir: &[u8],
+    prefix: &str,
+    depth: usize,
+    entries: &mut Vec<ImageEntry>,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(AkpError::InvalidDiskImage(format!("directory '{prefix}' nested too deeply")));
+    }
+
+    let mut long_name: Vec<(u8, Vec<u16>)> = Vec::new();
+    for entry in dir.chunks_exact(DIR_ENTRY_SIZE) {
+        match entry[0] {
+            0x00 => break,
+            0xE5 => {
+                long_name.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        let attr = entry[11];
+        if attr == ATTR_LONG_NAME {
+            // Long name parts come last-first; the sequence number orders them
+            let units = [&entry[1..11], &entry[14..26], &entry[28..32]]
+                .concat()
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&u| u != 0 && u != 0xFFFF)
+                .collect();
+            long_name.push((entry[0] & 0x1F, units));
+            continue;
+        }
+
+        let name = if long_name.is_empty() {
+            short_name(entry)
+        } else {
+            long_name.sort_by_key(|(seq, _)| *seq);
+            let units: Vec<u16> = long_name.iter().flat_map(|(_, u)| u.iter().copied()).collect();
+            String::from_utf16_lossy(&units)
+        };
+        long_name.clear();
+
+        if attr & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+            continue;
+        }
+
+        let name = safe_name(&name);
+        let path = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        let cluster = (((u16_le(entry, 20) as u32) << 16) | u16_le(entry, 26) as u32)
+            & if volume.fat_type == FatType::Fat32 { 0x0FFF_FFFF } else { 0xFFFF };
+
+        if attr & ATTR_DIRECTORY != 0 {
+            let child = read_chain(reader, volume, fat, cluster)?;
+            list_directory(reader, volume, fat, &child, &path, depth + 1, entries)?;
+        } else {
+            entries.push(ImageEntry {
+                path,
+                size: u32_le(entry, 28) as u64,
+                extents: volume.chain_extents(fat, cluster),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// 8.3 name with padding removed: `VIOLIN  AKP` → `VIOLIN.AKP`.
+fn short_name(entry: &[u8]) -> String {
+    let base = String::from_utf8_lossy(&entry[0..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&entry[8..11]).trim_end().to_string();
+    // 0x05 stands in for a leading 0xE5 byte
+    let base = base.replacen('\u{5}', "\u{E5}", 1);
+    if ext.is_empty() { base } else { format!("{base}.{ext}") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SECTOR: usize = 512;
+
+    fn dir_entry(name: &[u8; 11], attr: u8, cluster: u16, size: u32) -> [u8; 32] {
+        let mut e = [0u8; 32];
+        e[..11].copy_from_slice(name);
+        e[11] = attr;
+        e[26..28].copy_from_slice(&cluster.to_le_bytes());
+        e[28..32].copy_from_slice(&size.to_le_bytes());
+        e
+    }
+
+    fn long_name_entry(seq: u8, name: &str) -> [u8; 32] {
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        units.push(0);
+        units.resize(13, 0xFFFF);
+        let bytes: Vec<u8> = units.iter().flat_map(|u| u.to_le_bytes()).collect();
+        let mut e = [0u8; 32];
+        e[0] = seq;
+        e[1..11].copy_from_slice(&bytes[0..10]);
+        e[11] = ATTR_LONG_NAME;
+        e[14..26].copy_from_slice(&bytes[10..22]);
+        e[28..32].copy_from_slice(&bytes[22..26]);
+        e
+    }
+
+    /// FAT12 floppy-sized volume: 1 reserved sector, 1 FAT sector, 1 root
+    /// sector (16 entries), 1 sector per cluster.
+    ///
+    /// Root: "Strings" (long name) directory at cluster 2 holding
+    /// "VIOLIN.AKP" spanning clusters 3 → 5 (cluster 4 skipped).
+    fn make_fat12(base_sector: usize) -> Vec<u8> {
+        let total_sectors = 64u16;
+        let mut image = vec![0u8; (base_sector + total_sectors as usize) * SECTOR];
+        let b = base_sector * SECTOR;
+
+        let boot = &mut image[b..b + SECTOR];
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+        boot[16] = 1;
+        boot[17..19].copy_from_slice(&16u16.to_le_bytes());
+        boot[19..21].copy_from_slice(&total_sectors.to_le_bytes());
+        boot[22..24].copy_from_slice(&1u16.to_le_bytes());
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        // FAT12: cluster 2 → EOC, 3 → 5, 5 → EOC
+        let fat = &mut image[b + SECTOR..b + 2 * SECTOR];
+        let entries: [u16; 6] = [0xFF8, 0xFFF, 0xFFF, 0x005, 0x000, 0xFFF];
+        for (i, &v) in entries.iter().enumerate() {
+            let offset = i + i / 2;
+            let pair = u16::from_le_bytes([fat[offset], fat[offset + 1]]);
+            let pair = if i & 1 == 0 { (pair & 0xF000) | v } else { (pair & 0x000F) | (v << 4) };
+            fat[offset..offset + 2].copy_from_slice(&pair.to_le_bytes());
+        }
+
+        let root = b + 2 * SECTOR;
+        image[root..root + 32].copy_from_slice(&long_name_entry(0x41, "Strings"));
+        image[root + 32..root + 64].copy_from_slice(&dir_entry(b"STRINGS    ", ATTR_DIRECTORY, 2, 0));
+
+        let data = b + 3 * SECTOR;
+        image[data..data + 32].copy_from_slice(&dir_entry(b".          ", ATTR_DIRECTORY, 2, 0));
+        image[data + 32..data + 64].copy_from_slice(&dir_entry(b"VIOLIN  AKP", 0x20, 3, 600));
+        image[data + SECTOR..data + 2 * SECTOR].fill(b'a');
+        image[data + 3 * SECTOR..data + 4 * SECTOR].fill(b'b');
+        image
+    }
+
+    #[test]
+    fn test_list_fat12_with_long_names() {
+        let mut image = Cursor::new(make_fat12(0));
+        let len = image.get_ref().len() as u64;
+        let entries = list(&mut image, len).unwrap().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "Strings/VIOLIN.AKP");
+
+        let data = super::super::read_entry(&mut image, len, &entries[0]).unwrap();
+        assert_eq!(data.len(), 600);
+        assert!(data[..512].iter().all(|&b| b == b'a'));
+        assert!(data[512..].iter().all(|&b| b == b'b'));
+    }
+
+    #[test]
+    fn test_list_fat_behind_mbr() {
+        let mut image = make_fat12(4);
+        image[446 + 4] = 0x01;
+        image[446 + 8..446 + 12].copy_from_slice(&4u32.to_le_bytes());
+        image[510] = 0x55;
+        image[511] = 0xAA;
+
+        let len = image.len() as u64;
+        let entries = list(&mut Cursor::new(image), len).unwrap().unwrap();
+        assert_eq!(entries[0].path, "Strings/VIOLIN.AKP");
+    }
+
+    #[test]
+    fn test_header_sizes_past_end_of_image() {
+        // A 512-byte boot sector claiming a 1 TiB FAT
+        let mut boot = make_fat12(0)[..SECTOR].to_vec();
+        boot[19..21].copy_from_slice(&0u16.to_le_bytes());
+        boot[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        boot[22..24].copy_from_slice(&0u16.to_le_bytes());
+        boot[36..40].copy_from_slice(&0x7FFF_FFFFu32.to_le_bytes());
+        let result = list(&mut Cursor::new(boot), SECTOR as u64);
+        assert!(matches!(result, Err(AkpError::InvalidDiskImage(_))));
+
+        // A volume cut off after its FAT, before the root directory
+        let truncated = make_fat12(0)[..2 * SECTOR].to_vec();
+        let result = list(&mut Cursor::new(truncated), 2 * SECTOR as u64);
+        assert!(matches!(result, Err(AkpError::InvalidDiskImage(_))));
+    }
+
+    #[test]
+    fn test_not_fat() {
+        assert!(list(&mut Cursor::new(vec![0u8; 4096]), 4096).unwrap().is_none());
+    }
+}
@@ -0,0 +1,242 @@
+//! ISO 9660 reader. Uses the Joliet supplementary descriptor for long
+//! names when the image has one.
+
+use std::io::{Read, Seek};
+
+use super::{in_image, read_at, safe_name, u32_le, ImageEntry};
+use crate::error::{AkpError, Result};
+
+const SECTOR_SIZE: u64 = 2048;
+const FIRST_DESCRIPTOR: u64 = 16;
+const MAX_DEPTH: usize = 16;
+
+/// Whether sector 16 holds an ISO 9660 volume descriptor.
+pub(super) fn detect<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+    match read_at(reader, FIRST_DESCRIPTOR * SECTOR_SIZE + 1, 5) {
+        Ok(id) => Ok(id == b"CD001"),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) fn list<R: Read + Seek>(reader: &mut R, image_len: u64) -> Result<Vec<ImageEntry>> {
+    let mut primary_root = None;
+    let mut joliet_root = None;
+
+    // Descriptors run from sector 16 to the terminator (type 255)
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + 32 {
+        let descriptor = read_at(reader, sector * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        if &descriptor[1..6] != b"CD001" {
+            break;
+        }
+        let root = root_record(&descriptor);
+        match descriptor[0] {
+            1 => primary_root = Some(root),
+            // Joliet escape sequences are %/@, %/C and %/E
+            2 if &descriptor[88..90] == b"%/" => joliet_root = Some(root),
+            255 => break,
+            _ => {}
+        }
+    }
+
+    let (root, joliet) = match (joliet_root, primary_root) {
+        (Some(root), _) => (root, true),
+        (None, Some(root)) => (root, false),
+        (None, None) => {
+            return Err(AkpError::InvalidDiskImage("ISO 9660 image has no primary volume descriptor".to_string()));
+        }
+    };
+
+    let mut entries = Vec::new();
+    list_directory(reader, image_len, root, "", joliet, 0, &mut entries)?;
+    Ok(entries)
+}
+
+/// (extent sector, data length) of the root directory record at offset 156.
+fn root_record(descriptor: &[u8]) -> (u32, u32) {
+    (u32_le(descriptor, 158), u32_le(descriptor, 166))
+}
+
+fn list_directory<R: Read + Seek>(
+    reader: &mut R,
+    image_len: u64,
+    (extent, length): (u32, u32),
+    prefix: &str,
+    joliet: bool,
+    depth: usize,
+    entries: &mut Vec<ImageEntry>,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(AkpError::InvalidDiskImage(format!("directory '{prefix}' nested too deeply")));
+    }
+
+    let offset = extent as u64 * SECTOR_SIZE;
+    if !in_image(image_len, offset, length as u64) {
+        return Err(AkpError::InvalidDiskImage(format!("directory '{prefix}' extends past the end of the image")));
+    }
+    let data = read_at(reader, offset, length as usize)?;
+    let mut pos = 0;
+    while pos < data.len() {
+        let record_len = data[pos] as usize;
+        // Records never cross a sector boundary; zero length pads to the next one
+        if record_len == 0 {
+            pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        if record_len < 34 || pos + record_len > data.len() {
+            return Err(AkpError::InvalidDiskImage(format!("bad directory record in '{prefix}'")));
+        }
+        let record = &data[pos..pos + record_len];
+        pos += record_len;
+
+        let name_len = record[32] as usize;
+        let raw_name = &record[33..(33 + name_len).min(record.len())];
+        // 0x00 and 0x01 are the "." and ".." entries
+        if raw_name == [0] || raw_name == [1] {
+            continue;
+        }
+
+        let name = safe_name(&decode_name(raw_name, joliet));
+        let path = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        let child = (u32_le(record, 2), u32_le(record, 10));
+
+        if record[25] & 0x02 != 0 {
+            list_directory(reader, image_len, child, &path, joliet, depth + 1, entries)?;
+        } else {
+            entries.push(ImageEntry {
+                path,
+                size: child.1 as u64,
+                extents: vec![(child.0 as u64 * SECTOR_SIZE, child.1 as u64)],
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Decode a file identifier, dropping the `;1` version and a bare trailing dot.
+fn decode_name(raw: &[u8], joliet: bool) -> String {
+    let name = if joliet {
+        let units: Vec<u16> = raw.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(raw).into_owned()
+    };
+    let name = name.split(';').next().unwrap_or_default();
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn record(name: &[u8], extent: u32, length: u32, dir: bool) -> Vec<u8> {
+        let mut record = vec![0u8; 33 + name.len() + (name.len() + 1) % 2];
+        record[0] = record.len() as u8;
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[10..14].copy_from_slice(&length.to_le_bytes());
+        record[25] = if dir { 2 } else { 0 };
+        record[32] = name.len() as u8;
+        record[33..33 + name.len()].copy_from_slice(name);
+        record
+    }
+
+    fn utf16(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|u| u.to_be_bytes()).collect()
+    }
+
+    fn put(image: &mut [u8], sector: u64, data: &[u8]) {
+        let start = (sector * SECTOR_SIZE) as usize;
+        image[start..start + data.len()].copy_from_slice(data);
+    }
+
+    fn descriptor(kind: u8, root: (u32, u32), joliet: bool) -> Vec<u8> {
+        let mut d = vec![0u8; SECTOR_SIZE as usize];
+        d[0] = kind;
+        d[1..6].copy_from_slice(b"CD001");
+        if joliet {
+            d[88..91].copy_from_slice(b"%/E");
+        }
+        d[156..190].copy_from_slice(&record(&[0], root.0, root.1, true));
+        d
+    }
+
+    /// Root (sector 20) holds STRINGS/ (sector 21) holding VIOLIN.AKP (sector 22).
+    fn make_image(joliet: bool) -> Vec<u8> {
+        let mut image = vec![0u8; 23 * SECTOR_SIZE as usize];
+        put(&mut image, 16, &descriptor(1, (20, 2048), false));
+        let terminator_sector = if joliet {
+            put(&mut image, 17, &descriptor(2, (20, 2048), true));
+            18
+        } else {
+            17
+        };
+        put(&mut image, terminator_sector, &[255, b'C', b'D', b'0', b'0', b'1']);
+
+        let (dir_name, file_name) = if joliet {
+            (utf16("Strings"), utf16("Violin Sustain.akp;1"))
+        } else {
+            (b"STRINGS".to_vec(), b"VIOLIN.AKP;1".to_vec())
+        };
+        let mut root = record(&[0], 20, 2048, true);
+        root.extend(record(&[1], 20, 2048, true));
+        root.extend(record(&dir_name, 21, 2048, true));
+        put(&mut image, 20, &root);
+        put(&mut image, 21, &record(&file_name, 22, 5, false));
+        put(&mut image, 22, b"RIFF!");
+        image
+    }
+
+    #[test]
+    fn test_list_iso9660() {
+        let mut image = Cursor::new(make_image(false));
+        let len = image.get_ref().len() as u64;
+        assert!(detect(&mut image).unwrap());
+        let entries = list(&mut image, len).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "STRINGS/VIOLIN.AKP");
+        assert_eq!(entries[0].size, 5);
+        assert_eq!(super::super::read_entry(&mut image, len, &entries[0]).unwrap(), b"RIFF!");
+    }
+
+    #[test]
+    fn test_list_joliet_names() {
+        let image = make_image(true);
+        let len = image.len() as u64;
+        let entries = list(&mut Cursor::new(image), len).unwrap();
+        assert_eq!(entries[0].path, "Strings/Violin Sustain.akp");
+    }
+
+    #[test]
+    fn test_names_cant_leave_the_output_directory() {
+        // A directory named ".." holding "../EVIL.AKP"; the ISO name "..." loses
+        // its trailing dot as a bare-dot name would
+        let mut image = make_image(false);
+        let root = 20 * SECTOR_SIZE as usize + 2 * 34;
+        image[root..root + 40].fill(0);
+        let dir = record(b"...", 21, 2048, true);
+        image[root..root + dir.len()].copy_from_slice(&dir);
+        put(&mut image, 21, &record(b"../EVIL.AKP;1", 22, 5, false));
+        let len = image.len() as u64;
+
+        let entries = list(&mut Cursor::new(image), len).unwrap();
+        assert_eq!(entries[0].path, "__/.._EVIL.AKP");
+        let output = std::path::Path::new(&entries[0].path);
+        assert!(output.components().all(|c| matches!(c, std::path::Component::Normal(_))));
+    }
+
+    #[test]
+    fn test_directory_past_end_of_image() {
+        // The STRINGS directory claims 4 GB
+        let mut image = make_image(false);
+        let root = 20 * SECTOR_SIZE as usize + 2 * 34;
+        image[root + 10..root + 14].copy_from_slice(&0xFFFF_F800u32.to_le_bytes());
+        let len = image.len() as u64;
+        assert!(matches!(list(&mut Cursor::new(image), len), Err(AkpError::InvalidDiskImage(_))));
+    }
+
+    #[test]
+    fn test_detect_short_image() {
+        assert!(!detect(&mut Cursor::new(vec![0u8; 1024])).unwrap());
+    }
+}
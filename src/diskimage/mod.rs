@@ -0,0 +1,312 @@
+//! Read-only access to Akai CD-ROM and disk images.
+//!
+//! Three filesystems are recognised:
+//! - ISO 9660 (with Joliet long names when present), used by most S5000/S6000 CD-ROMs
+//! - FAT12/16/32, bare or behind an MBR partition table, used by S5000/S6000 disks
+//!   and some CDs
+//! - Akai's native S1000/S3000 layout (partitions → volumes → files)
+//!
+//! Each reader lists the image's files as `ImageEntry` values: a `/`-separated
+//! path and the byte ranges holding the file's data. Every name in the path
+//! goes through `safe_name`, so a path can be joined onto an output directory
+//! without leaving it. Nothing is ever written back to the image.
+
+mod akai;
+mod fat;
+mod iso9660;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::error::{AkpError, Result};
+use crate::s1000;
+use crate::samples::SampleSource;
+
+/// File extensions treated as disk images.
+pub const IMAGE_EXTENSIONS: [&str; 3] = ["iso", "img", "bin"];
+
+/// Whether a path has a disk image extension.
+pub fn is_image_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|i| i.eq_ignore_ascii_case(ext)))
+}
+
+/// Filesystem found in a disk image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Iso9660,
+    Fat,
+    /// S1000/S3000 native partitions and volumes.
+    Akai,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Iso9660 => write!(f, "ISO 9660"),
+            ImageFormat::Fat => write!(f, "FAT"),
+            ImageFormat::Akai => write!(f, "Akai S1000/S3000"),
+        }
+    }
+}
+
+/// A file inside a disk image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageEntry {
+    /// Path inside the image, `/`-separated, without a leading slash.
+    pub path: String,
+    pub size: u64,
+    /// Byte ranges (offset, length) holding the data, in order.
+    extents: Vec<(u64, u64)>,
+}
+
+impl ImageEntry {
+    fn extension(&self) -> Option<&str> {
+        let name = self.name();
+        name.rfind('.').map(|dot| &name[dot + 1..])
+    }
+
+    /// File name without the directory.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Directory part of the path (empty at the image root).
+    pub fn dir(&self) -> &str {
+        self.path.rfind('/').map_or("", |slash| &self.path[..slash])
+    }
+
+    /// Whether this is an AKP or S1000/S3000 program.
+    pub fn is_program(&self) -> bool {
+        self.extension().is_some_and(|ext| {
+            ext.eq_ignore_ascii_case("akp")
+                || s1000::PROGRAM_EXTENSIONS.iter().any(|p| p.eq_ignore_ascii_case(ext))
+        })
+    }
+}
+
+/// An opened disk image and the files it contains.
+pub struct DiskImage {
+    file: File,
+    /// Length of the image file, which every range read from it must fit in.
+    len: u64,
+    format: ImageFormat,
+    entries: Vec<ImageEntry>,
+}
+
+impl DiskImage {
+    /// Open an image and list its files.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        let (format, entries) = list_entries(&mut file, len)?;
+        Ok(DiskImage { file, len, format, entries })
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    pub fn entries(&self) -> &[ImageEntry] {
+        &self.entries
+    }
+
+    /// AKP and S1000/S3000 programs, in path order.
+    pub fn programs(&self) -> Vec<&ImageEntry> {
+        let mut programs: Vec<_> = self.entries.iter().filter(|e| e.is_program()).collect();
+        programs.sort_by(|a, b| a.path.cmp(&b.path));
+        programs
+    }
+
+    /// Find a file by path, ignoring case.
+    pub fn find(&self, path: &str) -> Option<&ImageEntry> {
+        self.entries.iter().find(|e| e.path.eq_ignore_ascii_case(path))
+    }
+
+    /// Read a file's contents.
+    pub fn read(&self, entry: &ImageEntry) -> Result<Vec<u8>> {
+        let mut reader = &self.file;
+        read_entry(&mut reader, self.len, entry)
+    }
+
+    /// Write a file's contents to `dest`, creating parent directories.
+    pub fn extract(&self, entry: &ImageEntry, dest: &Path) -> Result<()> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(dest, self.read(entry)?)?;
+        Ok(())
+    }
+
+    /// A sample source rooted at a directory inside the image, for `copy_samples_from`.
+    pub fn dir_source<'a>(&'a self, dir: &'a str) -> ImageDirSource<'a> {
+        ImageDirSource { image: self, dir }
+    }
+}
+
+/// Samples read from one directory of a disk image. Paths returned by
+/// `find` are image paths, not filesystem paths.
+pub struct ImageDirSource<'a> {
+    image: &'a DiskImage,
+    dir: &'a str,
+}
+
+impl SampleSource for ImageDirSource<'_> {
+    fn find(&self, sample_path: &str) -> Option<(PathBuf, bool)> {
+        let relative = sample_path.replace('\\', "/");
+        let path = if self.dir.is_empty() { relative } else { format!("{}/{relative}", self.dir) };
+        self.image.find(&path).map(|entry| (PathBuf::from(&entry.path), entry.path != path))
+    }
+
+    fn copy_to(&self, source: &Path, dest: &Path) -> io::Result<()> {
+        let path = source.to_string_lossy();
+        let entry = self.image.find(&path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("'{path}' not in image")))?;
+        let data = self.image.read(entry).map_err(|e| io::Error::other(e.to_string()))?;
+        fs::write(dest, data)
+    }
+}
+
+/// `image_len` is the length of the image. Sizes and offsets in the image's
+/// own headers are checked against it before anything is read, so a corrupt
+/// header can't ask for more memory than the image holds.
+fn list_entries<R: Read + Seek>(reader: &mut R, image_len: u64) -> Result<(ImageFormat, Vec<ImageEntry>)> {
+    if iso9660::detect(reader)? {
+        return Ok((ImageFormat::Iso9660, iso9660::list(reader, image_len)?));
+    }
+    if let Some(entries) = fat::list(reader, image_len)? {
+        return Ok((ImageFormat::Fat, entries));
+    }
+    if let Some(entries) = akai::list(reader, image_len)? {
+        return Ok((ImageFormat::Akai, entries));
+    }
+    Err(AkpError::InvalidDiskImage(
+        "no ISO 9660, FAT or Akai S1000/S3000 filesystem found".to_string(),
+    ))
+}
+
+fn read_entry<R: Read + Seek>(reader: &mut R, image_len: u64, entry: &ImageEntry) -> Result<Vec<u8>> {
+    // Check the extents before reading anything
+    let mut remaining = entry.size;
+    for &(offset, length) in &entry.extents {
+        let length = length.min(remaining);
+        if !in_image(image_len, offset, length) {
+            return Err(AkpError::InvalidDiskImage(format!(
+                "'{}' extends past the end of the image", entry.path,
+            )));
+        }
+        remaining -= length;
+    }
+    if remaining > 0 {
+        return Err(AkpError::InvalidDiskImage(format!(
+            "'{}' is truncated ({} of {} bytes)", entry.path, entry.size - remaining, entry.size,
+        )));
+    }
+
+    let mut data = Vec::new();
+    let mut remaining = entry.size;
+    for &(offset, length) in &entry.extents {
+        let length = length.min(remaining);
+        if length == 0 {
+            break;
+        }
+        data.extend(read_at(reader, offset, length as usize)?);
+        remaining -= length;
+    }
+    Ok(data)
+}
+
+/// Whether `len` bytes at `offset` lie inside an image of `image_len` bytes.
+fn in_image(image_len: u64, offset: u64, len: u64) -> bool {
+    offset.checked_add(len).is_some_and(|end| end <= image_len)
+}
+
+/// Read `len` bytes at `offset`. The buffer grows as data arrives, so a
+/// bogus length fails at the end of the image rather than allocating it.
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+/// A name read from an image, made safe to use as one path component.
+/// Separators and control characters become `_`, and a name made only of
+/// dots (or empty) becomes underscores, so it can't be `.` or `..`.
+fn safe_name(name: &str) -> String {
+    if name.chars().all(|c| c == '.') {
+        return "_".repeat(name.len().max(1));
+    }
+    name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+fn u16_le(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_entry_follows_extents() {
+        let mut image = Cursor::new(b"....world....hello".to_vec());
+        let entry = ImageEntry {
+            path: "GREETING.TXT".to_string(),
+            size: 9,
+            extents: vec![(13, 5), (4, 5)],
+        };
+        assert_eq!(read_entry(&mut image, 18, &entry).unwrap(), b"helloworl");
+    }
+
+    #[test]
+    fn test_read_entry_truncated() {
+        let mut image = Cursor::new(vec![0u8; 8]);
+        let entry = ImageEntry { path: "A".to_string(), size: 16, extents: vec![(0, 8)] };
+        assert!(matches!(read_entry(&mut image, 8, &entry), Err(AkpError::InvalidDiskImage(_))));
+
+        // A size and extent far beyond the image fail before allocating
+        let entry = ImageEntry { path: "A".to_string(), size: 1 << 44, extents: vec![(0, 1 << 44)] };
+        assert!(matches!(read_entry(&mut image, 8, &entry), Err(AkpError::InvalidDiskImage(_))));
+    }
+
+    #[test]
+    fn test_entry_paths() {
+        let entry = ImageEntry { path: "STRINGS/VIOLIN.AKP".to_string(), size: 0, extents: vec![] };
+        assert_eq!(entry.name(), "VIOLIN.AKP");
+        assert_eq!(entry.dir(), "STRINGS");
+        assert!(entry.is_program());
+
+        let entry = ImageEntry { path: "A/PIANO/PIANO C3.s3".to_string(), size: 0, extents: vec![] };
+        assert!(!entry.is_program());
+    }
+
+    #[test]
+    fn test_safe_name() {
+        assert_eq!(safe_name("VIOLIN.AKP"), "VIOLIN.AKP");
+        assert_eq!(safe_name(".."), "__");
+        assert_eq!(safe_name("."), "_");
+        assert_eq!(safe_name(""), "_");
+        assert_eq!(safe_name("../../etc"), ".._.._etc");
+        assert_eq!(safe_name("A\\B:C"), "A_B_C");
+    }
+
+    #[test]
+    fn test_unrecognised_image() {
+        let mut image = Cursor::new(vec![0u8; 64 * 1024]);
+        assert!(matches!(list_entries(&mut image, 64 * 1024), Err(AkpError::InvalidDiskImage(_))));
+    }
+}
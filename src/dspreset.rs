@@ -1,6 +1,7 @@
-use crate::types::{AkaiProgram, EnvelopeTiming, mod_source_name};
+use crate::instrument::{FilterKind, FilterStage, Group, ModRoute, ModSource, ModTarget, MIN_RELEASE};
+use crate::types::{AkaiProgram, ConvertOptions, Filter, FilterRouting};
 
-fn xml_escape(s: &str) -> String {
+pub(crate) fn xml_escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('"', "&quot;")
         .replace('<', "&lt;")
@@ -8,14 +9,37 @@ fn xml_escape(s: &str) -> String {
         .replace('\'', "&apos;")
 }
 
+/// DS envelope times: unset (zero) times fall back to the DS defaults.
+fn ds_envelope_times(attack: f32, decay: f32, release: f32) -> (f32, f32, f32) {
+    let attack = if attack == 0.0 { 0.001 } else { attack };
+    let decay = if decay == 0.0 { 0.1 } else { decay };
+    let release = if release <= MIN_RELEASE { 0.1 } else { release };
+    (attack, decay, release)
+}
+
 impl AkaiProgram {
     pub fn to_dspreset_string(&self) -> String {
+        self.to_dspreset_string_with(&ConvertOptions::default())
+    }
+
+    pub fn to_dspreset_string_with(&self, options: &ConvertOptions) -> String {
+        // DS has no tuning tables, so a detune table always splits per key
+        let instrument = self.to_instrument(true, options.drum_kit_layout);
+        // Instrument-wide settings (filters, modulators) come from the
+        // keygroups in program order, one group each, whatever the pad layout
+        let keyboard;
+        let keygroups = if options.drum_kit_layout && self.pads.is_some() {
+            keyboard = self.to_instrument(true, false);
+            &keyboard.groups
+        } else {
+            &instrument.groups
+        };
         let mut xml = String::new();
 
         // Pre-compute filter state for UI and modulators sections
-        let has_filter = self.keygroups.iter().any(|kg| kg.filter.is_some());
-        let filter_env_ref = self.keygroups.iter()
-            .find_map(|kg| kg.filter_env.as_ref().filter(|env| env.depth != 0));
+        let has_filter = keygroups.iter().any(|group| group.filter.is_some());
+        let filter_env_ref = keygroups.iter()
+            .find_map(|group| group.filter_env.as_ref().filter(|env| env.depth != 0.0));
         let has_filter_env = filter_env_ref.is_some();
 
         xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
@@ -46,10 +70,8 @@ impl AkaiProgram {
         // Filter envelope ADSR knobs (only when filter effect and filter env both present)
         if has_filter && has_filter_env {
             let fenv = filter_env_ref.unwrap();
-            let att_val = if fenv.attack == 0 { 0.001 } else { fenv.attack_time() };
-            let dec_val = if fenv.decay == 0 { 0.1 } else { fenv.decay_time() };
-            let sus_val = fenv.sustain_normalized();
-            let rel_val = if fenv.release == 0 { 0.1 } else { fenv.release_time() };
+            let (att_val, dec_val, rel_val) = ds_envelope_times(fenv.attack, fenv.decay, fenv.release);
+            let sus_val = fenv.sustain;
 
             xml.push_str(&format!(
                 "      <labeled-knob x=\"10\" y=\"140\" width=\"90\" height=\"100\" parameterName=\"FILT_ENV_ATTACK\" type=\"float\" minValue=\"0\" maxValue=\"5\" value=\"{att_val:.2}\" textColor=\"AA000000\">\n"
@@ -77,43 +99,61 @@ impl AkaiProgram {
         xml.push_str("  </ui>\n\n");
 
         // Groups section
-        if let Some(output) = &self.output {
-            xml.push_str(&format!("  <groups volume=\"{:.2}\">\n", output.volume_db()));
+        if let Some(output) = &instrument.output {
+            xml.push_str(&format!("  <groups volume=\"{:.2}\">\n", output.volume_db));
         } else {
             xml.push_str("  <groups>\n");
         }
 
-        for (group_id, keygroup) in self.keygroups.iter().enumerate() {
-            xml.push_str(&format!("    <group name=\"Group{}\"", group_id + 1));
+        let program_semitone = instrument.tuning.as_ref().map_or(0, |t| t.transpose);
+        let program_fine = instrument.tuning.as_ref().map_or(0, |t| t.tune_cents);
+
+        for (group_id, group) in instrument.groups.iter().enumerate() {
+            let group_name = match &group.label {
+                Some(label) => xml_escape(label),
+                None => format!("Group{}", group_id + 1),
+            };
+            xml.push_str(&format!("    <group name=\"{group_name}\""));
 
-            if let Some(env) = &keygroup.amp_env {
-                let attack = if env.attack == 0 { 0.001 } else { env.attack_time() };
-                let decay = if env.decay == 0 { 0.1 } else { env.decay_time() };
-                let sustain = env.sustain_normalized();
-                let release = if env.release == 0 { 0.1 } else { env.release_time() };
+            if let Some(env) = &group.amp_env {
+                let (attack, decay, release) = ds_envelope_times(env.attack, env.decay, env.release);
+                let sustain = env.sustain;
                 xml.push_str(&format!(" attack=\"{attack:.3}\" decay=\"{decay:.3}\" sustain=\"{sustain:.3}\" release=\"{release:.3}\""));
             }
 
             // Velocity sensitivity (DS range 0.0-1.0, negative not supported)
-            if let Some(output) = &self.output {
-                // AKP range (-100..100) → DS range (0.0..1.0), negative unsupported
-                let vel_track = (output.velocity_sensitivity.max(0) as f32 / 100.0).min(1.0);
+            if let Some(output) = &instrument.output {
+                let vel_track = (output.velocity_tracking.max(0.0) / 100.0).min(1.0);
                 if (vel_track - 1.0).abs() > f32::EPSILON {
                     xml.push_str(&format!(" ampVelTrack=\"{vel_track:.2}\""));
                 }
             }
 
+            // Mute group: a tag per group, silenced by any other voice carrying it
+            if group.mute_group != 0 {
+                xml.push_str(&format!(
+                    " tags=\"mute{0}\" silencedByTags=\"mute{0}\" silencingMode=\"fast\"",
+                    group.mute_group
+                ));
+            }
+
             xml.push_str(">\n");
 
-            // Each zone becomes a <sample>
-            for zone in &keygroup.zones {
+            // Each zone becomes a <sample>. DS has no velocity crossfade, and
+            // its only velocity gain (ampVelTrack) can't fade a layer out, so
+            // zone crossfade is dropped and the layers keep their own ranges.
+            for region in &group.regions {
+                let Some(sample) = &region.sample else { continue };
                 xml.push_str("      <sample ");
-                xml.push_str(&format!("path=\"{}\" ", xml_escape(&zone.sample_name)));
-                xml.push_str(&format!("loNote=\"{}\" hiNote=\"{}\" ", keygroup.low_key, keygroup.high_key));
-                xml.push_str(&format!("loVel=\"{}\" hiVel=\"{}\" ", zone.low_vel, zone.high_vel));
+                xml.push_str(&format!("path=\"{}\" ", xml_escape(sample)));
+                xml.push_str(&format!("loNote=\"{}\" hiNote=\"{}\" ", region.low_key, region.high_key));
+                xml.push_str(&format!("loVel=\"{}\" hiVel=\"{}\" ", region.low_vel, region.high_vel));
+                if let Some(root_key) = region.root_key {
+                    xml.push_str(&format!("rootNote=\"{root_key}\" "));
+                }
 
-                let semitone = keygroup.semitone_tune as i16 + zone.semitone_tune as i16;
-                let fine = keygroup.fine_tune as i16 + zone.fine_tune as i16;
+                let semitone = program_semitone + region.transpose;
+                let fine = program_fine + region.tune_cents;
                 if semitone != 0 {
                     xml.push_str(&format!("tuning=\"{semitone}\" "));
                 }
@@ -121,9 +161,21 @@ impl AkaiProgram {
                     xml.push_str(&format!("fineTuning=\"{fine}\" "));
                 }
 
-                if zone.pan != 0 {
-                    // DS pan: -100 to 100
-                    xml.push_str(&format!("pan=\"{}\" ", zone.pan as i32 * 2));
+                if region.pan != 0.0 {
+                    xml.push_str(&format!("pan=\"{:.0}\" ", region.pan));
+                }
+
+                if region.looped {
+                    xml.push_str("loopEnabled=\"true\" ");
+                    if let Some(sample_loop) = region.sample_loop {
+                        xml.push_str(&format!("loopStart=\"{}\" loopEnd=\"{}\" ", sample_loop.start, sample_loop.end));
+                        if sample_loop.crossfade > 0 {
+                            xml.push_str(&format!("loopCrossfade=\"{}\" ", sample_loop.crossfade));
+                        }
+                    }
+                } else if region.sample_loop.is_some() {
+                    // NO LOOP / ONE SHOT: stop DS from picking up the WAV's loop
+                    xml.push_str("loopEnabled=\"false\" ");
                 }
 
                 xml.push_str("/>\n");
@@ -139,6 +191,32 @@ impl AkaiProgram {
         if has_filter {
             xml.push_str("    <lowpass frequency=\"$FILTER_CUTOFF\" resonance=\"$FILTER_RESONANCE\" />\n");
         }
+        // Z4/Z8 filters 2 and 3 chain after filter 1 at fixed settings
+        if let Some(first) = keygroups.iter().position(|g| g.filter2.is_some() || g.filter3.is_some()) {
+            // Effects cover the whole preset, so keygroups set up differently
+            // from the first one with filters 2 and 3 are played through its
+            let group = &keygroups[first];
+            let extra_filters = |g: &Group| (g.filter2.clone(), g.filter3.clone(), g.filter_routing);
+            let differing: Vec<String> = keygroups.iter().enumerate()
+                .filter(|(_, g)| extra_filters(g) != extra_filters(group))
+                .map(|(i, _)| (i + 1).to_string())
+                .collect();
+            if !differing.is_empty() {
+                xml.push_str(&format!(
+                    "    <!-- Filters 2 and 3 are keygroup {}'s for every keygroup; not applied as set in keygroups {} -->\n",
+                    first + 1, differing.join(", "),
+                ));
+            }
+            if group.filter_routing == FilterRouting::Parallel {
+                xml.push_str("    <!-- AKP filters run in parallel; DS effects are chained in series -->\n");
+            }
+            for filter in [&group.filter2, &group.filter3].into_iter().flatten() {
+                xml.push_str(&format!(
+                    "    <{} frequency=\"{:.1}\" resonance=\"{:.1}\" />\n",
+                    filter.ds_effect(), filter.cutoff_hz, filter.resonance_db
+                ));
+            }
+        }
         xml.push_str("    <reverb roomSize=\"0.5\" damping=\"0.5\" wetLevel=\"0.3\" dryLevel=\"0.7\" width=\"1.0\" />\n");
         xml.push_str("  </effects>\n\n");
 
@@ -153,23 +231,18 @@ impl AkaiProgram {
         let mut mod_xml = String::new();
 
         // LFO modulator
-        if let Some(lfo) = &self.lfo1 {
-            if lfo.depth > 0 {
-                let amount = lfo.depth_normalized();
-                mod_xml.push_str(&format!(
-                    "    <lfo frequency=\"{:.2}\" waveform=\"{}\" target=\"FILTER_CUTOFF\" amount=\"{amount:.2}\" />\n",
-                    lfo.rate_hz(), lfo.waveform_name()));
-            }
+        if let Some(lfo) = instrument.lfo1.as_ref().filter(|lfo| lfo.depth > 0.0) {
+            mod_xml.push_str(&format!(
+                "    <lfo frequency=\"{:.2}\" waveform=\"{}\" target=\"FILTER_CUTOFF\" amount=\"{:.2}\" />\n",
+                lfo.rate_hz, lfo.waveform, lfo.depth));
         }
 
         // Filter envelope modulator (only when lowpass effect is present at effectIndex=0)
         if has_filter && has_filter_env {
             let fenv = filter_env_ref.unwrap();
-            let attack = if fenv.attack == 0 { 0.001 } else { fenv.attack_time() };
-            let decay = if fenv.decay == 0 { 0.1 } else { fenv.decay_time() };
-            let sustain = fenv.sustain_normalized();
-            let release = if fenv.release == 0 { 0.1 } else { fenv.release_time() };
-            let mod_amount = fenv.depth as f32 / 100.0;
+            let (attack, decay, release) = ds_envelope_times(fenv.attack, fenv.decay, fenv.release);
+            let sustain = fenv.sustain;
+            let mod_amount = fenv.depth;
 
             mod_xml.push_str(&format!(
                 "    <envelope attack=\"{attack:.3}\" decay=\"{decay:.3}\" sustain=\"{sustain:.3}\" release=\"{release:.3}\" modAmount=\"{mod_amount:.2}\" scope=\"voice\">\n"
@@ -180,95 +253,41 @@ impl AkaiProgram {
             mod_xml.push_str("    </envelope>\n");
         }
 
-        // Modulation bindings from ProgramModulation
-        if let Some(mods) = &self.modulation {
-            // Velocity -> filter (filter_mod_1_source == 5)
-            if mods.filter_mod_1_source == 5 {
-                if let Some(filter) = self.keygroups.iter().find_map(|kg| kg.filter.as_ref()) {
-                    if filter.mod_input_1 != 0 {
-                        let amount = filter.mod_input_1 as f32 / 100.0;
-                        mod_xml.push_str(&format!(
-                            "    <velocity modAmount=\"{amount:.2}\">\n"
-                        ));
-                        mod_xml.push_str(
-                            "      <binding type=\"effect\" level=\"instrument\" effectIndex=\"0\" parameter=\"FX_FILTER_FREQUENCY\" />\n"
-                        );
-                        mod_xml.push_str("    </velocity>\n");
-                    }
-                }
-            }
-
-            // Modwheel -> pan (pan_mod_3_source == 1)
-            if mods.pan_mod_3_source == 1 {
-                if let Some(output) = &self.output {
-                    if output.pan_mod_3 > 0 {
-                        let amount = output.pan_mod_3 as f32 / 100.0;
-                        mod_xml.push_str(&format!(
-                            "    <cc number=\"1\" modAmount=\"{amount:.2}\">\n"
-                        ));
-                        mod_xml.push_str(
-                            "      <binding type=\"general\" level=\"instrument\" parameter=\"PAN\" />\n"
-                        );
-                        mod_xml.push_str("    </cc>\n");
-                    }
-                }
-            }
-
-            // Unsupported routes as XML comments
-            let routes: &[(u8, &str, u8)] = &[
-                (mods.amp_mod_1_source, "amp_mod_1", self.output.as_ref().map_or(0, |o| o.amp_mod_1)),
-                (mods.amp_mod_2_source, "amp_mod_2", self.output.as_ref().map_or(0, |o| o.amp_mod_2)),
-                (mods.pan_mod_1_source, "pan_mod_1", self.output.as_ref().map_or(0, |o| o.pan_mod_1)),
-                (mods.pan_mod_2_source, "pan_mod_2", self.output.as_ref().map_or(0, |o| o.pan_mod_2)),
-            ];
-
-            for &(source, dest, amount) in routes {
-                if source != 0 && amount != 0 {
-                    mod_xml.push_str(&format!(
-                        "    <!-- AKP modulation: {} \u{2192} {} (amount={}, not supported in DS) -->\n",
-                        mod_source_name(source), dest, amount
-                    ));
-                }
-            }
-
-            // Also check pan_mod_3 for unsupported sources (not modwheel)
-            if mods.pan_mod_3_source != 0 && mods.pan_mod_3_source != 1 {
-                if let Some(output) = &self.output {
-                    if output.pan_mod_3 > 0 {
-                        mod_xml.push_str(&format!(
-                            "    <!-- AKP modulation: {} \u{2192} pan_mod_3 (amount={}, not supported in DS) -->\n",
-                            mod_source_name(mods.pan_mod_3_source), output.pan_mod_3
-                        ));
-                    }
-                }
-            }
-
-            // Check filter mod sources for unsupported (not velocity)
-            let filter_routes: &[(u8, &str, i8)] = &[
-                (mods.filter_mod_2_source, "filter_mod_2", self.keygroups.first().and_then(|kg| kg.filter.as_ref()).map_or(0, |f| f.mod_input_2)),
-                (mods.filter_mod_3_source, "filter_mod_3", self.keygroups.first().and_then(|kg| kg.filter.as_ref()).map_or(0, |f| f.mod_input_3)),
-            ];
-
-            for &(source, dest, amount) in filter_routes {
-                if source != 0 && amount != 0 {
-                    mod_xml.push_str(&format!(
-                        "    <!-- AKP modulation: {} \u{2192} {} (amount={}, not supported in DS) -->\n",
-                        mod_source_name(source), dest, amount
-                    ));
-                }
-            }
+        // Modulation routes. DS modulators are instrument-wide: the filter
+        // routes come from the first keygroup with a filter, and only
+        // velocity → cutoff (filter_mod_1) and modwheel → pan (pan_mod_3)
+        // have DS equivalents. Everything else becomes a comment.
+        let is = |route: &ModRoute, name: &str, source: ModSource| route.name == name && route.source == source;
+        let velocity_filter = keygroups.iter()
+            .find(|group| group.filter.is_some())
+            .and_then(|group| group.routes.iter().find(|route| is(route, "filter_mod_1", ModSource::Velocity)));
+        if let Some(route) = velocity_filter {
+            mod_xml.push_str(&format!("    <velocity modAmount=\"{:.2}\">\n", route.depth));
+            mod_xml.push_str(
+                "      <binding type=\"effect\" level=\"instrument\" effectIndex=\"0\" parameter=\"FX_FILTER_FREQUENCY\" />\n"
+            );
+            mod_xml.push_str("    </velocity>\n");
+        }
+        if let Some(route) = instrument.routes.iter().find(|route| is(route, "pan_mod_3", ModSource::ModWheel)) {
+            mod_xml.push_str(&format!("    <cc number=\"1\" modAmount=\"{:.2}\">\n", route.depth));
+            mod_xml.push_str(
+                "      <binding type=\"general\" level=\"instrument\" parameter=\"PAN\" />\n"
+            );
+            mod_xml.push_str("    </cc>\n");
+        }
 
-            // Check filter_mod_1 for unsupported sources (not velocity)
-            if mods.filter_mod_1_source != 0 && mods.filter_mod_1_source != 5 {
-                if let Some(filter) = self.keygroups.first().and_then(|kg| kg.filter.as_ref()) {
-                    if filter.mod_input_1 != 0 {
-                        mod_xml.push_str(&format!(
-                            "    <!-- AKP modulation: {} \u{2192} filter_mod_1 (amount={}, not supported in DS) -->\n",
-                            mod_source_name(mods.filter_mod_1_source), filter.mod_input_1
-                        ));
-                    }
-                }
-            }
+        let output_routes = instrument.routes.iter()
+            .filter(|route| matches!(route.target, ModTarget::Amplitude | ModTarget::Pan))
+            .filter(|route| !is(route, "pan_mod_3", ModSource::ModWheel));
+        let first_routes = keygroups.first().map_or(&[][..], |group| &group.routes[..]);
+        let filter_routes = ["filter_mod_2", "filter_mod_3", "filter_mod_1"].into_iter()
+            .filter_map(|name| first_routes.iter().find(|route| route.name == name))
+            .filter(|route| !is(route, "filter_mod_1", ModSource::Velocity));
+        for route in output_routes.chain(filter_routes) {
+            mod_xml.push_str(&format!(
+                "    <!-- AKP modulation: {} \u{2192} {} (amount={}, not supported in DS) -->\n",
+                route.source.name(), route.name, route.akai_amount()
+            ));
         }
 
         if !mod_xml.is_empty() {
@@ -289,6 +308,26 @@ impl AkaiProgram {
     }
 }
 
+impl FilterStage {
+    /// Decent Sampler effect element for the filter.
+    pub fn ds_effect(&self) -> &'static str {
+        match self.kind {
+            FilterKind::LowPass => "lowpass",
+            FilterKind::BandPass => "bandpass",
+            FilterKind::HighPass => "highpass",
+            FilterKind::Notch => "notch",
+            FilterKind::Peak => "peak",
+        }
+    }
+}
+
+impl Filter {
+    /// Map Akai filter type (0-25) to a Decent Sampler effect element.
+    pub fn ds_effect_type(&self) -> &'static str {
+        FilterStage::from(self).ds_effect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::types::*;
@@ -315,6 +354,167 @@ mod tests {
         assert!(xml.contains("hiNote=\"72\""));
     }
 
+    #[test]
+    fn test_dspreset_root_note() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup { low_key: 36, high_key: 47, ..Default::default() };
+        keygroup.zones.push(Zone {
+            sample_name: "bass.wav".to_string(),
+            root_key: Some(40),
+            root_tune: -25,
+            ..Default::default()
+        });
+        program.keygroups.push(keygroup);
+
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("rootNote=\"40\""));
+        assert!(xml.contains("fineTuning=\"-25\""));
+    }
+
+    #[test]
+    fn test_dspreset_loop_points() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup::default();
+        keygroup.zones.push(Zone {
+            sample_name: "pad.wav".to_string(),
+            playback: 3,
+            sample_loop: Some(SampleLoop { start: 100, end: 900, crossfade: 50 }),
+            ..Default::default()
+        });
+        keygroup.zones.push(Zone {
+            sample_name: "hit.wav".to_string(),
+            playback: 0,
+            sample_loop: Some(SampleLoop { start: 10, end: 20, crossfade: 0 }),
+            ..Default::default()
+        });
+        program.keygroups.push(keygroup);
+
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("loopEnabled=\"true\" loopStart=\"100\" loopEnd=\"900\" loopCrossfade=\"50\""));
+        assert!(xml.contains("loopEnabled=\"false\""));
+        assert!(!xml.contains("loopStart=\"10\""));
+    }
+
+    #[test]
+    fn test_dspreset_mute_group_hihat_choke() {
+        let mut program = AkaiProgram::default();
+        for (key, sample, mute_group) in [(42, "closed_hat.wav", 2), (46, "open_hat.wav", 2), (36, "kick.wav", 0)] {
+            let mut keygroup = Keygroup { low_key: key, high_key: key, mute_group, ..Default::default() };
+            keygroup.zones.push(Zone { sample_name: sample.to_string(), ..Default::default() });
+            program.keygroups.push(keygroup);
+        }
+
+        let xml = program.to_dspreset_string();
+        let groups: Vec<&str> = xml.lines().filter(|l| l.trim_start().starts_with("<group ")).collect();
+        assert_eq!(groups.len(), 3);
+        for hat in &groups[..2] {
+            assert!(hat.contains("tags=\"mute2\" silencedByTags=\"mute2\" silencingMode=\"fast\""));
+        }
+        assert!(!groups[2].contains("tags="));
+    }
+
+    #[test]
+    fn test_dspreset_velocity_crossfade_keeps_layer_ranges() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup { zone_crossfade: 1, ..Default::default() };
+        keygroup.zones.push(Zone { sample_name: "soft.wav".to_string(), low_vel: 0, high_vel: 63, ..Default::default() });
+        keygroup.zones.push(Zone { sample_name: "hard.wav".to_string(), low_vel: 64, high_vel: 127, ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        // DS has no crossfade, so the flag changes nothing
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("path=\"soft.wav\" loNote=\"21\" hiNote=\"127\" loVel=\"0\" hiVel=\"63\""));
+        assert!(xml.contains("path=\"hard.wav\" loNote=\"21\" hiNote=\"127\" loVel=\"64\" hiVel=\"127\""));
+        program.keygroups[0].zone_crossfade = 0;
+        assert_eq!(program.to_dspreset_string(), xml);
+
+        // Uneven layers aren't moved either
+        program.keygroups[0].zone_crossfade = 1;
+        program.keygroups[0].zones[0].high_vel = 39;
+        program.keygroups[0].zones[1].low_vel = 40;
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("loVel=\"0\" hiVel=\"39\""));
+        assert!(xml.contains("loVel=\"40\" hiVel=\"127\""));
+    }
+
+    #[test]
+    fn test_dspreset_program_tuning_and_detune_split() {
+        let mut tuning = ProgramTuning { semitone: -12, fine: 3, ..Default::default() };
+        tuning.detune[4] = -14; // E
+        let mut program = AkaiProgram { tuning: Some(tuning), ..Default::default() };
+        let mut keygroup = Keygroup { low_key: 60, high_key: 67, ..Default::default() };
+        keygroup.zones.push(Zone { sample_name: "oud.wav".to_string(), ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("loNote=\"60\" hiNote=\"63\" loVel=\"0\" hiVel=\"127\" tuning=\"-12\" fineTuning=\"3\""));
+        assert!(xml.contains("loNote=\"64\" hiNote=\"64\" loVel=\"0\" hiVel=\"127\" tuning=\"-12\" fineTuning=\"-11\""));
+        assert!(xml.contains("loNote=\"65\" hiNote=\"67\""));
+        assert_eq!(xml.matches("<sample ").count(), 3);
+    }
+
+    #[test]
+    fn test_dspreset_z8_filter_chain() {
+        let mut program = AkaiProgram::default();
+        program.keygroups.push(Keygroup {
+            filter: Some(Filter { filter_type: 0, cutoff: 80, ..Default::default() }),
+            filter2: Some(Filter { filter_type: 7, cutoff: 20, ..Default::default() }),
+            filter3: Some(Filter { filter_type: 18, cutoff: 60, resonance: 6, ..Default::default() }),
+            ..Default::default()
+        });
+
+        let xml = program.to_dspreset_string();
+        let effects = xml.split("<effects>").nth(1).unwrap();
+        let lowpass = effects.find("<lowpass").unwrap();
+        let highpass = effects.find("<highpass frequency=\"79.6\"").unwrap();
+        let peak = effects.find("<peak frequency=\"1261.9\" resonance=\"20.0\"").unwrap();
+        assert!(lowpass < highpass && highpass < peak);
+        assert!(!effects.contains("parallel"));
+    }
+
+    #[test]
+    fn test_dspreset_notes_keygroups_with_other_extra_filters() {
+        let z8_keygroup = |filter2_type| Keygroup {
+            filter: Some(Filter::default()),
+            filter2: Some(Filter { filter_type: filter2_type, ..Default::default() }),
+            ..Default::default()
+        };
+        let mut program = AkaiProgram {
+            keygroups: vec![Keygroup::default(), z8_keygroup(7), z8_keygroup(7), z8_keygroup(12)],
+            ..Default::default()
+        };
+
+        let xml = program.to_dspreset_string();
+        assert!(xml.contains("<!-- Filters 2 and 3 are keygroup 2's for every keygroup; not applied as set in keygroups 1, 4 -->"));
+        assert_eq!(xml.matches("<highpass").count(), 1);
+
+        program.keygroups.truncate(3);
+        program.keygroups.remove(0);
+        assert!(!program.to_dspreset_string().contains("not applied"));
+    }
+
+    #[test]
+    fn test_dspreset_drum_kit_layout_by_pad() {
+        let mut pads = PadAssignments::default();
+        pads.notes[0] = Some(38);
+        pads.notes[1] = Some(36);
+        let mut program = AkaiProgram { pads: Some(pads), ..Default::default() };
+        for (key, sample) in [(36, "kick.wav"), (38, "snare.wav")] {
+            let mut keygroup = Keygroup { low_key: key, high_key: key, ..Default::default() };
+            keygroup.zones.push(Zone { sample_name: sample.to_string(), ..Default::default() });
+            program.keygroups.push(keygroup);
+        }
+
+        let options = ConvertOptions { drum_kit_layout: true, ..Default::default() };
+        let xml = program.to_dspreset_string_with(&options);
+        let snare = xml.find("<group name=\"Pad A01\"").unwrap();
+        let kick = xml.find("<group name=\"Pad A02\"").unwrap();
+        assert!(snare < kick);
+        assert!(xml[snare..kick].contains("path=\"snare.wav\" loNote=\"38\" hiNote=\"38\""));
+
+        assert!(program.to_dspreset_string().contains("<group name=\"Group1\""));
+    }
+
     #[test]
     fn test_dspreset_filter_binding_uses_dollar_prefix() {
         let mut program = AkaiProgram::default();
@@ -536,4 +736,33 @@ mod tests {
         assert!(xml.contains("<!-- AKP modulation: AFTERTOUCH"), "Unsupported routes should be XML comments");
         assert!(xml.contains("not supported in DS"), "Comment should mention DS limitation");
     }
+
+    #[test]
+    fn test_dspreset_modulators_only_from_their_slots() {
+        // Modwheel on pan_mod_1 and velocity on filter_mod_2 stay comments;
+        // filter routes come from the first keygroup with a filter
+        let mut program = AkaiProgram {
+            modulation: Some(ProgramModulation {
+                pan_mod_1_source: 1,    // MODWHEEL
+                filter_mod_1_source: 5, // VELOCITY
+                filter_mod_2_source: 5, // VELOCITY
+                ..Default::default()
+            }),
+            output: Some(ProgramOutput { pan_mod_1: 40, ..Default::default() }),
+            ..Default::default()
+        };
+        program.keygroups.push(Keygroup::default());
+        program.keygroups.push(Keygroup {
+            filter: Some(Filter { mod_input_1: 30, mod_input_2: 20, ..Default::default() }),
+            ..Default::default()
+        });
+
+        let xml = program.to_dspreset_string();
+        assert!(!xml.contains("<cc number=\"1\" modAmount"));
+        assert!(xml.contains("<!-- AKP modulation: MODWHEEL \u{2192} pan_mod_1 (amount=40, not supported in DS) -->"));
+        assert!(xml.contains("<velocity modAmount=\"0.30\">"));
+        assert_eq!(xml.matches("<velocity").count(), 1);
+        // filter_mod_2 comments read the first keygroup, which has no filter
+        assert!(!xml.contains("filter_mod_2"));
+    }
 }
@@ -0,0 +1,824 @@
+//! Decent Sampler import: builds an `AkaiProgram` from a `.dspreset`, so DS
+//! libraries can be written as SFZ or AKP, and our own DS output can be read
+//! back for regression testing.
+//!
+//! Each `<sample>` becomes a zone, with attributes inherited from its
+//! `<group>` and from `<groups>`. Zones are placed in keygroups the same way
+//! as SFZ regions (see `AkaiProgram::add_zone`). Filter effects, the filter
+//! envelope, velocity → cutoff and mod wheel → pan modulators become the
+//! program's filters and modulation routes, which covers everything the DS
+//! writer emits. `$NAME` values are resolved from the UI control with that
+//! `parameterName`.
+//!
+//! Presets written by this crate are recognised by their `conversion-tool`
+//! tag: their plain group volume is in dB, and the placeholder times the
+//! writer uses for zero (0.1 s decay and release) are read back as zero.
+//!
+//! Everything else is listed in `DsImport::skipped`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::error::{AkpError, Result};
+use crate::instrument::{envelope_time_to_raw, release_time_to_raw};
+use crate::types::{
+    AkaiProgram, Envelope, Filter, FilterEnvelope, Keygroup, Lfo,
+    ProgramHeader, ProgramModel, ProgramModulation, ProgramOutput, SampleLoop, SkippedOpcode, Zone,
+};
+
+const FORMAT_NAME: &str = "Decent Sampler";
+/// Value of the `conversion-tool` tag in presets our writer produces.
+const OWN_TOOL_PREFIX: &str = "Rusty Samplers";
+/// What our writer puts in place of a zero decay or release time.
+const ZERO_TIME_MARKER: f32 = 0.1;
+/// DS plays a sample at this note when `rootNote` isn't given.
+const DEFAULT_ROOT_KEY: u8 = 60;
+/// Mute groups the S5000/S6000 offers (0 = off).
+const MAX_MUTE_GROUP: u8 = 32;
+/// Filters per keygroup on the Z4/Z8.
+const MAX_FILTERS: usize = 3;
+/// Modulation source numbers (see `instrument::ModSource`).
+const MODWHEEL_SOURCE: u8 = 1;
+const VELOCITY_SOURCE: u8 = 5;
+
+/// Result of importing a Decent Sampler preset.
+#[derive(Debug, Clone)]
+pub struct DsImport {
+    /// Zones keep the sample paths from the preset, relative to it.
+    pub program: AkaiProgram,
+    /// Attributes and elements that couldn't be represented, in line order.
+    pub skipped: Vec<SkippedOpcode>,
+}
+
+/// Read and import a `.dspreset` file.
+pub fn read_dspreset(path: &Path) -> Result<DsImport> {
+    let xml = fs::read_to_string(path)?;
+    parse_dspreset(&xml)
+}
+
+/// Import a Decent Sampler preset. Only malformed XML or a root element
+/// other than `<DecentSampler>` fails; anything that can't be represented
+/// is reported in `DsImport::skipped`.
+pub fn parse_dspreset(xml: &str) -> Result<DsImport> {
+    let root = parse_xml(xml)?;
+    if root.name != "DecentSampler" {
+        return Err(invalid(format!("expected <DecentSampler>, found <{}>", root.name)));
+    }
+
+    let mut importer = Importer {
+        own_output: root.children("tags").flat_map(|tags| tags.children("tag")).any(|tag| {
+            tag.attribute("name") == Some("conversion-tool")
+                && tag.attribute("value").is_some_and(|v| v.starts_with(OWN_TOOL_PREFIX))
+        }),
+        ..Default::default()
+    };
+    for ui in root.children("ui") {
+        importer.read_controls(ui);
+    }
+
+    // Effects and modulators apply to every keygroup, so they're read first
+    let mut base = Keygroup { low_key: 0, high_key: 127, ..Default::default() };
+    for effects in root.children("effects") {
+        importer.effects(effects, &mut base);
+    }
+    for modulators in root.children("modulators") {
+        importer.modulators(modulators, &mut base);
+    }
+    for groups in root.children("groups") {
+        importer.groups(groups, &base);
+    }
+
+    for element in &root.elements {
+        match element.name.as_str() {
+            "ui" | "tags" | "effects" | "modulators" | "groups" => {}
+            "midi" => importer.skip_element(element, "MIDI CC mappings have no AKP equivalent"),
+            _ => importer.skip_element(element, "no AKP equivalent"),
+        }
+    }
+
+    Ok(importer.finish())
+}
+
+fn invalid(reason: String) -> AkpError {
+    AkpError::InvalidPreset(FORMAT_NAME.to_string(), reason)
+}
+
+/// An XML attribute with the line of its element.
+#[derive(Debug, Clone)]
+struct Attribute {
+    name: String,
+    value: String,
+    line: usize,
+}
+
+/// Attributes in effect for one element, by name.
+type Attributes = BTreeMap<String, Attribute>;
+
+/// Just enough of an XML element tree for a preset.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    line: usize,
+    attributes: Vec<(String, String)>,
+    elements: Vec<Element>,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn attribute_map(&self) -> Attributes {
+        self.attributes.iter()
+            .map(|(name, value)| (name.clone(), Attribute { name: name.clone(), value: value.clone(), line: self.line }))
+            .collect()
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.elements.iter().filter(move |e| e.name == name)
+    }
+}
+
+/// Parse the XML into an element tree, keeping each element's line.
+fn parse_xml(xml: &str) -> Result<Element> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let line_at = |offset: usize| xml.as_bytes()[..offset.min(xml.len())].iter().filter(|&&b| b == b'\n').count() + 1;
+
+    // The root is the last element left on the stack
+    let mut stack = vec![Element::default()];
+    loop {
+        let position = reader.buffer_position() as usize;
+        let start = xml[position.min(xml.len())..].find('<').map_or(position, |i| position + i);
+        let event = reader.read_event().map_err(|e| {
+            invalid(format!("{e} at line {}", line_at(reader.error_position() as usize)))
+        })?;
+        match event {
+            Event::Start(tag) => stack.push(element(&tag, line_at(start))?),
+            Event::Empty(tag) => {
+                let element = element(&tag, line_at(start))?;
+                stack.last_mut().expect("stack holds the document").elements.push(element);
+            }
+            Event::End(_) => {
+                if stack.len() < 2 {
+                    return Err(invalid(format!("unexpected closing tag at line {}", line_at(start))));
+                }
+                let element = stack.pop().expect("checked above");
+                stack.last_mut().expect("checked above").elements.push(element);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let document = stack.pop().filter(|_| stack.is_empty()).ok_or_else(|| invalid("unclosed element".to_string()))?;
+    document.elements.into_iter().next().ok_or_else(|| invalid("no root element".to_string()))
+}
+
+fn element(tag: &BytesStart, line: usize) -> Result<Element> {
+    let mut attributes = Vec::new();
+    for attribute in tag.attributes() {
+        let attribute = attribute.map_err(|e| invalid(format!("{e} at line {line}")))?;
+        let value = attribute.unescape_value().map_err(|e| invalid(format!("{e} at line {line}")))?;
+        attributes.push((String::from_utf8_lossy(attribute.key.as_ref()).into_owned(), value.into_owned()));
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(tag.name().as_ref()).into_owned(),
+        line,
+        attributes,
+        elements: Vec::new(),
+    })
+}
+
+#[derive(Default)]
+struct Importer {
+    program: AkaiProgram,
+    skipped: Vec<SkippedOpcode>,
+    /// Written by this crate's DS writer.
+    own_output: bool,
+    /// UI control values by `parameterName`, for `$NAME` references.
+    controls: HashMap<String, String>,
+    /// Program-wide attributes: first value seen and its line.
+    program_values: HashMap<String, (String, usize)>,
+    /// Tag names used as mute groups, with the group number given to each.
+    mute_tags: Vec<(String, u8)>,
+    filter_velocity: bool,
+    modwheel_pan: bool,
+}
+
+impl Importer {
+    fn skip(&mut self, attribute: &Attribute, reason: impl Into<String>) {
+        let skipped = SkippedOpcode {
+            line: attribute.line,
+            opcode: attribute.name.clone(),
+            value: attribute.value.clone(),
+            reason: reason.into(),
+        };
+        // Group attributes are seen once per sample; report them once
+        if !self.skipped.contains(&skipped) {
+            self.skipped.push(skipped);
+        }
+    }
+
+    fn skip_element(&mut self, element: &Element, reason: &str) {
+        let attribute = Attribute { name: format!("<{}>", element.name), value: String::new(), line: element.line };
+        self.skip(&attribute, reason);
+    }
+
+    fn skip_all(&mut self, attributes: Attributes) {
+        for attribute in attributes.into_values() {
+            self.skip(&attribute, "no AKP equivalent");
+        }
+    }
+
+    fn read_controls(&mut self, element: &Element) {
+        if let (Some(name), Some(value)) = (element.attribute("parameterName"), element.attribute("value")) {
+            self.controls.entry(name.to_string()).or_insert_with(|| value.to_string());
+        }
+        for child in &element.elements {
+            self.read_controls(child);
+        }
+    }
+
+    /// Parse a number, resolving `$NAME` from the UI and reporting anything
+    /// that isn't one.
+    fn number(&mut self, attribute: &Attribute) -> Option<f32> {
+        let value = match attribute.value.strip_prefix('$') {
+            Some(name) => match self.controls.get(name) {
+                Some(value) => value.clone(),
+                None => {
+                    self.skip(attribute, "no UI control with this parameterName");
+                    return None;
+                }
+            },
+            None => attribute.value.clone(),
+        };
+        match value.trim().parse::<f32>() {
+            Ok(value) if value.is_finite() => Some(value),
+            _ => {
+                self.skip(attribute, "not a number");
+                None
+            }
+        }
+    }
+
+    /// Round a converted value into an AKP range, reporting it if it had to be clamped.
+    fn fit(&mut self, attribute: &Attribute, value: f32, min: i32, max: i32) -> i32 {
+        let rounded = if value.is_nan() { min } else { value.round().clamp(min as f32, max as f32) as i32 };
+        if value.is_nan() || value.round() < min as f32 || value.round() > max as f32 {
+            self.skip(attribute, format!("outside the AKP range, clamped to {rounded}"));
+        }
+        rounded
+    }
+
+    fn take_fit(&mut self, attributes: &mut Attributes, name: &str, min: i32, max: i32, convert: impl Fn(f32) -> f32) -> Option<i32> {
+        let attribute = attributes.remove(name)?;
+        let value = self.number(&attribute)?;
+        Some(self.fit(&attribute, convert(value), min, max))
+    }
+
+    /// Take a program-wide attribute. Only the first sample that sets it
+    /// applies; later samples with a different value are reported.
+    fn take_program(&mut self, attributes: &mut Attributes, name: &str) -> Option<Attribute> {
+        let attribute = attributes.remove(name)?;
+        match self.program_values.get(name) {
+            None => {
+                self.program_values.insert(name.to_string(), (attribute.value.clone(), attribute.line));
+                Some(attribute)
+            }
+            Some((value, _)) if *value == attribute.value => None,
+            Some((value, line)) => {
+                let reason = format!("AKP stores one value per program; using {value} from line {line}");
+                self.skip(&attribute, reason);
+                None
+            }
+        }
+    }
+
+    /// A volume in dB. DS reads a plain number as linear gain and `-6dB` as
+    /// dB; our writer leaves off the unit.
+    fn volume_db(&mut self, attribute: &Attribute) -> Option<f32> {
+        let value = attribute.value.trim();
+        match value.strip_suffix("dB").or_else(|| value.strip_suffix("db")) {
+            Some(db) => {
+                let attribute = Attribute { value: db.trim().to_string(), ..attribute.clone() };
+                self.number(&attribute)
+            }
+            None if self.own_output => self.number(attribute),
+            None => self.number(attribute).map(|gain| 20.0 * gain.log10()),
+        }
+    }
+
+    /// Attack, decay, sustain and release on the AKP 0-100 scales.
+    fn envelope(&mut self, attributes: &mut Attributes) -> [Option<u8>; 4] {
+        let own_output = self.own_output;
+        let zero_marker = move |raw: fn(f32) -> f32| {
+            move |seconds: f32| {
+                if own_output && (seconds - ZERO_TIME_MARKER).abs() < 0.0005 { 0.0 } else { raw(seconds) }
+            }
+        };
+        [
+            self.take_fit(attributes, "attack", 0, 100, envelope_time_to_raw),
+            self.take_fit(attributes, "decay", 0, 100, zero_marker(envelope_time_to_raw)),
+            self.take_fit(attributes, "sustain", 0, 100, |level| level * 100.0),
+            self.take_fit(attributes, "release", 0, 100, zero_marker(release_time_to_raw)),
+        ]
+        .map(|value| value.map(|v| v as u8))
+    }
+
+    /// Filter effects become filters 1-3, in order.
+    fn effects(&mut self, effects: &Element, base: &mut Keygroup) {
+        let mut filters = Vec::new();
+        for effect in &effects.elements {
+            let mut attributes = effect.attribute_map();
+            let kind = match effect.name.as_str() {
+                "effect" => attributes.remove("type").map_or_else(String::new, |a| a.value),
+                name => name.to_string(),
+            };
+            let Some((filter_type, exact)) = filter_type(&kind) else {
+                self.skip_element(effect, "no AKP equivalent");
+                continue;
+            };
+            if filters.len() == MAX_FILTERS {
+                self.skip_element(effect, "AKP keygroups have at most three filters");
+                continue;
+            }
+            if !exact {
+                let name = Filter { filter_type, ..Default::default() }.ds_effect_type();
+                self.skip_element(effect, &format!("approximated as {name}"));
+            }
+
+            let mut filter = Filter { filter_type, ..Default::default() };
+            if let Some(v) = self.take_fit(&mut attributes, "frequency", 0, 100, Filter::cutoff_from_hz) {
+                filter.cutoff = v as u8;
+            }
+            if let Some(v) = self.take_fit(&mut attributes, "resonance", 0, 12, Filter::resonance_from_db) {
+                filter.resonance = v as u8;
+            }
+            self.skip_all(attributes);
+            filters.push(filter);
+        }
+
+        if filters.len() > 1 {
+            self.program.model = ProgramModel::ZSeries;
+        }
+        let mut filters = filters.into_iter();
+        base.filter = filters.next();
+        base.filter2 = filters.next();
+        base.filter3 = filters.next();
+    }
+
+    /// LFOs, the filter envelope, velocity → cutoff and mod wheel → pan.
+    fn modulators(&mut self, modulators: &Element, base: &mut Keygroup) {
+        for modulator in &modulators.elements {
+            let mut attributes = modulator.attribute_map();
+            let target = modulator.children("binding").next().and_then(|b| b.attribute("parameter"));
+            match (modulator.name.as_str(), target) {
+                ("lfo", _) => {
+                    if self.program.lfo1.is_some() && self.program.lfo2.is_some() {
+                        self.skip_element(modulator, "AKP programs have two LFOs");
+                        continue;
+                    }
+                    let mut lfo = Lfo::default();
+                    if let Some(v) = self.take_fit(&mut attributes, "frequency", 0, 100, Lfo::rate_from_hz) {
+                        lfo.rate = v as u8;
+                    }
+                    for name in ["waveform", "shape"] {
+                        let Some(attribute) = attributes.remove(name) else { continue };
+                        match lfo_waveform(&attribute.value) {
+                            Some(waveform) => lfo.waveform = waveform,
+                            None => self.skip(&attribute, "no matching AKP LFO waveform"),
+                        }
+                    }
+                    for name in ["amount", "modAmount"] {
+                        if let Some(v) = self.take_fit(&mut attributes, name, 0, 100, |amount| amount * 100.0) {
+                            lfo.depth = v as u8;
+                        }
+                    }
+                    // Our writer names the target; AKP LFOs reach it through the matrix
+                    attributes.remove("target");
+                    attributes.remove("scope");
+                    self.skip_all(attributes);
+                    if self.program.lfo1.is_none() {
+                        self.program.lfo1 = Some(lfo);
+                    } else {
+                        self.program.lfo2 = Some(lfo);
+                    }
+                }
+                ("envelope", Some("FX_FILTER_FREQUENCY")) => {
+                    let [attack, decay, sustain, release] = self.envelope(&mut attributes);
+                    let mut env = FilterEnvelope {
+                        attack: attack.unwrap_or(0),
+                        decay: decay.unwrap_or(0),
+                        sustain: sustain.unwrap_or(100),
+                        release: release.unwrap_or(0),
+                        ..Default::default()
+                    };
+                    if let Some(v) = self.take_fit(&mut attributes, "modAmount", -100, 100, |amount| amount * 100.0) {
+                        env.depth = v as i8;
+                    }
+                    attributes.remove("scope");
+                    self.skip_all(attributes);
+                    base.filter_env = Some(env);
+                }
+                ("velocity", Some("FX_FILTER_FREQUENCY")) => {
+                    if let Some(v) = self.take_fit(&mut attributes, "modAmount", -100, 100, |amount| amount * 100.0) {
+                        base.filter.get_or_insert_with(Filter::default).mod_input_1 = v as i8;
+                        self.filter_velocity = true;
+                    }
+                    self.skip_all(attributes);
+                }
+                ("cc", Some("PAN")) if modulator.attribute("number") == Some("1") => {
+                    attributes.remove("number");
+                    if let Some(v) = self.take_fit(&mut attributes, "modAmount", 0, 100, |amount| amount * 100.0) {
+                        self.program.output.get_or_insert_with(ProgramOutput::default).pan_mod_3 = v as u8;
+                        self.modwheel_pan = true;
+                    }
+                    self.skip_all(attributes);
+                }
+                _ => self.skip_element(modulator, "no matching AKP modulation route"),
+            }
+        }
+    }
+
+    fn groups(&mut self, groups: &Element, base: &Keygroup) {
+        let mut inherited = groups.attribute_map();
+        // On <groups>, volume is the program's; on a group or sample it's the zone level
+        let mut loudness = 100;
+        if let Some(attribute) = inherited.remove("volume") {
+            if let Some(db) = self.volume_db(&attribute) {
+                loudness = self.fit(&attribute, ProgramOutput::loudness_from_db(db), 0, 100) as u8;
+            }
+        }
+        self.program.output.get_or_insert_with(ProgramOutput::default).loudness = loudness;
+
+        for group in &groups.elements {
+            if group.name != "group" {
+                self.skip_element(group, "no AKP equivalent");
+                continue;
+            }
+            let mut group_attributes = inherited.clone();
+            group_attributes.extend(group.attribute_map());
+            // AKP keygroups have no names
+            group_attributes.remove("name");
+
+            for sample in &group.elements {
+                if sample.name != "sample" {
+                    self.skip_element(sample, "no AKP equivalent");
+                    continue;
+                }
+                let mut attributes = group_attributes.clone();
+                attributes.extend(sample.attribute_map());
+                let Some(path) = attributes.remove("path") else {
+                    self.skip_element(sample, "sample has no path, skipped");
+                    continue;
+                };
+                let (keygroup, zone) = self.sample(path, &mut attributes, base);
+                self.skip_all(attributes);
+                self.program.add_zone(&keygroup, zone);
+            }
+        }
+    }
+
+    /// Convert one sample into its keygroup settings (no zones) and its zone.
+    fn sample(&mut self, path: Attribute, attributes: &mut Attributes, base: &Keygroup) -> (Keygroup, Zone) {
+        let mut keygroup = base.clone();
+        let mut zone = Zone {
+            sample_name: path.value.replace('\\', "/"),
+            root_key: Some(DEFAULT_ROOT_KEY),
+            ..Default::default()
+        };
+
+        if let Some(v) = self.take_fit(attributes, "loNote", 0, 127, |v| v) {
+            keygroup.low_key = v as u8;
+        }
+        if let Some(v) = self.take_fit(attributes, "hiNote", 0, 127, |v| v) {
+            keygroup.high_key = v as u8;
+        }
+        if let Some(v) = self.take_fit(attributes, "rootNote", 0, 127, |v| v) {
+            zone.root_key = Some(v as u8);
+        }
+        if let Some(v) = self.take_fit(attributes, "loVel", 0, 127, |v| v) {
+            zone.low_vel = v as u8;
+        }
+        if let Some(v) = self.take_fit(attributes, "hiVel", 0, 127, |v| v) {
+            zone.high_vel = v as u8;
+        }
+
+        // DS tuning is in semitones and may be fractional; our writer adds fineTuning in cents
+        let semitones = attributes.remove("tuning").and_then(|a| self.number(&a).map(|v| (a, v)));
+        let cents = attributes.remove("fineTuning").and_then(|a| self.number(&a).map(|v| (a, v)));
+        if semitones.is_some() || cents.is_some() {
+            let total = semitones.as_ref().map_or(0.0, |(_, v)| v * 100.0) + cents.as_ref().map_or(0.0, |(_, v)| *v);
+            let semitone = (total / 100.0).round();
+            let attribute = semitones.map_or_else(|| cents.unwrap().0, |(a, _)| a);
+            zone.semitone_tune = self.fit(&attribute, semitone, -36, 36) as i8;
+            zone.fine_tune = (total - semitone * 100.0).round().clamp(-50.0, 50.0) as i8;
+        }
+
+        if let Some(attribute) = attributes.remove("volume") {
+            if let Some(db) = self.volume_db(&attribute) {
+                zone.level = self.fit(&attribute, db, -50, 50) as i8;
+            }
+        }
+        // DS pan is -100 to 100, AKP -50 to 50
+        if let Some(v) = self.take_fit(attributes, "pan", -50, 50, |pan| pan / 2.0) {
+            zone.pan = v as i8;
+        }
+
+        self.sample_loop(attributes, &mut zone);
+        self.mute_group(attributes, &mut keygroup);
+
+        if let Some(attribute) = self.take_program(attributes, "ampVelTrack") {
+            if let Some(value) = self.number(&attribute) {
+                let sensitivity = self.fit(&attribute, value * 100.0, 0, 100) as i8;
+                self.program.output.get_or_insert_with(ProgramOutput::default).velocity_sensitivity = sensitivity;
+            }
+        }
+
+        // DS defaults: instant attack, full sustain, short release
+        let [attack, decay, sustain, release] = self.envelope(attributes);
+        keygroup.amp_env = Some(Envelope {
+            attack: attack.unwrap_or(0),
+            decay: decay.unwrap_or(0),
+            sustain: sustain.unwrap_or(100),
+            release: release.unwrap_or(0),
+            ..Default::default()
+        });
+
+        (keygroup, zone)
+    }
+
+    fn sample_loop(&mut self, attributes: &mut Attributes, zone: &mut Zone) {
+        if let Some(attribute) = attributes.remove("loopEnabled") {
+            match attribute.value.as_str() {
+                "true" => zone.playback = 2,
+                "false" => zone.playback = 0,
+                _ => self.skip(&attribute, "expected true or false"),
+            }
+        }
+        let start = self.take_fit(attributes, "loopStart", 0, i32::MAX, |v| v);
+        let end = self.take_fit(attributes, "loopEnd", 0, i32::MAX, |v| v);
+        let crossfade = self.take_fit(attributes, "loopCrossfade", 0, i32::MAX, |v| v);
+        if let (Some(start), Some(end)) = (start, end) {
+            zone.sample_loop = Some(SampleLoop { start: start as u32, end: end as u32, crossfade: crossfade.unwrap_or(0) as u32 });
+        }
+    }
+
+    /// A sample silenced by one of its own tags is in an AKP mute group.
+    /// Our writer's `muteN` tags keep their number; other tags get the next
+    /// free one.
+    fn mute_group(&mut self, attributes: &mut Attributes, keygroup: &mut Keygroup) {
+        let tags = attributes.remove("tags");
+        let silenced_by = attributes.remove("silencedByTags");
+        let mode = attributes.remove("silencingMode");
+        let Some(silenced_by) = silenced_by else {
+            if let Some(tags) = tags {
+                self.skip(&tags, "tags only map to AKP mute groups");
+            }
+            return;
+        };
+
+        let own_tag = tags.as_ref().is_some_and(|tags| {
+            tags.value.split(',').any(|tag| tag.trim() == silenced_by.value.trim())
+        });
+        if !own_tag || silenced_by.value.contains(',') {
+            self.skip(&silenced_by, "AKP mute groups only choke their own group");
+            return;
+        }
+
+        let tag = silenced_by.value.trim();
+        let number = match self.mute_tags.iter().find(|(name, _)| name == tag) {
+            Some(&(_, number)) => Some(number),
+            None => {
+                let number = tag.strip_prefix("mute").and_then(|n| n.parse::<u8>().ok())
+                    .filter(|n| (1..=MAX_MUTE_GROUP).contains(n))
+                    .or_else(|| (1..=MAX_MUTE_GROUP).find(|n| !self.mute_tags.iter().any(|(_, used)| used == n)));
+                if let Some(number) = number {
+                    self.mute_tags.push((tag.to_string(), number));
+                }
+                number
+            }
+        };
+        match number {
+            Some(number) => keygroup.mute_group = number,
+            None => self.skip(&silenced_by, "AKP programs have 32 mute groups"),
+        }
+        if let Some(mode) = mode.filter(|m| m.value != "fast") {
+            self.skip(&mode, "AKP mute groups cut notes off immediately");
+        }
+    }
+
+    fn finish(mut self) -> DsImport {
+        self.program.header = Some(ProgramHeader {
+            midi_program_number: 0,
+            number_of_keygroups: self.program.keygroups.len().min(u8::MAX as usize) as u8,
+        });
+        let output = self.program.output.get_or_insert_with(ProgramOutput::default);
+        if !self.program_values.contains_key("ampVelTrack") {
+            // DS default ampVelTrack
+            output.velocity_sensitivity = 100;
+        }
+        if self.filter_velocity || self.modwheel_pan {
+            let mut modulation = ProgramModulation::default();
+            if self.filter_velocity {
+                modulation.filter_mod_1_source = VELOCITY_SOURCE;
+            }
+            if self.modwheel_pan {
+                modulation.pan_mod_3_source = MODWHEEL_SOURCE;
+            }
+            self.program.modulation = Some(modulation);
+        }
+        self.skipped.sort_by_key(|s| s.line);
+        DsImport { program: self.program, skipped: self.skipped }
+    }
+}
+
+/// AKP filter type for a DS filter effect, and whether it's an exact match.
+fn filter_type(effect: &str) -> Option<(u8, bool)> {
+    match effect {
+        "lowpass" => Some((0, true)),
+        "lowpass_4pl" => Some((1, true)),
+        "lowpass_1pl" => Some((0, false)),
+        "bandpass" => Some((3, true)),
+        "highpass" => Some((7, true)),
+        "notch" => Some((12, true)),
+        "peak" => Some((17, true)),
+        _ => None,
+    }
+}
+
+/// AKP LFO waveform for a DS `waveform`/`shape`, as our writer names them.
+fn lfo_waveform(value: &str) -> Option<u8> {
+    match value {
+        "sine" => Some(0),
+        "triangle" => Some(1),
+        "square" => Some(2),
+        "saw" => Some(6),
+        "random" => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reasons(import: &DsImport) -> Vec<String> {
+        import.skipped.iter().map(|s| format!("{} {}", s.opcode, s.reason)).collect()
+    }
+
+    const PRESET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DecentSampler>
+  <ui>
+    <tab>
+      <labeled-knob parameterName="CUTOFF" value="1000" />
+    </tab>
+  </ui>
+  <groups volume="-6dB" attack="0.02">
+    <group name="Soft" hiVel="63" tags="hat" silencedByTags="hat">
+      <sample path="Samples\Hat C2.wav" loNote="36" hiNote="36" rootNote="36" />
+      <sample path="Samples/Hat D2.wav" loNote="36" hiNote="36" pan="-40" tuning="1.25" />
+    </group>
+    <group release="0.1" ampVelTrack="0.5">
+      <sample path="a.wav" loNote="40" hiNote="50" loVel="64" volume="0.5" seqMode="random" />
+      <sample path="b.wav" loNote="40" hiNote="50" loopEnabled="true" loopStart="100" loopEnd="2000" />
+      <sample path="c.wav" ampVelTrack="1" />
+    </group>
+  </groups>
+  <effects>
+    <effect type="lowpass" frequency="$CUTOFF" resonance="10" />
+    <reverb wetLevel="0.3" />
+  </effects>
+  <modulators>
+    <lfo shape="triangle" frequency="2" modAmount="0.5">
+      <binding type="effect" parameter="FX_FILTER_FREQUENCY" />
+    </lfo>
+    <velocity modAmount="0.4">
+      <binding type="effect" parameter="FX_FILTER_FREQUENCY" />
+    </velocity>
+    <cc number="74" modAmount="1">
+      <binding type="effect" parameter="FX_FILTER_FREQUENCY" />
+    </cc>
+  </modulators>
+</DecentSampler>
+"#;
+
+    #[test]
+    fn test_samples_become_zones() {
+        let import = parse_dspreset(PRESET).unwrap();
+        let program = &import.program;
+        assert_eq!(program.keygroups.len(), 3);
+
+        let hats = &program.keygroups[0];
+        assert_eq!((hats.low_key, hats.high_key, hats.mute_group), (36, 36, 1));
+        assert_eq!(hats.zones.len(), 2);
+        assert_eq!(hats.zones[0].sample_name, "Samples/Hat C2.wav");
+        assert_eq!((hats.zones[0].root_key, hats.zones[0].high_vel), (Some(36), 63));
+        assert_eq!((hats.zones[1].root_key, hats.zones[1].pan), (Some(60), -20));
+        assert_eq!((hats.zones[1].semitone_tune, hats.zones[1].fine_tune), (1, 25));
+        assert_eq!(hats.amp_env.as_ref().unwrap().attack, envelope_time_to_raw(0.02).round() as u8);
+
+        let layers = &program.keygroups[1];
+        assert_eq!((layers.low_key, layers.high_key), (40, 50));
+        assert_eq!(layers.zones[0].low_vel, 64);
+        assert_eq!(layers.zones[0].level, -6);
+        assert_eq!(layers.zones[1].playback, 2);
+        assert_eq!(layers.zones[1].sample_loop, Some(SampleLoop { start: 100, end: 2000, crossfade: 0 }));
+        // Only our own presets use 0.1 s to mean zero
+        assert_eq!(layers.amp_env.as_ref().unwrap().release, 92);
+        assert_eq!((program.keygroups[2].low_key, program.keygroups[2].high_key), (0, 127));
+
+        let output = program.output.as_ref().unwrap();
+        assert_eq!((output.loudness, output.velocity_sensitivity), (50, 50));
+    }
+
+    #[test]
+    fn test_effects_and_modulators() {
+        let import = parse_dspreset(PRESET).unwrap();
+        let program = &import.program;
+        for keygroup in &program.keygroups {
+            let filter = keygroup.filter.as_ref().unwrap();
+            assert_eq!(filter.cutoff, Filter::cutoff_from_hz(1000.0).round() as u8);
+            assert_eq!((filter.filter_type, filter.resonance, filter.mod_input_1), (0, 3, 40));
+        }
+        let lfo = program.lfo1.as_ref().unwrap();
+        assert_eq!((lfo.waveform, lfo.depth, lfo.rate), (1, 50, Lfo::rate_from_hz(2.0).round() as u8));
+        assert_eq!(program.modulation.as_ref().unwrap().filter_mod_1_source, VELOCITY_SOURCE);
+    }
+
+    #[test]
+    fn test_unrepresentable_attributes_reported() {
+        let import = parse_dspreset(PRESET).unwrap();
+        assert_eq!(
+            reasons(&import),
+            vec![
+                "seqMode no AKP equivalent",
+                "ampVelTrack AKP stores one value per program; using 0.5 from line 13",
+                "<reverb> no AKP equivalent",
+                "<cc> no matching AKP modulation route",
+            ]
+        );
+        assert_eq!(import.skipped[1].line, 16);
+    }
+
+    #[test]
+    fn test_invalid_presets() {
+        let error = parse_dspreset("<SamplerPreset />").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid Decent Sampler file: expected <DecentSampler>, found <SamplerPreset>");
+        assert!(parse_dspreset("<DecentSampler>\n<groups>\n</group>").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_through_ds_writer() {
+        let mut original = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 85, velocity_sensitivity: 40, pan_mod_3: 30, ..Default::default() }),
+            lfo1: Some(Lfo { waveform: 2, rate: 60, depth: 30, ..Default::default() }),
+            modulation: Some(ProgramModulation {
+                filter_mod_1_source: VELOCITY_SOURCE,
+                pan_mod_3_source: MODWHEEL_SOURCE,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let keygroup = |low_key, high_key, zones| Keygroup {
+            low_key,
+            high_key,
+            mute_group: 2,
+            amp_env: Some(Envelope { attack: 60, decay: 0, sustain: 70, release: 0, ..Default::default() }),
+            filter: Some(Filter { mod_input_1: 25, ..Default::default() }),
+            filter2: Some(Filter { filter_type: 7, cutoff: 40, resonance: 6, ..Default::default() }),
+            filter_env: Some(FilterEnvelope { attack: 90, decay: 100, sustain: 50, release: 80, depth: 60, ..Default::default() }),
+            zones,
+            ..Default::default()
+        };
+        let zone = |name: &str, low_vel, high_vel| Zone {
+            sample_name: name.to_string(),
+            low_vel,
+            high_vel,
+            semitone_tune: -2,
+            fine_tune: 10,
+            pan: 20,
+            root_key: Some(48),
+            ..Default::default()
+        };
+        original.keygroups = vec![
+            keygroup(36, 47, vec![zone("low soft.wav", 0, 63), zone("low loud.wav", 64, 127)]),
+            keygroup(48, 60, vec![zone("high.wav", 0, 127)]),
+        ];
+
+        let import = parse_dspreset(&original.to_dspreset_string()).unwrap();
+        let program = &import.program;
+        assert_eq!(program.model, ProgramModel::ZSeries);
+        assert_eq!(program.output, original.output);
+        assert_eq!(program.lfo1, original.lfo1);
+        assert_eq!(program.modulation, original.modulation);
+        assert_eq!(program.keygroups, original.keygroups);
+        // The writer's fixed MIDI mappings and reverb have no AKP equivalent
+        assert_eq!(reasons(&import), vec!["<reverb> no AKP equivalent", "<midi> MIDI CC mappings have no AKP equivalent"]);
+    }
+}
@@ -14,6 +14,76 @@ pub enum AkpError {
     InvalidVelocityRange(u8, u8),
     MissingRequiredChunk(String),
     InvalidParameterValue(String, u8),
+    InvalidDiskImage(String),
+    UnwritableProgram(String),
+    InvalidPreset(String, String),
+    UnsupportedFormat(String),
+    /// Another error, raised while parsing the chunk at `ChunkLocation`.
+    Located(ChunkLocation, Box<AkpError>),
+}
+
+/// Where a chunk sits in an AKP file: its path from the top level, e.g.
+/// `kgrp[3]/zone[2]`, and the absolute offset of its header. Repeated
+/// chunks are numbered from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkLocation {
+    pub path: String,
+    pub offset: u64,
+}
+
+impl fmt::Display for ChunkLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {} (0x{:X})", self.path, self.offset, self.offset)
+    }
+}
+
+impl AkpError {
+    /// A short, stable code for the kind of error, e.g. `corrupted-chunk`,
+    /// for grouping failures in reports. Located errors take the code of
+    /// the error they wrap.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AkpError::Io(_) => "io",
+            AkpError::InvalidRiffHeader => "invalid-riff-header",
+            AkpError::InvalidAprgSignature => "invalid-aprg-signature",
+            AkpError::UnknownChunkType(_) => "unknown-chunk",
+            AkpError::InvalidChunkSize(..) => "invalid-chunk-size",
+            AkpError::CorruptedChunk(..) => "corrupted-chunk",
+            AkpError::InvalidKeyRange(..) => "invalid-key-range",
+            AkpError::InvalidVelocityRange(..) => "invalid-velocity-range",
+            AkpError::MissingRequiredChunk(_) => "missing-chunk",
+            AkpError::InvalidParameterValue(..) => "invalid-parameter",
+            AkpError::InvalidDiskImage(_) => "invalid-disk-image",
+            AkpError::UnwritableProgram(_) => "unwritable-program",
+            AkpError::InvalidPreset(..) => "invalid-preset",
+            AkpError::UnsupportedFormat(_) => "unsupported-format",
+            AkpError::Located(_, error) => error.code(),
+        }
+    }
+
+    /// Where in the file the error was raised, if known.
+    pub fn location(&self) -> Option<&ChunkLocation> {
+        match self {
+            AkpError::Located(location, _) => Some(location),
+            _ => None,
+        }
+    }
+
+    /// The error without its location.
+    pub fn inner(&self) -> &AkpError {
+        match self {
+            AkpError::Located(_, error) => error.inner(),
+            error => error,
+        }
+    }
+
+    /// Attach a location, unless a more specific one is already attached.
+    pub(crate) fn at(self, path: &str, offset: u64) -> AkpError {
+        match self {
+            AkpError::Located(..) => self,
+            error => AkpError::Located(ChunkLocation { path: path.to_string(), offset }, Box::new(error)),
+        }
+    }
 }
 
 impl fmt::Display for AkpError {
@@ -29,6 +99,11 @@ impl fmt::Display for AkpError {
             AkpError::InvalidVelocityRange(low, high) => write!(f, "Invalid velocity range: low_vel ({low}) must be <= high_vel ({high})"),
             AkpError::MissingRequiredChunk(chunk) => write!(f, "Missing required '{chunk}' chunk"),
             AkpError::InvalidParameterValue(param, value) => write!(f, "Invalid value {value} for parameter '{param}'"),
+            AkpError::InvalidDiskImage(reason) => write!(f, "Invalid disk image: {reason}"),
+            AkpError::UnwritableProgram(reason) => write!(f, "Cannot write AKP program: {reason}"),
+            AkpError::InvalidPreset(format, reason) => write!(f, "Invalid {format} file: {reason}"),
+            AkpError::UnsupportedFormat(reason) => write!(f, "Unsupported format: {reason}"),
+            AkpError::Located(location, error) => write!(f, "{error} in {location}"),
         }
     }
 }
@@ -61,4 +136,21 @@ mod tests {
             "Invalid file format: Expected RIFF header but found different signature"
         );
     }
+
+    #[test]
+    fn test_located_error_keeps_code_and_innermost_location() {
+        let error = AkpError::CorruptedChunk("zone".to_string(), "truncated".to_string())
+            .at("kgrp[3]/zone[2]", 1234)
+            .at("kgrp[3]", 1000);
+
+        assert_eq!(error.code(), "corrupted-chunk");
+        assert!(matches!(error.inner(), AkpError::CorruptedChunk(..)));
+        let location = error.location().unwrap();
+        assert_eq!(location.path, "kgrp[3]/zone[2]");
+        assert_eq!(location.offset, 1234);
+        assert_eq!(
+            error.to_string(),
+            "Corrupted 'zone' chunk: truncated in kgrp[3]/zone[2] at offset 1234 (0x4D2)"
+        );
+    }
 }
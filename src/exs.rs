@@ -0,0 +1,439 @@
+//! Logic Pro / MainStage EXS24 instrument (`.exs`) output, also read by
+//! Logic's Sampler.
+//!
+//! An EXS file is a flat list of chunks, each an 84-byte header (type, data
+//! size, index, `TBOS` magic and a 64-byte name) followed by its data: one
+//! instrument header, then the zones, groups, samples and a parameter
+//! chunk. The layout follows ConvertWithMoss's EXS24 notes; everything is
+//! little-endian.
+//!
+//! Each AKP keygroup becomes a group and each zone an EXS zone. EXS24's
+//! envelopes and filter are instrument-wide parameters, so they come from
+//! the first keygroup that has each. Samples are referenced by folder and
+//! file name, with their length and format read through `samples.rs`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::samples::{find_wav_info, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions, Filter};
+
+/// Chunk types, with the `0x0101` marker EXS24 puts in every chunk.
+const INSTRUMENT_CHUNK: u32 = 0x0000_0101;
+const ZONE_CHUNK: u32 = 0x0100_0101;
+const GROUP_CHUNK: u32 = 0x0200_0101;
+const SAMPLE_CHUNK: u32 = 0x0300_0101;
+const PARAM_CHUNK: u32 = 0x0400_0101;
+
+const MAGIC: &[u8; 4] = b"TBOS";
+const NAME_LEN: usize = 64;
+const PATH_LEN: usize = 256;
+
+const INSTRUMENT_SIZE: usize = 40;
+const ZONE_SIZE: usize = 104;
+const GROUP_SIZE: usize = 168;
+const SAMPLE_SIZE: usize = 592;
+
+/// Zone option bits.
+const ZONE_ONE_SHOT: u8 = 1 << 0;
+const ZONE_PITCH_OFF: u8 = 1 << 1;
+const ZONE_VELOCITY_RANGE: u8 = 1 << 3;
+/// Loop option bits.
+const LOOP_ON: u8 = 1 << 0;
+const LOOP_PLAY_TO_END_ON_RELEASE: u8 = 1 << 2;
+
+/// Parameter IDs written to the parameter chunk.
+mod param {
+    pub const PITCH_BEND_UP: u8 = 3;
+    pub const PITCH_BEND_DOWN: u8 = 4;
+    pub const MASTER_VOLUME: u8 = 7;
+    pub const COARSE_TUNE: u8 = 14;
+    pub const FINE_TUNE: u8 = 15;
+    pub const FILTER1_RESO: u8 = 29;
+    pub const FILTER1_CUTOFF: u8 = 30;
+    pub const FILTER1_TOGGLE: u8 = 44;
+    pub const ENV1_ATTACK: u8 = 76;
+    pub const ENV1_DECAY: u8 = 78;
+    pub const ENV1_SUSTAIN: u8 = 79;
+    pub const ENV1_RELEASE: u8 = 80;
+    pub const ENV2_ATTACK: u8 = 82;
+    pub const ENV2_DECAY: u8 = 84;
+    pub const ENV2_SUSTAIN: u8 = 85;
+    pub const ENV2_RELEASE: u8 = 86;
+    pub const FILTER1_TYPE: u8 = 170;
+}
+const PARAM_SLOTS: usize = 100;
+
+/// EXS24 filter types.
+const FILTER_LP_24: i16 = 0;
+const FILTER_LP_12: i16 = 2;
+const FILTER_HP_12: i16 = 4;
+const FILTER_BP: i16 = 5;
+
+/// An `.exs` file and the samples it couldn't reference.
+#[derive(Debug, Clone)]
+pub struct ExsOutput {
+    pub bytes: Vec<u8>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample found on disk, by folder and file name.
+struct SampleRef {
+    index: u32,
+    folder: String,
+    file_name: String,
+    info: WavInfo,
+}
+
+impl AkaiProgram {
+    /// Build an EXS24 instrument named `name`, reading samples from
+    /// `sample_dir`. Zones whose sample can't be read are left out and
+    /// listed in the output; if none can be read this fails.
+    pub fn to_exs(&self, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<ExsOutput> {
+        let mut samples: LoadedSamples<SampleRef> = LoadedSamples::new();
+        let mut sample_chunks: Vec<Vec<u8>> = Vec::new();
+        // Names that resolve to the same file share one sample chunk
+        let mut sample_indices: HashMap<String, u32> = HashMap::new();
+        let mut zone_chunks: Vec<Vec<u8>> = Vec::new();
+
+        for range in self.key_layout(true, options.drum_kit_layout) {
+            let keygroup = range.keygroup;
+            let group = self.keygroups.iter().position(|kg| std::ptr::eq(kg, keygroup)).unwrap_or(0);
+            for zone in &keygroup.zones {
+                if zone.sample_name.is_empty() {
+                    continue;
+                }
+                let sample = samples.load(&zone.sample_name, |_| {
+                    let mut sample = find_sample_ref(sample_dir, &zone.sample_name, sample_chunks.len() as u32)?;
+                    let path = format!("{}/{}", sample.folder, sample.file_name);
+                    match sample_indices.get(&path) {
+                        Some(&index) => sample.index = index,
+                        None => {
+                            sample_indices.insert(path, sample.index);
+                            sample_chunks.push(sample_chunk(&sample));
+                        }
+                    }
+                    Ok(sample)
+                });
+                let Some(sample) = sample else { continue };
+
+                // Whole semitones go to coarse tune and the rest to fine (±50 cents)
+                let cents = (keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                    + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                    + zone.root_tune as i32 + range.detune as i32;
+                let coarse = (cents as f32 / 100.0).round() as i32;
+
+                let mut data = vec![0u8; ZONE_SIZE];
+                data[0] = ZONE_VELOCITY_RANGE
+                    | if zone.keyboard_track == 0 { ZONE_PITCH_OFF } else { 0 }
+                    | if zone.playback == 1 { ZONE_ONE_SHOT } else { 0 };
+                data[1] = zone.root_key.or(sample.info.unity_note).unwrap_or(DEFAULT_ROOT_KEY);
+                data[2] = (cents - coarse * 100) as i8 as u8;
+                data[3] = zone.pan as u8;
+                data[4] = zone.level as u8;
+                data[6] = range.low_key;
+                data[7] = range.high_key;
+                data[9] = zone.low_vel.max(1);
+                data[10] = zone.high_vel;
+                put_u32(&mut data, 16, sample.info.frames);
+
+                // LOOP carries on looping through the release; LOOP UNTIL RELEASE plays out
+                if let Some(sample_loop) = zone.sample_loop.or(sample.info.sample_loop).filter(|_| zone.loop_enabled()) {
+                    put_u32(&mut data, 20, sample_loop.start);
+                    put_u32(&mut data, 24, sample_loop.end + 1);
+                    put_u32(&mut data, 28, sample_loop.crossfade);
+                    data[33] = LOOP_ON | if zone.playback == 3 { LOOP_PLAY_TO_END_ON_RELEASE } else { 0 };
+                }
+                data[80] = coarse.clamp(-128, 127) as i8 as u8;
+                put_u32(&mut data, 88, group as u32);
+                put_u32(&mut data, 92, sample.index);
+
+                let zone_name = Path::new(&zone.sample_name.replace('\\', "/"))
+                    .file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                zone_chunks.push(chunk(ZONE_CHUNK, zone_chunks.len() as u32, &zone_name, &data));
+            }
+        }
+
+        let (_, missing_samples) = samples.finish(sample_dir)?;
+
+        let mut instrument = vec![0u8; INSTRUMENT_SIZE];
+        put_u32(&mut instrument, 0, 0x0100);
+        put_u32(&mut instrument, 4, zone_chunks.len() as u32);
+        put_u32(&mut instrument, 8, self.keygroups.len() as u32);
+        put_u32(&mut instrument, 12, sample_chunks.len() as u32);
+
+        let mut bytes = chunk(INSTRUMENT_CHUNK, 0, name, &instrument);
+        zone_chunks.iter().for_each(|c| bytes.extend_from_slice(c));
+        for (index, keygroup) in self.keygroups.iter().enumerate() {
+            let mut data = vec![0u8; GROUP_SIZE];
+            data[4] = keygroup.mute_group;
+            data[6] = 127; // velocity range 0-127; polyphony 0 is the maximum
+            bytes.extend(chunk(GROUP_CHUNK, index as u32, &format!("Keygroup {}", index + 1), &data));
+        }
+        sample_chunks.iter().for_each(|c| bytes.extend_from_slice(c));
+        bytes.extend(chunk(PARAM_CHUNK, 0, "", &self.exs_parameters()));
+
+        Ok(ExsOutput { bytes, missing_samples })
+    }
+
+    /// Write the program to an `.exs` file named after it, returning the
+    /// samples that couldn't be referenced (see `to_exs`).
+    pub fn write_exs(&self, path: &Path, sample_dir: &Path, options: &ConvertOptions) -> Result<MissingSamples> {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let output = self.to_exs(&name, sample_dir, options)?;
+        fs::write(path, output.bytes)?;
+        Ok(output.missing_samples)
+    }
+
+    /// The parameter chunk: a count, a byte per slot naming its parameter
+    /// (0 = unused), then an `i16` value per slot.
+    fn exs_parameters(&self) -> Vec<u8> {
+        let mut params: Vec<(u8, i16)> = Vec::new();
+
+        let volume = self.output.as_ref().map_or(0.0, |o| o.volume_db());
+        params.push((param::MASTER_VOLUME, volume.round().max(-60.0) as i16));
+        if let Some(tuning) = &self.tuning {
+            params.push((param::COARSE_TUNE, tuning.semitone as i16));
+            params.push((param::FINE_TUNE, tuning.fine as i16));
+            params.push((param::PITCH_BEND_UP, tuning.pitchbend_up as i16));
+            params.push((param::PITCH_BEND_DOWN, tuning.pitchbend_down as i16));
+        }
+
+        // Envelope times and levels are 0-127 in EXS24, 0-100 in AKP
+        let scale = |value: u8| (value as i16 * 127 + 50) / 100;
+        if let Some(env) = self.keygroups.iter().find_map(|kg| kg.amp_env.as_ref()) {
+            params.push((param::ENV2_ATTACK, scale(env.attack)));
+            params.push((param::ENV2_DECAY, scale(env.decay)));
+            params.push((param::ENV2_SUSTAIN, scale(env.sustain)));
+            params.push((param::ENV2_RELEASE, scale(env.release)));
+        }
+
+        // Notch, peak, morphing, phaser and voweliser types have no EXS24 filter
+        let filter = self.keygroups.iter().find_map(|kg| kg.filter.as_ref())
+            .and_then(|filter| exs_filter_type(filter).map(|kind| (filter, kind)));
+        params.push((param::FILTER1_TOGGLE, filter.is_some() as i16));
+        if let Some((filter, kind)) = filter {
+            params.push((param::FILTER1_TYPE, kind));
+            // Cutoff and resonance are 0-1000
+            params.push((param::FILTER1_CUTOFF, filter.cutoff as i16 * 10));
+            params.push((param::FILTER1_RESO, (filter.resonance as i16 * 1000 / 12).min(1000)));
+            if let Some(env) = self.keygroups.iter().find_map(|kg| kg.filter_env.as_ref()) {
+                params.push((param::ENV1_ATTACK, scale(env.attack)));
+                params.push((param::ENV1_DECAY, scale(env.decay)));
+                params.push((param::ENV1_SUSTAIN, scale(env.sustain)));
+                params.push((param::ENV1_RELEASE, scale(env.release)));
+            }
+        }
+
+        let mut data = vec![0u8; 4 + PARAM_SLOTS * 3];
+        put_u32(&mut data, 0, PARAM_SLOTS as u32);
+        for (slot, (id, value)) in params.into_iter().enumerate() {
+            data[4 + slot] = id;
+            data[4 + PARAM_SLOTS + slot * 2..4 + PARAM_SLOTS + slot * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// EXS24 filter type for an AKP filter, if it has one.
+fn exs_filter_type(filter: &Filter) -> Option<i16> {
+    match filter.filter_type {
+        0 | 2 => Some(FILTER_LP_12),
+        1 => Some(FILTER_LP_24),
+        3..=5 => Some(FILTER_BP),
+        6..=8 => Some(FILTER_HP_12),
+        _ => None,
+    }
+}
+
+/// Find a sample and read its header, with the absolute folder Logic
+/// looks in first.
+fn find_sample_ref(sample_dir: &Path, sample_name: &str, index: u32) -> std::result::Result<SampleRef, String> {
+    let (path, info) = find_wav_info(sample_dir, sample_name)?;
+    let path = fs::canonicalize(&path).map_err(|e| e.to_string())?;
+    let folder = path.parent().unwrap_or(Path::new("/")).to_string_lossy().into_owned();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    if folder.len() >= PATH_LEN || file_name.len() >= PATH_LEN {
+        return Err(format!("path longer than {} bytes", PATH_LEN - 1));
+    }
+    Ok(SampleRef { index, folder, file_name, info })
+}
+
+fn sample_chunk(sample: &SampleRef) -> Vec<u8> {
+    let mut data = vec![0u8; SAMPLE_SIZE];
+    put_u32(&mut data, 4, sample.info.frames);
+    put_u32(&mut data, 8, sample.info.sample_rate);
+    put_u32(&mut data, 12, sample.info.bits_per_sample as u32);
+    put_u32(&mut data, 16, sample.info.channels as u32);
+    put_u32(&mut data, 20, sample.info.channels as u32);
+    // File type as a four-character code, stored like the other fields
+    put_u32(&mut data, 28, u32::from_be_bytes(*b"WAVE"));
+    put_string(&mut data, 80, PATH_LEN, &sample.folder);
+    put_string(&mut data, 80 + PATH_LEN, PATH_LEN, &sample.file_name);
+    chunk(SAMPLE_CHUNK, sample.index, &sample.file_name, &data)
+}
+
+/// A chunk header followed by `data`. Names longer than the field are cut.
+fn chunk(kind: u32, index: u32, name: &str, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0u8; 20 + NAME_LEN];
+    put_u32(&mut bytes, 0, kind);
+    put_u32(&mut bytes, 4, data.len() as u32);
+    put_u32(&mut bytes, 8, index);
+    bytes[16..20].copy_from_slice(MAGIC);
+    put_string(&mut bytes, 20, NAME_LEN, name);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// A NUL-terminated string in a fixed `len`-byte field, cut to fit on a
+/// character boundary.
+fn put_string(data: &mut [u8], offset: usize, len: usize, value: &str) {
+    let mut end = value.len().min(len - 1);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    data[offset..offset + end].copy_from_slice(&value.as_bytes()[..end]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::WavAudio;
+    use crate::types::{Envelope, Keygroup, ProgramOutput, SampleLoop, Zone};
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels: vec![vec![0; frames]] }.to_wav_bytes()
+    }
+
+    /// (type, index, name, data) for each chunk.
+    fn chunks(bytes: &[u8]) -> Vec<(u32, u32, String, &[u8])> {
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let mut found = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            assert_eq!(&bytes[offset + 16..offset + 20], MAGIC);
+            let size = u32_at(offset + 4) as usize;
+            let name = &bytes[offset + 20..offset + 20 + NAME_LEN];
+            let name = String::from_utf8_lossy(&name[..name.iter().position(|&b| b == 0).unwrap()]).into_owned();
+            let data_start = offset + 20 + NAME_LEN;
+            found.push((u32_at(offset), u32_at(offset + 8), name, &bytes[data_start..data_start + size]));
+            offset = data_start + size;
+        }
+        found
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn string_at(data: &[u8], offset: usize) -> String {
+        let field = &data[offset..offset + PATH_LEN];
+        String::from_utf8_lossy(&field[..field.iter().position(|&b| b == 0).unwrap()]).into_owned()
+    }
+
+    #[test]
+    fn test_exs_chunks() {
+        let dir = TempDir::new().unwrap();
+        let looped = SampleLoop { start: 10, end: 29, crossfade: 4 };
+        fs::write(dir.path().join("piano.wav"), wav(40, Some(48), Some(looped))).unwrap();
+
+        let zone = Zone { sample_name: "PIANO".to_string(), high_vel: 100, level: -6, pan: -20, fine_tune: 70, playback: 3, sample_loop: Some(looped), ..Default::default() };
+        let program = AkaiProgram {
+            keygroups: vec![
+                Keygroup {
+                    low_key: 36,
+                    high_key: 59,
+                    semitone_tune: 1,
+                    zones: vec![zone, Zone { sample_name: "MISSING".to_string(), low_vel: 101, ..Default::default() }],
+                    ..Default::default()
+                },
+                Keygroup {
+                    low_key: 60,
+                    high_key: 72,
+                    mute_group: 2,
+                    zones: vec![Zone { sample_name: "piano.wav".to_string(), keyboard_track: 0, ..Default::default() }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let output = program.to_exs("Piano", dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+
+        let chunks = chunks(&output.bytes);
+        let kinds: Vec<u32> = chunks.iter().map(|c| c.0).collect();
+        assert_eq!(kinds, [INSTRUMENT_CHUNK, ZONE_CHUNK, ZONE_CHUNK, GROUP_CHUNK, GROUP_CHUNK, SAMPLE_CHUNK, PARAM_CHUNK]);
+        let (_, _, name, instrument) = &chunks[0];
+        assert_eq!(name, "Piano");
+        assert_eq!((u32_at(instrument, 4), u32_at(instrument, 8), u32_at(instrument, 12)), (2, 2, 1));
+
+        // 1 semitone + 70 cents: coarse 2, fine -30
+        let (_, index, name, zone) = &chunks[1];
+        assert_eq!((*index, name.as_str()), (0, "PIANO"));
+        assert_eq!(zone[0], ZONE_VELOCITY_RANGE);
+        assert_eq!((zone[1], zone[2] as i8, zone[3] as i8, zone[4] as i8), (48, -30, -20, -6));
+        assert_eq!((zone[6], zone[7], zone[9], zone[10]), (36, 59, 1, 100));
+        assert_eq!(u32_at(zone, 16), 40);
+        assert_eq!((u32_at(zone, 20), u32_at(zone, 24), u32_at(zone, 28)), (10, 30, 4));
+        assert_eq!(zone[33], LOOP_ON | LOOP_PLAY_TO_END_ON_RELEASE);
+        assert_eq!(zone[80] as i8, 2);
+        assert_eq!((u32_at(zone, 88), u32_at(zone, 92)), (0, 0));
+
+        let zone = chunks[2].3;
+        assert_eq!(zone[0], ZONE_VELOCITY_RANGE | ZONE_PITCH_OFF);
+        assert_eq!((u32_at(zone, 88), u32_at(zone, 92)), (1, 0));
+        assert_eq!(zone[33], 0);
+        assert_eq!(chunks[4].3[4], 2);
+
+        let (_, _, name, sample) = &chunks[5];
+        assert_eq!(name, "piano.wav");
+        assert_eq!((u32_at(sample, 4), u32_at(sample, 8), u32_at(sample, 12), u32_at(sample, 16)), (40, 44100, 16, 1));
+        let folder = fs::canonicalize(dir.path()).unwrap();
+        assert_eq!(string_at(sample, 80), folder.to_string_lossy());
+        assert_eq!(string_at(sample, 80 + PATH_LEN), "piano.wav");
+    }
+
+    #[test]
+    fn test_exs_parameters() {
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, ..Default::default() }),
+            keygroups: vec![
+                Keygroup::default(),
+                Keygroup {
+                    amp_env: Some(Envelope { attack: 10, decay: 50, sustain: 100, release: 25, ..Default::default() }),
+                    filter: Some(Filter { filter_type: 7, cutoff: 60, resonance: 6, ..Default::default() }),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let data = program.exs_parameters();
+        assert_eq!(u32_at(&data, 0), PARAM_SLOTS as u32);
+        let params: HashMap<u8, i16> = (0..PARAM_SLOTS)
+            .filter(|&slot| data[4 + slot] != 0)
+            .map(|slot| (data[4 + slot], i16::from_le_bytes([data[4 + PARAM_SLOTS + slot * 2], data[5 + PARAM_SLOTS + slot * 2]])))
+            .collect();
+        assert_eq!(params[&param::MASTER_VOLUME], -6);
+        assert_eq!((params[&param::ENV2_ATTACK], params[&param::ENV2_DECAY], params[&param::ENV2_SUSTAIN], params[&param::ENV2_RELEASE]), (13, 64, 127, 32));
+        assert_eq!((params[&param::FILTER1_TOGGLE], params[&param::FILTER1_TYPE]), (1, FILTER_HP_12));
+        assert_eq!((params[&param::FILTER1_CUTOFF], params[&param::FILTER1_RESO]), (600, 500));
+        assert!(!params.contains_key(&param::ENV1_ATTACK));
+    }
+}
@@ -0,0 +1,59 @@
+//! Byte-level AKP files shared by the parser, writer and exporter tests.
+
+fn chunk(id: &[u8; 4], data: Vec<u8>) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend(data);
+    out
+}
+
+fn with(size: usize, values: &[(usize, u8)]) -> Vec<u8> {
+    let mut data = vec![0u8; size];
+    for &(offset, value) in values {
+        data[offset] = value;
+    }
+    data
+}
+
+/// A one-zone keygroup on a single note, with full-range velocity.
+fn keygroup(note: u8, sample_name: &[u8]) -> Vec<u8> {
+    let mut zone = with(46, &[(34, 0), (35, 127), (40, 1)]);
+    zone[1] = sample_name.len() as u8;
+    zone[2..2 + sample_name.len()].copy_from_slice(sample_name);
+
+    let mut kgrp = chunk(b"kloc", with(16, &[(0, 1), (4, note), (5, note)]));
+    kgrp.extend(chunk(b"env ", with(18, &[(0, 1), (3, 50), (4, 15), (7, 100)])));
+    kgrp.extend(chunk(b"env ", with(18, &[(0, 1)])));
+    kgrp.extend(chunk(b"env ", with(18, &[(0, 1)])));
+    kgrp.extend(chunk(b"filt", with(10, &[(0, 1), (2, 100)])));
+    kgrp.extend(chunk(b"zone", zone));
+    chunk(b"kgrp", kgrp)
+}
+
+/// An MPC4000 drum kit: the pad chunk before `prg `, laid out as described
+/// in akp-format-reference.md, then a kick keygroup on note 36 and a snare
+/// on 38. Pads A01 and B01 play the kick and A02 the snare; the other
+/// pads are unassigned (0xFF).
+pub(crate) fn mpc4000_kit() -> Vec<u8> {
+    let mut pads = vec![0xFFu8; 65];
+    pads[0] = 1;
+    pads[1] = 36;  // A01
+    pads[2] = 38;  // A02
+    pads[17] = 36; // B01
+
+    let mut body = b"APRG".to_vec();
+    body.extend(chunk(b"pads", pads));
+    body.extend(chunk(b"prg ", with(6, &[(0, 1), (2, 2)])));
+    body.extend(chunk(b"out ", with(8, &[(0, 1), (1, 85)])));
+    body.extend(chunk(b"tune", with(22, &[(0, 1), (15, 2), (16, 2)])));
+    body.extend(chunk(b"lfo ", with(12, &[(0, 1)])));
+    body.extend(chunk(b"lfo ", with(12, &[(0, 1)])));
+    body.extend(chunk(b"mods", with(38, &[(0, 1)])));
+    body.extend(keygroup(36, b"KICK"));
+    body.extend(keygroup(38, b"SNARE"));
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
@@ -0,0 +1,865 @@
+//! Format-neutral instrument model between the AKP reader and the writers.
+//!
+//! `AkaiProgram::to_instrument` lowers a program once: envelope times come
+//! out in seconds, cutoffs in Hz, levels in dB, tuning and modulation depths
+//! in cents, and each active modulation route is named with its source and
+//! destination. Writers then deal only in target-format syntax instead of
+//! repeating the AKP 0-100 scale conversions. The conversion curves live
+//! here too, along with their inverses used by the importers, and are
+//! private to the crate.
+//!
+//! The SFZ and Decent Sampler writers use this model. The SF2, Bitwig,
+//! Ableton, EXS24, Renoise, Deluge, Blackbox and MPC writers still walk
+//! the raw program, converting its values themselves or through these
+//! curves; moving them onto `to_instrument` is follow-up work.
+
+use crate::types::{
+    AkaiProgram, AuxEnvelope, Envelope, Filter, FilterEnvelope, FilterRouting, KeyRange, Keygroup, Lfo,
+    ProgramModulation, ProgramOutput, ProgramTuning, SampleLoop, Zone,
+};
+
+/// Shortest release the conversions produce, to avoid clicks.
+pub const MIN_RELEASE: f32 = 0.001;
+
+/// A program lowered to plain units.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Instrument {
+    pub tuning: Option<InstrumentTuning>,
+    pub output: Option<InstrumentOutput>,
+    pub lfo1: Option<InstrumentLfo>,
+    pub lfo2: Option<InstrumentLfo>,
+    /// Program-level routes: amp and pan modulation, LFO cross-modulation.
+    pub routes: Vec<ModRoute>,
+    pub groups: Vec<Group>,
+}
+
+/// Program tuning (`tune` chunk). The detune table isn't here: it's baked
+/// into the regions' `tune_cents` when split, or written as a Scala file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstrumentTuning {
+    pub transpose: i16,
+    pub tune_cents: i16,
+    pub bend_up_cents: i32,
+    pub bend_down_cents: i32,
+    /// Hardwired aftertouch → pitch.
+    pub aftertouch_cents: i32,
+}
+
+/// Program output (`out` chunk).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstrumentOutput {
+    /// Linear gain, 0-100%.
+    pub amplitude: f32,
+    pub volume_db: f32,
+    /// Velocity → amplitude, -100..100%.
+    pub velocity_tracking: f32,
+}
+
+/// A program LFO, applied to pitch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstrumentLfo {
+    pub rate_hz: f32,
+    /// "sine", "triangle", "square", "saw" or "random".
+    pub waveform: &'static str,
+    /// Pitch depth, 0.0-1.0 (1.0 = 100 cents).
+    pub depth: f32,
+    pub delay: f32,
+    /// Hardwired modwheel and aftertouch → LFO depth, in cents.
+    pub modwheel_cents: f32,
+    pub aftertouch_cents: f32,
+}
+
+/// One keygroup over a run of consecutive key ranges sharing a label.
+/// Per-key detune splits, or pads in drum-kit layout, are its regions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Group {
+    /// Pad label in drum-kit layout, e.g. "Pad A01".
+    pub label: Option<String>,
+    /// Groups sharing a non-zero mute group choke each other.
+    pub mute_group: u8,
+    pub amp_env: Option<AmpEnv>,
+    pub filter_env: Option<FilterEnv>,
+    /// Aux envelope, used as a pitch envelope when a route selects it.
+    pub aux_env: Option<AuxEnv>,
+    pub filter: Option<FilterStage>,
+    pub filter2: Option<FilterStage>,
+    pub filter3: Option<FilterStage>,
+    pub filter_routing: FilterRouting,
+    /// Keygroup-level routes: pitch, filter and amp modulation.
+    pub routes: Vec<ModRoute>,
+    pub regions: Vec<Region>,
+}
+
+/// Amp envelope, times in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AmpEnv {
+    pub attack: f32,
+    pub decay: f32,
+    /// 0.0-1.0.
+    pub sustain: f32,
+    pub release: f32,
+    /// Velocity → attack and release time, -100..100%.
+    pub velocity_attack: i8,
+    pub velocity_release: i8,
+    pub keyscale: i8,
+}
+
+/// Filter envelope, times in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterEnv {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    /// -1.0..1.0 of the full cutoff range.
+    pub depth: f32,
+    pub depth_cents: f32,
+    pub velocity_attack: i8,
+    pub velocity_release: i8,
+}
+
+/// Aux envelope reduced to ADSR: rates 1, 2 and 4 as times in seconds,
+/// level 3 as sustain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuxEnv {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+/// Broad filter response. Akai types with no counterpart elsewhere
+/// (morphing, phaser, voweliser) lower to a 2-pole low pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FilterKind {
+    #[default]
+    LowPass,
+    BandPass,
+    HighPass,
+    Notch,
+    Peak,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilterStage {
+    pub kind: FilterKind,
+    pub poles: u8,
+    pub cutoff_hz: f32,
+    pub resonance_db: f32,
+    pub keytrack_cents: i32,
+}
+
+/// One zone over one key range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    /// `None` for a keygroup without zones, kept so its settings still show.
+    pub sample: Option<String>,
+    pub low_key: u8,
+    pub high_key: u8,
+    /// The zone's own velocity range. With zone crossfade on, the fades
+    /// reach past it into the neighbouring layers (see `faded_vel_range`).
+    pub low_vel: u8,
+    pub high_vel: u8,
+    pub fade_in: Option<(u8, u8)>,
+    pub fade_out: Option<(u8, u8)>,
+    pub root_key: Option<u8>,
+    /// Keygroup and zone tuning, on top of the instrument's.
+    pub transpose: i16,
+    /// Keygroup, zone and sample header fine tuning plus any detune split.
+    pub tune_cents: i16,
+    /// -100 (left) to 100 (right).
+    pub pan: f32,
+    pub volume_db: f32,
+    pub playback: Playback,
+    /// Whether the region loops (see `Zone::loop_enabled`).
+    pub looped: bool,
+    pub sample_loop: Option<SampleLoop>,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self {
+            sample: None,
+            low_key: 0,
+            high_key: 127,
+            low_vel: 0,
+            high_vel: 127,
+            fade_in: None,
+            fade_out: None,
+            root_key: None,
+            transpose: 0,
+            tune_cents: 0,
+            pan: 0.0,
+            volume_db: 0.0,
+            playback: Playback::AsSample,
+            looped: false,
+            sample_loop: None,
+        }
+    }
+}
+
+impl Region {
+    /// Velocity range the region plays over, widened to take in its fades.
+    pub fn faded_vel_range(&self) -> (u8, u8) {
+        (
+            self.fade_in.map_or(self.low_vel, |(from, _)| self.low_vel.min(from)),
+            self.fade_out.map_or(self.high_vel, |(_, to)| self.high_vel.max(to)),
+        )
+    }
+}
+
+/// Zone playback mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Playback {
+    NoLoop,
+    OneShot,
+    Loop,
+    LoopUntilRelease,
+    /// Defer to the sample: loop if the WAV has a loop.
+    #[default]
+    AsSample,
+}
+
+/// An active modulation route. AKP sets the source per program (`mods`
+/// chunk) and the amount per keygroup or in the `out`/`lfo` chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModRoute {
+    /// AKP slot name, e.g. "pitch_mod_1" or "lfo1_rate_mod".
+    pub name: &'static str,
+    pub source: ModSource,
+    pub target: ModTarget,
+    /// -1.0..1.0 of the slot's full range.
+    pub depth: f32,
+    /// Depth in the target's unit: cents for pitch and cutoff, percent
+    /// otherwise.
+    pub amount: f32,
+}
+
+impl ModRoute {
+    /// The amount as stored in the program, for notes on routes a format
+    /// can't express.
+    pub fn akai_amount(&self) -> i32 {
+        (self.depth * 100.0).round() as i32
+    }
+}
+
+/// AKP modulation source (0-14).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModSource {
+    ModWheel,
+    Bend,
+    Aftertouch,
+    External,
+    Velocity,
+    Keyboard,
+    Lfo1,
+    Lfo2,
+    AmpEnv,
+    FilterEnv,
+    AuxEnv,
+    MidiNote,
+    MidiVelocity,
+    MidiRandom,
+    Unknown(u8),
+}
+
+impl ModSource {
+    /// `None` for NO_SOURCE.
+    pub fn from_akai(source: u8) -> Option<Self> {
+        Some(match source {
+            0 => return None,
+            1 => Self::ModWheel,
+            2 => Self::Bend,
+            3 => Self::Aftertouch,
+            4 => Self::External,
+            5 => Self::Velocity,
+            6 => Self::Keyboard,
+            7 => Self::Lfo1,
+            8 => Self::Lfo2,
+            9 => Self::AmpEnv,
+            10 => Self::FilterEnv,
+            11 => Self::AuxEnv,
+            12 => Self::MidiNote,
+            13 => Self::MidiVelocity,
+            14 => Self::MidiRandom,
+            other => Self::Unknown(other),
+        })
+    }
+
+    /// Akai's name for the source.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ModWheel => "MODWHEEL",
+            Self::Bend => "BEND",
+            Self::Aftertouch => "AFTERTOUCH",
+            Self::External => "EXTERNAL",
+            Self::Velocity => "VELOCITY",
+            Self::Keyboard => "KEYBOARD",
+            Self::Lfo1 => "LFO1",
+            Self::Lfo2 => "LFO2",
+            Self::AmpEnv => "AMP_ENV",
+            Self::FilterEnv => "FILT_ENV",
+            Self::AuxEnv => "AUX_ENV",
+            Self::MidiNote => "MIDI_NOTE",
+            Self::MidiVelocity => "MIDI_VELOCITY",
+            Self::MidiRandom => "MIDI_RANDOM",
+            Self::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
+
+/// Modulation destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModTarget {
+    Pitch,
+    Cutoff,
+    Amplitude,
+    Pan,
+    /// Rate, delay or depth of LFO 1 or 2.
+    LfoRate(u8),
+    LfoDelay(u8),
+    LfoDepth(u8),
+}
+
+// ---- Lowering ----
+
+impl AkaiProgram {
+    /// Lower the program to the format-neutral model. `split_detune` and
+    /// `by_pad` pick the key layout, as for `key_layout`.
+    pub fn to_instrument(&self, split_detune: bool, by_pad: bool) -> Instrument {
+        let mods = self.modulation.as_ref();
+        let layout = self.key_layout(split_detune, by_pad);
+        // Consecutive ranges of the same keygroup (and pad) share a group
+        let groups = layout
+            .chunk_by(|a, b| std::ptr::eq(a.keygroup, b.keygroup) && a.label == b.label)
+            .map(|ranges| lower_group(ranges, mods))
+            .collect();
+
+        Instrument {
+            tuning: self.tuning.as_ref().map(lower_tuning),
+            output: self.output.as_ref().map(|output| InstrumentOutput {
+                amplitude: output.loudness as f32,
+                volume_db: output.volume_db(),
+                velocity_tracking: output.velocity_sensitivity as f32,
+            }),
+            lfo1: self.lfo1.as_ref().map(lower_lfo),
+            lfo2: self.lfo2.as_ref().map(lower_lfo),
+            routes: self.program_routes(mods),
+            groups,
+        }
+    }
+
+    fn program_routes(&self, mods: Option<&ProgramModulation>) -> Vec<ModRoute> {
+        let Some(mods) = mods else { return Vec::new() };
+        let mut routes = Vec::new();
+        if let Some(output) = &self.output {
+            let slots = [
+                ("amp_mod_1", mods.amp_mod_1_source, output.amp_mod_1, ModTarget::Amplitude),
+                ("amp_mod_2", mods.amp_mod_2_source, output.amp_mod_2, ModTarget::Amplitude),
+                ("pan_mod_1", mods.pan_mod_1_source, output.pan_mod_1, ModTarget::Pan),
+                ("pan_mod_2", mods.pan_mod_2_source, output.pan_mod_2, ModTarget::Pan),
+                ("pan_mod_3", mods.pan_mod_3_source, output.pan_mod_3, ModTarget::Pan),
+            ];
+            routes.extend(slots.into_iter().filter_map(|(name, source, amount, target)| route(name, source, amount as i32, target)));
+        }
+        let lfos = [
+            (1, &self.lfo1, ["lfo1_rate_mod", "lfo1_delay_mod", "lfo1_depth_mod"],
+                [mods.lfo1_rate_mod_source, mods.lfo1_delay_mod_source, mods.lfo1_depth_mod_source]),
+            (2, &self.lfo2, ["lfo2_rate_mod", "lfo2_delay_mod", "lfo2_depth_mod"],
+                [mods.lfo2_rate_mod_source, mods.lfo2_delay_mod_source, mods.lfo2_depth_mod_source]),
+        ];
+        for (n, lfo, names, sources) in lfos {
+            let Some(lfo) = lfo else { continue };
+            let slots = [
+                (lfo.rate_mod, ModTarget::LfoRate(n)),
+                (lfo.delay_mod, ModTarget::LfoDelay(n)),
+                (lfo.depth_mod, ModTarget::LfoDepth(n)),
+            ];
+            for ((name, source), (amount, target)) in names.into_iter().zip(sources).zip(slots) {
+                routes.extend(route(name, source, amount as i32, target));
+            }
+        }
+        routes
+    }
+}
+
+fn lower_group(ranges: &[KeyRange<'_>], mods: Option<&ProgramModulation>) -> Group {
+    let keygroup = ranges[0].keygroup;
+    let crossfades = keygroup.velocity_crossfades();
+    let regions = ranges.iter()
+        .flat_map(|range| {
+            let zones: Vec<_> = if keygroup.zones.is_empty() {
+                vec![None]
+            } else {
+                keygroup.zones.iter().zip(&crossfades).map(Some).collect()
+            };
+            zones.into_iter().map(move |zone| match zone {
+                Some((zone, xf)) => Region {
+                    low_vel: zone.low_vel,
+                    high_vel: zone.high_vel,
+                    fade_in: xf.fade_in,
+                    fade_out: xf.fade_out,
+                    ..lower_zone(keygroup, zone, range)
+                },
+                None => Region { low_key: range.low_key, high_key: range.high_key, ..Default::default() },
+            })
+        })
+        .collect();
+
+    Group {
+        label: ranges[0].label.clone(),
+        mute_group: keygroup.mute_group,
+        amp_env: keygroup.amp_env.as_ref().map(lower_amp_env),
+        filter_env: keygroup.filter_env.as_ref().map(lower_filter_env),
+        aux_env: keygroup.aux_env.as_ref().map(lower_aux_env),
+        filter: keygroup.filter.as_ref().map(FilterStage::from),
+        filter2: keygroup.filter2.as_ref().map(FilterStage::from),
+        filter3: keygroup.filter3.as_ref().map(FilterStage::from),
+        filter_routing: keygroup.filter_routing,
+        routes: mods.map_or_else(Vec::new, |mods| keygroup_routes(keygroup, mods)),
+        regions,
+    }
+}
+
+fn lower_zone(keygroup: &Keygroup, zone: &Zone, range: &KeyRange<'_>) -> Region {
+    Region {
+        sample: Some(zone.sample_name.clone()),
+        low_key: range.low_key,
+        high_key: range.high_key,
+        root_key: zone.root_key,
+        transpose: keygroup.semitone_tune as i16 + zone.semitone_tune as i16,
+        tune_cents: keygroup.fine_tune as i16 + zone.fine_tune as i16 + zone.root_tune as i16 + range.detune as i16,
+        // AKP pan is -50..50
+        pan: zone.pan as f32 * 2.0,
+        volume_db: zone.level as f32,
+        playback: match zone.playback {
+            0 => Playback::NoLoop,
+            1 => Playback::OneShot,
+            2 => Playback::Loop,
+            3 => Playback::LoopUntilRelease,
+            _ => Playback::AsSample,
+        },
+        looped: zone.loop_enabled(),
+        sample_loop: zone.sample_loop,
+        ..Default::default()
+    }
+}
+
+fn keygroup_routes(keygroup: &Keygroup, mods: &ProgramModulation) -> Vec<ModRoute> {
+    let mut slots = vec![
+        ("pitch_mod_1", mods.pitch_mod_1_source, keygroup.pitch_mod_1, ModTarget::Pitch),
+        ("pitch_mod_2", mods.pitch_mod_2_source, keygroup.pitch_mod_2, ModTarget::Pitch),
+    ];
+    if let Some(filter) = &keygroup.filter {
+        slots.extend([
+            ("filter_mod_1", mods.filter_mod_1_source, filter.mod_input_1, ModTarget::Cutoff),
+            ("filter_mod_2", mods.filter_mod_2_source, filter.mod_input_2, ModTarget::Cutoff),
+            ("filter_mod_3", mods.filter_mod_3_source, filter.mod_input_3, ModTarget::Cutoff),
+        ]);
+    }
+    slots.push(("amp_mod", mods.amp_mod_source, keygroup.amp_mod, ModTarget::Amplitude));
+    slots.into_iter()
+        .filter_map(|(name, source, amount, target)| route(name, source, amount as i32, target))
+        .collect()
+}
+
+/// A route for an AKP slot, or `None` if it has no source or no amount.
+fn route(name: &'static str, source: u8, amount: i32, target: ModTarget) -> Option<ModRoute> {
+    let source = ModSource::from_akai(source)?;
+    if amount == 0 {
+        return None;
+    }
+    let amount_in_units = match (target, source) {
+        // The aux envelope sweeps ±1 octave at full depth
+        (ModTarget::Pitch, ModSource::AuxEnv) => amount * 12,
+        // Pitch amounts are semitones
+        (ModTarget::Pitch, _) => amount * 100,
+        // Full depth sweeps the 8-octave cutoff range (truncated, as the
+        // SFZ writer has always written it)
+        (ModTarget::Cutoff, _) => (amount as f32 / 100.0 * 9600.0) as i32,
+        _ => amount,
+    };
+    Some(ModRoute {
+        name,
+        source,
+        target,
+        depth: amount as f32 / 100.0,
+        amount: amount_in_units as f32,
+    })
+}
+
+fn lower_tuning(tuning: &ProgramTuning) -> InstrumentTuning {
+    InstrumentTuning {
+        transpose: tuning.semitone as i16,
+        tune_cents: tuning.fine as i16,
+        bend_up_cents: tuning.pitchbend_up as i32 * 100,
+        bend_down_cents: tuning.pitchbend_down as i32 * 100,
+        aftertouch_cents: tuning.aftertouch as i32 * 100,
+    }
+}
+
+fn lower_lfo(lfo: &Lfo) -> InstrumentLfo {
+    InstrumentLfo {
+        rate_hz: lfo.rate_hz(),
+        waveform: lfo.waveform_name(),
+        depth: lfo.depth_normalized(),
+        // 0-100 → 0-10 seconds
+        delay: lfo.delay as f32 / 100.0 * 10.0,
+        modwheel_cents: lfo.modwheel as f32,
+        aftertouch_cents: lfo.aftertouch as f32,
+    }
+}
+
+fn lower_amp_env(env: &Envelope) -> AmpEnv {
+    AmpEnv {
+        attack: env.attack_time(),
+        decay: env.decay_time(),
+        sustain: env.sustain_normalized(),
+        release: env.release_time(),
+        velocity_attack: env.velocity_attack,
+        velocity_release: env.on_vel_release,
+        keyscale: env.keyscale,
+    }
+}
+
+fn lower_filter_env(env: &FilterEnvelope) -> FilterEnv {
+    FilterEnv {
+        attack: env.attack_time(),
+        decay: env.decay_time(),
+        sustain: env.sustain_normalized(),
+        release: env.release_time(),
+        depth: env.depth as f32 / 100.0,
+        // Full depth sweeps the 8-octave cutoff range
+        depth_cents: env.depth as f32 / 100.0 * 9600.0,
+        velocity_attack: env.velocity_attack,
+        velocity_release: env.on_vel_release,
+    }
+}
+
+fn lower_aux_env(env: &AuxEnvelope) -> AuxEnv {
+    // Rates use the same curves as the amp and filter envelopes
+    let rates = Envelope { attack: env.rate_1, decay: env.rate_2, release: env.rate_4, sustain: env.level_3, ..Default::default() };
+    AuxEnv {
+        attack: rates.attack_time(),
+        decay: rates.decay_time(),
+        sustain: rates.sustain_normalized(),
+        release: rates.release_time(),
+    }
+}
+
+impl From<&Filter> for FilterStage {
+    fn from(filter: &Filter) -> Self {
+        let (kind, poles) = match filter.filter_type {
+            0 | 2 => (FilterKind::LowPass, 2),  // 2-pole LP, 2-pole LP+
+            1 => (FilterKind::LowPass, 4),
+            3 | 5 => (FilterKind::BandPass, 2), // 2-pole BP, 2-pole BP+
+            4 => (FilterKind::BandPass, 4),
+            6 | 8 => (FilterKind::HighPass, 1), // 1-pole HP, 1-pole HP+
+            7 => (FilterKind::HighPass, 2),
+            12..=16 => (FilterKind::Notch, 2),
+            17..=21 => (FilterKind::Peak, 2),
+            _ => (FilterKind::LowPass, 2),      // Morphing, phaser, voweliser
+        };
+        FilterStage {
+            kind,
+            poles,
+            cutoff_hz: filter.cutoff_hz(),
+            resonance_db: filter.resonance_db(),
+            // -36..+36 semitones
+            keytrack_cents: filter.keyboard_track as i32 * 100,
+        }
+    }
+}
+
+// ---- AKP scale conversions ----
+
+/// Shared envelope timing conversions for amp and filter envelopes.
+/// Both use identical exponential curves: attack/decay use exp(x*4)*0.001,
+/// release uses exp(x*5)*0.001 with 0.001s minimum to avoid clicks.
+pub(crate) trait EnvelopeTiming {
+    fn attack_raw(&self) -> u8;
+    fn decay_raw(&self) -> u8;
+    fn release_raw(&self) -> u8;
+    fn sustain_raw(&self) -> u8;
+
+    /// Convert AKP attack (0-100) to seconds (exponential curve).
+    fn attack_time(&self) -> f32 {
+        let v = self.attack_raw();
+        if v == 0 { 0.0 } else { (v as f32 / 100.0 * 4.0).exp() * 0.001 }
+    }
+
+    /// Convert AKP decay (0-100) to seconds (exponential curve).
+    fn decay_time(&self) -> f32 {
+        let v = self.decay_raw();
+        if v == 0 { 0.0 } else { (v as f32 / 100.0 * 4.0).exp() * 0.001 }
+    }
+
+    /// Convert AKP release (0-100) to seconds. Minimum 0.001s to avoid clicks.
+    fn release_time(&self) -> f32 {
+        let v = self.release_raw();
+        if v == 0 { MIN_RELEASE } else { (v as f32 / 100.0 * 5.0).exp() * 0.001 }
+    }
+
+    /// Convert AKP sustain (0-100) to normalized 0.0-1.0.
+    fn sustain_normalized(&self) -> f32 {
+        self.sustain_raw() as f32 / 100.0
+    }
+}
+
+/// Inverse of `attack_time`/`decay_time`: seconds to the AKP 0-100 scale.
+/// Not clamped or rounded, so callers can tell when a time is out of range.
+pub(crate) fn envelope_time_to_raw(seconds: f32) -> f32 {
+    if seconds <= 0.001 { 0.0 } else { (seconds / 0.001).ln() / 4.0 * 100.0 }
+}
+
+/// Inverse of `release_time`: seconds to the AKP 0-100 scale (unclamped).
+pub(crate) fn release_time_to_raw(seconds: f32) -> f32 {
+    if seconds <= MIN_RELEASE { 0.0 } else { (seconds / 0.001).ln() / 5.0 * 100.0 }
+}
+
+impl EnvelopeTiming for Envelope {
+    fn attack_raw(&self) -> u8 { self.attack }
+    fn decay_raw(&self) -> u8 { self.decay }
+    fn release_raw(&self) -> u8 { self.release }
+    fn sustain_raw(&self) -> u8 { self.sustain }
+}
+
+impl EnvelopeTiming for FilterEnvelope {
+    fn attack_raw(&self) -> u8 { self.attack }
+    fn decay_raw(&self) -> u8 { self.decay }
+    fn release_raw(&self) -> u8 { self.release }
+    fn sustain_raw(&self) -> u8 { self.sustain }
+}
+
+impl Lfo {
+    /// Convert AKP waveform byte to name. Spec: 0=SINE,1=TRI,2=SQ,3=SQ+,4=SQ-,5=SAW_BI,6=SAW_UP,7=SAW_DN,8=RANDOM
+    pub(crate) fn waveform_name(&self) -> &'static str {
+        match self.waveform {
+            0 => "sine",
+            1 => "triangle",
+            2 => "square",
+            3 => "square",    // SQ+ (positive phase)
+            4 => "square",    // SQ- (negative phase)
+            5 => "saw",       // SAW BI (bipolar)
+            6 => "saw",       // SAW UP
+            7 => "saw",       // SAW DOWN (ramp)
+            8 => "random",
+            _ => "sine",
+        }
+    }
+
+    /// Convert AKP LFO rate (0-100) to Hz (0.1-30 Hz, logarithmic).
+    pub(crate) fn rate_hz(&self) -> f32 {
+        0.1 * (300.0f32).powf(self.rate as f32 / 100.0)
+    }
+
+    /// Inverse of `rate_hz`: Hz to the AKP 0-100 scale (unclamped).
+    pub(crate) fn rate_from_hz(hz: f32) -> f32 {
+        (hz / 0.1).ln() / 300.0f32.ln() * 100.0
+    }
+
+    /// Convert AKP LFO depth (0-100) to normalized 0.0-1.0.
+    /// Used for pitch modulation: depth * 100 gives cents (0-100 cent range).
+    /// depth=1 → 1 cent (subtle vibrato), depth=99 → 99 cents (dramatic wobble).
+    pub(crate) fn depth_normalized(&self) -> f32 {
+        self.depth as f32 / 100.0
+    }
+}
+
+impl ProgramOutput {
+    /// Convert AKP loudness (0-100) to dB. Loudness is a linear gain percentage,
+    /// so dB = 20 * log10(loudness / 100). Loudness=100 → 0dB, 85 → -1.4dB, 50 → -6dB.
+    pub(crate) fn volume_db(&self) -> f32 {
+        if self.loudness == 0 {
+            return -60.0;
+        }
+        20.0 * (self.loudness as f32 / 100.0).log10()
+    }
+
+    /// Inverse of `volume_db`: dB to AKP loudness (unclamped).
+    pub(crate) fn loudness_from_db(db: f32) -> f32 {
+        100.0 * 10.0f32.powf(db / 20.0)
+    }
+}
+
+impl Filter {
+    /// Convert AKP cutoff (0-100) to Hz (20-20000, logarithmic).
+    pub(crate) fn cutoff_hz(&self) -> f32 {
+        20.0 * (1000.0f32).powf(self.cutoff as f32 / 100.0)
+    }
+
+    /// Convert AKP resonance (0-12) to dB (0-40 range, linear).
+    pub(crate) fn resonance_db(&self) -> f32 {
+        (self.resonance as f32 / 12.0) * 40.0
+    }
+
+    /// Inverse of `cutoff_hz`: Hz to the AKP 0-100 scale (unclamped).
+    pub(crate) fn cutoff_from_hz(hz: f32) -> f32 {
+        (hz / 20.0).ln() / 1000.0f32.ln() * 100.0
+    }
+
+    /// Inverse of `resonance_db`: dB to the AKP 0-12 scale (unclamped).
+    pub(crate) fn resonance_from_db(db: f32) -> f32 {
+        db / 40.0 * 12.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ProgramModulation, ProgramOutput, ProgramTuning};
+
+    fn zone(sample_name: &str) -> Zone {
+        Zone { sample_name: sample_name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_to_instrument_units() {
+        let program = AkaiProgram {
+            tuning: Some(ProgramTuning { semitone: -2, fine: 5, pitchbend_up: 2, pitchbend_down: 12, ..Default::default() }),
+            output: Some(ProgramOutput { loudness: 50, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                semitone_tune: 1,
+                fine_tune: -10,
+                amp_env: Some(Envelope { attack: 0, decay: 50, sustain: 80, release: 0, ..Default::default() }),
+                filter: Some(Filter { filter_type: 7, cutoff: 100, resonance: 6, keyboard_track: 12, ..Default::default() }),
+                zones: vec![Zone { pan: -25, level: -6, playback: 3, root_tune: 20, ..zone("pad.wav") }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let instrument = program.to_instrument(false, false);
+        let tuning = instrument.tuning.unwrap();
+        assert_eq!((tuning.transpose, tuning.tune_cents, tuning.bend_up_cents, tuning.bend_down_cents), (-2, 5, 200, 1200));
+        assert!((instrument.output.unwrap().volume_db + 6.02).abs() < 0.01);
+
+        let group = &instrument.groups[0];
+        let env = group.amp_env.as_ref().unwrap();
+        assert_eq!((env.attack, env.sustain, env.release), (0.0, 0.8, MIN_RELEASE));
+        assert!((env.decay - 2f32.exp() * 0.001).abs() < 1e-6);
+
+        let filter = group.filter.as_ref().unwrap();
+        assert_eq!((filter.kind, filter.poles, filter.keytrack_cents), (FilterKind::HighPass, 2, 1200));
+        assert!((filter.cutoff_hz - 20000.0).abs() < 0.5);
+        assert!((filter.resonance_db - 20.0).abs() < f32::EPSILON);
+
+        let region = &group.regions[0];
+        assert_eq!(region.sample.as_deref(), Some("pad.wav"));
+        assert_eq!((region.transpose, region.tune_cents), (1, 10));
+        assert_eq!((region.pan, region.volume_db), (-50.0, -6.0));
+        assert_eq!((region.playback, region.looped), (Playback::LoopUntilRelease, true));
+    }
+
+    #[test]
+    fn test_modulation_routes() {
+        let program = AkaiProgram {
+            modulation: Some(ProgramModulation {
+                pitch_mod_1_source: 11, // AUX_ENV
+                pitch_mod_2_source: 2,  // BEND, no amount
+                filter_mod_1_source: 5, // VELOCITY
+                pan_mod_1_source: 1,    // MODWHEEL
+                amp_mod_source: 0,
+                ..Default::default()
+            }),
+            output: Some(ProgramOutput { pan_mod_1: 40, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                pitch_mod_1: 50,
+                amp_mod: 30,
+                filter: Some(Filter { mod_input_1: -25, ..Default::default() }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let instrument = program.to_instrument(false, false);
+        let summary = |routes: &[ModRoute]| -> Vec<(&str, ModSource, ModTarget, f32)> {
+            routes.iter().map(|r| (r.name, r.source, r.target, r.amount)).collect()
+        };
+        // Sourceless and zero-amount slots are dropped
+        assert_eq!(summary(&instrument.groups[0].routes), vec![
+            ("pitch_mod_1", ModSource::AuxEnv, ModTarget::Pitch, 600.0),
+            ("filter_mod_1", ModSource::Velocity, ModTarget::Cutoff, -2400.0),
+        ]);
+        assert_eq!(summary(&instrument.routes), vec![("pan_mod_1", ModSource::ModWheel, ModTarget::Pan, 40.0)]);
+        assert_eq!(instrument.routes[0].akai_amount(), 40);
+        assert_eq!(ModSource::from_akai(0), None);
+        assert_eq!(ModSource::from_akai(15).map(ModSource::name), Some("UNKNOWN"));
+    }
+
+    #[test]
+    fn test_groups_follow_key_layout() {
+        let mut tuning = ProgramTuning::default();
+        tuning.detune[4] = -14;
+        let program = AkaiProgram {
+            tuning: Some(tuning),
+            keygroups: vec![
+                Keygroup { low_key: 60, high_key: 67, zones: vec![zone("a.wav"), zone("b.wav")], ..Default::default() },
+                Keygroup { low_key: 70, high_key: 72, ..Default::default() },
+            ],
+            ..Default::default()
+        };
+
+        // Detune split: one group, its ranges × zones as regions
+        let instrument = program.to_instrument(true, false);
+        assert_eq!(instrument.groups.len(), 2);
+        let regions: Vec<_> = instrument.groups[0].regions.iter()
+            .map(|r| (r.sample.as_deref().unwrap(), r.low_key, r.high_key, r.tune_cents))
+            .collect();
+        assert_eq!(regions, vec![
+            ("a.wav", 60, 63, 0), ("b.wav", 60, 63, 0),
+            ("a.wav", 64, 64, -14), ("b.wav", 64, 64, -14),
+            ("a.wav", 65, 67, 0), ("b.wav", 65, 67, 0),
+        ]);
+
+        // A keygroup without zones keeps one sampleless region
+        let empty = &instrument.groups[1].regions;
+        assert_eq!((empty.len(), empty[0].sample.is_none(), empty[0].low_key), (1, true, 70));
+
+        assert_eq!(program.to_instrument(false, false).groups[0].regions.len(), 2);
+    }
+
+    #[test]
+    fn test_lfo_depth_normalized_boundaries() {
+        assert_eq!(Lfo { depth: 0, ..Default::default() }.depth_normalized(), 0.0);
+        assert!((Lfo { depth: 50, ..Default::default() }.depth_normalized() - 0.5).abs() < f32::EPSILON);
+        assert!((Lfo { depth: 100, ..Default::default() }.depth_normalized() - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_envelope_timing_trait_shared_behavior() {
+        let amp = Envelope { attack: 50, decay: 50, sustain: 80, release: 50, ..Default::default() };
+        let filt = FilterEnvelope { attack: 50, decay: 50, sustain: 80, release: 50, ..Default::default() };
+        // Same raw values produce identical timing
+        assert_eq!(amp.attack_time(), filt.attack_time());
+        assert_eq!(amp.decay_time(), filt.decay_time());
+        assert_eq!(amp.release_time(), filt.release_time());
+        assert_eq!(amp.sustain_normalized(), filt.sustain_normalized());
+    }
+
+    #[test]
+    fn test_envelope_timing_zero_values() {
+        let env = Envelope { attack: 0, decay: 0, sustain: 0, release: 0, ..Default::default() };
+        assert_eq!(env.attack_time(), 0.0);
+        assert_eq!(env.decay_time(), 0.0);
+        assert_eq!(env.sustain_normalized(), 0.0);
+        assert_eq!(env.release_time(), 0.001); // minimum to avoid clicks
+    }
+
+    #[test]
+    fn test_inverse_conversions_round_trip() {
+        for v in [1u8, 25, 50, 99, 100] {
+            let env = Envelope { attack: v, decay: v, release: v, ..Default::default() };
+            assert_eq!(envelope_time_to_raw(env.attack_time()).round() as u8, v);
+            assert_eq!(envelope_time_to_raw(env.decay_time()).round() as u8, v);
+            assert_eq!(release_time_to_raw(env.release_time()).round() as u8, v);
+            assert_eq!(Lfo::rate_from_hz(Lfo { rate: v, ..Default::default() }.rate_hz()).round() as u8, v);
+            assert_eq!(Filter::cutoff_from_hz(Filter { cutoff: v, ..Default::default() }.cutoff_hz()).round() as u8, v);
+        }
+        for v in 0u8..=12 {
+            assert_eq!(Filter::resonance_from_db(Filter { resonance: v, ..Default::default() }.resonance_db()).round() as u8, v);
+        }
+        assert_eq!(envelope_time_to_raw(0.0), 0.0);
+        assert_eq!(release_time_to_raw(0.001), 0.0);
+    }
+}
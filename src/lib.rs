@@ -1,48 +1,57 @@
 pub mod error;
 pub mod types;
+pub mod instrument;
 pub mod parser;
 pub mod sfz;
 pub mod dspreset;
+pub mod scala;
+pub mod akp;
 pub mod validate;
 pub mod samples;
-
-pub use error::{AkpError, Result};
-pub use types::{AkaiProgram, OutputFormat};
-pub use parser::{validate_riff_header, parse_top_level_chunks};
-pub use samples::{copy_samples, CopyConfig, CopyReport, SampleResult};
+pub mod s1000;
+pub mod diskimage;
+pub mod sfz_import;
+pub mod dspreset_import;
+pub mod sf2;
+pub mod sf2_import;
+pub mod bitwig;
+pub mod ableton;
+pub mod xpm;
+pub mod exs;
+pub mod renoise;
+pub mod deluge;
+pub mod blackbox;
+pub mod registry;
+#[cfg(test)]
+mod fixtures;
+
+pub use error::{AkpError, ChunkLocation, Result};
+pub use types::{AkaiProgram, ConvertOptions, DetuneMode};
+#[allow(deprecated)]
+pub use types::OutputFormat;
+pub use registry::{FormatInfo, ProgramReader, ProgramWriter, Registry, WriteTarget, WriterCapabilities};
+pub use parser::{parse_akp, parse_akp_bytes, parse_akp_bytes_with, validate_riff_header, parse_top_level_chunks, ParseObserver, ParseWarning};
+pub use samples::{apply_sample_info, copy_samples, copy_samples_from, CardExport, CopyConfig, CopyReport, MissingSamples, SampleInfoReport, SampleResult, SampleSource};
 
 use std::path::Path;
 
 /// Conversion function for GUI use — returns only the output string.
-pub fn convert_file(input_path: &Path, format: OutputFormat) -> std::result::Result<String, String> {
-    let (output, _program) = convert_file_with_program(input_path, format)?;
+/// `format` is the id or alias of a text writer in `registry`, e.g. `"sfz"`,
+/// so formats added with `Registry::register_writer` work too.
+pub fn convert_file(registry: &Registry, input_path: &Path, format: &str, options: &ConvertOptions) -> Result<String> {
+    let (output, _program) = convert_file_with_program(registry, input_path, format, options)?;
     Ok(output)
 }
 
 /// Like `convert_file()` but also returns the parsed `AkaiProgram`,
-/// so callers can access `sample_paths()` for sample copying.
-pub fn convert_file_with_program(input_path: &Path, format: OutputFormat) -> std::result::Result<(String, AkaiProgram), String> {
-    use std::fs::File;
-
-    let mut file = File::open(input_path)
-        .map_err(|e| format!("Failed to open file: {e}"))?;
-
-    validate_riff_header(&mut file)
-        .map_err(|e| format!("Invalid AKP file: {e}"))?;
-
-    let file_size = file.metadata()
-        .map_err(|e| format!("Failed to read file size: {e}"))?.len();
-
-    let mut program = AkaiProgram::default();
-    let progress = indicatif::ProgressBar::hidden();
-
-    parse_top_level_chunks(&mut file, file_size, &mut program, &progress)
-        .map_err(|e| format!("Failed to parse AKP chunks: {e}"))?;
-
-    let output = match format {
-        OutputFormat::Sfz => program.to_sfz_string(),
-        OutputFormat::DecentSampler => program.to_dspreset_string(),
-    };
-
-    Ok((output, program))
+/// so callers can access `sample_paths()` for sample copying. Any input
+/// the registry can read is accepted; the output must be one of the text
+/// formats (see `Registry::convert_file_with_program`).
+pub fn convert_file_with_program(
+    registry: &Registry,
+    input_path: &Path,
+    format: &str,
+    options: &ConvertOptions,
+) -> Result<(String, AkaiProgram)> {
+    registry.convert_file_with_program(input_path, format, options, &mut ())
 }
@@ -1,205 +1,408 @@
 use byteorder::{LittleEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Cursor};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Cursor};
 use std::str;
-use indicatif::ProgressBar;
 
-use crate::error::{AkpError, Result};
+use crate::error::{AkpError, ChunkLocation, Result};
 use crate::types::*;
 
 const MAX_CHUNK_SIZE: u32 = 64 * 1024 * 1024;
 const MAX_KEYGROUPS: usize = 1000;
 const MAX_ZONES_PER_KEYGROUP: usize = 4;
+const PAD_CHUNK_SIZE: u32 = 1 + (PAD_BANKS * PADS_PER_BANK) as u32;
+/// `RIFF`, the RIFF size and `APRG`.
+const RIFF_HEADER_LEN: usize = 12;
+
+/// Something the parser skipped over without failing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    /// A short, stable code for the kind of warning, e.g. `unknown-chunk`.
+    pub code: &'static str,
+    /// The chunk the warning is about.
+    pub location: ChunkLocation,
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}", self.message, self.location)
+    }
+}
+
+/// Told about progress and warnings while a program is parsed. Both
+/// methods do nothing by default; `()` ignores everything and a
+/// `Vec<ParseWarning>` collects the warnings.
+pub trait ParseObserver {
+    /// How much of the input has been parsed, from 0 to 1.
+    fn progress(&mut self, _fraction: f32) {}
+
+    fn warning(&mut self, _warning: ParseWarning) {}
+}
+
+impl ParseObserver for () {}
+
+impl ParseObserver for Vec<ParseWarning> {
+    fn warning(&mut self, warning: ParseWarning) {
+        self.push(warning);
+    }
+}
+
+/// Parse an AKP program from `reader`, from its RIFF header at the current
+/// position to the end of the stream. Fails if there are no keygroups.
+pub fn parse_akp<R: Read + Seek>(reader: &mut R, observer: &mut dyn ParseObserver) -> Result<AkaiProgram> {
+    validate_riff_header(reader)?;
+    let body_start = reader.stream_position()?;
+    let end_pos = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(body_start))?;
+
+    let mut program = AkaiProgram::default();
+    parse_chunks(&mut StreamSource(reader), end_pos, &mut program, observer)?;
+    require_keygroups(program)
+}
+
+/// Parse an AKP program held in memory. Chunks are parsed in place rather
+/// than copied out.
+pub fn parse_akp_bytes(bytes: &[u8]) -> Result<AkaiProgram> {
+    parse_akp_bytes_with(bytes, &mut ())
+}
+
+/// Like `parse_akp_bytes`, reporting warnings to `observer`.
+pub fn parse_akp_bytes_with(bytes: &[u8], observer: &mut dyn ParseObserver) -> Result<AkaiProgram> {
+    validate_riff_header(&mut Cursor::new(bytes))?;
+    let mut program = AkaiProgram::default();
+    let mut source = SliceSource { data: bytes, pos: RIFF_HEADER_LEN };
+    parse_chunks(&mut source, bytes.len() as u64, &mut program, observer)?;
+    require_keygroups(program)
+}
+
+fn require_keygroups(program: AkaiProgram) -> Result<AkaiProgram> {
+    if program.keygroups.is_empty() {
+        return Err(AkpError::MissingRequiredChunk("keygroup".to_string()));
+    }
+    Ok(program)
+}
 
-pub fn validate_riff_header(file: &mut File) -> Result<()> {
+pub fn validate_riff_header<R: Read + Seek>(file: &mut R) -> Result<()> {
+    let start = file.stream_position()?;
     let mut buf = [0u8; 4];
     file.read_exact(&mut buf)
-        .map_err(|_| AkpError::CorruptedChunk("RIFF".to_string(), "Failed to read RIFF signature".to_string()))?;
+        .map_err(|_| AkpError::CorruptedChunk("RIFF".to_string(), "Failed to read RIFF signature".to_string()).at("RIFF", start))?;
 
     if str::from_utf8(&buf).unwrap_or("") != "RIFF" {
-        return Err(AkpError::InvalidRiffHeader);
+        return Err(AkpError::InvalidRiffHeader.at("RIFF", start));
     }
 
     file.seek(SeekFrom::Current(4))?;
 
     file.read_exact(&mut buf)
-        .map_err(|_| AkpError::CorruptedChunk("APRG".to_string(), "Failed to read APRG signature".to_string()))?;
+        .map_err(|_| AkpError::CorruptedChunk("APRG".to_string(), "Failed to read APRG signature".to_string()).at("RIFF", start + 8))?;
 
     if str::from_utf8(&buf).unwrap_or("") != "APRG" {
-        return Err(AkpError::InvalidAprgSignature);
+        return Err(AkpError::InvalidAprgSignature.at("RIFF", start + 8));
     }
 
     Ok(())
 }
 
-pub fn parse_top_level_chunks(file: &mut File, end_pos: u64, program: &mut AkaiProgram, progress: &ProgressBar) -> Result<()> {
-    let mut processed = 0u64;
-    let mut lfo_count = 0u8;
+/// Parse the chunks after the RIFF header up to `end_pos` into `program`.
+/// `parse_akp` does this along with the header.
+pub fn parse_top_level_chunks<R: Read + Seek>(file: &mut R, end_pos: u64, program: &mut AkaiProgram, observer: &mut dyn ParseObserver) -> Result<()> {
+    parse_chunks(&mut StreamSource(file), end_pos, program, observer)
+}
 
-    while file.stream_position()? < end_pos {
-        let current_pos = file.stream_position()?;
-        if end_pos > 0 {
-            let progress_percent = (current_pos * 30) / end_pos;
-            if processed != progress_percent {
-                progress.set_position(20 + progress_percent);
-                processed = progress_percent;
-            }
-        }
+/// Where chunk bodies come from: a stream reads each one into a buffer,
+/// a byte slice lends it out.
+trait ChunkSource<'a> {
+    fn position(&mut self) -> Result<u64>;
+    fn read_header(&mut self) -> Result<RiffChunkHeader>;
+    fn body(&mut self, size: u32) -> Result<Cow<'a, [u8]>>;
+    fn skip(&mut self, size: u32) -> Result<()>;
+}
 
-        let header = read_chunk_header(file)?;
+struct StreamSource<'r, R>(&'r mut R);
 
-        if header.size > MAX_CHUNK_SIZE {
-            return Err(AkpError::InvalidChunkSize(header.id, header.size));
-        }
+impl<'a, R: Read + Seek> ChunkSource<'a> for StreamSource<'_, R> {
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.0.stream_position()?)
+    }
 
-        let chunk_start = file.stream_position()?;
-        if chunk_start + header.size as u64 > end_pos {
-            return Err(AkpError::CorruptedChunk(
-                header.id,
-                "Chunk extends beyond container boundary".to_string(),
-            ));
-        }
+    fn read_header(&mut self) -> Result<RiffChunkHeader> {
+        read_chunk_header(self.0)
+    }
 
-        match header.id.as_str() {
-            "prg " => {
-                if header.size < 3 {
-                    return Err(AkpError::InvalidChunkSize("prg".to_string(), header.size));
-                }
-                let mut chunk_data = vec![0; header.size as usize];
-                file.read_exact(&mut chunk_data)?;
-                program.header = Some(parse_program_header(&mut Cursor::new(chunk_data))?);
+    fn body(&mut self, size: u32) -> Result<Cow<'a, [u8]>> {
+        let mut data = vec![0; size as usize];
+        self.0.read_exact(&mut data)?;
+        Ok(Cow::Owned(data))
+    }
+
+    fn skip(&mut self, size: u32) -> Result<()> {
+        self.0.seek(SeekFrom::Current(size as i64))?;
+        Ok(())
+    }
+}
+
+struct SliceSource<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ChunkSource<'a> for SliceSource<'a> {
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.pos as u64)
+    }
+
+    fn read_header(&mut self) -> Result<RiffChunkHeader> {
+        read_chunk_header(&mut Cursor::new(self.body(8)?))
+    }
+
+    fn body(&mut self, size: u32) -> Result<Cow<'a, [u8]>> {
+        let end = self.pos.saturating_add(size as usize);
+        let body = self.data.get(self.pos..end)
+            .ok_or_else(|| AkpError::Io(io::ErrorKind::UnexpectedEof.into()))?;
+        self.pos = end;
+        Ok(Cow::Borrowed(body))
+    }
+
+    fn skip(&mut self, size: u32) -> Result<()> {
+        self.pos = self.pos.saturating_add(size as usize);
+        Ok(())
+    }
+}
+
+fn parse_chunks<'a>(source: &mut impl ChunkSource<'a>, end_pos: u64, program: &mut AkaiProgram, observer: &mut dyn ParseObserver) -> Result<()> {
+    let mut lfo_count = 0usize;
+
+    while source.position()? < end_pos {
+        let header_pos = source.position()?;
+        observer.progress(header_pos as f32 / end_pos as f32);
+
+        let header = source.read_header().map_err(|e| e.at("RIFF", header_pos))?;
+        let path = match header.id.as_str() {
+            "lfo " => format!("lfo[{lfo_count}]"),
+            "kgrp" => format!("kgrp[{}]", program.keygroups.len()),
+            id => id.trim_end().to_string(),
+        };
+        let chunk = ChunkLocation { path, offset: header_pos };
+        parse_chunk(source, header, &chunk, end_pos, program, &mut lfo_count, observer)
+            .map_err(|e| e.at(&chunk.path, chunk.offset))?;
+    }
+    Ok(())
+}
+
+fn parse_chunk<'a>(
+    source: &mut impl ChunkSource<'a>,
+    header: RiffChunkHeader,
+    chunk: &ChunkLocation,
+    end_pos: u64,
+    program: &mut AkaiProgram,
+    lfo_count: &mut usize,
+    observer: &mut dyn ParseObserver,
+) -> Result<()> {
+    if header.size > MAX_CHUNK_SIZE {
+        return Err(AkpError::InvalidChunkSize(header.id, header.size));
+    }
+
+    let chunk_start = source.position()?;
+    if chunk_start + header.size as u64 > end_pos {
+        return Err(AkpError::CorruptedChunk(
+            header.id,
+            "Chunk extends beyond container boundary".to_string(),
+        ));
+    }
+
+    match header.id.as_str() {
+        "prg " => {
+            if header.size < 3 {
+                return Err(AkpError::InvalidChunkSize("prg".to_string(), header.size));
             }
-            "out " => {
-                if header.size < 8 {
-                    return Err(AkpError::InvalidChunkSize("out".to_string(), header.size));
-                }
-                let mut chunk_data = vec![0; header.size as usize];
-                file.read_exact(&mut chunk_data)?;
-                program.output = Some(parse_out_chunk(&mut Cursor::new(chunk_data))?);
+            let chunk_data = source.body(header.size)?;
+            program.header = Some(parse_program_header(&mut Cursor::new(&*chunk_data))?);
+        }
+        "out " => {
+            if header.size < 8 {
+                return Err(AkpError::InvalidChunkSize("out".to_string(), header.size));
             }
-            "tune" => {
-                if header.size < 19 {
-                    return Err(AkpError::InvalidChunkSize("tune".to_string(), header.size));
-                }
-                let mut chunk_data = vec![0; header.size as usize];
-                file.read_exact(&mut chunk_data)?;
-                program.tuning = Some(parse_tune_chunk(&mut Cursor::new(chunk_data))?);
+            let chunk_data = source.body(header.size)?;
+            program.output = Some(parse_out_chunk(&mut Cursor::new(&*chunk_data))?);
+        }
+        "tune" => {
+            if header.size < 19 {
+                return Err(AkpError::InvalidChunkSize("tune".to_string(), header.size));
             }
-            "lfo " => {
-                if header.size < 12 {
-                    return Err(AkpError::InvalidChunkSize("lfo".to_string(), header.size));
-                }
-                let mut chunk_data = vec![0; header.size as usize];
-                file.read_exact(&mut chunk_data)?;
-                match lfo_count {
-                    0 => program.lfo1 = Some(parse_lfo1_chunk(&mut Cursor::new(chunk_data))?),
-                    1 => program.lfo2 = Some(parse_lfo2_chunk(&mut Cursor::new(chunk_data))?),
-                    _ => {} // ignore extra LFOs
-                }
-                lfo_count += 1;
+            let chunk_data = source.body(header.size)?;
+            program.tuning = Some(parse_tune_chunk(&mut Cursor::new(&*chunk_data))?);
+        }
+        "lfo " => {
+            if header.size < 12 {
+                return Err(AkpError::InvalidChunkSize("lfo".to_string(), header.size));
             }
-            "mods" => {
-                if header.size < 38 {
-                    return Err(AkpError::InvalidChunkSize("mods".to_string(), header.size));
-                }
-                let mut chunk_data = vec![0; header.size as usize];
-                file.read_exact(&mut chunk_data)?;
-                program.modulation = Some(parse_mods_chunk(&mut Cursor::new(chunk_data))?);
+            let chunk_data = source.body(header.size)?;
+            match *lfo_count {
+                0 => program.lfo1 = Some(parse_lfo1_chunk(&mut Cursor::new(&*chunk_data))?),
+                1 => program.lfo2 = Some(parse_lfo2_chunk(&mut Cursor::new(&*chunk_data))?),
+                _ => {} // ignore extra LFOs
             }
-            "kgrp" => {
-                if header.size == 0 {
-                    return Err(AkpError::InvalidChunkSize("kgrp".to_string(), header.size));
-                }
-                if program.keygroups.len() >= MAX_KEYGROUPS {
-                    return Err(AkpError::CorruptedChunk(
-                        "kgrp".to_string(),
-                        format!("Exceeded maximum of {MAX_KEYGROUPS} keygroups"),
-                    ));
-                }
-                progress.set_message("Parsing keygroup...");
-                let kgrp_end_pos = chunk_start + header.size as u64;
-                let keygroup = parse_keygroup(file, kgrp_end_pos, progress)?;
-                program.keygroups.push(keygroup);
+            *lfo_count += 1;
+        }
+        "mods" => {
+            if header.size < 38 {
+                return Err(AkpError::InvalidChunkSize("mods".to_string(), header.size));
+            }
+            let chunk_data = source.body(header.size)?;
+            program.modulation = Some(parse_mods_chunk(&mut Cursor::new(&*chunk_data))?);
+        }
+        "kgrp" => {
+            if header.size == 0 {
+                return Err(AkpError::InvalidChunkSize("kgrp".to_string(), header.size));
+            }
+            if program.keygroups.len() >= MAX_KEYGROUPS {
+                return Err(AkpError::CorruptedChunk(
+                    "kgrp".to_string(),
+                    format!("Exceeded maximum of {MAX_KEYGROUPS} keygroups"),
+                ));
             }
-            _ => {
-                progress.println(format!("Warning: Skipping unknown chunk type '{}'", header.id));
-                file.seek(SeekFrom::Current(header.size as i64))?;
+            let kgrp_end_pos = chunk_start + header.size as u64;
+            let keygroup = parse_keygroup(source, &chunk.path, kgrp_end_pos, observer)?;
+            // Only the Z4/Z8 write more than one filter per keygroup
+            if keygroup.filter2.is_some() {
+                program.model = ProgramModel::ZSeries;
             }
+            program.keygroups.push(keygroup);
+        }
+        // MPC4000 pad assignments: the only chunk written before `prg `.
+        // Its ID isn't documented, so it's recognised by position and size.
+        _ if program.header.is_none() && program.pads.is_none() && header.size >= PAD_CHUNK_SIZE => {
+            let chunk_data = source.body(header.size)?;
+            program.pads = Some(parse_pad_chunk(&mut Cursor::new(&*chunk_data))?);
+            program.model = ProgramModel::Mpc4000;
+        }
+        _ => {
+            observer.warning(ParseWarning {
+                code: "unknown-chunk",
+                message: "skipped unknown chunk".to_string(),
+                location: chunk.clone(),
+            });
+            source.skip(header.size)?;
         }
     }
     Ok(())
 }
 
-fn parse_keygroup(file: &mut File, end_pos: u64, progress: &ProgressBar) -> Result<Keygroup> {
-    let mut keygroup = Keygroup::default();
-    let mut env_count = 0u8;
+/// How many of each repeated chunk a keygroup has had so far.
+#[derive(Default)]
+struct KeygroupCounts {
+    env: usize,
+    filt: usize,
+    zone: usize,
+}
 
-    while file.stream_position()? < end_pos {
-        let header = read_chunk_header(file)?;
+/// Parse the sub-chunks of the keygroup at `kgrp_path` up to `end_pos`.
+fn parse_keygroup<'a>(source: &mut impl ChunkSource<'a>, kgrp_path: &str, end_pos: u64, observer: &mut dyn ParseObserver) -> Result<Keygroup> {
+    let mut keygroup = Keygroup::default();
+    let mut counts = KeygroupCounts::default();
+
+    while source.position()? < end_pos {
+        let header_pos = source.position()?;
+        let header = source.read_header().map_err(|e| e.at(kgrp_path, header_pos))?;
+        let name = match header.id.as_str() {
+            "env " => format!("env[{}]", counts.env),
+            "filt" => format!("filt[{}]", counts.filt),
+            "zone" => format!("zone[{}]", counts.zone),
+            id => id.trim_end().to_string(),
+        };
+        let chunk = ChunkLocation { path: format!("{kgrp_path}/{name}"), offset: header_pos };
+        parse_keygroup_chunk(source, header, &chunk, end_pos, &mut keygroup, &mut counts, observer)
+            .map_err(|e| e.at(&chunk.path, chunk.offset))?;
+    }
+    Ok(keygroup)
+}
 
-        if header.size > MAX_CHUNK_SIZE {
-            return Err(AkpError::InvalidChunkSize(header.id, header.size));
-        }
+fn parse_keygroup_chunk<'a>(
+    source: &mut impl ChunkSource<'a>,
+    header: RiffChunkHeader,
+    chunk: &ChunkLocation,
+    end_pos: u64,
+    keygroup: &mut Keygroup,
+    counts: &mut KeygroupCounts,
+    observer: &mut dyn ParseObserver,
+) -> Result<()> {
+    if header.size > MAX_CHUNK_SIZE {
+        return Err(AkpError::InvalidChunkSize(header.id, header.size));
+    }
 
-        let chunk_start = file.stream_position()?;
-        if chunk_start + header.size as u64 > end_pos {
-            return Err(AkpError::CorruptedChunk(
-                header.id,
-                "Chunk extends beyond keygroup boundary".to_string(),
-            ));
-        }
+    let chunk_start = source.position()?;
+    if chunk_start + header.size as u64 > end_pos {
+        return Err(AkpError::CorruptedChunk(
+            header.id,
+            "Chunk extends beyond keygroup boundary".to_string(),
+        ));
+    }
 
-        let mut chunk_data = vec![0; header.size as usize];
-        file.read_exact(&mut chunk_data)?;
-        let mut cursor = Cursor::new(chunk_data);
+    let chunk_data = source.body(header.size)?;
+    let mut cursor = Cursor::new(&*chunk_data);
 
-        match header.id.as_str() {
-            "kloc" => {
-                if header.size < 16 {
-                    return Err(AkpError::InvalidChunkSize("kloc".to_string(), header.size));
-                }
-                parse_kloc_chunk(&mut cursor, &mut keygroup)?;
+    match header.id.as_str() {
+        "kloc" => {
+            if header.size < 16 {
+                return Err(AkpError::InvalidChunkSize("kloc".to_string(), header.size));
             }
-            "env " => {
-                if header.size < 18 {
-                    return Err(AkpError::InvalidChunkSize("env".to_string(), header.size));
-                }
-                match env_count {
-                    0 => keygroup.amp_env = Some(parse_amp_env_chunk(&mut cursor)?),
-                    1 => keygroup.filter_env = Some(parse_filter_env_chunk(&mut cursor)?),
-                    2 => keygroup.aux_env = Some(parse_aux_env_chunk(&mut cursor)?),
-                    _ => {}
-                }
-                env_count += 1;
+            parse_kloc_chunk(&mut cursor, keygroup)?;
+        }
+        "env " => {
+            if header.size < 18 {
+                return Err(AkpError::InvalidChunkSize("env".to_string(), header.size));
             }
-            "filt" => {
-                if header.size < 9 {
-                    return Err(AkpError::InvalidChunkSize("filt".to_string(), header.size));
-                }
-                keygroup.filter = Some(parse_filt_chunk(&mut cursor)?);
+            match counts.env {
+                0 => keygroup.amp_env = Some(parse_amp_env_chunk(&mut cursor)?),
+                1 => keygroup.filter_env = Some(parse_filter_env_chunk(&mut cursor)?),
+                2 => keygroup.aux_env = Some(parse_aux_env_chunk(&mut cursor)?),
+                _ => {}
             }
-            "zone" => {
-                if header.size < 2 {
-                    return Err(AkpError::InvalidChunkSize("zone".to_string(), header.size));
-                }
-                if let Some(zone) = parse_zone_chunk(&mut cursor, header.size)? {
-                    if keygroup.zones.len() < MAX_ZONES_PER_KEYGROUP {
-                        keygroup.zones.push(zone);
-                    }
+            counts.env += 1;
+        }
+        "filt" => {
+            if header.size < 9 {
+                return Err(AkpError::InvalidChunkSize("filt".to_string(), header.size));
+            }
+            match counts.filt {
+                0 => {
+                    keygroup.filter = Some(parse_filt_chunk(&mut cursor)?);
+                    keygroup.filter_routing = parse_filter_routing(&mut cursor, header.size)?;
                 }
+                1 => keygroup.filter2 = Some(parse_filt_chunk(&mut cursor)?),
+                2 => keygroup.filter3 = Some(parse_filt_chunk(&mut cursor)?),
+                _ => {}
             }
-            "smpl" => {
-                // Not in spec — third-party tool artifact. Skip silently.
+            counts.filt += 1;
+        }
+        "zone" => {
+            if header.size < 2 {
+                return Err(AkpError::InvalidChunkSize("zone".to_string(), header.size));
             }
-            _ => {
-                progress.println(format!("Warning: Skipping unknown keygroup chunk type '{}'", header.id));
+            if let Some(zone) = parse_zone_chunk(&mut cursor, header.size)? {
+                if keygroup.zones.len() < MAX_ZONES_PER_KEYGROUP {
+                    keygroup.zones.push(zone);
+                }
             }
+            counts.zone += 1;
+        }
+        "smpl" => {
+            // Not in spec — third-party tool artifact. Skip silently.
+        }
+        _ => {
+            observer.warning(ParseWarning {
+                code: "unknown-chunk",
+                message: "skipped unknown keygroup chunk".to_string(),
+                location: chunk.clone(),
+            });
         }
     }
-    Ok(keygroup)
+    Ok(())
 }
 
-fn read_chunk_header(file: &mut File) -> Result<RiffChunkHeader> {
+fn read_chunk_header<R: Read>(file: &mut R) -> Result<RiffChunkHeader> {
     let mut buf = [0u8; 4];
     file.read_exact(&mut buf)?;
     let id = str::from_utf8(&buf).unwrap_or("????").trim_end_matches('\0').to_string();
@@ -207,14 +410,14 @@ fn read_chunk_header(file: &mut File) -> Result<RiffChunkHeader> {
     Ok(RiffChunkHeader { id, size })
 }
 
-fn parse_program_header(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramHeader> {
+fn parse_program_header(cursor: &mut Cursor<&[u8]>) -> Result<ProgramHeader> {
     cursor.seek(SeekFrom::Start(1))?;
     let midi_program_number = cursor.read_u8()?;
     let number_of_keygroups = cursor.read_u8()?;
     Ok(ProgramHeader { midi_program_number, number_of_keygroups })
 }
 
-pub fn parse_out_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramOutput> {
+pub fn parse_out_chunk(cursor: &mut Cursor<&[u8]>) -> Result<ProgramOutput> {
     cursor.seek(SeekFrom::Start(1))?;
     let loudness = cursor.read_u8()?;
     let amp_mod_1 = cursor.read_u8()?;
@@ -226,7 +429,7 @@ pub fn parse_out_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramOutput> {
     Ok(ProgramOutput { loudness, amp_mod_1, amp_mod_2, pan_mod_1, pan_mod_2, pan_mod_3, velocity_sensitivity })
 }
 
-pub fn parse_tune_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramTuning> {
+pub fn parse_tune_chunk(cursor: &mut Cursor<&[u8]>) -> Result<ProgramTuning> {
     cursor.seek(SeekFrom::Start(1))?;
     let semitone = cursor.read_i8()?;
     let fine = cursor.read_i8()?;
@@ -241,7 +444,7 @@ pub fn parse_tune_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramTuning> {
     Ok(ProgramTuning { semitone, fine, detune, pitchbend_up, pitchbend_down, bend_mode, aftertouch })
 }
 
-pub fn parse_lfo1_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Lfo> {
+pub fn parse_lfo1_chunk(cursor: &mut Cursor<&[u8]>) -> Result<Lfo> {
     cursor.seek(SeekFrom::Start(1))?;
     let waveform = cursor.read_u8()?;
     let rate = cursor.read_u8()?;
@@ -257,7 +460,7 @@ pub fn parse_lfo1_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Lfo> {
     Ok(Lfo { waveform, rate, delay, depth, sync, retrigger: 0, modwheel, aftertouch, rate_mod, delay_mod, depth_mod })
 }
 
-pub fn parse_lfo2_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Lfo> {
+pub fn parse_lfo2_chunk(cursor: &mut Cursor<&[u8]>) -> Result<Lfo> {
     cursor.seek(SeekFrom::Start(1))?;
     let waveform = cursor.read_u8()?;
     let rate = cursor.read_u8()?;
@@ -273,7 +476,7 @@ pub fn parse_lfo2_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Lfo> {
     Ok(Lfo { waveform, rate, delay, depth, sync: 0, retrigger, modwheel: 0, aftertouch: 0, rate_mod, delay_mod, depth_mod })
 }
 
-pub fn parse_mods_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramModulation> {
+pub fn parse_mods_chunk(cursor: &mut Cursor<&[u8]>) -> Result<ProgramModulation> {
     // Source bytes at odd offsets: 5,7,9,11,13,15,17,19,21,23,25,27,29,31,33,35,37
     let offsets: [u64; 17] = [5,7,9,11,13,15,17,19,21,23,25,27,29,31,33,35,37];
     let mut sources = [0u8; 17];
@@ -302,7 +505,7 @@ pub fn parse_mods_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<ProgramModulatio
     })
 }
 
-pub fn parse_kloc_chunk(cursor: &mut Cursor<Vec<u8>>, keygroup: &mut Keygroup) -> Result<()> {
+pub fn parse_kloc_chunk(cursor: &mut Cursor<&[u8]>, keygroup: &mut Keygroup) -> Result<()> {
     cursor.seek(SeekFrom::Start(4))?;
     keygroup.low_key = cursor.read_u8()?;
     keygroup.high_key = cursor.read_u8()?;
@@ -326,7 +529,7 @@ pub fn parse_kloc_chunk(cursor: &mut Cursor<Vec<u8>>, keygroup: &mut Keygroup) -
     Ok(())
 }
 
-pub fn parse_amp_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Envelope> {
+pub fn parse_amp_env_chunk(cursor: &mut Cursor<&[u8]>) -> Result<Envelope> {
     // Non-sequential: attack=1, decay=3, release=4, sustain=7
     cursor.seek(SeekFrom::Start(1))?;
     let attack = cursor.read_u8()?;
@@ -345,7 +548,7 @@ pub fn parse_amp_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Envelope> {
     Ok(Envelope { attack, decay, release, sustain, velocity_attack, keyscale, on_vel_release, off_vel_release })
 }
 
-pub fn parse_filter_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<FilterEnvelope> {
+pub fn parse_filter_env_chunk(cursor: &mut Cursor<&[u8]>) -> Result<FilterEnvelope> {
     cursor.seek(SeekFrom::Start(1))?;
     let attack = cursor.read_u8()?;
     cursor.seek(SeekFrom::Start(3))?;
@@ -364,7 +567,7 @@ pub fn parse_filter_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<FilterEnve
     Ok(FilterEnvelope { attack, decay, release, sustain, depth, velocity_attack, keyscale, on_vel_release, off_vel_release })
 }
 
-pub fn parse_aux_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<AuxEnvelope> {
+pub fn parse_aux_env_chunk(cursor: &mut Cursor<&[u8]>) -> Result<AuxEnvelope> {
     cursor.seek(SeekFrom::Start(1))?;
     let rate_1 = cursor.read_u8()?;
     let rate_2 = cursor.read_u8()?;
@@ -385,7 +588,7 @@ pub fn parse_aux_env_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<AuxEnvelope>
     Ok(AuxEnvelope { rate_1, rate_2, rate_3, rate_4, level_1, level_2, level_3, level_4, vel_rate_1, key_rate_2_4, vel_rate_4, off_vel_rate_4, vel_output_level })
 }
 
-pub fn parse_filt_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Filter> {
+pub fn parse_filt_chunk(cursor: &mut Cursor<&[u8]>) -> Result<Filter> {
     cursor.seek(SeekFrom::Start(1))?;
     let filter_type = cursor.read_u8()?;
     let cutoff = cursor.read_u8()?;
@@ -403,7 +606,34 @@ pub fn parse_filt_chunk(cursor: &mut Cursor<Vec<u8>>) -> Result<Filter> {
     Ok(Filter { filter_type, cutoff, resonance, keyboard_track, mod_input_1, mod_input_2, mod_input_3, headroom })
 }
 
-pub fn parse_zone_chunk(cursor: &mut Cursor<Vec<u8>>, chunk_size: u32) -> Result<Option<Zone>> {
+/// MPC4000 pad assignments: one note byte per pad at offsets 1-64, bank A
+/// pad 1 first. Values above 127 mean the pad is unassigned. Inferred
+/// layout — see akp-format-reference.md.
+pub fn parse_pad_chunk(cursor: &mut Cursor<&[u8]>) -> Result<PadAssignments> {
+    cursor.seek(SeekFrom::Start(1))?;
+    let mut pads = PadAssignments::default();
+    for note in &mut pads.notes {
+        let value = cursor.read_u8()?;
+        *note = (value <= 127).then_some(value);
+    }
+    Ok(pads)
+}
+
+/// Z4/Z8 filter routing, read from the byte after the S5000 fields of the
+/// first `filt` chunk (0 = series, 1 = parallel). S5000/S6000 chunks are
+/// 10 bytes and don't have it. Inferred layout — see akp-format-reference.md.
+pub fn parse_filter_routing(cursor: &mut Cursor<&[u8]>, chunk_size: u32) -> Result<FilterRouting> {
+    if chunk_size < 11 {
+        return Ok(FilterRouting::Series);
+    }
+    cursor.seek(SeekFrom::Start(10))?;
+    Ok(match cursor.read_u8()? {
+        1 => FilterRouting::Parallel,
+        _ => FilterRouting::Series,
+    })
+}
+
+pub fn parse_zone_chunk(cursor: &mut Cursor<&[u8]>, chunk_size: u32) -> Result<Option<Zone>> {
     cursor.seek(SeekFrom::Start(1))?;
     let name_len = cursor.read_u8()? as usize;
 
@@ -480,6 +710,9 @@ pub fn parse_zone_chunk(cursor: &mut Cursor<Vec<u8>>, chunk_size: u32) -> Result
         level,
         keyboard_track,
         vel_to_start,
+        root_key: None,
+        root_tune: 0,
+        sample_loop: None,
     }))
 }
 
@@ -526,7 +759,7 @@ mod tests {
     #[test]
     fn test_parse_zone_extracts_sample_name() {
         let data = make_zone_data(b"Piano_C3.wav", 0, 127);
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let zone = parse_zone_chunk(&mut cursor, 48).unwrap().unwrap();
         assert_eq!(zone.sample_name, "Piano_C3.wav");
         assert_eq!(zone.low_vel, 0);
@@ -536,7 +769,7 @@ mod tests {
     #[test]
     fn test_parse_zone_20char_name() {
         let data = make_zone_data(b"ABCDEFGHIJKLMNOPQRST", 1, 127);
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let zone = parse_zone_chunk(&mut cursor, 48).unwrap().unwrap();
         assert_eq!(zone.sample_name, "ABCDEFGHIJKLMNOPQRST.WAV");
     }
@@ -544,7 +777,7 @@ mod tests {
     #[test]
     fn test_parse_zone_zero_vel_full_range() {
         let data = make_zone_data(b"test.wav", 0, 0);
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let zone = parse_zone_chunk(&mut cursor, 48).unwrap().unwrap();
         assert_eq!(zone.low_vel, 0);
         assert_eq!(zone.high_vel, 127);
@@ -553,7 +786,7 @@ mod tests {
     #[test]
     fn test_parse_zone_invalid_velocity_range() {
         let data = make_zone_data(b"test.wav", 127, 64);
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let result = parse_zone_chunk(&mut cursor, 48);
         assert!(matches!(result, Err(AkpError::InvalidVelocityRange(127, 64))));
     }
@@ -562,7 +795,7 @@ mod tests {
     fn test_parse_zone_no_name_skipped() {
         let mut data = vec![0u8; 48];
         data[1] = 0;
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let result = parse_zone_chunk(&mut cursor, 48).unwrap();
         assert!(result.is_none());
     }
@@ -571,7 +804,7 @@ mod tests {
     fn test_parse_zone_name_len_overflow() {
         let mut data = vec![0u8; 48];
         data[1] = 21; // exceeds max 20
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let result = parse_zone_chunk(&mut cursor, 48);
         assert!(matches!(result, Err(AkpError::CorruptedChunk(_, _))));
     }
@@ -589,7 +822,7 @@ mod tests {
         data[43] = 1;               // keyboard_track
         data[44] = 0;               // vel_to_start low
         data[45] = 0;               // vel_to_start high
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let zone = parse_zone_chunk(&mut cursor, 48).unwrap().unwrap();
         assert_eq!(zone.fine_tune, -5);
         assert_eq!(zone.semitone_tune, 3);
@@ -616,7 +849,7 @@ mod tests {
         data[12] = (-50i8) as u8;  // amp_mod
         data[13] = 1;               // zone_crossfade (ON)
         data[14] = 3;               // mute_group
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let mut keygroup = Keygroup::default();
         parse_kloc_chunk(&mut cursor, &mut keygroup).unwrap();
         assert_eq!(keygroup.low_key, 36);
@@ -635,7 +868,7 @@ mod tests {
         let mut data = vec![0u8; 16];
         data[4] = 80;
         data[5] = 40;
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let mut keygroup = Keygroup::default();
         let result = parse_kloc_chunk(&mut cursor, &mut keygroup);
         assert!(matches!(result, Err(AkpError::InvalidKeyRange(80, 40))));
@@ -646,7 +879,7 @@ mod tests {
     #[test]
     fn test_parse_out_chunk() {
         let data = vec![0, 85, 10, 20, 30, 40, 50, 25];
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let out = parse_out_chunk(&mut cursor).unwrap();
         assert_eq!(out.loudness, 85);
         assert_eq!(out.amp_mod_1, 10);
@@ -666,7 +899,7 @@ mod tests {
         data[16] = 2;                // pitchbend_down
         data[17] = 0;                // bend_mode
         data[18] = (-6i8) as u8;    // aftertouch
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let tune = parse_tune_chunk(&mut cursor).unwrap();
         assert_eq!(tune.semitone, -12);
         assert_eq!(tune.fine, 25);
@@ -691,7 +924,7 @@ mod tests {
         data[9] = (-10i8) as u8;   // rate_mod
         data[10] = 0;               // delay_mod
         data[11] = (-20i8) as u8;  // depth_mod
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let lfo = parse_lfo1_chunk(&mut cursor).unwrap();
         assert_eq!(lfo.waveform, 1);
         assert_eq!(lfo.rate, 50);
@@ -712,7 +945,7 @@ mod tests {
         data[4] = 60;               // depth
         data[6] = 1;                // retrigger (ON)
         data[9] = 5;                // rate_mod
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let lfo = parse_lfo2_chunk(&mut cursor).unwrap();
         assert_eq!(lfo.waveform, 0);
         assert_eq!(lfo.rate, 30);
@@ -730,7 +963,7 @@ mod tests {
         data[5] = 6;    // amp_mod_1_source = KEYBOARD
         data[27] = 7;   // pitch_mod_1_source = LFO1
         data[31] = 5;   // amp_mod_source = VELOCITY
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let mods = parse_mods_chunk(&mut cursor).unwrap();
         assert_eq!(mods.amp_mod_1_source, 6);
         assert_eq!(mods.pitch_mod_1_source, 7);
@@ -751,7 +984,7 @@ mod tests {
         data[12] = 5;                // keyscale
         data[14] = (-10i8) as u8;   // on_vel_release
         data[15] = (-5i8) as u8;    // off_vel_release
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let env = parse_amp_env_chunk(&mut cursor).unwrap();
         assert_eq!(env.attack, 10);
         assert_eq!(env.decay, 50);
@@ -772,7 +1005,7 @@ mod tests {
         data[7] = 70;                // sustain
         data[9] = (-50i8) as u8;    // depth
         data[10] = (-15i8) as u8;   // velocity_attack
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let env = parse_filter_env_chunk(&mut cursor).unwrap();
         assert_eq!(env.attack, 5);
         assert_eq!(env.decay, 60);
@@ -798,7 +1031,7 @@ mod tests {
         data[14] = (-20i8) as u8; // vel_rate_4
         data[15] = (-30i8) as u8; // off_vel_rate_4
         data[16] = (-40i8) as u8; // vel_output_level
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let env = parse_aux_env_chunk(&mut cursor).unwrap();
         assert_eq!(env.rate_1, 10);
         assert_eq!(env.rate_4, 40);
@@ -813,7 +1046,7 @@ mod tests {
     #[test]
     fn test_parse_filt_chunk_expanded() {
         let data = vec![0, 2, 75, 8, 10, (-20i8) as u8, 30, (-40i8) as u8, 3];
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let filter = parse_filt_chunk(&mut cursor).unwrap();
         assert_eq!(filter.filter_type, 2);
         assert_eq!(filter.cutoff, 75);
@@ -828,7 +1061,7 @@ mod tests {
     #[test]
     fn test_parse_filt_chunk_type_zero_is_valid() {
         let data = vec![0, 0, 100, 0, 0, 0, 0, 0, 0];
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let filter = parse_filt_chunk(&mut cursor).unwrap();
         assert_eq!(filter.filter_type, 0); // 2-pole LP, active
         assert_eq!(filter.cutoff, 100);
@@ -837,11 +1070,258 @@ mod tests {
     #[test]
     fn test_parse_filt_chunk_invalid_type() {
         let data = vec![0, 26, 75, 8, 0, 0, 0, 0, 0];
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let result = parse_filt_chunk(&mut cursor);
         assert!(matches!(result, Err(AkpError::InvalidParameterValue(_, 26))));
     }
 
+    #[test]
+    fn test_parse_filter_routing() {
+        // S5000 chunk: no routing byte
+        let mut cursor = Cursor::new([0, 0, 100, 0, 0, 0, 0, 0, 0, 0].as_slice());
+        assert_eq!(parse_filter_routing(&mut cursor, 10).unwrap(), FilterRouting::Series);
+
+        let mut cursor = Cursor::new([0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 1, 0].as_slice());
+        assert_eq!(parse_filter_routing(&mut cursor, 12).unwrap(), FilterRouting::Parallel);
+    }
+
+    // ---- Keygroup tests ----
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn parse_program_bytes(chunks: &[u8]) -> Result<AkaiProgram> {
+        let mut program = AkaiProgram::default();
+        parse_top_level_chunks(&mut Cursor::new(chunks), chunks.len() as u64, &mut program, &mut ())?;
+        Ok(program)
+    }
+
+    fn akp_bytes(chunks: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"APRG");
+        bytes.extend_from_slice(chunks);
+        bytes
+    }
+
+    fn one_keygroup_program() -> Vec<u8> {
+        let mut kloc = vec![0u8; 16];
+        kloc[4] = 36;
+        kloc[5] = 96;
+        let mut kgrp = chunk(b"kloc", &kloc);
+        kgrp.extend(chunk(b"zone", &make_zone_data(b"PIANO C3", 0, 127)));
+        let mut chunks = chunk(b"prg ", &[0, 0, 1, 0, 0, 0]);
+        chunks.extend(chunk(b"kgrp", &kgrp));
+        akp_bytes(&chunks)
+    }
+
+    #[test]
+    fn test_parse_akp_from_bytes_and_stream() {
+        let bytes = one_keygroup_program();
+        let from_bytes = parse_akp_bytes(&bytes).unwrap();
+        let from_stream = parse_akp(&mut Cursor::new(&bytes), &mut ()).unwrap();
+        assert_eq!(format!("{from_bytes:?}"), format!("{from_stream:?}"));
+        assert_eq!(from_bytes.keygroups[0].low_key, 36);
+        assert_eq!(from_bytes.keygroups[0].zones[0].sample_name, "PIANO C3.WAV");
+    }
+
+    #[test]
+    fn test_parse_akp_embedded_in_stream() {
+        // A program inside a larger container starts at the reader's position
+        let mut container = vec![0xAAu8; 100];
+        container.extend(one_keygroup_program());
+        let mut reader = Cursor::new(container);
+        reader.set_position(100);
+        let program = parse_akp(&mut reader, &mut ()).unwrap();
+        assert_eq!(program.keygroups.len(), 1);
+    }
+
+    #[test]
+    fn test_observer_gets_warnings_and_progress() {
+        struct Observer {
+            warnings: Vec<ParseWarning>,
+            fractions: Vec<f32>,
+        }
+        impl ParseObserver for Observer {
+            fn progress(&mut self, fraction: f32) {
+                self.fractions.push(fraction);
+            }
+            fn warning(&mut self, warning: ParseWarning) {
+                self.warnings.push(warning);
+            }
+        }
+
+        let mut kloc = vec![0u8; 16];
+        kloc[5] = 127;
+        let mut kgrp = chunk(b"kloc", &kloc);
+        kgrp.extend(chunk(b"xtra", &[1, 2]));
+        let mut chunks = chunk(b"prg ", &[0, 0, 1, 0, 0, 0]);
+        chunks.extend(chunk(b"junk", &[0; 4]));
+        chunks.extend(chunk(b"kgrp", &kgrp));
+        let bytes = akp_bytes(&chunks);
+
+        let mut observer = Observer { warnings: Vec::new(), fractions: Vec::new() };
+        parse_akp_bytes_with(&bytes, &mut observer).unwrap();
+        let found: Vec<(&str, u64)> = observer.warnings.iter().map(|w| (w.location.path.as_str(), w.location.offset)).collect();
+        assert_eq!(found, [("junk", 26), ("kgrp[0]/xtra", 70)]);
+        assert!(observer.warnings.iter().all(|w| w.code == "unknown-chunk"));
+        assert_eq!(observer.warnings[0].to_string(), "skipped unknown chunk in junk at offset 26 (0x1A)");
+        assert_eq!(observer.fractions.len(), 3);
+        assert!(observer.fractions.windows(2).all(|w| w[0] < w[1] && w[1] < 1.0));
+
+        // The same warnings come out of a stream
+        let mut warnings: Vec<ParseWarning> = Vec::new();
+        parse_akp(&mut Cursor::new(&bytes), &mut warnings).unwrap();
+        assert_eq!(warnings, observer.warnings);
+    }
+
+    #[test]
+    fn test_parse_akp_bytes_errors() {
+        let error = parse_akp_bytes(b"RIFX\0\0\0\0APRG").unwrap_err();
+        assert!(matches!(error.inner(), AkpError::InvalidRiffHeader));
+        assert_eq!(error.location().map(|l| l.offset), Some(0));
+        let no_keygroups = akp_bytes(&chunk(b"prg ", &[0, 0, 1, 0, 0, 0]));
+        assert!(matches!(parse_akp_bytes(&no_keygroups), Err(AkpError::MissingRequiredChunk(_))));
+
+        // Cut short inside a chunk
+        let bytes = one_keygroup_program();
+        assert!(parse_akp_bytes(&bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn test_errors_carry_chunk_path_and_offset() {
+        let mut kloc = vec![0u8; 16];
+        kloc[5] = 127;
+        let zone = make_zone_data(b"PIANO C3", 0, 127);
+        let mut bad_zone = zone.clone();
+        bad_zone[1] = 40; // name longer than the field
+        let good = [chunk(b"kloc", &kloc), chunk(b"zone", &zone), chunk(b"zone", &zone)].concat();
+        let bad = [chunk(b"kloc", &kloc), chunk(b"zone", &zone), chunk(b"zone", &bad_zone)].concat();
+        let chunks = [chunk(b"prg ", &[0, 0, 2, 0, 0, 0]), chunk(b"kgrp", &good), chunk(b"kgrp", &bad)].concat();
+        let bytes = akp_bytes(&chunks);
+
+        // RIFF header 12 + prg 14 + kgrp[0] 144, then kgrp header 8 + kloc 24 + zone 56
+        let error = parse_akp_bytes(&bytes).unwrap_err();
+        assert_eq!(error.code(), "corrupted-chunk");
+        let location = error.location().unwrap();
+        assert_eq!(location.path, "kgrp[1]/zone[1]");
+        assert_eq!(location.offset, 258);
+
+        // Offsets are absolute in the stream, not relative to the program
+        let mut reader = Cursor::new([vec![0u8; 100], bytes].concat());
+        reader.set_position(100);
+        let error = parse_akp(&mut reader, &mut ()).unwrap_err();
+        assert_eq!(error.location().map(|l| (l.path.as_str(), l.offset)), Some(("kgrp[1]/zone[1]", 358)));
+    }
+
+    #[test]
+    fn test_chunk_indexes_past_255() {
+        // 300 empty zones are valid; the bad one after them is zone[300]
+        let mut kloc = vec![0u8; 16];
+        kloc[5] = 127;
+        let mut kgrp = chunk(b"kloc", &kloc);
+        for _ in 0..300 {
+            kgrp.extend(chunk(b"zone", &[0, 0]));
+        }
+        kgrp.extend(chunk(b"zone", &[0, 40]));
+        let bytes = akp_bytes(&[chunk(b"prg ", &[0, 0, 1, 0, 0, 0]), chunk(b"kgrp", &kgrp)].concat());
+
+        let error = parse_akp_bytes(&bytes).unwrap_err();
+        assert_eq!(error.location().unwrap().path, "kgrp[0]/zone[300]");
+    }
+
+    #[test]
+    fn test_parse_z8_keygroup_three_filters() {
+        let mut kloc = vec![0u8; 16];
+        kloc[4] = 36;
+        kloc[5] = 96;
+        let mut kgrp = chunk(b"kloc", &kloc);
+        kgrp.extend(chunk(b"filt", &[0, 1, 80, 4, 0, 0, 0, 0, 0, 0, 1, 0])); // 4-pole LP, parallel
+        kgrp.extend(chunk(b"filt", &[0, 7, 20, 2, 0, 0, 0, 0, 0, 0, 0, 0])); // 2-pole HP
+        kgrp.extend(chunk(b"filt", &[0, 18, 60, 6, 0, 0, 0, 0, 0, 0, 0, 0])); // peak
+        kgrp.extend(chunk(b"zone", &make_zone_data(b"PAD", 0, 127)));
+
+        let program = parse_program_bytes(&chunk(b"kgrp", &kgrp)).unwrap();
+        assert_eq!(program.model, ProgramModel::ZSeries);
+        let keygroup = &program.keygroups[0];
+        assert_eq!(keygroup.filter.as_ref().unwrap().filter_type, 1);
+        assert_eq!(keygroup.filter2.as_ref().unwrap().filter_type, 7);
+        assert_eq!(keygroup.filter3.as_ref().unwrap().filter_type, 18);
+        assert_eq!(keygroup.filter_routing, FilterRouting::Parallel);
+    }
+
+    #[test]
+    fn test_parse_s5000_keygroup_single_filter() {
+        let mut kloc = vec![0u8; 16];
+        kloc[5] = 127;
+        let mut kgrp = chunk(b"kloc", &kloc);
+        kgrp.extend(chunk(b"filt", &[0, 0, 100, 0, 0, 0, 0, 0, 0, 0]));
+
+        let program = parse_program_bytes(&chunk(b"kgrp", &kgrp)).unwrap();
+        assert_eq!(program.model, ProgramModel::S5000);
+        assert!(program.keygroups[0].filter.is_some());
+        assert!(program.keygroups[0].filter2.is_none());
+        assert_eq!(program.keygroups[0].filter_routing, FilterRouting::Series);
+    }
+
+    #[test]
+    fn test_parse_pad_chunk() {
+        let mut data = vec![0xFFu8; 65];
+        data[0] = 0;
+        data[1] = 36;   // A01
+        data[2] = 38;   // A02
+        data[17] = 42;  // B01
+        let pads = parse_pad_chunk(&mut Cursor::new(data.as_slice())).unwrap();
+        assert_eq!(pads.notes[0], Some(36));
+        assert_eq!(pads.notes[1], Some(38));
+        assert_eq!(pads.notes[2], None);
+        assert_eq!(pads.notes[16], Some(42));
+        assert_eq!(pads.assigned().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_mpc4000_pad_chunk_before_prg() {
+        let mut pad_data = vec![0xFFu8; 65];
+        pad_data[1] = 36;
+        let mut bytes = chunk(b"pads", &pad_data);
+        bytes.extend(chunk(b"prg ", &[0, 0, 1, 0, 0, 0]));
+        let mut kloc = vec![0u8; 16];
+        kloc[4] = 36;
+        kloc[5] = 36;
+        bytes.extend(chunk(b"kgrp", &chunk(b"kloc", &kloc)));
+
+        let program = parse_program_bytes(&bytes).unwrap();
+        assert_eq!(program.model, ProgramModel::Mpc4000);
+        assert_eq!(program.pads.as_ref().unwrap().notes[0], Some(36));
+        assert_eq!(program.keygroups.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_mpc4000_kit_fixture() {
+        let mut warnings: Vec<ParseWarning> = Vec::new();
+        let program = parse_akp_bytes_with(&crate::fixtures::mpc4000_kit(), &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(program.model, ProgramModel::Mpc4000);
+        let pads = program.pads.as_ref().unwrap();
+        assert_eq!(pads.assigned().collect::<Vec<_>>(), [(0, 36), (1, 38), (16, 36)]);
+        assert_eq!(program.keygroups.len(), 2);
+        assert_eq!(program.keygroups[1].low_key, 38);
+        assert_eq!(program.keygroups[1].zones[0].sample_name, "SNARE.WAV");
+    }
+
+    #[test]
+    fn test_unknown_chunk_after_prg_is_not_pads() {
+        let mut bytes = chunk(b"prg ", &[0, 0, 1, 0, 0, 0]);
+        bytes.extend(chunk(b"xtra", &[0u8; 65]));
+        let program = parse_program_bytes(&bytes).unwrap();
+        assert_eq!(program.model, ProgramModel::S5000);
+        assert!(program.pads.is_none());
+    }
+
     // ---- Sanitize path tests ----
 
     #[test]
@@ -900,7 +1380,7 @@ mod tests {
     #[test]
     fn test_parse_program_header() {
         let data = vec![0, 5, 11]; // flags=0, midi_pgm=5, num_keygroups=11
-        let mut cursor = Cursor::new(data);
+        let mut cursor = Cursor::new(data.as_slice());
         let header = parse_program_header(&mut cursor).unwrap();
         assert_eq!(header.midi_program_number, 5);
         assert_eq!(header.number_of_keygroups, 11);
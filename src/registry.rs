@@ -0,0 +1,880 @@
+//! Readers and writers for each format, behind the `ProgramReader` and
+//! `ProgramWriter` traits, and the `Registry` that holds them. The CLI's
+//! `--format` names, the GUI's format picker and `convert_file` all come
+//! from the registry, so a format added here (or registered by another
+//! crate) shows up everywhere.
+//!
+//! Input formats are detected by magic bytes where the format has them
+//! (RIFF `APRG` for AKP, RIFF `sfbk` for SF2) and by extension otherwise.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::dspreset_import;
+use crate::error::{AkpError, Result};
+use crate::parser::{parse_akp_bytes_with, ParseObserver};
+use crate::s1000::{self, SampleConversion};
+use crate::samples::{apply_sample_info, MissingSamples, SampleInfoReport};
+use crate::sf2_import;
+use crate::sfz_import;
+use crate::types::{AkaiProgram, ConvertOptions};
+
+/// Bytes read from the start of a file for `ProgramReader::detect`.
+pub const HEADER_LEN: usize = 64;
+
+/// Names and extensions of a format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatInfo {
+    /// Name used with `--format`, e.g. `ds`.
+    pub id: &'static str,
+    /// Other names accepted for `id`.
+    pub aliases: &'static [&'static str],
+    /// Display name, e.g. `Decent Sampler`.
+    pub name: &'static str,
+    /// One-line description for format pickers.
+    pub description: &'static str,
+    /// File extensions without the dot. Writers name their output with
+    /// the first.
+    pub extensions: &'static [&'static str],
+}
+
+impl FormatInfo {
+    /// Extension written output gets.
+    pub fn extension(&self) -> &'static str {
+        self.extensions.first().copied().unwrap_or_default()
+    }
+
+    /// Whether `name` is the id or an alias, ignoring case.
+    pub fn matches(&self, name: &str) -> bool {
+        self.id.eq_ignore_ascii_case(name) || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+
+    fn has_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+}
+
+/// What a writer's output is, so callers can treat writers alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WriterCapabilities {
+    /// A single text file, available from `ProgramWriter::to_text`.
+    pub text: bool,
+    /// The writer puts its own copy of the samples in the output, inside
+    /// the file or beside it, so there's no need to copy them alongside.
+    pub bundles_samples: bool,
+    /// Written as a folder layout for a hardware card rather than as one
+    /// file next to the input.
+    pub card_layout: bool,
+    /// Can refer to a Scala `.scl` file for the detune table
+    /// (`ConvertOptions::scala_file`).
+    pub scala_tuning: bool,
+}
+
+/// Where a writer puts a program.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteTarget<'a> {
+    /// Directory the output goes in (or the card root for card layouts).
+    pub output_dir: &'a Path,
+    /// Program name; single-file output is `<name>.<extension>`.
+    pub name: &'a str,
+    /// Directory the program's samples are read from.
+    pub sample_dir: &'a Path,
+}
+
+impl WriteTarget<'_> {
+    /// `<output_dir>/<name>.<extension>`.
+    pub fn path(&self, info: &FormatInfo) -> PathBuf {
+        self.output_dir.join(format!("{}.{}", self.name, info.extension()))
+    }
+}
+
+/// What a writer wrote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteOutcome {
+    /// The preset file.
+    pub path: PathBuf,
+    /// (sample name, reason) for each sample left out.
+    pub missing_samples: MissingSamples,
+}
+
+/// An output format.
+pub trait ProgramWriter: Send + Sync {
+    fn info(&self) -> &FormatInfo;
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities::default()
+    }
+
+    /// The output as text, for writers whose capabilities include `text`.
+    fn to_text(&self, _program: &AkaiProgram, _name: &str, _options: &ConvertOptions) -> Option<String> {
+        None
+    }
+
+    /// Write `program` to `target`. The default writes `to_text` to
+    /// `<name>.<extension>`, so text writers only need `to_text`.
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let text = self.to_text(program, target.name, options).ok_or_else(|| {
+            AkpError::UnsupportedFormat(format!("{} writer has no output", self.info().name))
+        })?;
+        let path = target.path(self.info());
+        fs::write(&path, text)?;
+        Ok(WriteOutcome { path, missing_samples: Vec::new() })
+    }
+}
+
+/// A program read from an input file.
+#[derive(Debug, Clone)]
+pub struct ReadProgram {
+    /// Name for the output, from the file name.
+    pub name: String,
+    pub program: AkaiProgram,
+    /// Parts of the input that couldn't be represented.
+    pub skipped: Vec<String>,
+}
+
+/// WAVs written by `ProgramReader::extract_samples`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractedSamples {
+    pub written: usize,
+    /// (sample name, reason) for each sample that couldn't be written.
+    pub missing_samples: MissingSamples,
+}
+
+/// An input format.
+pub trait ProgramReader: Send + Sync {
+    fn info(&self) -> &FormatInfo;
+
+    /// Whether `path` is in this format. `header` holds up to the first
+    /// `HEADER_LEN` bytes of the file, or nothing if it can't be read.
+    fn detect(&self, path: &Path, header: &[u8]) -> bool;
+
+    /// Writer id to use when no output format is chosen.
+    fn default_output(&self) -> &'static str {
+        "sfz"
+    }
+
+    /// Read the programs in `path`; most formats hold exactly one. Parse
+    /// progress and warnings go to `observer` for formats that report them.
+    /// Reading writes nothing; see `extract_samples`.
+    fn read(&self, path: &Path, observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>>;
+
+    /// Write the samples the programs in `path` play as WAVs in
+    /// `output_dir`, for formats whose samples aren't WAV files the
+    /// programs can point at. Source samples are looked up in
+    /// `sample_dir`. The default, for formats whose samples are WAVs
+    /// already, writes nothing and returns `None`.
+    fn extract_samples(&self, _path: &Path, _sample_dir: &Path, _output_dir: &Path) -> Result<Option<ExtractedSamples>> {
+        Ok(None)
+    }
+
+    /// Fill in what `program` takes from its WAVs in `sample_dir` (root
+    /// keys, tuning and loops), for formats that keep those in the sample
+    /// rather than the program. The default leaves the program as read.
+    fn apply_sample_info(&self, _program: &mut AkaiProgram, _sample_dir: &Path) -> Option<SampleInfoReport> {
+        None
+    }
+}
+
+/// The readers and writers available. `Default` has the built-in formats;
+/// later registrations with the same id replace earlier ones.
+pub struct Registry {
+    readers: Vec<Box<dyn ProgramReader>>,
+    writers: Vec<Box<dyn ProgramWriter>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register_reader(AkpReader);
+        registry.register_reader(S1000Reader);
+        registry.register_reader(SfzReader);
+        registry.register_reader(DsReader);
+        registry.register_reader(Sf2Reader);
+
+        registry.register_writer(SfzWriter);
+        registry.register_writer(DsWriter);
+        registry.register_writer(AkpWriter);
+        registry.register_writer(Sf2Writer);
+        registry.register_writer(BitwigWriter);
+        registry.register_writer(AbletonWriter);
+        registry.register_writer(XpmWriter);
+        registry.register_writer(ExsWriter);
+        registry.register_writer(RenoiseWriter);
+        registry.register_writer(DelugeWriter);
+        registry.register_writer(BlackboxWriter);
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry with no formats.
+    pub fn empty() -> Self {
+        Registry { readers: Vec::new(), writers: Vec::new() }
+    }
+
+    pub fn register_reader(&mut self, reader: impl ProgramReader + 'static) {
+        let id = reader.info().id;
+        match self.readers.iter_mut().find(|r| r.info().id == id) {
+            Some(existing) => *existing = Box::new(reader),
+            None => self.readers.push(Box::new(reader)),
+        }
+    }
+
+    pub fn register_writer(&mut self, writer: impl ProgramWriter + 'static) {
+        let id = writer.info().id;
+        match self.writers.iter_mut().find(|w| w.info().id == id) {
+            Some(existing) => *existing = Box::new(writer),
+            None => self.writers.push(Box::new(writer)),
+        }
+    }
+
+    pub fn readers(&self) -> impl Iterator<Item = &dyn ProgramReader> {
+        self.readers.iter().map(|r| r.as_ref())
+    }
+
+    pub fn writers(&self) -> impl Iterator<Item = &dyn ProgramWriter> {
+        self.writers.iter().map(|w| w.as_ref())
+    }
+
+    /// The writer with `name` as its id or an alias.
+    pub fn writer(&self, name: &str) -> Option<&dyn ProgramWriter> {
+        self.writers().find(|w| w.info().matches(name))
+    }
+
+    /// The writer ids, comma-separated, for error messages and help.
+    pub fn writer_ids(&self) -> String {
+        self.writers().map(|w| w.info().id).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Every extension a reader accepts, for file pickers.
+    pub fn input_extensions(&self) -> Vec<&'static str> {
+        self.readers().flat_map(|r| r.info().extensions.iter().copied()).collect()
+    }
+
+    /// The first reader that recognises `path`.
+    pub fn detect(&self, path: &Path) -> Option<&dyn ProgramReader> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        if let Ok(file) = File::open(path) {
+            // An unreadable file can still be recognised by extension
+            let _ = file.take(HEADER_LEN as u64).read_to_end(&mut header);
+        }
+        self.readers().find(|r| r.detect(path, &header))
+    }
+
+    /// Read the first program in `input_path` and return it with its
+    /// output from the text writer `format`, which may be any registered
+    /// writer's id or alias. Sample details come from the WAVs next to the
+    /// input; samples aren't extracted (see `ProgramReader::extract_samples`).
+    pub fn convert_file_with_program(
+        &self,
+        input_path: &Path,
+        format: &str,
+        options: &ConvertOptions,
+        observer: &mut dyn ParseObserver,
+    ) -> Result<(String, AkaiProgram)> {
+        let writer = self.writer(format).ok_or_else(|| {
+            AkpError::UnsupportedFormat(format!("unknown output format '{format}' (valid: {})", self.writer_ids()))
+        })?;
+        let reader = self.detect(input_path).ok_or_else(|| {
+            AkpError::UnsupportedFormat(format!("'{}' isn't a format that can be read", input_path.display()))
+        })?;
+        if reader.info().id == writer.info().id {
+            return Err(AkpError::UnsupportedFormat(format!(
+                "'{}' is already {}", input_path.display(), writer.info().name,
+            )));
+        }
+        if !writer.capabilities().text {
+            return Err(AkpError::UnsupportedFormat(format!(
+                "{} output isn't text; use ProgramWriter::write", writer.info().name,
+            )));
+        }
+
+        let mut read = reader.read(input_path, observer)?.into_iter().next().ok_or_else(|| {
+            AkpError::InvalidPreset(reader.info().name.to_string(), "no programs found".to_string())
+        })?;
+        reader.apply_sample_info(&mut read.program, input_path.parent().unwrap_or(Path::new(".")));
+        let output = writer.to_text(&read.program, &read.name, options).ok_or_else(|| {
+            AkpError::UnsupportedFormat(format!("{} writer has no text output", writer.info().name))
+        })?;
+        Ok((output, read.program))
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+// ---- Readers ----
+
+/// AKP programs (S5000/S6000, Z-series, MPC4000). Root keys come from the
+/// WAVs.
+pub struct AkpReader;
+
+impl ProgramReader for AkpReader {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "akp",
+            aliases: &[],
+            name: "AKP",
+            description: "Akai S5000/S6000 program",
+            extensions: &["akp"],
+        }
+    }
+
+    fn detect(&self, path: &Path, header: &[u8]) -> bool {
+        header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"APRG"
+            || self.info().has_extension(path)
+    }
+
+    fn read(&self, path: &Path, observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>> {
+        let program = parse_akp_bytes_with(&fs::read(path)?, observer)?;
+        Ok(vec![ReadProgram { name: file_stem(path), program, skipped: Vec::new() }])
+    }
+
+    fn apply_sample_info(&self, program: &mut AkaiProgram, sample_dir: &Path) -> Option<SampleInfoReport> {
+        Some(apply_sample_info(program, sample_dir))
+    }
+}
+
+/// S1000/S3000 program files. Their samples are a separate format, which
+/// `extract_samples` converts to WAV; root keys come from those WAVs.
+pub struct S1000Reader;
+
+impl ProgramReader for S1000Reader {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "s1000",
+            aliases: &["s3000"],
+            name: "S1000/S3000",
+            description: "Akai S1000/S3000 program",
+            extensions: &s1000::PROGRAM_EXTENSIONS,
+        }
+    }
+
+    fn detect(&self, path: &Path, _header: &[u8]) -> bool {
+        s1000::is_program_file(path)
+    }
+
+    fn read(&self, path: &Path, _observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>> {
+        let program = s1000::read_s1000_program(path)?;
+        Ok(vec![ReadProgram { name: file_stem(path), program, skipped: Vec::new() }])
+    }
+
+    fn extract_samples(&self, path: &Path, sample_dir: &Path, output_dir: &Path) -> Result<Option<ExtractedSamples>> {
+        let program = s1000::read_s1000_program(path)?;
+        let mut extracted = ExtractedSamples::default();
+        for conversion in s1000::convert_s1000_samples(&program, sample_dir, output_dir) {
+            match conversion {
+                SampleConversion::Converted { .. } => extracted.written += 1,
+                SampleConversion::Missing(name) => extracted.missing_samples.push((name, "not found".to_string())),
+                SampleConversion::Failed { sample, error } => extracted.missing_samples.push((sample, error)),
+            }
+        }
+        Ok(Some(extracted))
+    }
+
+    fn apply_sample_info(&self, program: &mut AkaiProgram, sample_dir: &Path) -> Option<SampleInfoReport> {
+        Some(apply_sample_info(program, sample_dir))
+    }
+}
+
+pub struct SfzReader;
+
+impl ProgramReader for SfzReader {
+    fn info(&self) -> &FormatInfo {
+        &SFZ_INFO
+    }
+
+    fn detect(&self, path: &Path, _header: &[u8]) -> bool {
+        self.info().has_extension(path)
+    }
+
+    /// SFZ input is mostly for building S5000/S6000 programs.
+    fn default_output(&self) -> &'static str {
+        "akp"
+    }
+
+    fn read(&self, path: &Path, _observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>> {
+        let import = sfz_import::read_sfz(path)?;
+        let skipped = import.skipped.iter().map(|s| s.to_string()).collect();
+        Ok(vec![ReadProgram { name: file_stem(path), program: import.program, skipped }])
+    }
+}
+
+pub struct DsReader;
+
+impl ProgramReader for DsReader {
+    fn info(&self) -> &FormatInfo {
+        &DS_INFO
+    }
+
+    fn detect(&self, path: &Path, header: &[u8]) -> bool {
+        self.info().has_extension(path)
+            || header.windows(14).any(|w| w == b"<DecentSampler")
+    }
+
+    fn read(&self, path: &Path, _observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>> {
+        let import = dspreset_import::read_dspreset(path)?;
+        let skipped = import.skipped.iter().map(|s| s.to_string()).collect();
+        Ok(vec![ReadProgram { name: file_stem(path), program: import.program, skipped }])
+    }
+}
+
+/// SoundFont 2 files, one program per preset. The programs play the
+/// embedded samples by file name, once `extract_samples` has written them.
+pub struct Sf2Reader;
+
+impl ProgramReader for Sf2Reader {
+    fn info(&self) -> &FormatInfo {
+        &SF2_INFO
+    }
+
+    fn detect(&self, path: &Path, header: &[u8]) -> bool {
+        header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"sfbk"
+            || self.info().has_extension(path)
+    }
+
+    fn read(&self, path: &Path, _observer: &mut dyn ParseObserver) -> Result<Vec<ReadProgram>> {
+        let import = sf2_import::read_sf2(path)?;
+        let skipped: Vec<String> = import.skipped.iter().map(|s| s.to_string()).collect();
+        let names = import.file_names(&file_stem(path));
+        Ok(import.presets.into_iter().zip(names)
+            .map(|(preset, name)| ReadProgram { name, program: preset.program, skipped: skipped.clone() })
+            .collect())
+    }
+
+    fn extract_samples(&self, path: &Path, _sample_dir: &Path, output_dir: &Path) -> Result<Option<ExtractedSamples>> {
+        let import = sf2_import::read_sf2(path)?;
+        import.write_samples(output_dir)?;
+        Ok(Some(ExtractedSamples { written: import.samples.len(), missing_samples: Vec::new() }))
+    }
+}
+
+// ---- Writers ----
+
+const SFZ_INFO: FormatInfo = FormatInfo {
+    id: "sfz",
+    aliases: &[],
+    name: "SFZ",
+    description: "Standard sampler format — compatible with most samplers",
+    extensions: &["sfz"],
+};
+
+const DS_INFO: FormatInfo = FormatInfo {
+    id: "ds",
+    aliases: &["dspreset", "decent", "decentsampler"],
+    name: "Decent Sampler",
+    description: "Decent Sampler XML — includes UI controls and effects",
+    extensions: &["dspreset"],
+};
+
+const SF2_INFO: FormatInfo = FormatInfo {
+    id: "sf2",
+    aliases: &["soundfont"],
+    name: "SF2",
+    description: "SoundFont 2 with embedded samples",
+    extensions: &["sf2"],
+};
+
+pub struct SfzWriter;
+
+impl ProgramWriter for SfzWriter {
+    fn info(&self) -> &FormatInfo {
+        &SFZ_INFO
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { text: true, scala_tuning: true, ..Default::default() }
+    }
+
+    fn to_text(&self, program: &AkaiProgram, _name: &str, options: &ConvertOptions) -> Option<String> {
+        Some(program.to_sfz_string_with(options))
+    }
+}
+
+pub struct DsWriter;
+
+impl ProgramWriter for DsWriter {
+    fn info(&self) -> &FormatInfo {
+        &DS_INFO
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { text: true, ..Default::default() }
+    }
+
+    fn to_text(&self, program: &AkaiProgram, _name: &str, options: &ConvertOptions) -> Option<String> {
+        Some(program.to_dspreset_string_with(options))
+    }
+}
+
+pub struct XpmWriter;
+
+impl ProgramWriter for XpmWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "mpc",
+            aliases: &["xpm"],
+            name: "MPC",
+            description: "Akai MPC keygroup program",
+            extensions: &["xpm"],
+        }
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { text: true, ..Default::default() }
+    }
+
+    fn to_text(&self, program: &AkaiProgram, name: &str, options: &ConvertOptions) -> Option<String> {
+        Some(program.to_xpm_string_with(name, options))
+    }
+}
+
+/// Writes the program with its samples beside it, under the names the
+/// S5000/S6000 looks for (see `AkaiProgram::write_akp_with_samples`).
+pub struct AkpWriter;
+
+impl ProgramWriter for AkpWriter {
+    fn info(&self) -> &FormatInfo {
+        AkpReader.info()
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, _options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_akp_with_samples(&path, target.sample_dir)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+pub struct Sf2Writer;
+
+impl ProgramWriter for Sf2Writer {
+    fn info(&self) -> &FormatInfo {
+        &SF2_INFO
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_sf2(&path, target.sample_dir, options)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+pub struct BitwigWriter;
+
+impl ProgramWriter for BitwigWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "bitwig",
+            aliases: &["multisample"],
+            name: "Bitwig multisample",
+            description: "Bitwig Studio multisample with bundled samples",
+            extensions: &["multisample"],
+        }
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_multisample(&path, target.sample_dir, options)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+pub struct AbletonWriter;
+
+impl ProgramWriter for AbletonWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "ableton",
+            aliases: &["adv"],
+            name: "Ableton Sampler",
+            description: "Ableton Live Sampler preset",
+            extensions: &["adv"],
+        }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_adv(&path, target.sample_dir, options)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+pub struct ExsWriter;
+
+impl ProgramWriter for ExsWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "exs",
+            aliases: &["exs24", "logic"],
+            name: "EXS24",
+            description: "Logic EXS24 instrument",
+            extensions: &["exs"],
+        }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_exs(&path, target.sample_dir, options)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+pub struct RenoiseWriter;
+
+impl ProgramWriter for RenoiseWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "renoise",
+            aliases: &["xrni"],
+            name: "Renoise",
+            description: "Renoise instrument with bundled samples",
+            extensions: &["xrni"],
+        }
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let path = target.path(self.info());
+        let missing_samples = program.write_xrni(&path, target.sample_dir, options)?;
+        Ok(WriteOutcome { path, missing_samples })
+    }
+}
+
+/// Writes `SYNTHS/<name>.XML` and `SAMPLES/<name>/` under the output
+/// directory.
+pub struct DelugeWriter;
+
+impl ProgramWriter for DelugeWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "deluge",
+            aliases: &[],
+            name: "Deluge",
+            description: "Synthstrom Deluge synth preset",
+            extensions: &["XML"],
+        }
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, card_layout: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let export = program.write_deluge(target.output_dir, target.name, target.sample_dir, options)?;
+        Ok(WriteOutcome { path: export.preset_path, missing_samples: export.missing_samples })
+    }
+}
+
+/// Writes `Presets/<name>/preset.xml` under the output directory.
+pub struct BlackboxWriter;
+
+impl ProgramWriter for BlackboxWriter {
+    fn info(&self) -> &FormatInfo {
+        &FormatInfo {
+            id: "blackbox",
+            aliases: &["1010"],
+            name: "Blackbox",
+            description: "1010music Blackbox multisample preset",
+            extensions: &["xml"],
+        }
+    }
+
+    fn capabilities(&self) -> WriterCapabilities {
+        WriterCapabilities { bundles_samples: true, card_layout: true, ..Default::default() }
+    }
+
+    fn write(&self, program: &AkaiProgram, target: &WriteTarget, options: &ConvertOptions) -> Result<WriteOutcome> {
+        let export = program.write_blackbox(target.output_dir, target.name, target.sample_dir, options)?;
+        Ok(WriteOutcome { path: export.preset_path, missing_samples: export.missing_samples })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Keygroup, Zone};
+    use tempfile::TempDir;
+
+    fn program() -> AkaiProgram {
+        AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "Piano C3".to_string(), ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_writer_lookup() {
+        let registry = Registry::default();
+        assert_eq!(registry.writer("DecentSampler").unwrap().info().id, "ds");
+        assert_eq!(registry.writer("logic").unwrap().info().extension(), "exs");
+        assert!(registry.writer("wav").is_none());
+        assert!(registry.writer("sfz").unwrap().capabilities().scala_tuning);
+        assert!(registry.writer("deluge").unwrap().capabilities().card_layout);
+
+        let text: Vec<&str> = registry.writers()
+            .filter(|w| w.capabilities().text)
+            .map(|w| w.info().id)
+            .collect();
+        assert_eq!(text, ["sfz", "ds", "mpc"]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_output_format_ids_name_writers() {
+        use crate::types::OutputFormat;
+
+        let registry = Registry::default();
+        for format in [OutputFormat::Sfz, OutputFormat::Akp, OutputFormat::MpcXpm, OutputFormat::Blackbox] {
+            assert_eq!(registry.writer(format.id()).unwrap().info().id, format.id());
+        }
+    }
+
+    #[test]
+    fn test_detect_by_magic_and_extension() {
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::default();
+        let detected = |name: &str, bytes: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, bytes).unwrap();
+            registry.detect(&path).map(|r| r.info().id)
+        };
+
+        // Magic bytes win over a misleading extension
+        assert_eq!(detected("program.bin", b"RIFF\x04\x00\x00\x00APRG"), Some("akp"));
+        assert_eq!(detected("bank.dat", b"RIFF\x04\x00\x00\x00sfbk"), Some("sf2"));
+        assert_eq!(detected("preset.xml", b"<?xml version=\"1.0\"?>\n<DecentSampler>"), Some("ds"));
+        assert_eq!(detected("piano.SFZ", b"<region>"), Some("sfz"));
+        assert_eq!(detected("PIANO.P3", &[0; 16]), Some("s1000"));
+        assert_eq!(detected("notes.txt", b"hello"), None);
+    }
+
+    #[test]
+    fn test_write_through_trait() {
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::default();
+        let target = WriteTarget { output_dir: dir.path(), name: "Piano", sample_dir: dir.path() };
+
+        let outcome = registry.writer("mpc").unwrap().write(&program(), &target, &ConvertOptions::default()).unwrap();
+        assert_eq!(outcome.path, dir.path().join("Piano.xpm"));
+        assert!(fs::read_to_string(&outcome.path).unwrap().contains("Piano C3"));
+
+        let outcome = registry.writer("akp").unwrap().write(&program(), &target, &ConvertOptions::default()).unwrap();
+        assert_eq!(registry.detect(&outcome.path).unwrap().info().id, "akp");
+    }
+
+    #[test]
+    fn test_sf2_samples_extract_only_on_request() {
+        use crate::samples::{WavAudio, WavInfo};
+
+        let source = TempDir::new().unwrap();
+        let info = WavInfo {
+            sample_rate: 22050, channels: 1, bits_per_sample: 16, frames: 4,
+            unity_note: Some(48), pitch_fraction_cents: 0, sample_loop: None,
+        };
+        fs::write(source.path().join("Piano C3.wav"), WavAudio { info, channels: vec![vec![1, 2, 3, 4]] }.to_wav_bytes()).unwrap();
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("piano.sf2");
+        let target = WriteTarget { output_dir: dir.path(), name: "piano", sample_dir: source.path() };
+        Registry::default().writer("sf2").unwrap().write(&program(), &target, &ConvertOptions::default()).unwrap();
+
+        let registry = Registry::default();
+        let reader = registry.detect(&input).unwrap();
+        let read = reader.read(&input, &mut ()).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "read wrote files");
+
+        let extracted = reader.extract_samples(&input, dir.path(), dir.path()).unwrap().unwrap();
+        assert_eq!(extracted, ExtractedSamples { written: 1, missing_samples: Vec::new() });
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // WAV-based readers have nothing to extract
+        let akp = dir.path().join("piano.akp");
+        program().write_akp(&akp).unwrap();
+        assert_eq!(registry.detect(&akp).unwrap().extract_samples(&akp, dir.path(), dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_registered_format_replaces_and_converts() {
+        struct Upper;
+        impl ProgramWriter for Upper {
+            fn info(&self) -> &FormatInfo {
+                &FormatInfo { id: "sfz", aliases: &["upper"], name: "Upper SFZ", description: "", extensions: &["sfz"] }
+            }
+            fn capabilities(&self) -> WriterCapabilities {
+                WriterCapabilities { text: true, ..Default::default() }
+            }
+            fn to_text(&self, program: &AkaiProgram, _name: &str, options: &ConvertOptions) -> Option<String> {
+                Some(program.to_sfz_string_with(options).to_uppercase())
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("piano.akp");
+        program().write_akp(&input).unwrap();
+
+        let mut registry = Registry::default();
+        registry.register_writer(Upper);
+        assert_eq!(registry.writers().count(), Registry::default().writers().count());
+        let options = ConvertOptions::default();
+        let (output, read) = registry.convert_file_with_program(&input, "upper", &options, &mut ()).unwrap();
+        assert!(output.contains("<REGION>"));
+        assert_eq!(read.keygroups.len(), 1);
+
+        assert!(matches!(
+            registry.convert_file_with_program(&input, "akp", &options, &mut ()),
+            Err(AkpError::UnsupportedFormat(_)),
+        ));
+    }
+
+    #[test]
+    fn test_convert_passes_options_and_rejects_missing_text() {
+        // Text only for drum kits, to see the options arrive
+        struct KitsOnly;
+        impl ProgramWriter for KitsOnly {
+            fn info(&self) -> &FormatInfo {
+                &FormatInfo { id: "kits", aliases: &[], name: "Kits", description: "", extensions: &["txt"] }
+            }
+            fn capabilities(&self) -> WriterCapabilities {
+                WriterCapabilities { text: true, ..Default::default() }
+            }
+            fn to_text(&self, _program: &AkaiProgram, name: &str, options: &ConvertOptions) -> Option<String> {
+                options.drum_kit_layout.then(|| format!("kit {name}"))
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("piano.akp");
+        program().write_akp(&input).unwrap();
+        let mut registry = Registry::default();
+        registry.register_writer(KitsOnly);
+
+        let options = ConvertOptions { drum_kit_layout: true, ..Default::default() };
+        let (output, _) = registry.convert_file_with_program(&input, "kits", &options, &mut ()).unwrap();
+        assert_eq!(output, "kit piano");
+
+        let result = registry.convert_file_with_program(&input, "kits", &ConvertOptions::default(), &mut ());
+        assert!(matches!(result, Err(AkpError::UnsupportedFormat(_))));
+    }
+}
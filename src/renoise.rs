@@ -0,0 +1,377 @@
+//! Renoise instrument (`.xrni`) output: a zip holding `Instrument.xml` and
+//! a `SampleData` file per sample, which Renoise loads into its instrument
+//! list.
+//!
+//! Renoise samples each carry their own audio, so every WAV becomes one
+//! sample, stored once, and mapped across the keys and velocities of the
+//! zones that play it; its other settings are the first zone's. Each keygroup gets a modulation set holding its amp
+//! envelope (volume AHDSR), filter 1 and filter envelope (cutoff AHDSR),
+//! plus the program's LFO 1 on pitch. Modulation device parameters are
+//! 0-1 in Renoise, so envelope times and levels and the cutoff are written
+//! as the same fraction of Akai's 0-100 range.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::bitwig::zip_stored;
+use crate::dspreset::xml_escape;
+use crate::error::Result;
+use crate::samples::{find_wav_info, LoadedSamples, MissingSamples, WavInfo, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions, Filter, Keygroup, Lfo, Zone};
+
+/// Renoise notes run C-0 to B-9, with C-4 (48) on MIDI note 60.
+const NOTE_OFFSET: u8 = 12;
+const HIGHEST_NOTE: u8 = 119;
+
+/// Highest sample mute group Renoise has.
+const MAX_MUTE_GROUP: u8 = 15;
+
+/// A `.xrni` file and the samples that couldn't go in it.
+#[derive(Debug, Clone)]
+pub struct XrniOutput {
+    pub bytes: Vec<u8>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample file read for the zip.
+struct LoadedSample {
+    stem: String,
+    bytes: Vec<u8>,
+    info: WavInfo,
+}
+
+/// Where a Renoise sample plays: the first zone using its file, and the
+/// key and velocity range covering every zone that does.
+struct MappedSample<'a> {
+    zone: &'a Zone,
+    keygroup: &'a Keygroup,
+    modulation_set: usize,
+    keys: (u8, u8),
+    velocities: (u8, u8),
+}
+
+impl AkaiProgram {
+    /// Build a `.xrni` instrument named `name`, reading samples from
+    /// `sample_dir`. Zones whose sample can't be read are left out and
+    /// listed in the output; if none can be read this fails.
+    pub fn to_xrni(&self, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<XrniOutput> {
+        let mut loaded: LoadedSamples<LoadedSample> = LoadedSamples::new();
+        // In load order, so the nth is the nth loaded sample
+        let mut mapped: Vec<MappedSample> = Vec::new();
+        let mut mapped_indices: HashMap<&str, usize> = HashMap::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+        let program_volume = self.output.as_ref().map_or(1.0, |o| o.loudness as f32 / 100.0);
+
+        // Per-key detune would copy the sample for every key, so it isn't split
+        for range in self.key_layout(false, options.drum_kit_layout) {
+            let keygroup = range.keygroup;
+            let modulation_set = self.keygroups.iter().position(|kg| std::ptr::eq(kg, keygroup)).unwrap_or(0);
+            for zone in &keygroup.zones {
+                if zone.sample_name.is_empty() || loaded.load(&zone.sample_name, |_| load_sample(sample_dir, &zone.sample_name)).is_none() {
+                    continue;
+                }
+                let velocities = (zone.low_vel.min(127), zone.high_vel.min(127));
+                match mapped_indices.get(zone.sample_name.as_str()) {
+                    Some(&index) => {
+                        let sample = &mut mapped[index];
+                        sample.keys = (sample.keys.0.min(range.low_key), sample.keys.1.max(range.high_key));
+                        sample.velocities = (sample.velocities.0.min(velocities.0), sample.velocities.1.max(velocities.1));
+                    }
+                    None => {
+                        mapped_indices.insert(&zone.sample_name, mapped.len());
+                        mapped.push(MappedSample { zone, keygroup, modulation_set, keys: (range.low_key, range.high_key), velocities });
+                    }
+                }
+            }
+        }
+        let (loaded, missing_samples) = loaded.finish(sample_dir)?;
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<RenoiseInstrument doc_version=\"14\">\n");
+        xml.push_str(&format!("  <Name>{}</Name>\n", xml_escape(name)));
+        xml.push_str("  <GlobalProperties>\n");
+        xml.push_str(&format!("    <Volume>{program_volume:.4}</Volume>\n"));
+        xml.push_str("  </GlobalProperties>\n");
+        xml.push_str("  <SampleGenerator>\n");
+        xml.push_str("    <Samples>\n");
+
+        for (mapped, sample) in mapped.iter().zip(&loaded) {
+            let (zone, keygroup) = (mapped.zone, mapped.keygroup);
+
+            // Whole semitones go to Transpose; Finetune steps are 1/128 semitone
+            let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                + zone.root_tune as i32;
+            let transpose = (cents as f32 / 100.0).round() as i32;
+            let finetune = ((cents - transpose * 100) as f32 * 1.28).round() as i32;
+
+            xml.push_str("      <Sample>\n");
+            xml.push_str(&format!("        <Name>{}</Name>\n", xml_escape(&zone.sample_name)));
+            xml.push_str(&format!("        <Volume>{:.4}</Volume>\n", 10f32.powf(zone.level as f32 / 20.0)));
+            xml.push_str(&format!("        <Panning>{:.2}</Panning>\n", 0.5 + zone.pan as f32 / 100.0));
+            xml.push_str(&format!("        <Transpose>{transpose}</Transpose>\n"));
+            xml.push_str(&format!("        <Finetune>{finetune}</Finetune>\n"));
+            xml.push_str(&format!("        <OneShot>{}</OneShot>\n", zone.playback == 1));
+            let mute_group = if keygroup.mute_group <= MAX_MUTE_GROUP { keygroup.mute_group } else { 0 };
+            xml.push_str(&format!("        <MuteGroup>{mute_group}</MuteGroup>\n"));
+
+            match zone.sample_loop.or(sample.info.sample_loop).filter(|_| zone.loop_enabled()) {
+                Some(sample_loop) => {
+                    xml.push_str("        <LoopMode>Forward</LoopMode>\n");
+                    // LOOP UNTIL RELEASE plays out after note-off
+                    xml.push_str(&format!("        <LoopRelease>{}</LoopRelease>\n", zone.playback == 3));
+                    xml.push_str(&format!("        <LoopStart>{}</LoopStart>\n", sample_loop.start));
+                    xml.push_str(&format!("        <LoopEnd>{}</LoopEnd>\n", sample_loop.end));
+                }
+                None => xml.push_str("        <LoopMode>Off</LoopMode>\n"),
+            }
+
+            let root_key = zone.root_key.or(sample.info.unity_note).unwrap_or(DEFAULT_ROOT_KEY);
+            xml.push_str("        <Mapping>\n");
+            xml.push_str("          <Layer>Note-On</Layer>\n");
+            xml.push_str(&format!("          <BaseNote>{}</BaseNote>\n", renoise_note(root_key)));
+            xml.push_str(&format!("          <NoteStart>{}</NoteStart>\n", renoise_note(mapped.keys.0)));
+            xml.push_str(&format!("          <NoteEnd>{}</NoteEnd>\n", renoise_note(mapped.keys.1)));
+            xml.push_str(&format!("          <MapKeyToPitch>{}</MapKeyToPitch>\n", zone.keyboard_track != 0));
+            xml.push_str(&format!("          <VelocityStart>{}</VelocityStart>\n", mapped.velocities.0));
+            xml.push_str(&format!("          <VelocityEnd>{}</VelocityEnd>\n", mapped.velocities.1));
+            xml.push_str("          <MapVelocityToVolume>true</MapVelocityToVolume>\n");
+            xml.push_str("        </Mapping>\n");
+            xml.push_str(&format!("        <ModulationSetIndex>{}</ModulationSetIndex>\n", mapped.modulation_set));
+            xml.push_str("      </Sample>\n");
+        }
+        xml.push_str("    </Samples>\n");
+
+        let lfo = self.lfo1.as_ref().filter(|lfo| lfo.depth > 0);
+        xml.push_str("    <ModulationSets>\n");
+        for (index, keygroup) in self.keygroups.iter().enumerate() {
+            write_modulation_set(&mut xml, index, keygroup, lfo);
+        }
+        xml.push_str("    </ModulationSets>\n");
+        xml.push_str("  </SampleGenerator>\n");
+        xml.push_str("</RenoiseInstrument>\n");
+
+        let paths: Vec<String> = loaded.iter().enumerate()
+            .map(|(index, sample)| format!("SampleData/Sample{index:02} ({}).wav", sample.stem))
+            .collect();
+        let mut files: Vec<(&str, &[u8])> = vec![("Instrument.xml", xml.as_bytes())];
+        files.extend(paths.iter().zip(&loaded).map(|(path, sample)| (path.as_str(), sample.bytes.as_slice())));
+        let bytes = zip_stored(&files)?;
+        Ok(XrniOutput { bytes, missing_samples })
+    }
+
+    /// Write the program to a `.xrni` file named after it, returning the
+    /// samples that couldn't be included (see `to_xrni`).
+    pub fn write_xrni(&self, path: &Path, sample_dir: &Path, options: &ConvertOptions) -> Result<MissingSamples> {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let output = self.to_xrni(&name, sample_dir, options)?;
+        fs::write(path, output.bytes)?;
+        Ok(output.missing_samples)
+    }
+}
+
+/// A keygroup's modulation set: mixer levels, a volume AHDSR for the amp
+/// envelope, a cutoff AHDSR for the filter envelope and a pitch LFO.
+fn write_modulation_set(xml: &mut String, index: usize, keygroup: &Keygroup, lfo: Option<&Lfo>) {
+    let filter = keygroup.filter.as_ref().and_then(|f| renoise_filter_type(f).map(|kind| (f, kind)));
+
+    xml.push_str("      <ModulationSet>\n");
+    xml.push_str(&format!("        <Name>Keygroup {}</Name>\n", index + 1));
+    xml.push_str("        <Devices>\n");
+
+    xml.push_str("          <SampleMixerModulationDevice type=\"SampleMixerModulationDevice\">\n");
+    let (cutoff, resonance) = filter.map_or((1.0, 0.0), |(f, _)| (f.cutoff as f32 / 100.0, f.resonance as f32 / 12.0));
+    device_value(xml, "Cutoff", cutoff);
+    device_value(xml, "Resonance", resonance);
+    xml.push_str("          </SampleMixerModulationDevice>\n");
+
+    if let Some(env) = &keygroup.amp_env {
+        write_ahdsr(xml, "Volume", None, [env.attack, env.decay, env.sustain, env.release]);
+    }
+    if let (Some(_), Some(env)) = (filter, keygroup.filter_env.as_ref().filter(|env| env.depth != 0)) {
+        let amount = env.depth as f32 / 100.0;
+        write_ahdsr(xml, "Cutoff", Some(amount), [env.attack, env.decay, env.sustain, env.release]);
+    }
+
+    if let Some(lfo) = lfo {
+        xml.push_str("          <SampleLfoModulationDevice type=\"SampleLfoModulationDevice\">\n");
+        xml.push_str("            <Target>Pitch</Target>\n");
+        xml.push_str(&format!("            <Mode>{}</Mode>\n", renoise_lfo_mode(lfo.waveform)));
+        xml.push_str(&format!("            <Frequency><Value>{:.2}</Value></Frequency>\n", lfo.rate_hz()));
+        device_value(xml, "Amount", lfo.depth_normalized());
+        device_value(xml, "Delay", lfo.delay as f32 / 100.0);
+        xml.push_str("          </SampleLfoModulationDevice>\n");
+    }
+
+    xml.push_str("        </Devices>\n");
+    let kind = filter.map_or("None", |(_, kind)| kind);
+    xml.push_str(&format!("        <FilterType>{kind}</FilterType>\n"));
+    // LFO depth spans a semitone, as for SFZ lfo1_pitch
+    xml.push_str("        <PitchModulationRange>1</PitchModulationRange>\n");
+    xml.push_str("      </ModulationSet>\n");
+}
+
+/// An AHDSR device on `target`, with times and sustain as a fraction of
+/// the AKP's 0-100 and, for targets other than volume, a bipolar amount.
+fn write_ahdsr(xml: &mut String, target: &str, amount: Option<f32>, [attack, decay, sustain, release]: [u8; 4]) {
+    xml.push_str("          <SampleAhdsrModulationDevice type=\"SampleAhdsrModulationDevice\">\n");
+    xml.push_str(&format!("            <Target>{target}</Target>\n"));
+    if let Some(amount) = amount {
+        device_value(xml, "Amount", amount);
+    }
+    device_value(xml, "Attack", attack as f32 / 100.0);
+    device_value(xml, "Hold", 0.0);
+    device_value(xml, "Decay", decay as f32 / 100.0);
+    device_value(xml, "Sustain", sustain as f32 / 100.0);
+    device_value(xml, "Release", release as f32 / 100.0);
+    xml.push_str("          </SampleAhdsrModulationDevice>\n");
+}
+
+fn device_value(xml: &mut String, name: &str, value: f32) {
+    xml.push_str(&format!("            <{name}><Value>{value:.4}</Value></{name}>\n"));
+}
+
+/// Renoise note for an AKP key, clamped to C-0..B-9.
+fn renoise_note(key: u8) -> u8 {
+    key.saturating_sub(NOTE_OFFSET).min(HIGHEST_NOTE)
+}
+
+/// Renoise filter type for the AKP filter types it has.
+fn renoise_filter_type(filter: &Filter) -> Option<&'static str> {
+    match filter.filter_type {
+        0 | 2 => Some("LP Clean"),
+        1 => Some("LP Moog"),
+        3 | 5 => Some("BP Clean"),
+        4 => Some("BP Moog"),
+        6..=8 => Some("HP Clean"),
+        12..=16 => Some("BandStop"),
+        _ => None,
+    }
+}
+
+/// Renoise LFO mode for an AKP waveform (0=SINE .. 8=RANDOM). Renoise has
+/// no triangle, so it plays as a sine.
+fn renoise_lfo_mode(waveform: u8) -> &'static str {
+    match waveform {
+        2..=4 => "Pulse",
+        5..=7 => "Saw",
+        8 => "Random",
+        _ => "Sin",
+    }
+}
+
+/// Read a sample for the zip, with the file stem Renoise shows it under.
+fn load_sample(sample_dir: &Path, sample_name: &str) -> std::result::Result<LoadedSample, String> {
+    let (path, info) = find_wav_info(sample_dir, sample_name)?;
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    Ok(LoadedSample { stem, bytes, info })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitwig::tests::zip_entries;
+    use crate::samples::WavAudio;
+    use crate::types::{Envelope, FilterEnvelope, ProgramOutput, SampleLoop, Zone};
+    use tempfile::TempDir;
+
+    fn wav(frames: usize, unity_note: Option<u8>, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 44100,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: frames as u32,
+            unity_note,
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels: vec![vec![0; frames]] }.to_wav_bytes()
+    }
+
+    #[test]
+    fn test_xrni_zip_and_mapping() {
+        let dir = TempDir::new().unwrap();
+        let looped = SampleLoop { start: 10, end: 29, crossfade: 0 };
+        let piano = wav(40, Some(48), Some(looped));
+        fs::write(dir.path().join("piano.wav"), &piano).unwrap();
+
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                semitone_tune: 1,
+                mute_group: 3,
+                zones: vec![
+                    Zone { sample_name: "piano".to_string(), high_vel: 100, fine_tune: 70, playback: 3, ..Default::default() },
+                    Zone { sample_name: "piano".to_string(), low_vel: 101, keyboard_track: 0, ..Default::default() },
+                    Zone { sample_name: "MISSING".to_string(), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_xrni("Piano & Co", dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+
+        // Both zones play one copy of the audio, across both velocity ranges
+        let entries = zip_entries(&output.bytes);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Instrument.xml", "SampleData/Sample00 (piano).wav"]);
+        assert_eq!(entries[1].1, piano);
+
+        let xml = String::from_utf8(entries[0].1.clone()).unwrap();
+        let flat: String = xml.lines().map(str::trim).collect();
+        assert!(flat.contains("<Name>Piano &amp; Co</Name><GlobalProperties><Volume>0.5000</Volume>"));
+        // 1 semitone + 70 cents: Transpose 2 and -30 cents as Finetune
+        assert!(flat.contains("<Transpose>2</Transpose><Finetune>-38</Finetune><OneShot>false</OneShot><MuteGroup>3</MuteGroup>"));
+        assert!(flat.contains("<LoopMode>Forward</LoopMode><LoopRelease>true</LoopRelease><LoopStart>10</LoopStart><LoopEnd>29</LoopEnd>"));
+        assert!(flat.contains("<BaseNote>36</BaseNote><NoteStart>24</NoteStart><NoteEnd>47</NoteEnd><MapKeyToPitch>true</MapKeyToPitch><VelocityStart>0</VelocityStart><VelocityEnd>127</VelocityEnd>"));
+        assert_eq!(flat.matches("<Sample>").count(), 1);
+        assert_eq!(flat.matches("<ModulationSetIndex>0</ModulationSetIndex>").count(), 1);
+    }
+
+    #[test]
+    fn test_xrni_modulation_sets() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("hit.wav"), wav(20, None, None)).unwrap();
+        let zones = vec![Zone { sample_name: "hit.wav".to_string(), ..Default::default() }];
+        let program = AkaiProgram {
+            lfo1: Some(Lfo { waveform: 2, depth: 50, ..Default::default() }),
+            keygroups: vec![
+                Keygroup {
+                    zones: zones.clone(),
+                    amp_env: Some(Envelope { attack: 10, decay: 50, sustain: 100, release: 25, ..Default::default() }),
+                    filter: Some(Filter { filter_type: 1, cutoff: 60, resonance: 6, ..Default::default() }),
+                    filter_env: Some(FilterEnvelope { attack: 20, depth: -50, ..Default::default() }),
+                    ..Default::default()
+                },
+                // Morphing filters have no Renoise type
+                Keygroup { zones, filter: Some(Filter { filter_type: 10, ..Default::default() }), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+
+        let output = program.to_xrni("Hit", dir.path(), &ConvertOptions::default()).unwrap();
+        let xml = String::from_utf8(zip_entries(&output.bytes)[0].1.clone()).unwrap();
+        let flat: String = xml.lines().map(str::trim).collect();
+        let sets: Vec<&str> = flat.split("<ModulationSet>").skip(1).collect();
+        assert_eq!(sets.len(), 2);
+
+        assert!(sets[0].contains("<Cutoff><Value>0.6000</Value></Cutoff><Resonance><Value>0.5000</Value></Resonance>"));
+        assert!(sets[0].contains("<Target>Volume</Target><Attack><Value>0.1000</Value></Attack><Hold><Value>0.0000</Value></Hold><Decay><Value>0.5000</Value></Decay><Sustain><Value>1.0000</Value></Sustain><Release><Value>0.2500</Value></Release>"));
+        assert!(sets[0].contains("<Target>Cutoff</Target><Amount><Value>-0.5000</Value></Amount><Attack><Value>0.2000</Value></Attack>"));
+        assert!(sets[0].contains("<Target>Pitch</Target><Mode>Pulse</Mode>"));
+        assert!(sets[0].contains("<FilterType>LP Moog</FilterType>"));
+
+        assert!(sets[1].contains("<Cutoff><Value>1.0000</Value></Cutoff>"));
+        assert!(!sets[1].contains("SampleAhdsrModulationDevice"));
+        assert!(sets[1].contains("<FilterType>None</FilterType>"));
+    }
+}
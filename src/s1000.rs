@@ -0,0 +1,521 @@
+//! Akai S1000/S3000 program and sample files.
+//!
+//! These predate AKP: a program file is a fixed-size program header followed
+//! by one block per keygroup, and a sample file is a sample header followed
+//! by 16-bit mono PCM. S1000 blocks are 150 bytes, S3000 blocks 192 bytes
+//! (the S3000 appends fields to the same layout, so only the common part is
+//! read). Layouts follow the S1000/S3000 SysEx documentation linked from
+//! docs/akp-format-reference.md.
+//!
+//! Programs are mapped onto `AkaiProgram` so the SFZ and Decent Sampler
+//! writers work unchanged; samples are converted to WAV with a `smpl` chunk
+//! so `apply_sample_info` picks up their root key and loop.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{AkpError, Result};
+use crate::samples::{WavAudio, WavInfo};
+use crate::types::*;
+
+const S1000_BLOCK_SIZE: usize = 150;
+const S3000_BLOCK_SIZE: usize = 192;
+const ZONE_OFFSET: usize = 34;
+const ZONE_SIZE: usize = 24;
+const NAME_LEN: usize = 12;
+
+/// File extensions used for exported S1000/S3000 programs.
+pub const PROGRAM_EXTENSIONS: [&str; 3] = ["p", "p1", "p3"];
+/// File extensions used for exported S1000/S3000 samples.
+pub const SAMPLE_EXTENSIONS: [&str; 3] = ["s", "s1", "s3"];
+
+/// Whether a path has an S1000/S3000 program extension.
+pub fn is_program_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| PROGRAM_EXTENSIONS.iter().any(|p| p.eq_ignore_ascii_case(ext)))
+}
+
+/// Decode a name in the S1000 character set: 0-9 digits, 10 space,
+/// 11-36 A-Z, then `#`, `+`, `-`, `.`. Trailing spaces are trimmed.
+pub fn akai_to_ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&b| match b {
+            0..=9 => (b'0' + b) as char,
+            11..=36 => (b'A' + b - 11) as char,
+            37 => '#',
+            38 => '+',
+            39 => '-',
+            40 => '.',
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Parse an S1000 (150-byte blocks) or S3000 (192-byte blocks) program file.
+pub fn parse_s1000_program(data: &[u8]) -> Result<AkaiProgram> {
+    if data.len() < S1000_BLOCK_SIZE || data[0] != 1 {
+        return Err(AkpError::CorruptedChunk(
+            "S1000 program".to_string(),
+            "Missing program header block".to_string(),
+        ));
+    }
+
+    let keygroup_count = data[42] as usize;
+    if keygroup_count == 0 {
+        return Err(AkpError::MissingRequiredChunk("keygroup".to_string()));
+    }
+
+    // Exact fit first, then whichever block size the data is long enough for
+    let block_size = if data.len() == S1000_BLOCK_SIZE * (keygroup_count + 1) {
+        S1000_BLOCK_SIZE
+    } else if data.len() >= S3000_BLOCK_SIZE * (keygroup_count + 1) {
+        S3000_BLOCK_SIZE
+    } else if data.len() >= S1000_BLOCK_SIZE * (keygroup_count + 1) {
+        S1000_BLOCK_SIZE
+    } else {
+        return Err(AkpError::CorruptedChunk(
+            "S1000 program".to_string(),
+            format!("{keygroup_count} keygroups declared but file is only {} bytes", data.len()),
+        ));
+    };
+
+    let mut program = parse_program_block(data);
+    for index in 0..keygroup_count {
+        let start = block_size * (index + 1);
+        program.keygroups.push(parse_keygroup_block(&data[start..start + S1000_BLOCK_SIZE])?);
+    }
+    Ok(program)
+}
+
+fn parse_program_block(block: &[u8]) -> AkaiProgram {
+    let mut detune = [0i8; 12];
+    for (i, d) in detune.iter_mut().enumerate() {
+        *d = block[44 + i] as i8;
+    }
+
+    AkaiProgram {
+        model: ProgramModel::S1000,
+        header: Some(ProgramHeader {
+            midi_program_number: block[15],
+            number_of_keygroups: block[42],
+        }),
+        output: Some(ProgramOutput {
+            loudness: block[25].min(100),
+            ..Default::default()
+        }),
+        tuning: Some(ProgramTuning {
+            detune,
+            pitchbend_up: block[39],
+            pitchbend_down: block[39],
+            ..Default::default()
+        }),
+        // The S1000 LFO is a triangle
+        lfo1: Some(Lfo {
+            waveform: 1,
+            rate: block[33],
+            depth: block[34],
+            delay: block[35],
+            modwheel: block[36],
+            aftertouch: block[37],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn parse_keygroup_block(block: &[u8]) -> Result<Keygroup> {
+    if block[0] != 2 {
+        return Err(AkpError::CorruptedChunk(
+            "S1000 keygroup".to_string(),
+            format!("Expected block id 2, found {}", block[0]),
+        ));
+    }
+
+    let (low_key, high_key) = (block[3], block[4]);
+    if low_key > high_key {
+        return Err(AkpError::InvalidKeyRange(low_key, high_key));
+    }
+
+    let mut keygroup = Keygroup {
+        low_key,
+        high_key,
+        fine_tune: block[5] as i8,
+        semitone_tune: block[6] as i8,
+        zone_crossfade: block[30],
+        // env1 is the amp envelope; S1000 rates are 0-99 like the S5000's 0-100
+        amp_env: Some(Envelope {
+            attack: block[12],
+            decay: block[13],
+            sustain: block[14],
+            release: block[15],
+            velocity_attack: block[16] as i8,
+            on_vel_release: block[17] as i8,
+            off_vel_release: block[18] as i8,
+            keyscale: block[19] as i8,
+        }),
+        // S1000 has a single lowpass: cutoff 0-99, no resonance
+        filter: Some(Filter {
+            filter_type: 0,
+            cutoff: block[7],
+            keyboard_track: block[8] as i8,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // env2 → filter, depth -50..50
+    let env2_depth = block[11] as i8;
+    if env2_depth != 0 {
+        keygroup.filter_env = Some(FilterEnvelope {
+            attack: block[20],
+            decay: block[21],
+            sustain: block[22],
+            release: block[23],
+            depth: env2_depth.saturating_mul(2),
+            ..Default::default()
+        });
+    }
+
+    for i in 0..4 {
+        let zone = &block[ZONE_OFFSET + i * ZONE_SIZE..ZONE_OFFSET + (i + 1) * ZONE_SIZE];
+        // Unused zones have a blank name, written as spaces or zero bytes
+        let name = akai_to_ascii(&zone[..NAME_LEN]);
+        if name.is_empty() || zone[..NAME_LEN].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let (low_vel, high_vel) = (zone[12], zone[13]);
+        if low_vel > high_vel {
+            return Err(AkpError::InvalidVelocityRange(low_vel, high_vel));
+        }
+        keygroup.zones.push(Zone {
+            sample_name: format!("{name}.WAV"),
+            low_vel,
+            high_vel: high_vel.min(127),
+            fine_tune: zone[14] as i8,
+            semitone_tune: zone[15] as i8,
+            level: zone[16] as i8,
+            filter: zone[17] as i8,
+            pan: zone[18] as i8,
+            playback: playback_from_loop_mode(zone[19]),
+            ..Default::default()
+        });
+    }
+
+    Ok(keygroup)
+}
+
+/// Map the S1000 zone loop mode onto the AKP zone playback byte.
+/// S1000: 0=AS SAMPLE, 1=LOOP IN RELEASE, 2=LOOP UNTIL RELEASE, 3=NO LOOP, 4=PLAY TO END.
+/// AKP: 0=NO LOOP, 1=ONE SHOT, 2=LOOP, 3=LOOP UNTIL RELEASE, 4=AS SAMPLE.
+fn playback_from_loop_mode(mode: u8) -> u8 {
+    match mode {
+        1 => 2,
+        2 => 3,
+        3 => 0,
+        4 => 1,
+        _ => 4,
+    }
+}
+
+/// An S1000/S3000 sample: header fields plus 16-bit mono PCM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct S1000Sample {
+    pub name: String,
+    /// Original pitch as a MIDI note.
+    pub root_key: u8,
+    pub sample_rate: u32,
+    /// First active loop, unless the sample's playback type is NO LOOP.
+    pub sample_loop: Option<SampleLoop>,
+    pub frames: Vec<i16>,
+}
+
+/// Parse an S1000 (150-byte header) or S3000 (192-byte header) sample file.
+pub fn parse_s1000_sample(data: &[u8]) -> Result<S1000Sample> {
+    if data.len() < S1000_BLOCK_SIZE || data[0] != 3 {
+        return Err(AkpError::CorruptedChunk(
+            "S1000 sample".to_string(),
+            "Missing sample header block".to_string(),
+        ));
+    }
+
+    let length = read_u32(data, 26) as usize;
+    let header_size = if data.len() >= S3000_BLOCK_SIZE + length * 2 && data.len() != S1000_BLOCK_SIZE + length * 2 {
+        S3000_BLOCK_SIZE
+    } else {
+        S1000_BLOCK_SIZE
+    };
+    let pcm = &data[header_size..];
+    if pcm.len() < length * 2 {
+        return Err(AkpError::CorruptedChunk(
+            "S1000 sample".to_string(),
+            format!("Header declares {length} samples but file holds {}", pcm.len() / 2),
+        ));
+    }
+
+    let frames = pcm[..length * 2]
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+
+    // Loops are (loop at, fine length, coarse length, time); "loop at" is the
+    // loop's end, so it starts `coarse length` frames earlier.
+    let loop_count = data[16] as usize;
+    let first_loop = data[17] as usize;
+    let playback_type = data[19];
+    let sample_loop = if loop_count > 0 && first_loop < 8 && playback_type != 2 {
+        let offset = 38 + first_loop * 12;
+        let loop_at = read_u32(data, offset);
+        let loop_length = read_u32(data, offset + 6);
+        (loop_length > 0 && loop_length <= loop_at).then(|| SampleLoop {
+            start: loop_at - loop_length,
+            end: loop_at - 1,
+            crossfade: 0,
+        })
+    } else {
+        None
+    };
+
+    let sample_rate = match u16::from_le_bytes([data[138], data[139]]) {
+        0 => 44100,
+        rate => rate as u32,
+    };
+
+    Ok(S1000Sample {
+        name: akai_to_ascii(&data[3..3 + NAME_LEN]),
+        root_key: data[2].min(127),
+        sample_rate,
+        sample_loop,
+        frames,
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+impl S1000Sample {
+    /// Render as a 16-bit mono WAV with a `smpl` chunk for root key and loop.
+    pub fn to_wav_bytes(&self) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: self.sample_rate,
+            channels: 1,
+            bits_per_sample: 16,
+            frames: self.frames.len() as u32,
+            unity_note: Some(self.root_key),
+            pitch_fraction_cents: 0,
+            sample_loop: self.sample_loop,
+        };
+        WavAudio { info, channels: vec![self.frames.clone()] }.to_wav_bytes()
+    }
+}
+
+/// Result of converting one sample referenced by an S1000/S3000 program.
+#[derive(Debug)]
+pub enum SampleConversion {
+    Converted { source: PathBuf, dest: PathBuf },
+    /// No `.s`/`.s3` file with the sample's name in the source directory.
+    Missing(String),
+    Failed { sample: String, error: String },
+}
+
+/// Convert every sample the program references from `source_dir` into a WAV
+/// in `output_dir`, named to match the zone's `sample_name`.
+pub fn convert_s1000_samples(program: &AkaiProgram, source_dir: &Path, output_dir: &Path) -> Vec<SampleConversion> {
+    program.sample_paths().into_iter().map(|sample_path| {
+        let name = sample_path.trim_end_matches(".WAV");
+        let Some(source) = find_sample_file(source_dir, name) else {
+            return SampleConversion::Missing(name.to_string());
+        };
+        let dest = output_dir.join(sample_path);
+        let result = fs::read(&source)
+            .map_err(AkpError::from)
+            .and_then(|data| parse_s1000_sample(&data))
+            .and_then(|sample| fs::write(&dest, sample.to_wav_bytes()).map_err(AkpError::from));
+        match result {
+            Ok(()) => SampleConversion::Converted { source, dest },
+            Err(e) => SampleConversion::Failed { sample: name.to_string(), error: e.to_string() },
+        }
+    }).collect()
+}
+
+/// Find `<name>.s`, `.s1` or `.s3` in `dir`, ignoring case.
+fn find_sample_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    entries.filter_map(|e| e.ok().map(|e| e.path())).find(|path| {
+        let stem_matches = path.file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|stem| stem.trim_end().eq_ignore_ascii_case(name));
+        let ext_matches = path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| SAMPLE_EXTENSIONS.iter().any(|s| s.eq_ignore_ascii_case(ext)));
+        stem_matches && ext_matches
+    })
+}
+
+/// Read and parse an S1000/S3000 program file.
+pub fn read_s1000_program(path: &Path) -> Result<AkaiProgram> {
+    let data = fs::read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => AkpError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("File '{}' not found", path.display()),
+        )),
+        _ => AkpError::Io(e),
+    })?;
+    parse_s1000_program(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn akai_name(name: &str) -> [u8; 12] {
+        let mut bytes = [10u8; 12]; // spaces
+        for (i, c) in name.bytes().take(12).enumerate() {
+            bytes[i] = match c {
+                b'0'..=b'9' => c - b'0',
+                b'A'..=b'Z' => c - b'A' + 11,
+                b'#' => 37,
+                b'-' => 39,
+                _ => 10,
+            };
+        }
+        bytes
+    }
+
+    fn program_bytes(block_size: usize, zones: &[(&str, u8, u8, u8)]) -> Vec<u8> {
+        let mut data = vec![0u8; block_size * 2];
+        data[0] = 1;
+        data[3..15].copy_from_slice(&akai_name("STRINGS"));
+        data[25] = 80;      // volume
+        data[39] = 2;       // bend range
+        data[42] = 1;       // keygroups
+        data[44 + 4] = (-14i8) as u8; // E temperament
+
+        let kg = &mut data[block_size..];
+        kg[0] = 2;
+        kg[3] = 36;
+        kg[4] = 60;
+        kg[7] = 70;         // filter
+        kg[12] = 5;         // env1 attack
+        kg[14] = 90;        // env1 sustain
+        for (i, &(name, low_vel, high_vel, loop_mode)) in zones.iter().enumerate() {
+            let zone = &mut kg[ZONE_OFFSET + i * ZONE_SIZE..];
+            zone[..12].copy_from_slice(&akai_name(name));
+            zone[12] = low_vel;
+            zone[13] = high_vel;
+            zone[19] = loop_mode;
+        }
+        data
+    }
+
+    fn sample_bytes(header_size: usize, frames: &[i16], loop_at: Option<(u32, u32)>) -> Vec<u8> {
+        let mut data = vec![0u8; header_size];
+        data[0] = 3;
+        data[2] = 48;
+        data[3..15].copy_from_slice(&akai_name("VIOLIN C2"));
+        data[26..30].copy_from_slice(&(frames.len() as u32).to_le_bytes());
+        data[138..140].copy_from_slice(&22050u16.to_le_bytes());
+        if let Some((at, length)) = loop_at {
+            data[16] = 1;
+            data[38..42].copy_from_slice(&at.to_le_bytes());
+            data[44..48].copy_from_slice(&length.to_le_bytes());
+        }
+        for frame in frames {
+            data.extend_from_slice(&frame.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_akai_to_ascii() {
+        assert_eq!(akai_to_ascii(&akai_name("PIANO C#3")), "PIANO C#3");
+        assert_eq!(akai_to_ascii(&[11, 10, 1, 40, 2, 39, 38, 10, 10]), "A 1.2-+");
+    }
+
+    #[test]
+    fn test_parse_s1000_program() {
+        let data = program_bytes(S1000_BLOCK_SIZE, &[("VIOLIN C2", 0, 63, 1), ("VIOLIN FF C2", 64, 127, 3)]);
+        let program = parse_s1000_program(&data).unwrap();
+
+        assert_eq!(program.output.as_ref().unwrap().loudness, 80);
+        assert_eq!(program.tuning.as_ref().unwrap().detune[4], -14);
+        assert_eq!(program.keygroups.len(), 1);
+
+        let keygroup = &program.keygroups[0];
+        assert_eq!((keygroup.low_key, keygroup.high_key), (36, 60));
+        assert_eq!(keygroup.filter.as_ref().unwrap().cutoff, 70);
+        assert_eq!(keygroup.amp_env.as_ref().unwrap().sustain, 90);
+        assert_eq!(keygroup.zones.len(), 2);
+        assert_eq!(keygroup.zones[0].sample_name, "VIOLIN C2.WAV");
+        assert_eq!(keygroup.zones[0].playback, 2); // LOOP IN RELEASE → LOOP
+        assert_eq!(keygroup.zones[1].playback, 0); // NO LOOP
+        assert_eq!((keygroup.zones[1].low_vel, keygroup.zones[1].high_vel), (64, 127));
+    }
+
+    #[test]
+    fn test_parse_s3000_program_block_size() {
+        let data = program_bytes(S3000_BLOCK_SIZE, &[("CELLO", 0, 127, 0)]);
+        let program = parse_s1000_program(&data).unwrap();
+        assert_eq!(program.keygroups[0].zones[0].sample_name, "CELLO.WAV");
+        assert_eq!(program.keygroups[0].zones[0].playback, 4); // AS SAMPLE
+    }
+
+    #[test]
+    fn test_parse_s1000_program_rejects_bad_data() {
+        assert!(parse_s1000_program(b"RIFF").is_err());
+
+        let mut data = program_bytes(S1000_BLOCK_SIZE, &[("A", 0, 127, 0)]);
+        data[42] = 3; // more keygroups than the file holds
+        assert!(matches!(parse_s1000_program(&data), Err(AkpError::CorruptedChunk(..))));
+    }
+
+    #[test]
+    fn test_parse_s1000_sample_with_loop() {
+        let frames: Vec<i16> = (0..100).collect();
+        let sample = parse_s1000_sample(&sample_bytes(S1000_BLOCK_SIZE, &frames, Some((90, 40)))).unwrap();
+        assert_eq!(sample.name, "VIOLIN C2");
+        assert_eq!(sample.root_key, 48);
+        assert_eq!(sample.sample_rate, 22050);
+        assert_eq!(sample.frames, frames);
+        assert_eq!(sample.sample_loop, Some(SampleLoop { start: 50, end: 89, crossfade: 0 }));
+
+        let s3000 = parse_s1000_sample(&sample_bytes(S3000_BLOCK_SIZE, &frames, None)).unwrap();
+        assert_eq!(s3000.frames, frames);
+        assert_eq!(s3000.sample_loop, None);
+    }
+
+    #[test]
+    fn test_sample_to_wav_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let frames: Vec<i16> = vec![0, 1000, -1000, 0];
+        let sample = parse_s1000_sample(&sample_bytes(S1000_BLOCK_SIZE, &frames, Some((4, 2)))).unwrap();
+        let path = dir.path().join("VIOLIN C2.WAV");
+        fs::write(&path, sample.to_wav_bytes()).unwrap();
+
+        let info = crate::samples::read_wav_info(&path).unwrap();
+        assert_eq!(info.sample_rate, 22050);
+        assert_eq!(info.frames, 4);
+        assert_eq!(info.unity_note, Some(48));
+        assert_eq!(info.sample_loop, Some(SampleLoop { start: 2, end: 3, crossfade: 0 }));
+    }
+
+    #[test]
+    fn test_convert_s1000_samples() {
+        let dir = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        fs::write(dir.path().join("violin c2.s"), sample_bytes(S1000_BLOCK_SIZE, &[1, 2, 3], None)).unwrap();
+
+        let data = program_bytes(S1000_BLOCK_SIZE, &[("VIOLIN C2", 0, 63, 0), ("VIOLIN FF C2", 64, 127, 0)]);
+        let program = parse_s1000_program(&data).unwrap();
+        let results = convert_s1000_samples(&program, dir.path(), out.path());
+
+        assert!(matches!(&results[0], SampleConversion::Converted { dest, .. } if dest.ends_with("VIOLIN C2.WAV")));
+        assert!(matches!(&results[1], SampleConversion::Missing(name) if name == "VIOLIN FF C2"));
+        assert!(out.path().join("VIOLIN C2.WAV").exists());
+    }
+}
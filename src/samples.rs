@@ -1,6 +1,13 @@
-use std::fs;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::error::{AkpError, Result};
+use crate::types::{AkaiProgram, SampleLoop};
+
 /// Configuration for sample copying.
 pub struct CopyConfig<'a> {
     /// Where to search for source WAV files (typically the AKP parent directory).
@@ -120,7 +127,9 @@ fn resolve_sample_path(search_dir: &Path, sample_path: &str) -> ResolveResult {
 
         // Case-insensitive search in current directory
         let target_lower = target.to_lowercase();
-        let entries = match fs::read_dir(&current) {
+        // `Path::parent` of a bare file name is "", which read_dir rejects
+        let dir = if current.as_os_str().is_empty() { Path::new(".") } else { current.as_path() };
+        let entries = match fs::read_dir(dir) {
             Ok(entries) => entries,
             Err(_) => return ResolveResult::NotFound,
         };
@@ -152,20 +161,50 @@ fn resolve_sample_path(search_dir: &Path, sample_path: &str) -> ResolveResult {
     }
 }
 
+/// Where `copy_samples_from` reads sample files from: a directory on disk,
+/// or a directory inside a disk image.
+pub trait SampleSource {
+    /// Locate a sample by its preset-relative path. Returns the source path
+    /// and whether it only matched ignoring case.
+    fn find(&self, sample_path: &str) -> Option<(PathBuf, bool)>;
+
+    /// Copy a located sample to `dest`. Parent directories already exist.
+    fn copy_to(&self, source: &Path, dest: &Path) -> io::Result<()>;
+}
+
+impl SampleSource for Path {
+    fn find(&self, sample_path: &str) -> Option<(PathBuf, bool)> {
+        match resolve_sample_path(self, sample_path) {
+            ResolveResult::Exact(path) => Some((path, false)),
+            ResolveResult::CaseMismatch(path) => Some((path, true)),
+            ResolveResult::NotFound => None,
+        }
+    }
+
+    fn copy_to(&self, source: &Path, dest: &Path) -> io::Result<()> {
+        fs::copy(source, dest).map(|_| ())
+    }
+}
+
 /// Copy all referenced sample files from `search_dir` to `output_dir`,
 /// preserving relative subdirectory structure.
 ///
 /// Missing samples are reported but do not cause failure.
 pub fn copy_samples(config: &CopyConfig) -> CopyReport {
+    copy_samples_from(config.search_dir, config.output_dir, config.sample_paths)
+}
+
+/// Like `copy_samples()`, reading from any `SampleSource`.
+pub fn copy_samples_from<S: SampleSource + ?Sized>(source: &S, output_dir: &Path, sample_paths: &[&str]) -> CopyReport {
     let mut report = CopyReport::default();
 
-    for &sample_path in config.sample_paths {
+    for &sample_path in sample_paths {
         // Build destination path (always use the original relative path from the preset)
         let normalized = sample_path.replace('\\', "/");
 
         // Append .wav if no recognized audio extension
         let dest_name = ensure_wav_extension(&normalized);
-        let dest = config.output_dir.join(&dest_name);
+        let dest = output_dir.join(&dest_name);
 
         // Skip if destination already exists
         if dest.exists() {
@@ -174,36 +213,508 @@ pub fn copy_samples(config: &CopyConfig) -> CopyReport {
         }
 
         // Try to find the source file (with .wav appended if needed)
-        let result = resolve_sample_path(config.search_dir, &dest_name);
-
-        match result {
-            ResolveResult::Exact(source) => {
-                match copy_with_dirs(&source, &dest) {
-                    Ok(()) => report.results.push(SampleResult::Copied { source, dest }),
-                    Err(e) => report.results.push(SampleResult::CopyError {
-                        path: sample_path.to_string(),
-                        error: e.to_string(),
-                    }),
-                }
+        let Some((found, case_mismatch)) = source.find(&dest_name) else {
+            report.results.push(SampleResult::Missing(sample_path.to_string()));
+            continue;
+        };
+
+        match copy_with_dirs(source, &found, &dest) {
+            Ok(()) if case_mismatch => report.results.push(SampleResult::CopiedCaseMismatch { source: found, dest }),
+            Ok(()) => report.results.push(SampleResult::Copied { source: found, dest }),
+            Err(e) => report.results.push(SampleResult::CopyError {
+                path: sample_path.to_string(),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    report
+}
+
+/// A preset written into a hardware sampler's SD card folder layout.
+#[derive(Debug, Clone)]
+pub struct CardExport {
+    /// The preset file, under the card root it was written to.
+    pub preset_path: PathBuf,
+    pub missing_samples: MissingSamples,
+}
+
+/// A name a sampler's card browser can show: ASCII only, with characters
+/// FAT rejects replaced and cut to `max_len` characters.
+pub(crate) fn card_name(name: &str, max_len: usize) -> String {
+    let safe: String = name.chars()
+        .map(|c| if !c.is_ascii() || c.is_ascii_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .take(max_len)
+        .collect();
+    let safe = safe.trim().trim_end_matches('.');
+    if safe.is_empty() { "Sample".to_string() } else { safe.to_string() }
+}
+
+/// A card file name for a sample, from its stem, numbered if `taken`
+/// already has it (compared without case, as FAT does).
+pub(crate) fn card_file_name(stem: &str, extension: &str, max_stem: usize, taken: &[String]) -> String {
+    let base = card_name(stem, max_stem);
+    let mut name = format!("{base}.{extension}");
+    let mut number = 2;
+    while taken.iter().any(|t| t.eq_ignore_ascii_case(&name)) {
+        let suffix = format!(" {number}");
+        let stem = card_name(&base, max_stem.saturating_sub(suffix.len()));
+        name = format!("{stem}{suffix}.{extension}");
+        number += 1;
+    }
+    name
+}
+
+/// Header fields read from a WAV file's `fmt `, `data` and `smpl` chunks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WavInfo {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// Length in sample frames (data size / block align).
+    pub frames: u32,
+    /// MIDI unity note from the `smpl` chunk, if one is present.
+    pub unity_note: Option<u8>,
+    /// `smpl` pitch fraction converted to cents (0-99). The sample plays
+    /// this many cents sharp of `unity_note`.
+    pub pitch_fraction_cents: u8,
+    /// First forward loop from the `smpl` chunk.
+    pub sample_loop: Option<SampleLoop>,
+}
+
+/// Read the header chunks of a WAV file without loading the audio data.
+pub fn read_wav_info(path: &Path) -> io::Result<WavInfo> {
+    let mut file = File::open(path)?;
+    parse_wav_info(&mut file)
+}
+
+fn parse_wav_info<R: Read + Seek>(reader: &mut R) -> io::Result<WavInfo> {
+    let mut id = [0u8; 4];
+    reader.read_exact(&mut id)?;
+    if &id != b"RIFF" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a RIFF file"));
+    }
+    let riff_size = reader.read_u32::<LittleEndian>()? as u64;
+    reader.read_exact(&mut id)?;
+    if &id != b"WAVE" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WAVE file"));
+    }
+
+    let end = 8 + riff_size;
+    let mut info = WavInfo::default();
+    let mut block_align = 0u16;
+    let mut data_size = 0u32;
+
+    while reader.stream_position()? + 8 <= end {
+        if reader.read_exact(&mut id).is_err() {
+            break;
+        }
+        let size = reader.read_u32::<LittleEndian>()?;
+        let chunk_start = reader.stream_position()?;
+
+        match &id {
+            b"fmt " if size >= 16 => {
+                let _format_tag = reader.read_u16::<LittleEndian>()?;
+                info.channels = reader.read_u16::<LittleEndian>()?;
+                info.sample_rate = reader.read_u32::<LittleEndian>()?;
+                let _byte_rate = reader.read_u32::<LittleEndian>()?;
+                block_align = reader.read_u16::<LittleEndian>()?;
+                info.bits_per_sample = reader.read_u16::<LittleEndian>()?;
+            }
+            b"data" => {
+                data_size = size;
             }
-            ResolveResult::CaseMismatch(source) => {
-                match copy_with_dirs(&source, &dest) {
-                    Ok(()) => report.results.push(SampleResult::CopiedCaseMismatch { source, dest }),
-                    Err(e) => report.results.push(SampleResult::CopyError {
-                        path: sample_path.to_string(),
-                        error: e.to_string(),
-                    }),
+            b"smpl" if size >= 36 => {
+                // manufacturer, product, sample period precede the unity note
+                reader.seek(SeekFrom::Current(12))?;
+                let unity_note = reader.read_u32::<LittleEndian>()?;
+                let pitch_fraction = reader.read_u32::<LittleEndian>()?;
+                if unity_note <= 127 {
+                    info.unity_note = Some(unity_note as u8);
+                }
+                info.pitch_fraction_cents = ((pitch_fraction as u64 * 100) >> 32) as u8;
+
+                // SMPTE format/offset, then the loop count and sampler data size
+                reader.seek(SeekFrom::Current(8))?;
+                let num_loops = reader.read_u32::<LittleEndian>()?;
+                let _sampler_data = reader.read_u32::<LittleEndian>()?;
+
+                // Each loop is 24 bytes: cue id, type, start, end, fraction, play count
+                let max_loops = (size - 36) / 24;
+                for _ in 0..num_loops.min(max_loops) {
+                    let _cue_id = reader.read_u32::<LittleEndian>()?;
+                    let loop_type = reader.read_u32::<LittleEndian>()?;
+                    let start = reader.read_u32::<LittleEndian>()?;
+                    let end = reader.read_u32::<LittleEndian>()?;
+                    reader.seek(SeekFrom::Current(8))?;
+                    if loop_type == 0 && end > start && info.sample_loop.is_none() {
+                        info.sample_loop = Some(SampleLoop { start, end, crossfade: 0 });
+                    }
                 }
             }
-            ResolveResult::NotFound => {
-                report.results.push(SampleResult::Missing(sample_path.to_string()));
+            _ => {}
+        }
+
+        // Chunks are word-aligned
+        let next = chunk_start + size as u64 + (size & 1) as u64;
+        reader.seek(SeekFrom::Start(next))?;
+    }
+
+    if block_align > 0 {
+        info.frames = data_size / block_align as u32;
+    }
+    Ok(info)
+}
+
+/// WAV audio converted to 16-bit, one buffer per channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WavAudio {
+    pub info: WavInfo,
+    pub channels: Vec<Vec<i16>>,
+}
+
+/// Read a WAV file's headers and audio. 8-, 16-, 24- and 32-bit PCM and
+/// 32-bit float data are converted to 16-bit.
+pub fn read_wav_audio(path: &Path) -> io::Result<WavAudio> {
+    let bytes = fs::read(path)?;
+    let info = parse_wav_info(&mut io::Cursor::new(&bytes))?;
+    let unsupported = |what: String| io::Error::new(io::ErrorKind::InvalidData, what);
+
+    let fmt = wav_chunk(&bytes, b"fmt ").filter(|c| c.len() >= 16).ok_or_else(|| unsupported("no fmt chunk".to_string()))?;
+    let data = wav_chunk(&bytes, b"data").ok_or_else(|| unsupported("no data chunk".to_string()))?;
+    let mut format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+    if format_tag == 0xFFFE && fmt.len() >= 26 {
+        // WAVE_FORMAT_EXTENSIBLE: the real format starts the sub-format GUID
+        format_tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+    }
+
+    let width = info.bits_per_sample as usize / 8;
+    let decode: fn(&[u8]) -> i16 = match (format_tag, info.bits_per_sample) {
+        (1, 8) => |b| (b[0] as i16 - 128) << 8,
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]),
+        (1, 24) => |b| i16::from_le_bytes([b[1], b[2]]),
+        (1, 32) => |b| i16::from_le_bytes([b[2], b[3]]),
+        (3, 32) => |b| (f32::from_le_bytes([b[0], b[1], b[2], b[3]]) * 32767.0).clamp(-32768.0, 32767.0) as i16,
+        (tag, bits) => return Err(unsupported(format!("unsupported WAV format {tag} with {bits} bits"))),
+    };
+
+    let channel_count = info.channels.max(1) as usize;
+    let mut channels = vec![Vec::with_capacity(data.len() / width / channel_count); channel_count];
+    for frame in data.chunks_exact(width * channel_count) {
+        for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(width)) {
+            channel.push(decode(sample));
+        }
+    }
+    Ok(WavAudio { info, channels })
+}
+
+impl WavAudio {
+    /// Render as 16-bit PCM, with a `smpl` chunk for the unity note, pitch
+    /// fraction and loop when there is one.
+    pub fn to_wav_bytes(&self) -> Vec<u8> {
+        let channel_count = self.channels.len().max(1) as u16;
+        let frames = self.channels.first().map_or(0, |c| c.len());
+        let sample_rate = self.info.sample_rate;
+        let block_align = 2 * channel_count;
+
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"fmt ");
+        chunks.extend_from_slice(&16u32.to_le_bytes());
+        chunks.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        chunks.extend_from_slice(&channel_count.to_le_bytes());
+        chunks.extend_from_slice(&sample_rate.to_le_bytes());
+        chunks.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        chunks.extend_from_slice(&block_align.to_le_bytes());
+        chunks.extend_from_slice(&16u16.to_le_bytes());
+
+        if self.info.unity_note.is_some() || self.info.sample_loop.is_some() {
+            let loop_count = self.info.sample_loop.is_some() as u32;
+            let pitch_fraction = ((self.info.pitch_fraction_cents as u64) << 32) / 100;
+            chunks.extend_from_slice(b"smpl");
+            chunks.extend_from_slice(&(36 + loop_count * 24).to_le_bytes());
+            chunks.extend_from_slice(&[0u8; 8]); // manufacturer, product
+            chunks.extend_from_slice(&(1_000_000_000 / sample_rate.max(1)).to_le_bytes());
+            chunks.extend_from_slice(&(self.info.unity_note.unwrap_or(60) as u32).to_le_bytes());
+            chunks.extend_from_slice(&(pitch_fraction as u32).to_le_bytes());
+            chunks.extend_from_slice(&[0u8; 8]); // SMPTE format/offset
+            chunks.extend_from_slice(&loop_count.to_le_bytes());
+            chunks.extend_from_slice(&0u32.to_le_bytes());
+            if let Some(sample_loop) = self.info.sample_loop {
+                chunks.extend_from_slice(&[0u8; 8]); // cue id, forward loop
+                chunks.extend_from_slice(&sample_loop.start.to_le_bytes());
+                chunks.extend_from_slice(&sample_loop.end.to_le_bytes());
+                chunks.extend_from_slice(&[0u8; 8]); // fraction, infinite play count
+            }
+        }
+
+        chunks.extend_from_slice(b"data");
+        chunks.extend_from_slice(&((frames * block_align as usize) as u32).to_le_bytes());
+        for frame in 0..frames {
+            for channel in &self.channels {
+                chunks.extend_from_slice(&channel.get(frame).copied().unwrap_or(0).to_le_bytes());
+            }
+        }
+
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend(chunks);
+        wav
+    }
+}
+
+/// Body of the first top-level chunk with this ID, cut short if the file is.
+fn wav_chunk<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let start = offset + 8;
+        if &bytes[offset..offset + 4] == id {
+            return Some(&bytes[start..start.saturating_add(size).min(bytes.len())]);
+        }
+        offset = start.saturating_add(size + (size & 1));
+    }
+    None
+}
+
+/// Find a program's sample under `search_dir`, ignoring case and adding
+/// `.wav` to names without an audio extension.
+pub fn find_sample_file(search_dir: &Path, sample_name: &str) -> Option<PathBuf> {
+    let file_name = ensure_wav_extension(&sample_name.replace('\\', "/"));
+    match resolve_sample_path(search_dir, &file_name) {
+        ResolveResult::Exact(path) | ResolveResult::CaseMismatch(path) => Some(path),
+        ResolveResult::NotFound => None,
+    }
+}
+
+/// Root key for a sample when neither its zone nor its WAV has one (C3, as
+/// on the S5000).
+pub(crate) const DEFAULT_ROOT_KEY: u8 = 60;
+
+/// (sample name, reason) for each sample a writer left out; zones playing
+/// it are dropped.
+pub type MissingSamples = Vec<(String, String)>;
+
+/// Find a program's sample under `search_dir` and read its WAV header. The
+/// error is the reason to report the sample missing.
+pub fn find_wav_info(search_dir: &Path, sample_name: &str) -> std::result::Result<(PathBuf, WavInfo), String> {
+    let path = find_sample_file(search_dir, sample_name).ok_or_else(|| "not found".to_string())?;
+    let info = read_wav_info(&path).map_err(|e| e.to_string())?;
+    Ok((path, info))
+}
+
+/// The samples a writer loads for a program, each loaded once however many
+/// zones play it, with the ones that couldn't be.
+pub(crate) struct LoadedSamples<T> {
+    samples: Vec<T>,
+    indices: HashMap<String, Option<usize>>,
+    missing: MissingSamples,
+}
+
+impl<T> LoadedSamples<T> {
+    pub(crate) fn new() -> Self {
+        Self { samples: Vec::new(), indices: HashMap::new(), missing: Vec::new() }
+    }
+
+    /// The sample `sample_name`, loaded with `load` the first time it's
+    /// asked for; `load` sees the samples loaded so far. `None` if it
+    /// couldn't be loaded, in which case it's reported missing once.
+    pub(crate) fn load(&mut self, sample_name: &str, load: impl FnOnce(&[T]) -> std::result::Result<T, String>) -> Option<&T> {
+        if !self.indices.contains_key(sample_name) {
+            let index = match load(&self.samples) {
+                Ok(sample) => {
+                    self.samples.push(sample);
+                    Some(self.samples.len() - 1)
+                }
+                Err(reason) => {
+                    self.missing.push((sample_name.to_string(), reason));
+                    None
+                }
+            };
+            self.indices.insert(sample_name.to_string(), index);
+        }
+        self.indices[sample_name].map(|index| &self.samples[index])
+    }
+
+    /// The loaded samples in load order and the missing ones. Fails if none
+    /// of the program's samples could be loaded from `search_dir`.
+    pub(crate) fn finish(self, search_dir: &Path) -> Result<(Vec<T>, MissingSamples)> {
+        if self.samples.is_empty() {
+            return Err(AkpError::UnwritableProgram(format!(
+                "none of the program's samples could be read from '{}'", search_dir.display(),
+            )));
+        }
+        Ok((self.samples, self.missing))
+    }
+}
+
+/// Parse a root note from the end of an Akai-style sample name.
+///
+/// Akai factory samples usually end with the note they were recorded at,
+/// e.g. `BRASS 02-C.1`, `PNO93L-C#3` or `Bass Eb2`. The note must follow a
+/// separator (space, `-`, `_`) and may use `.` between name and octave.
+/// Octaves follow the Akai convention where C3 is middle C (MIDI 60).
+pub fn note_from_sample_name(name: &str) -> Option<u8> {
+    let stem = match name.rsplit_once('.') {
+        Some((stem, ext)) if matches!(ext.to_ascii_lowercase().as_str(), "wav" | "aif" | "aiff") => stem,
+        _ => name,
+    };
+    let stem = stem.rsplit('/').next().unwrap_or(stem);
+
+    // Try the shortest tail after a separator first, so `PNO-C-1` finds `C-1`
+    // only after `1` fails to parse.
+    stem.char_indices()
+        .rev()
+        .filter(|&(_, c)| matches!(c, ' ' | '-' | '_'))
+        .map(|(i, _)| &stem[i + 1..])
+        .take_while(|tail| tail.len() <= 5)
+        .find_map(parse_note_token)
+}
+
+/// Parse a note token such as `C3`, `C#3`, `Eb2`, `C.1` or `C-1`.
+fn parse_note_token(token: &str) -> Option<u8> {
+    let mut chars = token.chars();
+    let pitch_class = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let mut rest = chars.as_str();
+    let mut accidental = 0;
+    if let Some(r) = rest.strip_prefix('#') {
+        accidental = 1;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('b') {
+        accidental = -1;
+        rest = r;
+    }
+    let rest = rest.strip_prefix('.').unwrap_or(rest);
+
+    let octave: i32 = rest.parse().ok()?;
+    if !(-2..=8).contains(&octave) {
+        return None;
+    }
+
+    let note = (octave + 2) * 12 + pitch_class + accidental;
+    u8::try_from(note).ok().filter(|&n| n <= 127)
+}
+
+/// Where a zone's root key came from.
+#[derive(Debug, Clone)]
+pub enum SampleInfoResult {
+    /// Read from the WAV `smpl` chunk.
+    FromWav { sample: String, root_key: u8 },
+    /// WAV missing or has no `smpl` chunk — parsed from the sample name.
+    FromName { sample: String, root_key: u8 },
+    /// No root key could be determined; the player default applies.
+    Unresolved(String),
+}
+
+/// Summary of root key resolution for one program.
+#[derive(Debug, Clone, Default)]
+pub struct SampleInfoReport {
+    pub results: Vec<SampleInfoResult>,
+}
+
+impl SampleInfoReport {
+    pub fn from_wav_count(&self) -> usize {
+        self.results.iter().filter(|r| matches!(r, SampleInfoResult::FromWav { .. })).count()
+    }
+
+    pub fn from_name_count(&self) -> usize {
+        self.results.iter().filter(|r| matches!(r, SampleInfoResult::FromName { .. })).count()
+    }
+
+    pub fn unresolved(&self) -> Vec<&str> {
+        self.results.iter().filter_map(|r| match r {
+            SampleInfoResult::Unresolved(name) => Some(name.as_str()),
+            _ => None,
+        }).collect()
+    }
+
+    /// One-line summary suitable for CLI output.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} from WAV", self.from_wav_count())];
+        let from_name = self.from_name_count();
+        if from_name > 0 {
+            parts.push(format!("{from_name} from sample name"));
+        }
+        let unresolved = self.unresolved().len();
+        if unresolved > 0 {
+            parts.push(format!("{unresolved} unresolved"));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Fill in each zone's root key, tuning and loop points from its sample file.
+///
+/// Samples are resolved under `search_dir` the same way `copy_samples` does.
+/// When the WAV can't be found or has no `smpl` chunk, the root key is
+/// parsed from the sample name (see `note_from_sample_name`). Each unique
+/// sample is reported once.
+pub fn apply_sample_info(program: &mut AkaiProgram, search_dir: &Path) -> SampleInfoReport {
+    let mut report = SampleInfoReport::default();
+    let mut resolved: HashMap<String, ResolvedSample> = HashMap::new();
+
+    for keygroup in &mut program.keygroups {
+        for zone in &mut keygroup.zones {
+            if zone.sample_name.is_empty() {
+                continue;
             }
+
+            let sample = *resolved.entry(zone.sample_name.clone()).or_insert_with(|| {
+                let (sample, result) = resolve_sample_info(search_dir, &zone.sample_name);
+                report.results.push(result);
+                sample
+            });
+
+            zone.root_key = sample.root_key;
+            zone.root_tune = sample.root_tune;
+            zone.sample_loop = sample.sample_loop;
         }
     }
 
     report
 }
 
+/// Per-sample fields copied onto every zone that plays the sample.
+#[derive(Clone, Copy)]
+struct ResolvedSample {
+    root_key: Option<u8>,
+    root_tune: i8,
+    sample_loop: Option<SampleLoop>,
+}
+
+fn resolve_sample_info(search_dir: &Path, sample_name: &str) -> (ResolvedSample, SampleInfoResult) {
+    let file_name = ensure_wav_extension(&sample_name.replace('\\', "/"));
+    let wav_info = match resolve_sample_path(search_dir, &file_name) {
+        ResolveResult::Exact(path) | ResolveResult::CaseMismatch(path) => read_wav_info(&path).ok(),
+        ResolveResult::NotFound => None,
+    };
+    let sample_loop = wav_info.as_ref().and_then(|info| info.sample_loop);
+
+    if let Some(note) = wav_info.as_ref().and_then(|info| info.unity_note) {
+        // smpl pitch fraction means the recording is sharp — tune down to compensate
+        let tune = -(wav_info.as_ref().map_or(0, |info| info.pitch_fraction_cents) as i8);
+        let sample = ResolvedSample { root_key: Some(note), root_tune: tune, sample_loop };
+        return (sample, SampleInfoResult::FromWav { sample: sample_name.to_string(), root_key: note });
+    }
+
+    let root_key = note_from_sample_name(sample_name);
+    let sample = ResolvedSample { root_key, root_tune: 0, sample_loop };
+    match root_key {
+        Some(note) => (sample, SampleInfoResult::FromName { sample: sample_name.to_string(), root_key: note }),
+        None => (sample, SampleInfoResult::Unresolved(sample_name.to_string())),
+    }
+}
+
 /// Append `.wav` if the path doesn't already have a recognized audio extension.
 fn ensure_wav_extension(path: &str) -> String {
     let known_extensions = ["wav", "aif", "aiff"];
@@ -216,12 +727,11 @@ fn ensure_wav_extension(path: &str) -> String {
 }
 
 /// Copy a file, creating parent directories as needed.
-fn copy_with_dirs(source: &Path, dest: &Path) -> std::io::Result<()> {
+fn copy_with_dirs<S: SampleSource + ?Sized>(from: &S, source: &Path, dest: &Path) -> std::io::Result<()> {
     if let Some(parent) = dest.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::copy(source, dest)?;
-    Ok(())
+    from.copy_to(source, dest)
 }
 
 #[cfg(test)]
@@ -243,7 +753,7 @@ mod tests {
         let out = TempDir::new().unwrap();
         create_file(src.path(), "Piano_C3.wav", b"RIFF_FAKE_WAV");
 
-        let paths = vec!["Piano_C3"];
+        let paths = ["Piano_C3"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -262,7 +772,7 @@ mod tests {
         let out = TempDir::new().unwrap();
         create_file(src.path(), "Strings/Violin_C3.wav", b"RIFF_FAKE_WAV");
 
-        let paths = vec!["Strings/Violin_C3"];
+        let paths = ["Strings/Violin_C3"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -284,7 +794,7 @@ mod tests {
         // Detect case-insensitive filesystem (macOS default)
         let case_insensitive_fs = src.path().join("Strings/Violin_C3.wav").exists();
 
-        let paths = vec!["Strings/Violin_C3"];
+        let paths = ["Strings/Violin_C3"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -308,7 +818,7 @@ mod tests {
         let src = TempDir::new().unwrap();
         let out = TempDir::new().unwrap();
 
-        let paths = vec!["NonExistent_Sample"];
+        let paths = ["NonExistent_Sample"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -327,7 +837,7 @@ mod tests {
         create_file(src.path(), "Piano_C3.wav", b"SOURCE_DATA");
         create_file(out.path(), "Piano_C3.wav", b"EXISTING_DATA");
 
-        let paths = vec!["Piano_C3"];
+        let paths = ["Piano_C3"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -376,7 +886,7 @@ mod tests {
         create_file(src.path(), "Strings/Violin_C3.wav", b"RIFF_FAKE_WAV");
 
         // AKP files use backslash paths
-        let paths = vec!["Strings\\Violin_C3"];
+        let paths = ["Strings\\Violin_C3"];
         let config = CopyConfig {
             search_dir: src.path(),
             output_dir: out.path(),
@@ -397,6 +907,197 @@ mod tests {
         assert_eq!(ensure_wav_extension("BRASS 02-C.1"), "BRASS 02-C.1.wav");
     }
 
+    /// Build a minimal 16-bit mono WAV, optionally with a `smpl` chunk
+    /// carrying (unity note, pitch fraction) and an optional forward loop.
+    fn make_wav(frames: u32, smpl: Option<(u32, u32)>) -> Vec<u8> {
+        make_looped_wav(frames, smpl, None)
+    }
+
+    fn make_looped_wav(frames: u32, smpl: Option<(u32, u32)>, sample_loop: Option<(u32, u32)>) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"fmt ");
+        chunks.extend_from_slice(&16u32.to_le_bytes());
+        chunks.extend_from_slice(&1u16.to_le_bytes());      // PCM
+        chunks.extend_from_slice(&1u16.to_le_bytes());      // mono
+        chunks.extend_from_slice(&44100u32.to_le_bytes());
+        chunks.extend_from_slice(&88200u32.to_le_bytes());
+        chunks.extend_from_slice(&2u16.to_le_bytes());      // block align
+        chunks.extend_from_slice(&16u16.to_le_bytes());
+        if let Some((unity_note, pitch_fraction)) = smpl {
+            let num_loops = sample_loop.is_some() as u32;
+            chunks.extend_from_slice(b"smpl");
+            chunks.extend_from_slice(&(36 + num_loops * 24).to_le_bytes());
+            chunks.extend_from_slice(&[0u8; 12]);
+            chunks.extend_from_slice(&unity_note.to_le_bytes());
+            chunks.extend_from_slice(&pitch_fraction.to_le_bytes());
+            chunks.extend_from_slice(&[0u8; 8]);
+            chunks.extend_from_slice(&num_loops.to_le_bytes());
+            chunks.extend_from_slice(&0u32.to_le_bytes());
+            if let Some((start, end)) = sample_loop {
+                chunks.extend_from_slice(&[0u8; 8]);        // cue id, forward loop
+                chunks.extend_from_slice(&start.to_le_bytes());
+                chunks.extend_from_slice(&end.to_le_bytes());
+                chunks.extend_from_slice(&[0u8; 8]);
+            }
+        }
+        chunks.extend_from_slice(b"data");
+        chunks.extend_from_slice(&(frames * 2).to_le_bytes());
+        chunks.extend(std::iter::repeat_n(0u8, frames as usize * 2));
+
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend(chunks);
+        wav
+    }
+
+    #[test]
+    fn test_read_wav_info_with_smpl() {
+        let dir = TempDir::new().unwrap();
+        create_file(dir.path(), "Piano_C3.wav", &make_wav(100, Some((60, 0x4000_0000))));
+
+        let info = read_wav_info(&dir.path().join("Piano_C3.wav")).unwrap();
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(info.frames, 100);
+        assert_eq!(info.unity_note, Some(60));
+        assert_eq!(info.pitch_fraction_cents, 25); // quarter of a semitone
+        assert_eq!(info.sample_loop, None);
+    }
+
+    #[test]
+    fn test_read_wav_info_loop_points() {
+        let dir = TempDir::new().unwrap();
+        create_file(dir.path(), "PAD-C3.wav", &make_looped_wav(1000, Some((60, 0)), Some((200, 899))));
+
+        let info = read_wav_info(&dir.path().join("PAD-C3.wav")).unwrap();
+        assert_eq!(info.unity_note, Some(60));
+        assert_eq!(info.frames, 1000);
+        assert_eq!(info.sample_loop, Some(SampleLoop { start: 200, end: 899, crossfade: 0 }));
+    }
+
+    #[test]
+    fn test_read_wav_info_without_smpl() {
+        let dir = TempDir::new().unwrap();
+        create_file(dir.path(), "plain.wav", &make_wav(10, None));
+
+        let info = read_wav_info(&dir.path().join("plain.wav")).unwrap();
+        assert_eq!(info.unity_note, None);
+        assert_eq!(info.frames, 10);
+    }
+
+    #[test]
+    fn test_loaded_samples_load_once_and_need_one() {
+        let dir = TempDir::new().unwrap();
+        let mut samples = LoadedSamples::new();
+        let mut loads = 0;
+        for name in ["kick", "gone", "kick", "gone"] {
+            samples.load(name, |loaded| {
+                loads += 1;
+                find_wav_info(dir.path(), name).map(|_| loaded.len())
+            });
+        }
+        assert_eq!(loads, 2);
+        assert!(matches!(samples.finish(dir.path()), Err(AkpError::UnwritableProgram(_))));
+
+        create_file(dir.path(), "Kick.wav", &WavAudio { info: WavInfo { channels: 1, ..Default::default() }, channels: vec![vec![0; 4]] }.to_wav_bytes());
+        let mut samples = LoadedSamples::new();
+        for name in ["kick", "gone", "kick"] {
+            samples.load(name, |_| find_wav_info(dir.path(), name).map(|(_, info)| info.frames));
+        }
+        let (loaded, missing) = samples.finish(dir.path()).unwrap();
+        assert_eq!(loaded, [4]);
+        assert_eq!(missing, vec![("gone".to_string(), "not found".to_string())]);
+    }
+
+    #[test]
+    fn test_read_wav_info_rejects_non_wav() {
+        let dir = TempDir::new().unwrap();
+        create_file(dir.path(), "fake.wav", b"RIFF_FAKE_WAV");
+        assert!(read_wav_info(&dir.path().join("fake.wav")).is_err());
+    }
+
+    #[test]
+    fn test_read_wav_audio_converts_to_16_bit() {
+        let dir = TempDir::new().unwrap();
+        // 24-bit stereo, two frames
+        let mut wav = make_wav(0, None);
+        let fmt = 20;
+        wav[fmt + 2..fmt + 4].copy_from_slice(&2u16.to_le_bytes());
+        wav[fmt + 12..fmt + 14].copy_from_slice(&6u16.to_le_bytes());
+        wav[fmt + 14..fmt + 16].copy_from_slice(&24u16.to_le_bytes());
+        let frames: [[i32; 2]; 2] = [[0x123456, -0x123456], [0x7FFFFF, -0x800000]];
+        wav.truncate(wav.len() - 4);
+        wav.extend_from_slice(&12u32.to_le_bytes());
+        for sample in frames.iter().flatten() {
+            wav.extend_from_slice(&sample.to_le_bytes()[..3]);
+        }
+        let riff_size = wav.len() as u32 - 8;
+        wav[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        create_file(dir.path(), "stereo.wav", &wav);
+
+        let audio = read_wav_audio(&dir.path().join("stereo.wav")).unwrap();
+        assert_eq!(audio.info.frames, 2);
+        assert_eq!(audio.channels, vec![vec![0x1234, 0x7FFF], vec![-0x1235, -0x8000]]);
+        assert_eq!(find_sample_file(dir.path(), "STEREO"), Some(dir.path().join("stereo.wav")));
+    }
+
+    #[test]
+    fn test_note_from_sample_name() {
+        assert_eq!(note_from_sample_name("BRASS 02-C.1"), Some(36));
+        assert_eq!(note_from_sample_name("BRASS 02-C.1.WAV"), Some(36));
+        assert_eq!(note_from_sample_name("PNO93L-C#3.WAV"), Some(61));
+        assert_eq!(note_from_sample_name("Bass Eb2"), Some(51));
+        assert_eq!(note_from_sample_name("Piano_C3"), Some(60));
+        assert_eq!(note_from_sample_name("SUB-C-1"), Some(12));
+        assert_eq!(note_from_sample_name("Strings/VIOLIN A4.wav"), Some(81));
+        assert_eq!(note_from_sample_name("KICK 01"), None);
+        assert_eq!(note_from_sample_name("SNAREC3"), None); // not a separate token
+        assert_eq!(note_from_sample_name(""), None);
+    }
+
+    #[test]
+    fn test_apply_sample_info_sources() {
+        use crate::types::{AkaiProgram, Keygroup, Zone};
+
+        let dir = TempDir::new().unwrap();
+        create_file(dir.path(), "LOOPED.WAV", &make_looped_wav(10, Some((48, 0x8000_0000)), Some((2, 7))));
+        create_file(dir.path(), "PLAIN-D3.WAV", &make_wav(10, None));
+
+        let mut program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![
+                    Zone { sample_name: "LOOPED.WAV".to_string(), ..Default::default() },
+                    Zone { sample_name: "PLAIN-D3.WAV".to_string(), ..Default::default() },
+                    Zone { sample_name: "MISSING-A2.WAV".to_string(), ..Default::default() },
+                    Zone { sample_name: "MISSING.WAV".to_string(), ..Default::default() },
+                    Zone { sample_name: "LOOPED.WAV".to_string(), ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let report = apply_sample_info(&mut program, dir.path());
+        let zones = &program.keygroups[0].zones;
+        assert_eq!((zones[0].root_key, zones[0].root_tune), (Some(48), -50));
+        assert_eq!((zones[1].root_key, zones[1].root_tune), (Some(62), 0));
+        assert_eq!(zones[2].root_key, Some(57));
+        assert_eq!(zones[3].root_key, None);
+        assert_eq!(zones[4].root_key, Some(48));
+        assert_eq!(zones[0].sample_loop, Some(SampleLoop { start: 2, end: 7, crossfade: 0 }));
+        assert_eq!(zones[4].sample_loop, zones[0].sample_loop);
+        assert_eq!(zones[1].sample_loop, None);
+
+        // Duplicate sample reported once
+        assert_eq!(report.results.len(), 4);
+        assert_eq!(report.from_wav_count(), 1);
+        assert_eq!(report.from_name_count(), 2);
+        assert_eq!(report.unresolved(), vec!["MISSING.WAV"]);
+        assert_eq!(report.summary(), "1 from WAV, 2 from sample name, 1 unresolved");
+    }
+
     #[test]
     fn test_summary_formatting() {
         let report = CopyReport {
@@ -408,4 +1109,14 @@ mod tests {
         };
         assert_eq!(report.summary(), "2 copied, 1 case mismatch, 1 missing");
     }
+
+    #[test]
+    fn test_card_file_name() {
+        assert_eq!(card_name("Pad: Ärger?", 40), "Pad_ _rger_");
+        assert_eq!(card_name("...", 40), "Sample");
+
+        let taken = vec!["Long Sample Na.wav".to_string(), "long sample 2.wav".to_string()];
+        assert_eq!(card_file_name("Short", "wav", 14, &taken), "Short.wav");
+        assert_eq!(card_file_name("Long Sample Name", "wav", 14, &taken), "Long Sample 3.wav");
+    }
 }
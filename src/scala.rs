@@ -0,0 +1,59 @@
+use crate::types::AkaiProgram;
+
+impl AkaiProgram {
+    /// Render the `tune` chunk's detune table as a Scala `.scl` scale.
+    ///
+    /// Degrees are relative to C, so C's own offset isn't in the file — the
+    /// SFZ writer moves `scala_root_freq` instead. Returns an equal-tempered
+    /// scale when the program has no tune chunk.
+    pub fn to_scala_string(&self, description: &str) -> String {
+        let detune = self.tuning.as_ref().map_or([0; 12], |t| t.detune);
+
+        let mut scl = String::new();
+        scl.push_str("! Generated by Rusty Samplers\n");
+        scl.push_str("!\n");
+        scl.push_str(description);
+        scl.push('\n');
+        scl.push_str(" 12\n");
+        scl.push_str("!\n");
+        for (degree, &cents) in detune.iter().enumerate().skip(1) {
+            let pitch = degree as i32 * 100 + cents as i32 - detune[0] as i32;
+            scl.push_str(&format!(" {pitch}.0\n"));
+        }
+        scl.push_str(" 2/1\n");
+        scl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::*;
+
+    #[test]
+    fn test_scala_equal_temperament_without_tune_chunk() {
+        let scl = AkaiProgram::default().to_scala_string("Equal");
+        let lines: Vec<&str> = scl.lines().filter(|l| !l.starts_with('!')).collect();
+        assert_eq!(lines[0], "Equal");
+        assert_eq!(lines[1], " 12");
+        assert_eq!(lines[2], " 100.0");
+        assert_eq!(lines[12], " 1100.0");
+        assert_eq!(lines[13], " 2/1");
+        assert_eq!(lines.len(), 14);
+    }
+
+    #[test]
+    fn test_scala_detune_relative_to_c() {
+        let mut tuning = ProgramTuning::default();
+        tuning.detune[0] = 6;    // C
+        tuning.detune[4] = -8;   // E
+        tuning.detune[7] = 8;    // G
+        let program = AkaiProgram { tuning: Some(tuning), ..Default::default() };
+
+        let scl = program.to_scala_string("Test temperament");
+        let lines: Vec<&str> = scl.lines().filter(|l| !l.starts_with('!')).collect();
+        assert_eq!(lines[2], " 94.0");    // C#: 100 - 6
+        assert_eq!(lines[5], " 386.0");   // E: 400 - 8 - 6
+        assert_eq!(lines[8], " 702.0");   // G: 700 + 8 - 6
+        assert_eq!(lines[13], " 2/1");
+    }
+}
@@ -0,0 +1,607 @@
+//! SoundFont 2 output. Unlike SFZ and Decent Sampler, an SF2 carries its
+//! samples, so each WAV is read from disk (see `samples::read_wav_audio`)
+//! and stored as 16-bit.
+//!
+//! The file has one preset with one instrument per keygroup. Each instrument
+//! starts with a global zone holding the keygroup's envelopes, filter,
+//! vibrato LFO and mute group, followed by a zone per sample with its key
+//! and velocity range, tuning, root key, pan, level and loop mode. Stereo
+//! WAVs become a linked left/right sample pair with a zone for each side.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::instrument::EnvelopeTiming;
+use crate::samples::{find_sample_file, read_wav_audio, LoadedSamples, MissingSamples, WavAudio, DEFAULT_ROOT_KEY};
+use crate::types::{AkaiProgram, ConvertOptions, Keygroup, SampleLoop};
+
+/// SF2 generator numbers (SoundFont 2.01 spec, section 8.1.2).
+pub(crate) mod gen {
+    pub const START_LOOP_ADDRS_OFFSET: u16 = 2;
+    pub const END_LOOP_ADDRS_OFFSET: u16 = 3;
+    pub const VIB_LFO_TO_PITCH: u16 = 6;
+    pub const INITIAL_FILTER_FC: u16 = 8;
+    pub const INITIAL_FILTER_Q: u16 = 9;
+    pub const MOD_ENV_TO_FILTER_FC: u16 = 11;
+    pub const PAN: u16 = 17;
+    pub const DELAY_VIB_LFO: u16 = 23;
+    pub const FREQ_VIB_LFO: u16 = 24;
+    pub const ATTACK_MOD_ENV: u16 = 26;
+    pub const DECAY_MOD_ENV: u16 = 28;
+    pub const SUSTAIN_MOD_ENV: u16 = 29;
+    pub const RELEASE_MOD_ENV: u16 = 30;
+    pub const ATTACK_VOL_ENV: u16 = 34;
+    pub const DECAY_VOL_ENV: u16 = 36;
+    pub const SUSTAIN_VOL_ENV: u16 = 37;
+    pub const RELEASE_VOL_ENV: u16 = 38;
+    pub const INSTRUMENT: u16 = 41;
+    pub const KEY_RANGE: u16 = 43;
+    pub const VEL_RANGE: u16 = 44;
+    pub const START_LOOP_ADDRS_COARSE_OFFSET: u16 = 45;
+    pub const INITIAL_ATTENUATION: u16 = 48;
+    pub const END_LOOP_ADDRS_COARSE_OFFSET: u16 = 50;
+    pub const COARSE_TUNE: u16 = 51;
+    pub const FINE_TUNE: u16 = 52;
+    pub const SAMPLE_ID: u16 = 53;
+    pub const SAMPLE_MODES: u16 = 54;
+    pub const SCALE_TUNING: u16 = 56;
+    pub const EXCLUSIVE_CLASS: u16 = 57;
+    pub const OVERRIDING_ROOT_KEY: u16 = 58;
+}
+
+/// `shdr` sample types.
+const MONO_SAMPLE: u16 = 1;
+pub(crate) const RIGHT_SAMPLE: u16 = 2;
+pub(crate) const LEFT_SAMPLE: u16 = 4;
+/// Zero points the spec requires after each sample.
+const SAMPLE_PADDING: usize = 46;
+/// SF2 names are 20 bytes, NUL-terminated.
+pub(crate) const NAME_LEN: usize = 20;
+/// Absolute cents are relative to 8.176 Hz (MIDI note 0).
+pub(crate) const CENTS_REFERENCE_HZ: f32 = 8.176;
+
+/// An SF2 file and the samples that couldn't go in it.
+#[derive(Debug, Clone)]
+pub struct Sf2Output {
+    pub bytes: Vec<u8>,
+    pub missing_samples: MissingSamples,
+}
+
+/// A sample as stored in `sdta`, with its `shdr` fields.
+struct SampleHeader {
+    name: String,
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    link: u16,
+    sample_type: u16,
+}
+
+/// Sample indices for a WAV: (left or mono, right).
+type SampleIds = (u16, Option<u16>);
+
+/// Generators of one zone, as (operator, amount).
+type Generators = Vec<(u16, u16)>;
+
+impl AkaiProgram {
+    /// Build an SF2 named `name`, reading samples from `sample_dir`. Zones
+    /// whose sample can't be read are left out and listed in the output;
+    /// if none can be read this fails.
+    pub fn to_sf2(&self, name: &str, sample_dir: &Path, options: &ConvertOptions) -> Result<Sf2Output> {
+        let mut data: Vec<i16> = Vec::new();
+        let mut headers: Vec<SampleHeader> = Vec::new();
+        let mut sample_ids: LoadedSamples<SampleIds> = LoadedSamples::new();
+
+        let program_semitone = self.tuning.as_ref().map_or(0, |t| t.semitone as i32);
+        let program_fine = self.tuning.as_ref().map_or(0, |t| t.fine as i32);
+        let program_db = self.output.as_ref().map_or(0.0, |o| o.volume_db());
+
+        // Per-key detune has no SF2 equivalent, so those keygroups split per key
+        let layout = self.key_layout(true, options.drum_kit_layout);
+        let mut instruments: Vec<(String, Vec<Generators>)> = Vec::new();
+
+        for (index, keygroup) in self.keygroups.iter().enumerate() {
+            let mut zones = vec![self.global_zone(keygroup)];
+            let crossfades = keygroup.velocity_crossfades();
+
+            for range in layout.iter().filter(|r| std::ptr::eq(r.keygroup, keygroup)) {
+                for (zone, xf) in keygroup.zones.iter().zip(&crossfades) {
+                    if zone.sample_name.is_empty() {
+                        continue;
+                    }
+                    let ids = sample_ids.load(&zone.sample_name, |_| {
+                        let path = find_sample_file(sample_dir, &zone.sample_name).ok_or_else(|| "not found".to_string())?;
+                        let audio = read_wav_audio(&path).map_err(|e| e.to_string())?;
+                        Ok(add_sample(&mut data, &mut headers, &zone.sample_name, &audio, zone.sample_loop))
+                    });
+                    let Some(&(left, right)) = ids else { continue };
+
+                    let mut gens: Generators = vec![
+                        (gen::KEY_RANGE, range_amount(range.low_key, range.high_key)),
+                        (gen::VEL_RANGE, range_amount(xf.low_vel, xf.high_vel)),
+                    ];
+
+                    let cents = (program_semitone + keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+                        + program_fine + keygroup.fine_tune as i32 + zone.fine_tune as i32
+                        + zone.root_tune as i32 + range.detune as i32;
+                    let coarse = (cents as f32 / 100.0).round() as i32;
+                    if coarse != 0 {
+                        gens.push((gen::COARSE_TUNE, coarse as i16 as u16));
+                    }
+                    if cents - coarse * 100 != 0 {
+                        gens.push((gen::FINE_TUNE, (cents - coarse * 100) as i16 as u16));
+                    }
+
+                    let attenuation = (-(program_db + zone.level as f32) * 10.0).round().clamp(0.0, 1440.0) as i16;
+                    if attenuation != 0 {
+                        gens.push((gen::INITIAL_ATTENUATION, attenuation as u16));
+                    }
+                    if zone.keyboard_track == 0 {
+                        gens.push((gen::SCALE_TUNING, 0));
+                    }
+                    if let Some(root_key) = zone.root_key {
+                        gens.push((gen::OVERRIDING_ROOT_KEY, root_key as u16));
+                    }
+                    match zone.playback {
+                        3 => gens.push((gen::SAMPLE_MODES, 3)),
+                        _ if zone.loop_enabled() => gens.push((gen::SAMPLE_MODES, 1)),
+                        _ => {}
+                    }
+
+                    // Stereo pairs are panned hard left and right instead
+                    match right {
+                        None => {
+                            if zone.pan != 0 {
+                                gens.push((gen::PAN, (zone.pan as i16 * 10) as u16));
+                            }
+                            gens.push((gen::SAMPLE_ID, left));
+                            zones.push(gens);
+                        }
+                        Some(right) => {
+                            for (id, pan) in [(left, -500i16), (right, 500)] {
+                                let mut side = gens.clone();
+                                side.push((gen::PAN, pan as u16));
+                                side.push((gen::SAMPLE_ID, id));
+                                zones.push(side);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if zones.len() > 1 {
+                instruments.push((format!("Keygroup {}", index + 1), zones));
+            }
+        }
+
+        let (_, missing_samples) = sample_ids.finish(sample_dir)?;
+        let preset_number = self.header.as_ref().map_or(0, |h| h.midi_program_number as u16);
+        let bytes = sf2_bytes(name, preset_number, &instruments, &headers, &data);
+        Ok(Sf2Output { bytes, missing_samples })
+    }
+
+    /// Write the program to an `.sf2` file named after it, returning the
+    /// samples that couldn't be included (see `to_sf2`).
+    pub fn write_sf2(&self, path: &Path, sample_dir: &Path, options: &ConvertOptions) -> Result<MissingSamples> {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let output = self.to_sf2(&name, sample_dir, options)?;
+        fs::write(path, output.bytes)?;
+        Ok(output.missing_samples)
+    }
+
+    /// Instrument global zone: the keygroup settings every sample shares.
+    fn global_zone(&self, keygroup: &Keygroup) -> Generators {
+        let mut gens = Generators::new();
+
+        if let Some(env) = &keygroup.amp_env {
+            gens.push((gen::ATTACK_VOL_ENV, timecents(env.attack_time()) as u16));
+            gens.push((gen::DECAY_VOL_ENV, timecents(env.decay_time()) as u16));
+            // Sustain is attenuation in centibels
+            let sustain = env.sustain_normalized();
+            let attenuation = if sustain <= 0.0 { 1440.0 } else { (-200.0 * sustain.log10()).min(1440.0) };
+            gens.push((gen::SUSTAIN_VOL_ENV, attenuation.round() as u16));
+            gens.push((gen::RELEASE_VOL_ENV, timecents(env.release_time()) as u16));
+        }
+
+        // SF2 filters are lowpass only
+        if let Some(filter) = keygroup.filter.as_ref().filter(|f| f.ds_effect_type() == "lowpass") {
+            gens.push((gen::INITIAL_FILTER_FC, absolute_cents(filter.cutoff_hz()).clamp(1500, 13500) as u16));
+            gens.push((gen::INITIAL_FILTER_Q, (filter.resonance_db() * 10.0).round().clamp(0.0, 960.0) as u16));
+
+            if let Some(env) = keygroup.filter_env.as_ref().filter(|env| env.depth != 0) {
+                gens.push((gen::ATTACK_MOD_ENV, timecents(env.attack_time()) as u16));
+                gens.push((gen::DECAY_MOD_ENV, timecents(env.decay_time()) as u16));
+                // Sustain is the drop from full level, in 0.1% units
+                gens.push((gen::SUSTAIN_MOD_ENV, ((1.0 - env.sustain_normalized()) * 1000.0).round() as u16));
+                gens.push((gen::RELEASE_MOD_ENV, timecents(env.release_time()) as u16));
+                // Same depth scaling as fileg_depth: ±100 → ±9600 cents
+                let depth = (env.depth as f32 / 100.0 * 9600.0).round() as i16;
+                gens.push((gen::MOD_ENV_TO_FILTER_FC, depth as u16));
+            }
+        }
+
+        // LFO 1 drives pitch by default, which is the SF2 vibrato LFO
+        if let Some(lfo) = self.lfo1.as_ref().filter(|lfo| lfo.depth > 0) {
+            gens.push((gen::FREQ_VIB_LFO, absolute_cents(lfo.rate_hz()).clamp(-16000, 4500) as u16));
+            gens.push((gen::VIB_LFO_TO_PITCH, (lfo.depth_normalized() * 100.0).round() as u16));
+            if lfo.delay > 0 {
+                gens.push((gen::DELAY_VIB_LFO, timecents(lfo.delay as f32 / 100.0 * 10.0) as u16));
+            }
+        }
+
+        if keygroup.mute_group != 0 {
+            gens.push((gen::EXCLUSIVE_CLASS, keygroup.mute_group as u16));
+        }
+
+        gens
+    }
+}
+
+/// Append a WAV's channels to the sample data, returning their sample IDs.
+/// `zone_loop` (from the program) takes precedence over the WAV's own loop.
+fn add_sample(data: &mut Vec<i16>, headers: &mut Vec<SampleHeader>, sample_name: &str, audio: &WavAudio, zone_loop: Option<SampleLoop>) -> SampleIds {
+    let file_name = sample_name.rsplit(['/', '\\']).next().unwrap_or(sample_name);
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+    let sample_loop = zone_loop.or(audio.info.sample_loop);
+    let stereo = audio.channels.len() >= 2;
+
+    let first = headers.len() as u16;
+    for (channel, samples) in audio.channels.iter().take(2).enumerate() {
+        let start = data.len() as u32;
+        data.extend_from_slice(samples);
+        data.extend(std::iter::repeat_n(0, SAMPLE_PADDING));
+        let end = start + samples.len() as u32;
+
+        // No loop: the spec still wants valid points, so span the sample
+        let (loop_start, loop_end) = match sample_loop {
+            Some(l) => (start + l.start.min(samples.len() as u32), start + (l.end + 1).min(samples.len() as u32)),
+            None => (start, end),
+        };
+        let (name, link, sample_type) = match (stereo, channel) {
+            (false, _) => (stem.to_string(), 0, MONO_SAMPLE),
+            (true, 0) => (format!("{stem} L"), first + 1, LEFT_SAMPLE),
+            (true, _) => (format!("{stem} R"), first, RIGHT_SAMPLE),
+        };
+        headers.push(SampleHeader {
+            name,
+            start,
+            end,
+            loop_start,
+            loop_end,
+            sample_rate: audio.info.sample_rate,
+            original_pitch: audio.info.unity_note.unwrap_or(DEFAULT_ROOT_KEY),
+            link,
+            sample_type,
+        });
+    }
+
+    (first, stereo.then_some(first + 1))
+}
+
+/// Seconds to SF2 timecents; zero and very short times are the -12000 minimum.
+fn timecents(seconds: f32) -> i16 {
+    if seconds <= 0.001 {
+        -12000
+    } else {
+        (1200.0 * seconds.log2()).round().clamp(-12000.0, 8000.0) as i16
+    }
+}
+
+/// Hz to SF2 absolute cents.
+fn absolute_cents(hz: f32) -> i16 {
+    (1200.0 * (hz / CENTS_REFERENCE_HZ).log2()).round() as i16
+}
+
+/// A key or velocity range generator amount: low byte first.
+fn range_amount(low: u8, high: u8) -> u16 {
+    u16::from_le_bytes([low, high])
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+fn push_list(out: &mut Vec<u8>, list_type: &[u8; 4], chunks: &[u8]) {
+    let mut body = list_type.to_vec();
+    body.extend_from_slice(chunks);
+    push_chunk(out, b"LIST", &body);
+}
+
+/// A name field: truncated to leave room for the terminating NUL.
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    let mut field = [0u8; NAME_LEN];
+    for (byte, c) in field.iter_mut().zip(name.bytes().take(NAME_LEN - 1)) {
+        *byte = if c.is_ascii() { c } else { b'_' };
+    }
+    out.extend_from_slice(&field);
+}
+
+/// A NUL-terminated INFO string, padded to an even length.
+fn info_string(text: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text.bytes().map(|c| if c.is_ascii() { c } else { b'_' }).collect();
+    bytes.push(0);
+    if bytes.len() & 1 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn push_bag(out: &mut Vec<u8>, generator_index: usize) {
+    out.extend_from_slice(&(generator_index as u16).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+}
+
+fn push_generator(out: &mut Vec<u8>, (operator, amount): (u16, u16)) {
+    out.extend_from_slice(&operator.to_le_bytes());
+    out.extend_from_slice(&amount.to_le_bytes());
+}
+
+fn sf2_bytes(name: &str, preset_number: u16, instruments: &[(String, Vec<Generators>)], headers: &[SampleHeader], data: &[i16]) -> Vec<u8> {
+    let mut info = Vec::new();
+    push_chunk(&mut info, b"ifil", &[2, 0, 1, 0]);
+    push_chunk(&mut info, b"isng", &info_string("EMU8000"));
+    push_chunk(&mut info, b"INAM", &info_string(name));
+    push_chunk(&mut info, b"ISFT", &info_string("Rusty Samplers"));
+
+    let mut sdta = Vec::new();
+    let samples: Vec<u8> = data.iter().flat_map(|s| s.to_le_bytes()).collect();
+    push_chunk(&mut sdta, b"smpl", &samples);
+
+    // One preset with a zone per instrument
+    let (mut phdr, mut pbag, mut pgen) = (Vec::new(), Vec::new(), Vec::new());
+    push_name(&mut phdr, name);
+    phdr.extend_from_slice(&preset_number.to_le_bytes());
+    phdr.extend_from_slice(&[0; 2 + 2 + 12]); // bank, bag index 0, library/genre/morphology
+    for (index, _) in instruments.iter().enumerate() {
+        push_bag(&mut pbag, index);
+        push_generator(&mut pgen, (gen::INSTRUMENT, index as u16));
+    }
+    push_name(&mut phdr, "EOP");
+    phdr.extend_from_slice(&[0; 4]);
+    phdr.extend_from_slice(&(instruments.len() as u16).to_le_bytes());
+    phdr.extend_from_slice(&[0; 12]);
+    push_bag(&mut pbag, instruments.len());
+    push_generator(&mut pgen, (0, 0));
+
+    let (mut inst, mut ibag, mut igen) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut bags, mut generators) = (0, 0);
+    for (instrument_name, zones) in instruments {
+        push_name(&mut inst, instrument_name);
+        inst.extend_from_slice(&(bags as u16).to_le_bytes());
+        for zone in zones {
+            push_bag(&mut ibag, generators);
+            for &generator in zone {
+                push_generator(&mut igen, generator);
+            }
+            bags += 1;
+            generators += zone.len();
+        }
+    }
+    push_name(&mut inst, "EOI");
+    inst.extend_from_slice(&(bags as u16).to_le_bytes());
+    push_bag(&mut ibag, generators);
+    push_generator(&mut igen, (0, 0));
+
+    let mut shdr = Vec::new();
+    for header in headers {
+        push_name(&mut shdr, &header.name);
+        for value in [header.start, header.end, header.loop_start, header.loop_end, header.sample_rate] {
+            shdr.extend_from_slice(&value.to_le_bytes());
+        }
+        shdr.push(header.original_pitch);
+        shdr.push(0); // pitch correction; root_tune is in the zone's fine tune
+        shdr.extend_from_slice(&header.link.to_le_bytes());
+        shdr.extend_from_slice(&header.sample_type.to_le_bytes());
+    }
+    push_name(&mut shdr, "EOS");
+    shdr.extend_from_slice(&[0; 26]);
+
+    let mut pdta = Vec::new();
+    push_chunk(&mut pdta, b"phdr", &phdr);
+    push_chunk(&mut pdta, b"pbag", &pbag);
+    push_chunk(&mut pdta, b"pmod", &[0; 10]);
+    push_chunk(&mut pdta, b"pgen", &pgen);
+    push_chunk(&mut pdta, b"inst", &inst);
+    push_chunk(&mut pdta, b"ibag", &ibag);
+    push_chunk(&mut pdta, b"imod", &[0; 10]);
+    push_chunk(&mut pdta, b"igen", &igen);
+    push_chunk(&mut pdta, b"shdr", &shdr);
+
+    let mut body = b"sfbk".to_vec();
+    push_list(&mut body, b"INFO", &info);
+    push_list(&mut body, b"sdta", &sdta);
+    push_list(&mut body, b"pdta", &pdta);
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::types::{Envelope, Filter, Lfo, ProgramOutput, Zone};
+    use tempfile::TempDir;
+
+    /// 16-bit PCM WAV with interleaved `samples` and a `smpl` loop.
+    fn wav(channels: u16, samples: &[i16], unity_note: u32, sample_loop: (u32, u32)) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"fmt ");
+        chunks.extend_from_slice(&16u32.to_le_bytes());
+        chunks.extend_from_slice(&1u16.to_le_bytes());
+        chunks.extend_from_slice(&channels.to_le_bytes());
+        chunks.extend_from_slice(&22050u32.to_le_bytes());
+        chunks.extend_from_slice(&(22050 * 2 * channels as u32).to_le_bytes());
+        chunks.extend_from_slice(&(2 * channels).to_le_bytes());
+        chunks.extend_from_slice(&16u16.to_le_bytes());
+        chunks.extend_from_slice(b"smpl");
+        chunks.extend_from_slice(&60u32.to_le_bytes());
+        chunks.extend_from_slice(&[0; 12]);
+        chunks.extend_from_slice(&unity_note.to_le_bytes());
+        chunks.extend_from_slice(&[0; 12]);
+        chunks.extend_from_slice(&1u32.to_le_bytes());
+        chunks.extend_from_slice(&[0; 12]);
+        chunks.extend_from_slice(&sample_loop.0.to_le_bytes());
+        chunks.extend_from_slice(&sample_loop.1.to_le_bytes());
+        chunks.extend_from_slice(&[0; 8]);
+        chunks.extend_from_slice(b"data");
+        chunks.extend_from_slice(&(samples.len() as u32 * 2).to_le_bytes());
+        chunks.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend(chunks);
+        bytes
+    }
+
+    /// Sub-chunks of a RIFF or LIST body, by ID.
+    fn chunks(body: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut found = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= body.len() {
+            let size = u32::from_le_bytes(body[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            found.push((&body[offset..offset + 4], &body[offset + 8..offset + 8 + size]));
+            offset += 8 + size + (size & 1);
+        }
+        found
+    }
+
+    /// The `pdta` sub-chunks and the `smpl` data of an SF2.
+    fn sections(bytes: &[u8]) -> (HashMap<String, Vec<u8>>, Vec<i16>) {
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(&bytes[8..12], b"sfbk");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+        let mut pdta = HashMap::new();
+        let mut smpl = Vec::new();
+        for (_, list) in chunks(&bytes[12..]) {
+            for (id, data) in chunks(&list[4..]) {
+                let id = String::from_utf8_lossy(id).into_owned();
+                if id == "smpl" {
+                    smpl = data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+                }
+                pdta.insert(id, data.to_vec());
+            }
+        }
+        (pdta, smpl)
+    }
+
+    /// Instrument zones as generator lists, one `Vec` per instrument.
+    fn instrument_zones(pdta: &HashMap<String, Vec<u8>>) -> Vec<Vec<Generators>> {
+        let u16_at = |data: &[u8], offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let (inst, ibag, igen) = (&pdta["inst"], &pdta["ibag"], &pdta["igen"]);
+        let generator = |index: usize| (u16_at(igen, index * 4), u16_at(igen, index * 4 + 2));
+        let bag = |index: usize| u16_at(ibag, index * 4) as usize;
+        (0..inst.len() / 22 - 1)
+            .map(|i| {
+                let (first, last) = (u16_at(inst, i * 22 + 20) as usize, u16_at(inst, (i + 1) * 22 + 20) as usize);
+                (first..last).map(|z| (bag(z)..bag(z + 1)).map(generator).collect()).collect()
+            })
+            .collect()
+    }
+
+    fn find(gens: &Generators, operator: u16) -> Option<i16> {
+        gens.iter().find(|(op, _)| *op == operator).map(|&(_, amount)| amount as i16)
+    }
+
+    #[test]
+    fn test_sf2_layout_and_generators() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("piano c3.wav"), wav(1, &[1, 2, 3, 4, 5, 6, 7, 8], 48, (2, 5))).unwrap();
+
+        let program = AkaiProgram {
+            output: Some(ProgramOutput { loudness: 50, ..Default::default() }),
+            lfo1: Some(Lfo { rate: 50, depth: 40, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                semitone_tune: 1,
+                mute_group: 3,
+                amp_env: Some(Envelope { attack: 0, decay: 50, sustain: 50, release: 100, ..Default::default() }),
+                filter: Some(Filter { cutoff: 50, resonance: 6, ..Default::default() }),
+                zones: vec![
+                    Zone { sample_name: "PIANO C3".to_string(), high_vel: 100, fine_tune: -30, pan: -25, root_key: Some(48), playback: 2, ..Default::default() },
+                    Zone { sample_name: "MISSING".to_string(), low_vel: 101, ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_sf2("Piano", dir.path(), &ConvertOptions::default()).unwrap();
+        assert_eq!(output.missing_samples, vec![("MISSING".to_string(), "not found".to_string())]);
+
+        let (pdta, smpl) = sections(&output.bytes);
+        assert_eq!(&smpl[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(smpl.len(), 8 + SAMPLE_PADDING);
+        assert_eq!(&pdta["phdr"][..6], b"Piano\0");
+        assert_eq!(pdta["phdr"].len(), 38 * 2);
+
+        let shdr = &pdta["shdr"];
+        assert_eq!(shdr.len(), 46 * 2);
+        assert_eq!(&shdr[..9], b"PIANO C3\0");
+        let field = |offset: usize| u32::from_le_bytes(shdr[offset..offset + 4].try_into().unwrap());
+        assert_eq!((field(20), field(24), field(28), field(32), field(36)), (0, 8, 2, 6, 22050));
+        assert_eq!((shdr[40], u16::from_le_bytes([shdr[44], shdr[45]])), (48, MONO_SAMPLE));
+
+        let instruments = instrument_zones(&pdta);
+        assert_eq!(instruments.len(), 1);
+        let [global, zone] = &instruments[0][..] else { panic!("expected a global and one sample zone") };
+        assert_eq!(find(global, gen::ATTACK_VOL_ENV), Some(-12000));
+        assert_eq!(find(global, gen::SUSTAIN_VOL_ENV), Some(60)); // 50% = 6 dB down
+        assert_eq!(find(global, gen::RELEASE_VOL_ENV), Some(timecents(0.001 * 5.0f32.exp())));
+        assert_eq!(find(global, gen::INITIAL_FILTER_Q), Some(200));
+        assert_eq!(find(global, gen::VIB_LFO_TO_PITCH), Some(40));
+        assert_eq!(find(global, gen::EXCLUSIVE_CLASS), Some(3));
+        assert_eq!(find(global, gen::SAMPLE_ID), None);
+
+        assert_eq!(zone[0], (gen::KEY_RANGE, range_amount(36, 59)));
+        assert_eq!(zone[1], (gen::VEL_RANGE, range_amount(0, 100)));
+        assert_eq!(find(zone, gen::COARSE_TUNE), Some(1));
+        assert_eq!(find(zone, gen::FINE_TUNE), Some(-30));
+        assert_eq!(find(zone, gen::INITIAL_ATTENUATION), Some(60));
+        assert_eq!(find(zone, gen::PAN), Some(-250));
+        assert_eq!(find(zone, gen::OVERRIDING_ROOT_KEY), Some(48));
+        assert_eq!(find(zone, gen::SAMPLE_MODES), Some(1));
+        assert_eq!(zone.last(), Some(&(gen::SAMPLE_ID, 0)));
+    }
+
+    #[test]
+    fn test_stereo_sample_pair() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("pad.wav"), wav(2, &[1, -1, 2, -2, 3, -3], 60, (0, 2))).unwrap();
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "pad.wav".to_string(), ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let output = program.to_sf2("Pad", dir.path(), &ConvertOptions::default()).unwrap();
+        let (pdta, smpl) = sections(&output.bytes);
+        assert_eq!(&smpl[..3], &[1, 2, 3]);
+        assert_eq!(&smpl[3 + SAMPLE_PADDING..6 + SAMPLE_PADDING], &[-1, -2, -3]);
+
+        let shdr = &pdta["shdr"];
+        let link_and_type = |i: usize| (u16::from_le_bytes([shdr[i * 46 + 42], shdr[i * 46 + 43]]), u16::from_le_bytes([shdr[i * 46 + 44], shdr[i * 46 + 45]]));
+        assert_eq!(&shdr[..6], b"pad L\0");
+        assert_eq!((link_and_type(0), link_and_type(1)), ((1, LEFT_SAMPLE), (0, RIGHT_SAMPLE)));
+
+        let zones = &instrument_zones(&pdta)[0];
+        assert_eq!(zones.len(), 3);
+        assert_eq!((find(&zones[1], gen::PAN), find(&zones[1], gen::SAMPLE_ID)), (Some(-500), Some(0)));
+        assert_eq!((find(&zones[2], gen::PAN), find(&zones[2], gen::SAMPLE_ID)), (Some(500), Some(1)));
+    }
+}
@@ -0,0 +1,1010 @@
+//! SoundFont 2 import: builds one `AkaiProgram` per preset, with the
+//! embedded samples extracted as 16-bit WAVs (see `Sf2Import::write_samples`).
+//!
+//! Generators are combined as the spec lays out: instrument zones override
+//! the instrument's global zone, preset values are offsets added on top, and
+//! key and velocity ranges are intersected. Each instrument zone then becomes
+//! an AKP zone placed with `AkaiProgram::add_zone`, with its envelopes,
+//! filter and mute group going to the keygroup. Values are converted back to
+//! the AKP scales with the same inverse helpers the SFZ import uses, so an
+//! SF2 written by this crate imports to the program it came from.
+//!
+//! Linked stereo samples are joined into one stereo WAV, and the pair of
+//! zones playing them into one zone. Generators and modulators that have no
+//! AKP equivalent or fall outside the AKP range are listed in
+//! `Sf2Import::skipped`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::{AkpError, Result};
+use crate::instrument::{envelope_time_to_raw, release_time_to_raw};
+use crate::samples::{WavAudio, WavInfo};
+use crate::sf2::{gen, CENTS_REFERENCE_HZ, LEFT_SAMPLE, NAME_LEN, RIGHT_SAMPLE};
+use crate::types::{
+    AkaiProgram, Envelope, Filter, FilterEnvelope, Keygroup, Lfo,
+    ProgramHeader, ProgramOutput, SampleLoop, Zone,
+};
+
+/// `pdta` record sizes.
+const PHDR_SIZE: usize = 38;
+const BAG_SIZE: usize = 4;
+const MOD_SIZE: usize = 10;
+const GEN_SIZE: usize = 4;
+const INST_SIZE: usize = 22;
+const SHDR_SIZE: usize = 46;
+/// Generators defined by SF2 2.01, `endOper` excluded.
+const GENERATOR_COUNT: usize = 60;
+/// `shdr` sample type bit for samples held in sound ROM rather than the file.
+const ROM_SAMPLE: u16 = 0x8000;
+/// Mute groups the S5000/S6000 offers (0 = off).
+const MAX_MUTE_GROUP: i32 = 32;
+/// Original pitch for unpitched samples (`shdr` 255) and out-of-range values.
+const DEFAULT_ROOT_KEY: u8 = 60;
+/// Filter cutoff at or above this is the spec's "filter off".
+const FILTER_OFF_CENTS: i32 = 13500;
+/// SF2's vibrato LFO is a triangle (AKP waveform 1).
+const TRIANGLE_WAVEFORM: u8 = 1;
+
+/// Generators with no AKP equivalent, reported when they aren't at their default.
+const UNSUPPORTED: [(u16, &str); 20] = [
+    (0, "sample start and end offsets aren't supported"),
+    (1, "sample start and end offsets aren't supported"),
+    (4, "sample start and end offsets aren't supported"),
+    (12, "sample start and end offsets aren't supported"),
+    (5, "no AKP equivalent for the modulation LFO"),
+    (10, "no AKP equivalent for the modulation LFO"),
+    (13, "no AKP equivalent for the modulation LFO"),
+    (7, "the AKP filter envelope only drives the filter"),
+    (15, "effects sends aren't imported"),
+    (16, "effects sends aren't imported"),
+    (25, "the AKP filter envelope has no delay, hold or key scaling"),
+    (27, "the AKP filter envelope has no delay, hold or key scaling"),
+    (31, "the AKP filter envelope has no delay, hold or key scaling"),
+    (32, "the AKP filter envelope has no delay, hold or key scaling"),
+    (33, "the AKP amp envelope has no delay, hold or key scaling"),
+    (35, "the AKP amp envelope has no delay, hold or key scaling"),
+    (39, "the AKP amp envelope has no delay, hold or key scaling"),
+    (40, "the AKP amp envelope has no delay, hold or key scaling"),
+    (46, "fixed keys and velocities aren't supported"),
+    (47, "fixed keys and velocities aren't supported"),
+];
+
+/// Result of importing an SF2 file.
+#[derive(Debug, Clone)]
+pub struct Sf2Import {
+    /// One program per preset with playable zones, by bank and preset number.
+    pub presets: Vec<Sf2Preset>,
+    /// Samples the presets play; zones refer to them by `file_name`.
+    pub samples: Vec<Sf2Sample>,
+    /// Generators and modulators that couldn't be represented.
+    pub skipped: Vec<SkippedGenerator>,
+}
+
+/// An SF2 preset converted to a program.
+#[derive(Debug, Clone)]
+pub struct Sf2Preset {
+    pub name: String,
+    pub bank: u16,
+    pub number: u16,
+    pub program: AkaiProgram,
+}
+
+/// An embedded sample, joined with its linked partner if it's stereo.
+#[derive(Debug, Clone)]
+pub struct Sf2Sample {
+    /// File name the zones use as `sample_name`, from the SF2 sample name.
+    pub file_name: String,
+    /// 16-bit audio with the original pitch as unity note and the loop.
+    pub audio: WavAudio,
+}
+
+/// A generator (or modulator list) that couldn't be represented.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedGenerator {
+    /// Preset and instrument it's in: `Grand Piano / Piano L`.
+    pub location: String,
+    /// Spec name, e.g. `releaseVolEnv`; empty for notes about a whole zone.
+    pub generator: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.generator.is_empty(), self.value.is_empty()) {
+            (true, _) => write!(f, "{}: {}", self.location, self.reason),
+            (false, true) => write!(f, "{}: {} ({})", self.location, self.generator, self.reason),
+            (false, false) => write!(f, "{}: {}={} ({})", self.location, self.generator, self.value, self.reason),
+        }
+    }
+}
+
+impl Sf2Import {
+    /// Write every sample as a WAV in `dir`, under its `file_name`.
+    pub fn write_samples(&self, dir: &Path) -> Result<()> {
+        for sample in &self.samples {
+            fs::write(dir.join(&sample.file_name), sample.audio.to_wav_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Output file names (without extension) for the presets, in order. A
+    /// single preset is named `stem`; several get the preset name appended,
+    /// and the bank and number too if names still clash.
+    pub fn file_names(&self, stem: &str) -> Vec<String> {
+        let single = self.presets.len() == 1;
+        let mut names: Vec<String> = Vec::new();
+        for preset in &self.presets {
+            let mut name = if single {
+                stem.to_string()
+            } else {
+                format!("{stem} {}", safe_file_name(&preset.name))
+            };
+            if names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+                name = format!("{name} {:03}-{:03}", preset.bank, preset.number);
+            }
+            names.push(name);
+        }
+        names
+    }
+}
+
+/// Read and import an `.sf2` file.
+pub fn read_sf2(path: &Path) -> Result<Sf2Import> {
+    let bytes = fs::read(path)?;
+    parse_sf2(&bytes)
+}
+
+/// Import an SF2. Only a file that isn't an SF2 or has a damaged preset
+/// structure fails; anything that can't be represented is reported in
+/// `Sf2Import::skipped`.
+pub fn parse_sf2(bytes: &[u8]) -> Result<Sf2Import> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+        return Err(AkpError::InvalidPreset("SF2".to_string(), "expected a RIFF sfbk header".to_string()));
+    }
+    let riff_end = (8 + u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize).min(bytes.len());
+
+    let mut smpl: &[u8] = &[];
+    let mut pdta: HashMap<[u8; 4], &[u8]> = HashMap::new();
+    for (id, body) in chunks(&bytes[12..riff_end]) {
+        if &id != b"LIST" || body.len() < 4 {
+            continue;
+        }
+        for (sub_id, data) in chunks(&body[4..]) {
+            match &body[..4] {
+                b"sdta" if &sub_id == b"smpl" => smpl = data,
+                b"pdta" => {
+                    pdta.insert(sub_id, data);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let hydra = Hydra::new(&pdta)?;
+    let mut importer = Importer {
+        hydra,
+        data: smpl.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect(),
+        samples: Vec::new(),
+        sample_files: HashMap::new(),
+        skipped: Vec::new(),
+    };
+
+    let mut headers = importer.hydra.presets();
+    headers.sort_by_key(|h| (h.bank, h.number));
+    let mut presets = Vec::new();
+    for header in headers {
+        let program = importer.preset(&header)?;
+        if program.keygroups.is_empty() {
+            importer.skip(&header.name, "", String::new(), "no playable zones, preset skipped");
+            continue;
+        }
+        presets.push(Sf2Preset { name: header.name, bank: header.bank, number: header.number, program });
+    }
+
+    Ok(Sf2Import { presets, samples: importer.samples, skipped: importer.skipped })
+}
+
+/// A file name for an SF2 or sample name, with path separators and other
+/// characters file systems reject replaced.
+pub fn safe_file_name(name: &str) -> String {
+    let safe: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    safe.trim().trim_end_matches('.').to_string()
+}
+
+/// RIFF sub-chunks of `body`, cut short if the file is.
+fn chunks(body: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= body.len() {
+        let id = body[offset..offset + 4].try_into().unwrap();
+        let size = u32::from_le_bytes(body[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let start = offset + 8;
+        found.push((id, &body[start..start.saturating_add(size).min(body.len())]));
+        offset = start.saturating_add(size + (size & 1));
+    }
+    found
+}
+
+fn read_name(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).trim().to_string()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn corrupt(reason: impl Into<String>) -> AkpError {
+    AkpError::CorruptedChunk("pdta".to_string(), reason.into())
+}
+
+/// The `pdta` sub-chunks ("hydra" in the spec).
+struct Hydra<'a> {
+    phdr: &'a [u8],
+    pbag: &'a [u8],
+    pmod: &'a [u8],
+    pgen: &'a [u8],
+    inst: &'a [u8],
+    ibag: &'a [u8],
+    imod: &'a [u8],
+    igen: &'a [u8],
+    shdr: &'a [u8],
+}
+
+struct PresetHeader {
+    name: String,
+    number: u16,
+    bank: u16,
+    bags: Range<usize>,
+}
+
+/// One zone's generators, as (operator, amount), and how many modulators it has.
+struct RawZone {
+    generators: Vec<(u16, u16)>,
+    modulators: usize,
+}
+
+impl RawZone {
+    fn get(&self, operator: u16) -> Option<u16> {
+        self.generators.iter().find(|(op, _)| *op == operator).map(|&(_, amount)| amount)
+    }
+}
+
+impl<'a> Hydra<'a> {
+    fn new(pdta: &HashMap<[u8; 4], &'a [u8]>) -> Result<Self> {
+        let chunk = |id: &[u8; 4], size: usize| -> Result<&'a [u8]> {
+            let data = pdta.get(id).copied().ok_or_else(|| AkpError::MissingRequiredChunk(String::from_utf8_lossy(id).into_owned()))?;
+            if data.len() % size != 0 || data.is_empty() {
+                return Err(AkpError::InvalidChunkSize(String::from_utf8_lossy(id).into_owned(), data.len() as u32));
+            }
+            Ok(data)
+        };
+        Ok(Hydra {
+            phdr: chunk(b"phdr", PHDR_SIZE)?,
+            pbag: chunk(b"pbag", BAG_SIZE)?,
+            pmod: chunk(b"pmod", MOD_SIZE)?,
+            pgen: chunk(b"pgen", GEN_SIZE)?,
+            inst: chunk(b"inst", INST_SIZE)?,
+            ibag: chunk(b"ibag", BAG_SIZE)?,
+            imod: chunk(b"imod", MOD_SIZE)?,
+            igen: chunk(b"igen", GEN_SIZE)?,
+            shdr: chunk(b"shdr", SHDR_SIZE)?,
+        })
+    }
+
+    /// Preset headers, without the terminal `EOP` record.
+    fn presets(&self) -> Vec<PresetHeader> {
+        let records: Vec<&[u8]> = self.phdr.chunks_exact(PHDR_SIZE).collect();
+        records.windows(2).map(|pair| PresetHeader {
+            name: read_name(&pair[0][..NAME_LEN]),
+            number: read_u16(pair[0], 20),
+            bank: read_u16(pair[0], 22),
+            bags: read_u16(pair[0], 24) as usize..read_u16(pair[1], 24) as usize,
+        }).collect()
+    }
+
+    /// Name and zones of instrument `index`.
+    fn instrument(&self, index: usize) -> Result<(String, Vec<RawZone>)> {
+        let count = self.inst.len() / INST_SIZE - 1;
+        if index >= count {
+            return Err(corrupt(format!("instrument {index} doesn't exist")));
+        }
+        let record = &self.inst[index * INST_SIZE..];
+        let bags = read_u16(record, 20) as usize..read_u16(record, 20 + INST_SIZE) as usize;
+        Ok((read_name(&record[..NAME_LEN]), zones(self.ibag, self.igen, self.imod, bags)?))
+    }
+
+    fn preset_zones(&self, preset: &PresetHeader) -> Result<Vec<RawZone>> {
+        zones(self.pbag, self.pgen, self.pmod, preset.bags.clone())
+    }
+
+    fn sample_header(&self, index: usize) -> Option<SampleHeader> {
+        if index + 1 >= self.shdr.len() / SHDR_SIZE {
+            return None;
+        }
+        let record = &self.shdr[index * SHDR_SIZE..(index + 1) * SHDR_SIZE];
+        Some(SampleHeader {
+            name: read_name(&record[..NAME_LEN]),
+            start: read_u32(record, 20) as usize,
+            end: read_u32(record, 24) as usize,
+            loop_start: read_u32(record, 28) as usize,
+            loop_end: read_u32(record, 32) as usize,
+            sample_rate: read_u32(record, 36),
+            original_pitch: record[40],
+            pitch_correction: record[41] as i8,
+            link: read_u16(record, 42) as usize,
+            sample_type: read_u16(record, 44),
+        })
+    }
+}
+
+/// Zones for a range of bags. Each bag's generators and modulators run up
+/// to the next bag's, so the bag list has a terminal record after the last.
+fn zones(bag: &[u8], generators: &[u8], modulators: &[u8], bags: Range<usize>) -> Result<Vec<RawZone>> {
+    let bag_count = bag.len() / BAG_SIZE;
+    if bags.start > bags.end || bags.end >= bag_count {
+        return Err(corrupt(format!("zone indices {}..{} out of range", bags.start, bags.end)));
+    }
+    bags.map(|index| {
+        let record = &bag[index * BAG_SIZE..];
+        let (gen_start, gen_end) = (read_u16(record, 0) as usize, read_u16(record, BAG_SIZE) as usize);
+        let (mod_start, mod_end) = (read_u16(record, 2) as usize, read_u16(record, BAG_SIZE + 2) as usize);
+        if gen_start > gen_end || gen_end > generators.len() / GEN_SIZE || mod_start > mod_end || mod_end > modulators.len() / MOD_SIZE {
+            return Err(corrupt(format!("zone {index} has out-of-range generator or modulator indices")));
+        }
+        let generators = generators[gen_start * GEN_SIZE..gen_end * GEN_SIZE]
+            .chunks_exact(GEN_SIZE)
+            .map(|g| (read_u16(g, 0), read_u16(g, 2)))
+            .collect();
+        Ok(RawZone { generators, modulators: mod_end - mod_start })
+    }).collect()
+}
+
+struct SampleHeader {
+    name: String,
+    start: usize,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    sample_rate: u32,
+    original_pitch: u8,
+    pitch_correction: i8,
+    link: usize,
+    sample_type: u16,
+}
+
+/// A sample a zone plays: its index in `Sf2Import::samples`, and for a
+/// stereo pair, which side the zone asked for.
+#[derive(Clone, Copy, PartialEq)]
+struct SampleRef {
+    index: usize,
+    side: Option<u16>,
+    pitch_correction: i8,
+}
+
+/// Combined generator values for one zone.
+#[derive(Clone)]
+struct Generators {
+    values: [i32; GENERATOR_COUNT],
+    keys: (u8, u8),
+    velocities: (u8, u8),
+}
+
+impl Default for Generators {
+    fn default() -> Self {
+        let mut values = [0; GENERATOR_COUNT];
+        for op in [21, 23, 25, 26, 27, 28, 30, 33, 34, 35, 36, 38] {
+            values[op] = -12000;
+        }
+        values[gen::INITIAL_FILTER_FC as usize] = FILTER_OFF_CENTS;
+        values[gen::SCALE_TUNING as usize] = 100;
+        for op in [46, 47, gen::OVERRIDING_ROOT_KEY as usize] {
+            values[op] = -1;
+        }
+        Generators { values, keys: (0, 127), velocities: (0, 127) }
+    }
+}
+
+impl Generators {
+    fn get(&self, operator: u16) -> i32 {
+        self.values[operator as usize]
+    }
+
+    /// Instrument level: values replace what's there.
+    fn set(&mut self, zone: &RawZone) {
+        for &(op, amount) in &zone.generators {
+            let [low, high] = amount.to_le_bytes();
+            match op {
+                gen::KEY_RANGE => self.keys = (low, high),
+                gen::VEL_RANGE => self.velocities = (low, high),
+                gen::SAMPLE_ID | gen::SAMPLE_MODES => self.values[op as usize] = amount as i32,
+                op if (op as usize) < GENERATOR_COUNT => self.values[op as usize] = amount as i16 as i32,
+                _ => {}
+            }
+        }
+    }
+
+    /// Preset level: ranges replace what's there and everything else is an
+    /// offset (see `add`).
+    fn set_offsets(&mut self, zone: &RawZone) {
+        for &(op, amount) in &zone.generators {
+            let [low, high] = amount.to_le_bytes();
+            match op {
+                gen::KEY_RANGE => self.keys = (low, high),
+                gen::VEL_RANGE => self.velocities = (low, high),
+                op if (op as usize) < GENERATOR_COUNT => self.values[op as usize] = amount as i16 as i32,
+                _ => {}
+            }
+        }
+    }
+
+    /// Add preset offsets and narrow to the preset's ranges. Generators the
+    /// spec only allows on instruments are ignored.
+    fn add(&mut self, offsets: &Generators) {
+        const INSTRUMENT_ONLY: [u16; 13] = [0, 1, 2, 3, 4, 12, 45, 46, 47, 50, 54, 57, 58];
+        for (op, offset) in offsets.values.iter().enumerate() {
+            let op_u16 = op as u16;
+            if INSTRUMENT_ONLY.contains(&op_u16) || op_u16 == gen::SAMPLE_ID || op_u16 == gen::INSTRUMENT {
+                continue;
+            }
+            self.values[op] += offset;
+        }
+        self.keys = (self.keys.0.max(offsets.keys.0), self.keys.1.min(offsets.keys.1));
+        self.velocities = (self.velocities.0.max(offsets.velocities.0), self.velocities.1.min(offsets.velocities.1));
+    }
+}
+
+/// Offsets start at zero, not at the instrument defaults.
+fn no_offsets() -> Generators {
+    Generators { values: [0; GENERATOR_COUNT], keys: (0, 127), velocities: (0, 127) }
+}
+
+/// A zone converted for the AKP, before stereo pairs are joined.
+struct ImportedZone {
+    keygroup: Keygroup,
+    zone: Zone,
+    sample: SampleRef,
+}
+
+struct Importer<'a> {
+    hydra: Hydra<'a>,
+    data: Vec<i16>,
+    samples: Vec<Sf2Sample>,
+    /// `shdr` index to `samples` index, or why the sample can't be used.
+    sample_files: HashMap<usize, std::result::Result<usize, String>>,
+    skipped: Vec<SkippedGenerator>,
+}
+
+impl Importer<'_> {
+    fn skip(&mut self, location: &str, generator: &str, value: String, reason: impl Into<String>) {
+        let skipped = SkippedGenerator {
+            location: location.to_string(),
+            generator: generator.to_string(),
+            value,
+            reason: reason.into(),
+        };
+        // Global zones are seen once per zone; report them once
+        if !self.skipped.contains(&skipped) {
+            self.skipped.push(skipped);
+        }
+    }
+
+    /// Round a converted value into an AKP range, reporting it if it had to be clamped.
+    fn fit(&mut self, location: &str, gens: &Generators, op: u16, value: f32, min: i32, max: i32) -> i32 {
+        let rounded = if value.is_nan() { min } else { value.round().clamp(min as f32, max as f32) as i32 };
+        if value.is_nan() || value.round() < min as f32 || value.round() > max as f32 {
+            let reason = format!("outside the AKP range, clamped to {rounded}");
+            self.skip(location, generator_name(op), gens.get(op).to_string(), reason);
+        }
+        rounded
+    }
+
+    fn preset(&mut self, preset: &PresetHeader) -> Result<AkaiProgram> {
+        let mut program = AkaiProgram::default();
+        // Vibrato parameters of the zone that set LFO 1, and where it was
+        let mut vibrato: Option<([i32; 3], String)> = None;
+
+        let mut preset_zones = self.hydra.preset_zones(preset)?;
+        let mut preset_global = no_offsets();
+        if preset_zones.first().is_some_and(|z| z.get(gen::INSTRUMENT).is_none()) {
+            let global = preset_zones.remove(0);
+            preset_global.set_offsets(&global);
+            self.report_modulators(&preset.name, &global);
+        }
+
+        for preset_zone in &preset_zones {
+            let Some(instrument) = preset_zone.get(gen::INSTRUMENT) else { continue };
+            let mut offsets = preset_global.clone();
+            offsets.set_offsets(preset_zone);
+
+            let (instrument_name, mut zones) = self.hydra.instrument(instrument as usize)?;
+            let location = format!("{} / {instrument_name}", preset.name);
+            self.report_modulators(&location, preset_zone);
+
+            let mut instrument_global = Generators::default();
+            if zones.first().is_some_and(|z| z.get(gen::SAMPLE_ID).is_none()) {
+                let global = zones.remove(0);
+                instrument_global.set(&global);
+                self.report_modulators(&location, &global);
+            }
+
+            let mut imported = Vec::new();
+            for zone in &zones {
+                if zone.get(gen::SAMPLE_ID).is_none() {
+                    continue;
+                }
+                self.report_modulators(&location, zone);
+                let mut gens = instrument_global.clone();
+                gens.set(zone);
+                gens.add(&offsets);
+                if gens.keys.0 > gens.keys.1 || gens.velocities.0 > gens.velocities.1 {
+                    // The preset and instrument ranges don't overlap
+                    continue;
+                }
+                if let Some(zone) = self.zone(&location, &gens) {
+                    imported.push(zone);
+                }
+
+                if gens.get(gen::VIB_LFO_TO_PITCH) != 0 {
+                    let params = [gens.get(gen::VIB_LFO_TO_PITCH), gens.get(gen::FREQ_VIB_LFO), gens.get(gen::DELAY_VIB_LFO)];
+                    match &vibrato {
+                        None => {
+                            program.lfo1 = Some(self.vibrato(&location, &gens));
+                            vibrato = Some((params, location.clone()));
+                        }
+                        Some((first, _)) if *first == params => {}
+                        Some((_, first_location)) => {
+                            let reason = format!("AKP stores one LFO 1 per program; using the one from {first_location}");
+                            self.skip(&location, generator_name(gen::VIB_LFO_TO_PITCH), params[0].to_string(), reason);
+                        }
+                    }
+                }
+            }
+
+            for zone in join_stereo_pairs(imported) {
+                program.add_zone(&zone.keygroup, zone.zone);
+            }
+        }
+
+        program.header = Some(ProgramHeader {
+            midi_program_number: preset.number.min(127) as u8,
+            number_of_keygroups: program.keygroups.len().min(u8::MAX as usize) as u8,
+        });
+        // Attenuation maps to zone level, so the program plays at full level;
+        // SF2's default velocity-to-attenuation modulator is full sensitivity
+        program.output = Some(ProgramOutput { loudness: 100, velocity_sensitivity: 100, ..Default::default() });
+        Ok(program)
+    }
+
+    fn report_modulators(&mut self, location: &str, zone: &RawZone) {
+        if zone.modulators > 0 {
+            self.skip(location, "modulators", zone.modulators.to_string(), "SF2 modulators aren't imported");
+        }
+    }
+
+    /// Convert one instrument zone into its keygroup settings and zone.
+    fn zone(&mut self, location: &str, gens: &Generators) -> Option<ImportedZone> {
+        let sample_id = gens.get(gen::SAMPLE_ID) as usize;
+        let sample = match self.sample(sample_id) {
+            Ok(sample) => sample,
+            Err(reason) => {
+                self.skip(location, generator_name(gen::SAMPLE_ID), sample_id.to_string(), format!("{reason}, zone skipped"));
+                return None;
+            }
+        };
+        let defaults = Generators::default();
+        for (op, reason) in UNSUPPORTED {
+            if gens.get(op) != defaults.get(op) {
+                self.skip(location, generator_name(op), gens.get(op).to_string(), reason);
+            }
+        }
+
+        let audio = &self.samples[sample.index].audio;
+        let original_pitch = audio.info.unity_note.unwrap_or(DEFAULT_ROOT_KEY);
+        let sample_loop = audio.info.sample_loop;
+        let mut zone = Zone {
+            sample_name: self.samples[sample.index].file_name.clone(),
+            low_vel: gens.velocities.0,
+            high_vel: gens.velocities.1,
+            ..Default::default()
+        };
+
+        // Coarse and fine tune share the zone's semitone/fine pair; fine is ±50 cents
+        let cents = gens.get(gen::COARSE_TUNE) * 100 + gens.get(gen::FINE_TUNE) + sample.pitch_correction as i32;
+        let semitones = (cents as f32 / 100.0).round();
+        zone.semitone_tune = self.fit(location, gens, gen::COARSE_TUNE, semitones, -36, 36) as i8;
+        zone.fine_tune = (cents as f32 - semitones * 100.0).clamp(-50.0, 50.0) as i8;
+        zone.root_key = Some(match gens.get(gen::OVERRIDING_ROOT_KEY) {
+            key @ 0..=127 => key as u8,
+            _ => original_pitch,
+        });
+        match gens.get(gen::SCALE_TUNING) {
+            0 => zone.keyboard_track = 0,
+            100 => {}
+            other => {
+                let reason = "AKP key tracking is on (100) or off (0)";
+                self.skip(location, generator_name(gen::SCALE_TUNING), other.to_string(), reason);
+            }
+        }
+
+        let attenuation = gens.get(gen::INITIAL_ATTENUATION) as f32 / 10.0;
+        zone.level = self.fit(location, gens, gen::INITIAL_ATTENUATION, -attenuation, -50, 50) as i8;
+        zone.pan = self.fit(location, gens, gen::PAN, gens.get(gen::PAN) as f32 / 10.0, -50, 50) as i8;
+
+        zone.playback = match gens.get(gen::SAMPLE_MODES) & 3 {
+            1 => 2, // loop in release
+            3 => 3, // loop until release
+            _ => 0, // no loop
+        };
+        if zone.playback != 0 {
+            let start_offset = gens.get(gen::START_LOOP_ADDRS_OFFSET) + gens.get(gen::START_LOOP_ADDRS_COARSE_OFFSET) * 32768;
+            let end_offset = gens.get(gen::END_LOOP_ADDRS_OFFSET) + gens.get(gen::END_LOOP_ADDRS_COARSE_OFFSET) * 32768;
+            zone.sample_loop = sample_loop.and_then(|l| {
+                let start = l.start as i64 + start_offset as i64;
+                let end = l.end as i64 + end_offset as i64;
+                (start >= 0 && end > start).then_some(SampleLoop { start: start as u32, end: end as u32, crossfade: 0 })
+            });
+        }
+
+        let mut keygroup = Keygroup { low_key: gens.keys.0, high_key: gens.keys.1, ..Default::default() };
+        if gens.get(gen::EXCLUSIVE_CLASS) != 0 {
+            keygroup.mute_group = self.fit(location, gens, gen::EXCLUSIVE_CLASS, gens.get(gen::EXCLUSIVE_CLASS) as f32, 0, MAX_MUTE_GROUP) as u8;
+        }
+        keygroup.amp_env = Some(self.amp_envelope(location, gens));
+        self.filter(location, gens, &mut keygroup);
+
+        Some(ImportedZone { keygroup, zone, sample })
+    }
+
+    fn amp_envelope(&mut self, location: &str, gens: &Generators) -> Envelope {
+        // Sustain is attenuation in centibels
+        let sustain = 100.0 * 10f32.powf(-gens.get(gen::SUSTAIN_VOL_ENV) as f32 / 200.0);
+        Envelope {
+            attack: self.fit(location, gens, gen::ATTACK_VOL_ENV, envelope_time_to_raw(seconds(gens.get(gen::ATTACK_VOL_ENV))), 0, 100) as u8,
+            decay: self.fit(location, gens, gen::DECAY_VOL_ENV, envelope_time_to_raw(seconds(gens.get(gen::DECAY_VOL_ENV))), 0, 100) as u8,
+            sustain: self.fit(location, gens, gen::SUSTAIN_VOL_ENV, sustain, 0, 100) as u8,
+            release: self.fit(location, gens, gen::RELEASE_VOL_ENV, release_time_to_raw(seconds(gens.get(gen::RELEASE_VOL_ENV))), 0, 100) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// SF2's lowpass filter and the modulation envelope driving it.
+    fn filter(&mut self, location: &str, gens: &Generators, keygroup: &mut Keygroup) {
+        let (cutoff, q, depth) = (gens.get(gen::INITIAL_FILTER_FC), gens.get(gen::INITIAL_FILTER_Q), gens.get(gen::MOD_ENV_TO_FILTER_FC));
+        if cutoff >= FILTER_OFF_CENTS && q == 0 && depth == 0 {
+            return;
+        }
+        keygroup.filter = Some(Filter {
+            cutoff: self.fit(location, gens, gen::INITIAL_FILTER_FC, Filter::cutoff_from_hz(hz(cutoff)), 0, 100) as u8,
+            resonance: self.fit(location, gens, gen::INITIAL_FILTER_Q, Filter::resonance_from_db(q as f32 / 10.0), 0, 12) as u8,
+            ..Default::default()
+        });
+
+        if depth != 0 {
+            // Sustain is the drop from full level, in 0.1% units
+            let sustain = 100.0 - gens.get(gen::SUSTAIN_MOD_ENV) as f32 / 10.0;
+            keygroup.filter_env = Some(FilterEnvelope {
+                attack: self.fit(location, gens, gen::ATTACK_MOD_ENV, envelope_time_to_raw(seconds(gens.get(gen::ATTACK_MOD_ENV))), 0, 100) as u8,
+                decay: self.fit(location, gens, gen::DECAY_MOD_ENV, envelope_time_to_raw(seconds(gens.get(gen::DECAY_MOD_ENV))), 0, 100) as u8,
+                sustain: self.fit(location, gens, gen::SUSTAIN_MOD_ENV, sustain, 0, 100) as u8,
+                release: self.fit(location, gens, gen::RELEASE_MOD_ENV, release_time_to_raw(seconds(gens.get(gen::RELEASE_MOD_ENV))), 0, 100) as u8,
+                // Same depth scaling as fileg_depth: ±9600 cents → ±100
+                depth: self.fit(location, gens, gen::MOD_ENV_TO_FILTER_FC, depth as f32 / 9600.0 * 100.0, -100, 100) as i8,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// The vibrato LFO as LFO 1, which our writers route to pitch.
+    fn vibrato(&mut self, location: &str, gens: &Generators) -> Lfo {
+        let delay = match gens.get(gen::DELAY_VIB_LFO) {
+            -12000 => 0.0,
+            timecents => seconds(timecents) / 10.0 * 100.0,
+        };
+        Lfo {
+            waveform: TRIANGLE_WAVEFORM,
+            rate: self.fit(location, gens, gen::FREQ_VIB_LFO, Lfo::rate_from_hz(hz(gens.get(gen::FREQ_VIB_LFO))), 0, 100) as u8,
+            depth: self.fit(location, gens, gen::VIB_LFO_TO_PITCH, gens.get(gen::VIB_LFO_TO_PITCH) as f32, 0, 100) as u8,
+            delay: self.fit(location, gens, gen::DELAY_VIB_LFO, delay, 0, 100) as u8,
+            ..Default::default()
+        }
+    }
+
+    /// Extract a sample (and its stereo partner) the first time a zone uses it.
+    fn sample(&mut self, id: usize) -> std::result::Result<SampleRef, String> {
+        let header = self.hydra.sample_header(id).ok_or_else(|| "no such sample".to_string())?;
+        let partner = self.hydra.sample_header(header.link).filter(|p| {
+            let pair = matches!((header.sample_type, p.sample_type), (LEFT_SAMPLE, RIGHT_SAMPLE) | (RIGHT_SAMPLE, LEFT_SAMPLE));
+            pair && p.link == id && p.end - p.start == header.end - header.start && p.sample_rate == header.sample_rate
+        });
+        let side = partner.as_ref().map(|_| header.sample_type);
+        let pitch_correction = header.pitch_correction;
+
+        let index = match self.sample_files.get(&id) {
+            Some(result) => result.clone()?,
+            None => {
+                let result = self.extract(&header, partner.as_ref());
+                self.sample_files.insert(id, result.clone());
+                if partner.is_some() {
+                    self.sample_files.insert(header.link, result.clone());
+                }
+                result?
+            }
+        };
+        Ok(SampleRef { index, side, pitch_correction })
+    }
+
+    fn extract(&mut self, header: &SampleHeader, partner: Option<&SampleHeader>) -> std::result::Result<usize, String> {
+        if header.sample_type & ROM_SAMPLE != 0 {
+            return Err("ROM samples aren't stored in the file".to_string());
+        }
+        if header.end <= header.start {
+            return Err("sample is empty".to_string());
+        }
+        let frames = |h: &SampleHeader| {
+            (h.start < h.end && h.end <= self.data.len()).then(|| self.data[h.start..h.end].to_vec())
+        };
+        let (channels, name) = match partner {
+            None => (vec![frames(header)], header.name.clone()),
+            Some(partner) => {
+                let (left, right) = if header.sample_type == LEFT_SAMPLE { (header, partner) } else { (partner, header) };
+                (vec![frames(left), frames(right)], pair_name(&left.name, &right.name))
+            }
+        };
+        let Some(channels) = channels.into_iter().collect::<Option<Vec<_>>>() else {
+            return Err("sample data is outside the smpl chunk".to_string());
+        };
+
+        let length = header.end - header.start;
+        let sample_loop = (header.loop_start >= header.start && header.loop_end > header.loop_start && header.loop_end <= header.end)
+            .then(|| SampleLoop {
+                start: (header.loop_start - header.start) as u32,
+                // SF2 loop ends are exclusive, WAV `smpl` ones inclusive
+                end: (header.loop_end - header.start - 1) as u32,
+                crossfade: 0,
+            });
+        let info = WavInfo {
+            sample_rate: header.sample_rate,
+            channels: channels.len() as u16,
+            bits_per_sample: 16,
+            frames: length as u32,
+            unity_note: Some(if header.original_pitch <= 127 { header.original_pitch } else { DEFAULT_ROOT_KEY }),
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+
+        let file_name = self.unique_file_name(&name);
+        self.samples.push(Sf2Sample { file_name, audio: WavAudio { info, channels } });
+        Ok(self.samples.len() - 1)
+    }
+
+    /// `<name>.wav`, numbered if another sample already has the name. Names
+    /// stay within the AKP's 20 characters.
+    fn unique_file_name(&self, name: &str) -> String {
+        let base = match safe_file_name(name) {
+            safe if safe.is_empty() => "Sample".to_string(),
+            safe => safe,
+        };
+        let taken = |stem: &str| self.samples.iter().any(|s| s.file_name.eq_ignore_ascii_case(&format!("{stem}.wav")));
+        let mut stem = base.clone();
+        let mut number = 2;
+        while taken(&stem) {
+            let suffix = format!(" {number}");
+            let keep = base.chars().count().min(NAME_LEN - suffix.len());
+            stem = format!("{}{suffix}", base.chars().take(keep).collect::<String>());
+            number += 1;
+        }
+        format!("{stem}.wav")
+    }
+}
+
+/// Join the zones playing the two sides of a stereo pair into one zone on
+/// the stereo WAV, centred between their pans. Sides without a matching
+/// zone are kept as they are.
+fn join_stereo_pairs(zones: Vec<ImportedZone>) -> Vec<ImportedZone> {
+    let mut joined: Vec<ImportedZone> = Vec::new();
+    for zone in zones {
+        let partner = joined.iter_mut().find(|other| {
+            other.sample.index == zone.sample.index
+                && other.sample.side.is_some()
+                && zone.sample.side.is_some()
+                && other.sample.side != zone.sample.side
+                && other.keygroup.low_key == zone.keygroup.low_key
+                && other.keygroup.high_key == zone.keygroup.high_key
+                && (other.zone.low_vel, other.zone.high_vel) == (zone.zone.low_vel, zone.zone.high_vel)
+        });
+        match partner {
+            Some(other) => {
+                other.zone.pan = ((other.zone.pan as i16 + zone.zone.pan as i16) / 2) as i8;
+                // Joined; no other zone should pair with it
+                other.sample.side = None;
+            }
+            None => joined.push(zone),
+        }
+    }
+    joined
+}
+
+/// Name for a joined stereo pair: what the two names share, without the
+/// side marker (`Piano C3 L` and `Piano C3 R` → `Piano C3`).
+fn pair_name(left: &str, right: &str) -> String {
+    let shared: String = left.chars().zip(right.chars()).take_while(|(l, r)| l == r).map(|(l, _)| l).collect();
+    let trimmed = shared.trim_end_matches([' ', '_', '-', '(', '[', '.']);
+    if trimmed.is_empty() { left.to_string() } else { trimmed.to_string() }
+}
+
+/// SF2 timecents to seconds.
+fn seconds(timecents: i32) -> f32 {
+    2f32.powf(timecents as f32 / 1200.0)
+}
+
+/// SF2 absolute cents to Hz.
+fn hz(cents: i32) -> f32 {
+    CENTS_REFERENCE_HZ * 2f32.powf(cents as f32 / 1200.0)
+}
+
+/// Spec name of a generator, for reports.
+fn generator_name(operator: u16) -> &'static str {
+    const NAMES: [&str; GENERATOR_COUNT] = [
+        "startAddrsOffset", "endAddrsOffset", "startloopAddrsOffset", "endloopAddrsOffset",
+        "startAddrsCoarseOffset", "modLfoToPitch", "vibLfoToPitch", "modEnvToPitch", "initialFilterFc",
+        "initialFilterQ", "modLfoToFilterFc", "modEnvToFilterFc", "endAddrsCoarseOffset", "modLfoToVolume",
+        "unused1", "chorusEffectsSend", "reverbEffectsSend", "pan", "unused2", "unused3", "unused4",
+        "delayModLFO", "freqModLFO", "delayVibLFO", "freqVibLFO", "delayModEnv", "attackModEnv", "holdModEnv",
+        "decayModEnv", "sustainModEnv", "releaseModEnv", "keynumToModEnvHold", "keynumToModEnvDecay",
+        "delayVolEnv", "attackVolEnv", "holdVolEnv", "decayVolEnv", "sustainVolEnv", "releaseVolEnv",
+        "keynumToVolEnvHold", "keynumToVolEnvDecay", "instrument", "reserved1", "keyRange", "velRange",
+        "startloopAddrsCoarseOffset", "keynum", "velocity", "initialAttenuation", "reserved2",
+        "endloopAddrsCoarseOffset", "coarseTune", "fineTune", "sampleID", "sampleModes", "reserved3",
+        "scaleTuning", "exclusiveClass", "overridingRootKey", "unused5",
+    ];
+    NAMES.get(operator as usize).copied().unwrap_or("unknown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ConvertOptions;
+    use tempfile::TempDir;
+
+    fn wav(channels: Vec<Vec<i16>>, unity_note: u8, sample_loop: Option<SampleLoop>) -> Vec<u8> {
+        let info = WavInfo {
+            sample_rate: 22050,
+            channels: channels.len() as u16,
+            bits_per_sample: 16,
+            frames: channels[0].len() as u32,
+            unity_note: Some(unity_note),
+            pitch_fraction_cents: 0,
+            sample_loop,
+        };
+        WavAudio { info, channels }.to_wav_bytes()
+    }
+
+    fn piano() -> AkaiProgram {
+        AkaiProgram {
+            lfo1: Some(Lfo { waveform: TRIANGLE_WAVEFORM, rate: 50, depth: 40, delay: 20, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                mute_group: 3,
+                amp_env: Some(Envelope { attack: 10, decay: 50, sustain: 50, release: 100, ..Default::default() }),
+                filter: Some(Filter { cutoff: 50, resonance: 6, ..Default::default() }),
+                filter_env: Some(FilterEnvelope { attack: 20, decay: 40, sustain: 30, release: 60, depth: 50, ..Default::default() }),
+                zones: vec![Zone {
+                    sample_name: "piano c3.wav".to_string(),
+                    high_vel: 100,
+                    semitone_tune: 2,
+                    fine_tune: -30,
+                    pan: -25,
+                    level: -6,
+                    playback: 2,
+                    root_key: Some(48),
+                    sample_loop: Some(SampleLoop { start: 2, end: 5, crossfade: 0 }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn sf2_of(program: &AkaiProgram, dir: &Path) -> Vec<u8> {
+        program.to_sf2("Piano", dir, &ConvertOptions::default()).unwrap().bytes
+    }
+
+    #[test]
+    fn test_round_trip_through_sf2_writer() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("piano c3.wav"), wav(vec![(1..=8).collect()], 48, None)).unwrap();
+        let program = piano();
+
+        let import = parse_sf2(&sf2_of(&program, dir.path())).unwrap();
+        assert!(import.skipped.is_empty(), "{:?}", import.skipped);
+        assert_eq!(import.presets.len(), 1);
+        let preset = &import.presets[0];
+        assert_eq!((preset.name.as_str(), preset.bank, preset.number), ("Piano", 0, 0));
+
+        let imported = &preset.program;
+        assert_eq!(imported.lfo1, program.lfo1);
+        assert_eq!(imported.keygroups.len(), 1);
+        let (keygroup, original) = (&imported.keygroups[0], &program.keygroups[0]);
+        assert_eq!((keygroup.low_key, keygroup.high_key, keygroup.mute_group), (36, 59, 3));
+        assert_eq!(keygroup.amp_env, original.amp_env);
+        assert_eq!(keygroup.filter, original.filter);
+        assert_eq!(keygroup.filter_env, original.filter_env);
+        assert_eq!(keygroup.zones, vec![Zone { sample_name: "piano c3.wav".to_string(), ..original.zones[0].clone() }]);
+
+        assert_eq!(import.samples.len(), 1);
+        let sample = &import.samples[0];
+        assert_eq!(sample.file_name, "piano c3.wav");
+        assert_eq!(sample.audio.channels, vec![(1..=8).collect::<Vec<i16>>()]);
+        assert_eq!(sample.audio.info.unity_note, Some(48));
+        assert_eq!(sample.audio.info.sample_loop, Some(SampleLoop { start: 2, end: 5, crossfade: 0 }));
+    }
+
+    #[test]
+    fn test_stereo_pair_joins_into_one_zone() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("pad.wav"), wav(vec![vec![1, 2, 3], vec![-1, -2, -3]], 60, None)).unwrap();
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                zones: vec![Zone { sample_name: "pad.wav".to_string(), ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let import = parse_sf2(&sf2_of(&program, dir.path())).unwrap();
+        assert_eq!(import.samples.len(), 1);
+        assert_eq!(import.samples[0].file_name, "pad.wav");
+        assert_eq!(import.samples[0].audio.channels, vec![vec![1, 2, 3], vec![-1, -2, -3]]);
+
+        let zones = &import.presets[0].program.keygroups[0].zones;
+        assert_eq!(zones.len(), 1);
+        assert_eq!((zones[0].sample_name.as_str(), zones[0].pan), ("pad.wav", 0));
+
+        import.write_samples(dir.path()).unwrap();
+        let written = crate::samples::read_wav_audio(&dir.path().join("pad.wav")).unwrap();
+        assert_eq!(written.channels, import.samples[0].audio.channels);
+    }
+
+    #[test]
+    fn test_out_of_range_generator_is_reported() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("piano c3.wav"), wav(vec![vec![0; 8]], 48, None)).unwrap();
+        let mut bytes = sf2_of(&piano(), dir.path());
+
+        // Stretch the release to one second (0 timecents)
+        let igen = bytes.windows(4).position(|w| w == b"igen").unwrap();
+        let size = read_u32(&bytes, igen + 4) as usize;
+        let release = (igen + 8..igen + 8 + size).step_by(GEN_SIZE)
+            .find(|&offset| read_u16(&bytes, offset) == gen::RELEASE_VOL_ENV)
+            .unwrap();
+        bytes[release + 2..release + 4].copy_from_slice(&0u16.to_le_bytes());
+
+        let import = parse_sf2(&bytes).unwrap();
+        assert_eq!(import.presets[0].program.keygroups[0].amp_env.as_ref().unwrap().release, 100);
+        assert_eq!(
+            import.skipped.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            vec!["Piano / Keygroup 1: releaseVolEnv=0 (outside the AKP range, clamped to 100)"],
+        );
+    }
+
+    #[test]
+    fn test_rejects_non_sf2() {
+        assert!(matches!(parse_sf2(b"RIFF\x04\0\0\0WAVE"), Err(AkpError::InvalidPreset(..))));
+        assert_eq!(pair_name("Piano C3(L)", "Piano C3(R)"), "Piano C3");
+        assert_eq!(safe_file_name("Kick/Snare: 1"), "Kick_Snare_ 1");
+    }
+}
@@ -1,393 +1,336 @@
-use crate::types::{AkaiProgram, EnvelopeTiming, Keygroup, mod_source_name, mod_source_sfz_suffix, mod_source_type};
+use crate::instrument::{FilterKind, FilterStage, Group, Instrument, ModRoute, ModSource, ModTarget, Playback};
+use crate::types::{AkaiProgram, ConvertOptions, DetuneMode, Filter, FilterRouting};
 
 impl AkaiProgram {
     pub fn to_sfz_string(&self) -> String {
+        self.to_sfz_string_with(&ConvertOptions::default())
+    }
+
+    pub fn to_sfz_string_with(&self, options: &ConvertOptions) -> String {
         let mut sfz = String::new();
         sfz.push_str("// Generated by Rusty Samplers\n\n");
 
+        // Detune table: Scala file when one is being written, otherwise split regions per key
+        let has_detune = self.tuning.as_ref().is_some_and(|t| t.has_detune());
+        let scala_file = match (options.detune, &options.scala_file) {
+            (DetuneMode::Scala, Some(name)) if has_detune => Some(name),
+            _ => None,
+        };
+        let split_detune = has_detune && scala_file.is_none();
+
+        if let (Some(name), Some(tuning)) = (scala_file, &self.tuning) {
+            sfz.push_str("<control>\n");
+            sfz.push_str(&format!("scala_name={name}\n"));
+            sfz.push_str("scala_root_key=60\n");
+            // The .scl is relative to C, so C's own offset moves the root frequency
+            if tuning.detune[0] != 0 {
+                let root_freq = 261.6256 * 2f32.powf(tuning.detune[0] as f32 / 1200.0);
+                sfz.push_str(&format!("scala_root_freq={root_freq:.3}\n"));
+            }
+            sfz.push('\n');
+        }
+
+        let instrument = self.to_instrument(split_detune, options.drum_kit_layout);
+        let program_semitone = instrument.tuning.as_ref().map_or(0, |t| t.transpose);
+        let program_fine = instrument.tuning.as_ref().map_or(0, |t| t.tune_cents);
+
         // Global header — program-level settings that apply to all regions
-        let has_global = self.tuning.is_some() || self.output.is_some()
-            || self.lfo1.is_some() || self.lfo2.is_some();
+        let has_global = instrument.tuning.is_some() || instrument.output.is_some()
+            || instrument.lfo1.is_some() || instrument.lfo2.is_some();
         if has_global {
             sfz.push_str("<global>\n");
 
-            if let Some(tuning) = &self.tuning {
-                sfz.push_str(&format!("bend_up={}\n", tuning.pitchbend_up as i32 * 100));
-                sfz.push_str(&format!("bend_down=-{}\n", tuning.pitchbend_down as i32 * 100));
+            if program_semitone != 0 {
+                sfz.push_str(&format!("transpose={program_semitone}\n"));
+            }
+            if program_fine != 0 {
+                sfz.push_str(&format!("tune={program_fine}\n"));
+            }
+
+            if let Some(tuning) = &instrument.tuning {
+                sfz.push_str(&format!("bend_up={}\n", tuning.bend_up_cents));
+                sfz.push_str(&format!("bend_down=-{}\n", tuning.bend_down_cents));
 
                 // Aftertouch → pitch (hardwired)
-                if tuning.aftertouch != 0 {
-                    sfz.push_str(&format!("pitch_chanaft={}\n", tuning.aftertouch as i32 * 100));
+                if tuning.aftertouch_cents != 0 {
+                    sfz.push_str(&format!("pitch_chanaft={}\n", tuning.aftertouch_cents));
                 }
             }
 
-            if let Some(output) = &self.output {
-                if output.loudness != 85 {
-                    sfz.push_str(&format!("amplitude={}\n", output.loudness));
+            if let Some(output) = &instrument.output {
+                if output.amplitude != 85.0 {
+                    sfz.push_str(&format!("amplitude={}\n", output.amplitude));
                 }
 
                 // AKP range (-100..100) maps directly to SFZ amp_veltrack (-100..100)
-                let vel_track = output.velocity_sensitivity as i32;
-                if vel_track != 100 {
-                    sfz.push_str(&format!("amp_veltrack={vel_track}\n"));
+                if output.velocity_tracking != 100.0 {
+                    sfz.push_str(&format!("amp_veltrack={}\n", output.velocity_tracking));
                 }
             }
 
             // LFO hardwired modwheel/aftertouch shortcuts (global scope)
-            if let Some(lfo) = &self.lfo1 {
-                if lfo.modwheel > 0 {
-                    let depth_cents = lfo.modwheel as f32;
-                    sfz.push_str(&format!("pitchlfo_depthcc1={depth_cents:.0}\n"));
+            if let Some(lfo) = &instrument.lfo1 {
+                if lfo.modwheel_cents > 0.0 {
+                    sfz.push_str(&format!("pitchlfo_depthcc1={:.0}\n", lfo.modwheel_cents));
                 }
-                if lfo.aftertouch > 0 {
-                    let depth_cents = lfo.aftertouch as f32;
-                    sfz.push_str(&format!("pitchlfo_depthchanaft={depth_cents:.0}\n"));
+                if lfo.aftertouch_cents > 0.0 {
+                    sfz.push_str(&format!("pitchlfo_depthchanaft={:.0}\n", lfo.aftertouch_cents));
                 }
             }
 
             sfz.push('\n');
         }
 
-        for keygroup in &self.keygroups {
-            // Each zone becomes a <region>
-            let zones: Vec<_> = if keygroup.zones.is_empty() {
-                // No zones — emit one region with no sample (keeps envelope/filter data visible)
-                vec![None]
-            } else {
-                keygroup.zones.iter().map(Some).collect()
-            };
-
-            for zone in &zones {
+        for group in &instrument.groups {
+            // Each region repeats its group's settings
+            for region in &group.regions {
                 sfz.push_str("<region>\n");
-
-                // Sample + velocity from zone
-                if let Some(z) = zone {
-                    sfz.push_str(&format!("sample={}\n", z.sample_name));
-                    sfz.push_str(&format!("lokey={}\nhikey={}\n", keygroup.low_key, keygroup.high_key));
-                    sfz.push_str(&format!("lovel={}\nhivel={}\n", z.low_vel, z.high_vel));
-
-                    // Zone-level tuning (additive with keygroup)
-                    let semitone = keygroup.semitone_tune as i16 + z.semitone_tune as i16;
-                    let fine = keygroup.fine_tune as i16 + z.fine_tune as i16;
-                    if semitone != 0 {
-                        sfz.push_str(&format!("transpose={semitone}\n"));
-                    }
-                    if fine != 0 {
-                        sfz.push_str(&format!("tune={fine}\n"));
-                    }
-
-                    // Zone pan
-                    if z.pan != 0 {
-                        sfz.push_str(&format!("pan={}\n", z.pan));
-                    }
-
-                    // Zone level
-                    if z.level != 0 {
-                        sfz.push_str(&format!("volume={}\n", z.level));
-                    }
-
-                    // Playback mode from zone
-                    match z.playback {
-                        0 => sfz.push_str("loop_mode=no_loop\n"),
-                        1 => sfz.push_str("loop_mode=one_shot\n"),
-                        2 => sfz.push_str("loop_mode=loop_continuous\n"),
-                        3 => sfz.push_str("loop_mode=loop_sustain\n"),
-                        _ => {} // 4=AS SAMPLE, use sample header default
-                    }
-                } else {
-                    sfz.push_str(&format!("lokey={}\nhikey={}\n", keygroup.low_key, keygroup.high_key));
+                if let Some(label) = &group.label {
+                    sfz.push_str(&format!("region_label={label}\n"));
                 }
 
-                // Amp envelope
-                if let Some(env) = &keygroup.amp_env {
-                    sfz.push_str(&format!("ampeg_attack={:.3}\n", env.attack_time()));
-                    sfz.push_str(&format!("ampeg_decay={:.3}\n", env.decay_time()));
-                    sfz.push_str(&format!("ampeg_sustain={}\n", env.sustain));
-                    sfz.push_str(&format!("ampeg_release={:.3}\n", env.release_time()));
-
-                    if env.velocity_attack != 0 {
-                        sfz.push_str(&format!("ampeg_vel2attack={}\n", env.velocity_attack));
+                if let Some(sample) = &region.sample {
+                    sfz.push_str(&format!("sample={sample}\n"));
+                    sfz.push_str(&format!("lokey={}\nhikey={}\n", region.low_key, region.high_key));
+                    let (low_vel, high_vel) = region.faded_vel_range();
+                    sfz.push_str(&format!("lovel={low_vel}\nhivel={high_vel}\n"));
+                    if let Some((lo, hi)) = region.fade_in {
+                        sfz.push_str(&format!("xfin_lovel={lo}\nxfin_hivel={hi}\n"));
                     }
-                    if env.on_vel_release != 0 {
-                        sfz.push_str(&format!("ampeg_vel2release={}\n", env.on_vel_release));
+                    if let Some((lo, hi)) = region.fade_out {
+                        sfz.push_str(&format!("xfout_lovel={lo}\nxfout_hivel={hi}\n"));
                     }
-                    if env.keyscale != 0 {
-                        sfz.push_str(&format!("// AKP amp env keyscale={}\n", env.keyscale));
+                    if region.fade_in.is_some() || region.fade_out.is_some() {
+                        sfz.push_str("xf_velcurve=power\n");
                     }
-                }
 
-                // Filter
-                if let Some(filter) = &keygroup.filter {
-                    sfz.push_str(&format!("fil_type={}\n", filter.sfz_filter_type()));
-                    sfz.push_str(&format!("cutoff={:.1}\n", filter.cutoff_hz()));
-                    sfz.push_str(&format!("resonance={:.1}\n", filter.resonance_db()));
-
-                    if filter.keyboard_track != 0 {
-                        // Convert -36..+36 semitones to cents
-                        sfz.push_str(&format!("fil_keytrack={}\n", filter.keyboard_track as i32 * 100));
+                    if let Some(root_key) = region.root_key {
+                        sfz.push_str(&format!("pitch_keycenter={root_key}\n"));
                     }
-                }
 
-                // Filter envelope
-                if let Some(env) = &keygroup.filter_env {
-                    sfz.push_str(&format!("fileg_attack={:.3}\n", env.attack_time()));
-                    sfz.push_str(&format!("fileg_decay={:.3}\n", env.decay_time()));
-                    sfz.push_str(&format!("fileg_sustain={}\n", env.sustain));
-                    sfz.push_str(&format!("fileg_release={:.3}\n", env.release_time()));
-
-                    if env.depth != 0 {
-                        // Convert depth (-100..100) to cents
-                        let depth_cents = env.depth as f32 / 100.0 * 9600.0;
-                        sfz.push_str(&format!("fileg_depth={depth_cents:.0}\n"));
+                    // Region opcodes replace the <global> ones, so they carry the program tuning too
+                    if region.transpose != 0 {
+                        sfz.push_str(&format!("transpose={}\n", program_semitone + region.transpose));
+                    }
+                    if region.tune_cents != 0 {
+                        sfz.push_str(&format!("tune={}\n", program_fine + region.tune_cents));
                     }
 
-                    if env.velocity_attack != 0 {
-                        sfz.push_str(&format!("fileg_vel2attack={}\n", env.velocity_attack));
+                    // Written on the AKP -50..50 scale, as the SFZ importer reads it back
+                    if region.pan != 0.0 {
+                        sfz.push_str(&format!("pan={}\n", (region.pan / 2.0) as i32));
                     }
-                    if env.on_vel_release != 0 {
-                        sfz.push_str(&format!("fileg_vel2release={}\n", env.on_vel_release));
+                    if region.volume_db != 0.0 {
+                        sfz.push_str(&format!("volume={}\n", region.volume_db));
                     }
-                }
 
-                // LFOs from program level
-                if let Some(lfo) = &self.lfo1 {
-                    if lfo.depth > 0 {
-                        sfz.push_str(&format!("lfo1_freq={:.2}\n", lfo.rate_hz()));
-                        sfz.push_str(&format!("lfo1_wave={}\n", lfo.waveform_name()));
-                        let depth_cents = lfo.depth_normalized() * 100.0;
-                        sfz.push_str(&format!("lfo1_pitch={depth_cents:.1}\n"));
-
-                        if lfo.delay > 0 {
-                            let delay_time = (lfo.delay as f32 / 100.0) * 10.0;
-                            sfz.push_str(&format!("lfo1_delay={delay_time:.2}\n"));
-                        }
+                    match region.playback {
+                        Playback::NoLoop => sfz.push_str("loop_mode=no_loop\n"),
+                        Playback::OneShot => sfz.push_str("loop_mode=one_shot\n"),
+                        Playback::Loop => sfz.push_str("loop_mode=loop_continuous\n"),
+                        Playback::LoopUntilRelease => sfz.push_str("loop_mode=loop_sustain\n"),
+                        // AS SAMPLE: loop if the WAV has a loop, else use sample header default
+                        Playback::AsSample if region.looped => sfz.push_str("loop_mode=loop_continuous\n"),
+                        Playback::AsSample => {}
                     }
-                }
 
-                if let Some(lfo) = &self.lfo2 {
-                    if lfo.depth > 0 {
-                        sfz.push_str(&format!("lfo2_freq={:.2}\n", lfo.rate_hz()));
-                        sfz.push_str(&format!("lfo2_wave={}\n", lfo.waveform_name()));
-                        let depth_cents = lfo.depth_normalized() * 100.0;
-                        sfz.push_str(&format!("lfo2_pitch={depth_cents:.1}\n"));
-
-                        if lfo.delay > 0 {
-                            let delay_time = (lfo.delay as f32 / 100.0) * 10.0;
-                            sfz.push_str(&format!("lfo2_delay={delay_time:.2}\n"));
-                        }
+                    // Loop points from the WAV smpl chunk
+                    if let (true, Some(sample_loop)) = (region.looped, region.sample_loop) {
+                        sfz.push_str(&format!("loop_start={}\nloop_end={}\n", sample_loop.start, sample_loop.end));
                     }
+                } else {
+                    // No zones — keeps envelope/filter data visible
+                    sfz.push_str(&format!("lokey={}\nhikey={}\n", region.low_key, region.high_key));
                 }
 
-                // Flexible modulation matrix
-                self.emit_flexible_modulation(&mut sfz, keygroup);
-
+                write_group_opcodes(&mut sfz, &instrument, group);
                 sfz.push('\n');
             }
         }
 
         sfz
     }
+}
 
-    /// Emit SFZ opcodes for the flexible modulation matrix.
-    ///
-    /// The AKP modulation system has 17 assignable routes where the source is
-    /// set in the program-level `mods` chunk and the amount is stored per-keygroup
-    /// (in kloc, filt, out chunks). This maps each active route to the closest
-    /// SFZ opcode.
-    fn emit_flexible_modulation(&self, sfz: &mut String, keygroup: &Keygroup) {
-        let mods = match &self.modulation {
-            Some(m) => m,
-            None => return,
-        };
+/// Keygroup-level opcodes, repeated in each of the group's regions.
+fn write_group_opcodes(sfz: &mut String, instrument: &Instrument, group: &Group) {
+    // Mute group: keygroups sharing a group choke each other (e.g. open/closed hi-hat)
+    if group.mute_group != 0 {
+        sfz.push_str(&format!("group={0}\noff_by={0}\noff_mode=fast\n", group.mute_group));
+    }
 
-        // --- Pitch modulation (pitch_mod_1/2 from kloc, source from mods) ---
-        self.emit_pitch_mod(sfz, keygroup, mods.pitch_mod_1_source, keygroup.pitch_mod_1, "pitch_mod_1");
-        self.emit_pitch_mod(sfz, keygroup, mods.pitch_mod_2_source, keygroup.pitch_mod_2, "pitch_mod_2");
+    if let Some(env) = &group.amp_env {
+        sfz.push_str(&format!("ampeg_attack={:.3}\n", env.attack));
+        sfz.push_str(&format!("ampeg_decay={:.3}\n", env.decay));
+        sfz.push_str(&format!("ampeg_sustain={:.0}\n", env.sustain * 100.0));
+        sfz.push_str(&format!("ampeg_release={:.3}\n", env.release));
 
-        // --- Filter modulation (mod_input_1/2/3 from filt, source from mods) ---
-        if let Some(filter) = &keygroup.filter {
-            Self::emit_filter_mod(sfz, mods.filter_mod_1_source, filter.mod_input_1, "filter_mod_1");
-            Self::emit_filter_mod(sfz, mods.filter_mod_2_source, filter.mod_input_2, "filter_mod_2");
-            Self::emit_filter_mod(sfz, mods.filter_mod_3_source, filter.mod_input_3, "filter_mod_3");
+        if env.velocity_attack != 0 {
+            sfz.push_str(&format!("ampeg_vel2attack={}\n", env.velocity_attack));
         }
-
-        // --- Amp modulation (amp_mod from kloc + amp_mod_1/2 from out) ---
-        Self::emit_amp_mod(sfz, mods.amp_mod_source, keygroup.amp_mod as i32, "amp_mod");
-        if let Some(output) = &self.output {
-            Self::emit_amp_mod(sfz, mods.amp_mod_1_source, output.amp_mod_1 as i32, "amp_mod_1");
-            Self::emit_amp_mod(sfz, mods.amp_mod_2_source, output.amp_mod_2 as i32, "amp_mod_2");
+        if env.velocity_release != 0 {
+            sfz.push_str(&format!("ampeg_vel2release={}\n", env.velocity_release));
         }
-
-        // --- Pan modulation (pan_mod_1/2/3 from out, source from mods) ---
-        if let Some(output) = &self.output {
-            Self::emit_pan_mod(sfz, mods.pan_mod_1_source, output.pan_mod_1 as i32, "pan_mod_1");
-            Self::emit_pan_mod(sfz, mods.pan_mod_2_source, output.pan_mod_2 as i32, "pan_mod_2");
-            Self::emit_pan_mod(sfz, mods.pan_mod_3_source, output.pan_mod_3 as i32, "pan_mod_3");
+        if env.keyscale != 0 {
+            sfz.push_str(&format!("// AKP amp env keyscale={}\n", env.keyscale));
         }
+    }
 
-        // --- LFO cross-modulation (SFZ2-only, emit as comments) ---
-        if let Some(lfo) = &self.lfo1 {
-            Self::emit_lfo_mod_comment(sfz, mods.lfo1_rate_mod_source, lfo.rate_mod as i32, "LFO1 rate");
-            Self::emit_lfo_mod_comment(sfz, mods.lfo1_delay_mod_source, lfo.delay_mod as i32, "LFO1 delay");
-            Self::emit_lfo_mod_comment(sfz, mods.lfo1_depth_mod_source, lfo.depth_mod as i32, "LFO1 depth");
-        }
-        if let Some(lfo) = &self.lfo2 {
-            Self::emit_lfo_mod_comment(sfz, mods.lfo2_rate_mod_source, lfo.rate_mod as i32, "LFO2 rate");
-            Self::emit_lfo_mod_comment(sfz, mods.lfo2_delay_mod_source, lfo.delay_mod as i32, "LFO2 delay");
-            Self::emit_lfo_mod_comment(sfz, mods.lfo2_depth_mod_source, lfo.depth_mod as i32, "LFO2 depth");
+    if let Some(filter) = &group.filter {
+        sfz.push_str(&format!("fil_type={}\n", filter.sfz_type()));
+        sfz.push_str(&format!("cutoff={:.1}\n", filter.cutoff_hz));
+        sfz.push_str(&format!("resonance={:.1}\n", filter.resonance_db));
+        if filter.keytrack_cents != 0 {
+            sfz.push_str(&format!("fil_keytrack={}\n", filter.keytrack_cents));
         }
     }
 
-    /// Emit pitch modulation for one route.
-    fn emit_pitch_mod(&self, sfz: &mut String, keygroup: &Keygroup, source: u8, amount: i8, label: &str) {
-        if source == 0 || amount == 0 {
-            return;
+    // Z4/Z8 filter 2 (SFZ has two filters, both in series)
+    if let Some(filter) = &group.filter2 {
+        sfz.push_str(&format!("fil2_type={}\n", filter.sfz_type()));
+        sfz.push_str(&format!("cutoff2={:.1}\n", filter.cutoff_hz));
+        sfz.push_str(&format!("resonance2={:.1}\n", filter.resonance_db));
+        if filter.keytrack_cents != 0 {
+            sfz.push_str(&format!("fil2_keytrack={}\n", filter.keytrack_cents));
         }
-        let cents = amount as i32 * 100;
-        let src_type = mod_source_type(source);
-        match src_type {
-            "lfo1" => {
-                // Only emit if LFO1 isn't already emitting pitch via depth > 0
-                let lfo1_already = self.lfo1.as_ref().is_some_and(|l| l.depth > 0);
-                if !lfo1_already {
-                    sfz.push_str(&format!("pitchlfo_depth={cents}\n"));
-                }
-            }
-            "lfo2" => {
-                sfz.push_str(&format!("lfo2_pitch={cents}\n"));
-            }
-            "velocity" => {
-                sfz.push_str(&format!("pitch_veltrack={cents}\n"));
-            }
-            "keyboard" => {
-                // Default SFZ behavior, skip
-            }
-            "aux_env" => {
-                // Pitch envelope from aux envelope
-                if let Some(aux) = &keygroup.aux_env {
-                    let depth_cents = amount as i32 * 12; // ±100 → ±1200 cents
-                    sfz.push_str(&format!("pitcheg_depth={depth_cents}\n"));
-                    sfz.push_str(&format!("pitcheg_attack={:.3}\n", aux_env_rate_to_time(aux.rate_1)));
-                    sfz.push_str(&format!("pitcheg_decay={:.3}\n", aux_env_rate_to_time(aux.rate_2)));
-                    sfz.push_str(&format!("pitcheg_sustain={}\n", aux.level_3));
-                    sfz.push_str(&format!("pitcheg_release={:.3}\n", aux_env_release(aux.rate_4)));
-                }
-            }
-            "cc" => {
-                if let Some(suffix) = mod_source_sfz_suffix(source) {
-                    sfz.push_str(&format!("pitch{suffix}={cents}\n"));
-                }
-            }
-            _ => {
-                // Envelopes, deltas, unknown — emit as comment
-                sfz.push_str(&format!("// {label}: source={}, amount={amount}\n", mod_source_name(source)));
-            }
+        if group.filter_routing == FilterRouting::Parallel {
+            sfz.push_str("// AKP filters 1 and 2 run in parallel; SFZ chains them in series\n");
         }
     }
+    if let Some(filter) = &group.filter3 {
+        sfz.push_str(&format!(
+            "// AKP filter 3: {} cutoff={:.1} resonance={:.1} (SFZ has no third filter)\n",
+            filter.sfz_type(), filter.cutoff_hz, filter.resonance_db
+        ));
+    }
+
+    if let Some(env) = &group.filter_env {
+        sfz.push_str(&format!("fileg_attack={:.3}\n", env.attack));
+        sfz.push_str(&format!("fileg_decay={:.3}\n", env.decay));
+        sfz.push_str(&format!("fileg_sustain={:.0}\n", env.sustain * 100.0));
+        sfz.push_str(&format!("fileg_release={:.3}\n", env.release));
 
-    /// Emit filter modulation for one route.
-    fn emit_filter_mod(sfz: &mut String, source: u8, amount: i8, label: &str) {
-        if source == 0 || amount == 0 {
-            return;
+        if env.depth_cents != 0.0 {
+            sfz.push_str(&format!("fileg_depth={:.0}\n", env.depth_cents));
         }
-        // Convert amount (-100..+100) to cents (-9600..+9600)
-        let cents = (amount as f32 / 100.0 * 9600.0) as i32;
-        let src_type = mod_source_type(source);
-        match src_type {
-            "velocity" => {
-                sfz.push_str(&format!("fil_veltrack={cents}\n"));
-            }
-            "lfo1" => {
-                sfz.push_str(&format!("fillfo_depth={cents}\n"));
-            }
-            "lfo2" => {
-                sfz.push_str(&format!("lfo2_cutoff={cents}\n"));
-            }
-            "keyboard" => {
-                // Handled by fil_keytrack, skip
-            }
-            "cc" => {
-                if let Some(suffix) = mod_source_sfz_suffix(source) {
-                    sfz.push_str(&format!("cutoff{suffix}={cents}\n"));
-                }
-            }
-            _ => {
-                // Envelopes, deltas, unknown — emit as comment
-                sfz.push_str(&format!("// {label}: source={}, amount={amount}\n", mod_source_name(source)));
-            }
+        if env.velocity_attack != 0 {
+            sfz.push_str(&format!("fileg_vel2attack={}\n", env.velocity_attack));
+        }
+        if env.velocity_release != 0 {
+            sfz.push_str(&format!("fileg_vel2release={}\n", env.velocity_release));
         }
     }
 
-    /// Emit amplitude modulation for one route.
-    fn emit_amp_mod(sfz: &mut String, source: u8, amount: i32, label: &str) {
-        if source == 0 || amount == 0 {
-            return;
-        }
-        let src_type = mod_source_type(source);
-        match src_type {
-            "velocity" => {
-                // Already handled by amp_veltrack, skip
-            }
-            "lfo1" => {
-                sfz.push_str(&format!("amplfo_depth={amount}\n"));
-            }
-            "lfo2" => {
-                sfz.push_str(&format!("lfo2_volume={amount}\n"));
-            }
-            "cc" => {
-                if let Some(suffix) = mod_source_sfz_suffix(source) {
-                    sfz.push_str(&format!("volume{suffix}={amount}\n"));
-                }
-            }
-            _ => {
-                // Keyboard, envelopes, deltas — emit as comment
-                sfz.push_str(&format!("// {label}: source={}, amount={amount}\n", mod_source_name(source)));
+    // LFOs from program level
+    for (n, lfo) in [(1, &instrument.lfo1), (2, &instrument.lfo2)] {
+        if let Some(lfo) = lfo.as_ref().filter(|lfo| lfo.depth > 0.0) {
+            sfz.push_str(&format!("lfo{n}_freq={:.2}\n", lfo.rate_hz));
+            sfz.push_str(&format!("lfo{n}_wave={}\n", lfo.waveform));
+            sfz.push_str(&format!("lfo{n}_pitch={:.1}\n", lfo.depth * 100.0));
+            if lfo.delay > 0.0 {
+                sfz.push_str(&format!("lfo{n}_delay={:.2}\n", lfo.delay));
             }
         }
     }
 
-    /// Emit pan modulation for one route.
-    fn emit_pan_mod(sfz: &mut String, source: u8, amount: i32, label: &str) {
-        if source == 0 || amount == 0 {
-            return;
-        }
-        let src_type = mod_source_type(source);
-        match src_type {
-            "cc" => {
-                if let Some(suffix) = mod_source_sfz_suffix(source) {
-                    sfz.push_str(&format!("pan{suffix}={amount}\n"));
-                }
-            }
-            "lfo1" => {
-                sfz.push_str(&format!("lfo1_pan={amount}\n"));
-            }
-            "lfo2" => {
-                sfz.push_str(&format!("lfo2_pan={amount}\n"));
-            }
-            _ => {
-                // Velocity, keyboard, envelopes, deltas — emit as comment
-                sfz.push_str(&format!("// {label}: source={}, amount={amount}\n", mod_source_name(source)));
-            }
-        }
+    // Flexible modulation matrix: keygroup routes, then program routes
+    for route in group.routes.iter().chain(&instrument.routes) {
+        write_route(sfz, instrument, group, route);
     }
+}
+
+/// Write the closest SFZ opcode for one modulation route, or a comment
+/// when there isn't one.
+fn write_route(sfz: &mut String, instrument: &Instrument, group: &Group, route: &ModRoute) {
+    let amount = route.amount;
+    let opcode = match (route.target, route.source) {
+        // Keyboard tracking is SFZ's default for pitch and has fil_keytrack for
+        // the filter; velocity → amplitude is amp_veltrack
+        (ModTarget::Pitch | ModTarget::Cutoff, ModSource::Keyboard) => return,
+        (ModTarget::Amplitude, ModSource::Velocity) => return,
+
+        // Only when LFO1 isn't already on pitch through its own depth
+        (ModTarget::Pitch, ModSource::Lfo1) if instrument.lfo1.as_ref().is_some_and(|l| l.depth > 0.0) => return,
+        (ModTarget::Pitch, ModSource::Lfo1) => format!("pitchlfo_depth={amount:.0}\n"),
+        (ModTarget::Pitch, ModSource::Lfo2) => format!("lfo2_pitch={amount:.0}\n"),
+        (ModTarget::Pitch, ModSource::Velocity) => format!("pitch_veltrack={amount:.0}\n"),
+        (ModTarget::Pitch, ModSource::AuxEnv) => match &group.aux_env {
+            Some(aux) => format!(
+                "pitcheg_depth={amount:.0}\npitcheg_attack={:.3}\npitcheg_decay={:.3}\npitcheg_sustain={:.0}\npitcheg_release={:.3}\n",
+                aux.attack, aux.decay, aux.sustain * 100.0, aux.release
+            ),
+            None => return,
+        },
+
+        (ModTarget::Cutoff, ModSource::Velocity) => format!("fil_veltrack={amount:.0}\n"),
+        (ModTarget::Cutoff, ModSource::Lfo1) => format!("fillfo_depth={amount:.0}\n"),
+        (ModTarget::Cutoff, ModSource::Lfo2) => format!("lfo2_cutoff={amount:.0}\n"),
+
+        (ModTarget::Amplitude, ModSource::Lfo1) => format!("amplfo_depth={amount:.0}\n"),
+        (ModTarget::Amplitude, ModSource::Lfo2) => format!("lfo2_volume={amount:.0}\n"),
 
-    /// Emit LFO cross-modulation as a comment (SFZ2-only, no clean SFZ1 mapping).
-    fn emit_lfo_mod_comment(sfz: &mut String, source: u8, amount: i32, param: &str) {
-        if source == 0 || amount == 0 {
-            return;
+        (ModTarget::Pan, ModSource::Lfo1) => format!("lfo1_pan={amount:.0}\n"),
+        (ModTarget::Pan, ModSource::Lfo2) => format!("lfo2_pan={amount:.0}\n"),
+
+        // LFO cross-modulation is SFZ2-only
+        (ModTarget::LfoRate(n) | ModTarget::LfoDelay(n) | ModTarget::LfoDepth(n), source) => {
+            let param = match route.target {
+                ModTarget::LfoRate(_) => "rate",
+                ModTarget::LfoDelay(_) => "delay",
+                _ => "depth",
+            };
+            format!("// LFO{n} {param} mod: source={}, depth={}\n", source.name(), route.akai_amount())
         }
-        sfz.push_str(&format!("// {param} mod: source={}, depth={amount}\n", mod_source_name(source)));
+
+        (target, source) => match sfz_source_suffix(source) {
+            Some(suffix) => {
+                let param = match target {
+                    ModTarget::Pitch => "pitch",
+                    ModTarget::Cutoff => "cutoff",
+                    ModTarget::Amplitude => "volume",
+                    _ => "pan",
+                };
+                format!("{param}{suffix}={amount:.0}\n")
+            }
+            // Envelopes, deltas, unknown
+            None => format!("// {}: source={}, amount={}\n", route.name, source.name(), route.akai_amount()),
+        },
+    };
+    sfz.push_str(&opcode);
+}
+
+/// SFZ opcode suffix for a controller source, including the connector:
+/// "_oncc1", "_chanaft", etc. Bend and aftertouch use dedicated suffixes
+/// without the "on" prefix, avoiding invalid opcodes like "pitch_onbend".
+fn sfz_source_suffix(source: ModSource) -> Option<&'static str> {
+    match source {
+        ModSource::ModWheel => Some("_oncc1"),
+        ModSource::Bend => Some("_bend"),
+        ModSource::Aftertouch => Some("_chanaft"),
+        ModSource::External => Some("_oncc16"), // typically a general purpose controller
+        _ => None,
     }
 }
 
-/// Convert AKP aux envelope rate to attack/decay time (same curve as amp/filter).
-fn aux_env_rate_to_time(v: u8) -> f32 {
-    if v == 0 { 0.0 } else { (v as f32 / 100.0 * 4.0).exp() * 0.001 }
+impl FilterStage {
+    /// SFZ `fil_type` for the filter.
+    pub fn sfz_type(&self) -> &'static str {
+        match (self.kind, self.poles) {
+            (FilterKind::LowPass, _) => "lpf_2p",
+            (FilterKind::BandPass, _) => "bpf_2p",
+            (FilterKind::HighPass, 1) => "hpf_1p",
+            (FilterKind::HighPass, _) => "hpf_2p",
+            (FilterKind::Notch, _) => "brf_2p",
+            (FilterKind::Peak, _) => "pkf_2p",
+        }
+    }
 }
 
-/// Convert AKP aux envelope rate to release time (steeper curve, 0.001s minimum).
-fn aux_env_release(v: u8) -> f32 {
-    if v == 0 { 0.001 } else { (v as f32 / 100.0 * 5.0).exp() * 0.001 }
+impl Filter {
+    /// Map Akai filter type (0-25) to SFZ fil_type opcode.
+    pub fn sfz_filter_type(&self) -> &'static str {
+        FilterStage::from(self).sfz_type()
+    }
 }
 
 #[cfg(test)]
@@ -415,6 +358,205 @@ mod tests {
         assert!(sfz.contains("hivel=127"));
     }
 
+    #[test]
+    fn test_sfz_root_key_from_sample_info() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup { low_key: 48, high_key: 59, fine_tune: 5, ..Default::default() };
+        keygroup.zones.push(Zone {
+            sample_name: "BRASS 02-C.2.WAV".to_string(),
+            root_key: Some(48),
+            root_tune: -12,
+            ..Default::default()
+        });
+        keygroup.zones.push(Zone { sample_name: "unknown.wav".to_string(), ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        let sfz = program.to_sfz_string();
+        assert_eq!(sfz.matches("pitch_keycenter=").count(), 1);
+        assert!(sfz.contains("pitch_keycenter=48"));
+        assert!(sfz.contains("tune=-7")); // 5 (keygroup) - 12 (smpl pitch fraction)
+    }
+
+    #[test]
+    fn test_sfz_loop_points() {
+        let looped = Some(SampleLoop { start: 1200, end: 48000, crossfade: 0 });
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup::default();
+        keygroup.zones.push(Zone { sample_name: "pad.wav".to_string(), playback: 4, sample_loop: looped, ..Default::default() });
+        keygroup.zones.push(Zone { sample_name: "hit.wav".to_string(), playback: 1, sample_loop: looped, ..Default::default() });
+        keygroup.zones.push(Zone { sample_name: "dry.wav".to_string(), playback: 4, ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        let sfz = program.to_sfz_string();
+        let regions: Vec<&str> = sfz.split("<region>").skip(1).collect();
+        // AS SAMPLE with a looped WAV loops
+        assert!(regions[0].contains("loop_mode=loop_continuous"));
+        assert!(regions[0].contains("loop_start=1200\nloop_end=48000\n"));
+        // ONE SHOT ignores the WAV loop
+        assert!(regions[1].contains("loop_mode=one_shot"));
+        assert!(!regions[1].contains("loop_start="));
+        // AS SAMPLE without a loop leaves the default
+        assert!(!regions[2].contains("loop_"));
+    }
+
+    #[test]
+    fn test_sfz_mute_group_hihat_choke() {
+        let mut program = AkaiProgram::default();
+        for (key, sample) in [(42, "closed_hat.wav"), (46, "open_hat.wav"), (36, "kick.wav")] {
+            let mute_group = if sample.contains("hat") { 1 } else { 0 };
+            let mut keygroup = Keygroup { low_key: key, high_key: key, mute_group, ..Default::default() };
+            keygroup.zones.push(Zone { sample_name: sample.to_string(), ..Default::default() });
+            program.keygroups.push(keygroup);
+        }
+
+        let sfz = program.to_sfz_string();
+        let regions: Vec<&str> = sfz.split("<region>").skip(1).collect();
+        // Closed hat cuts the open hat and vice versa
+        for hat in &regions[..2] {
+            assert!(hat.contains("group=1\noff_by=1\noff_mode=fast\n"));
+        }
+        // Kick is not in a mute group
+        assert!(!regions[2].contains("group="));
+        assert!(!regions[2].contains("off_by="));
+    }
+
+    #[test]
+    fn test_sfz_pan_and_cutoff_mod_scales() {
+        // Pan stays on the AKP -50..50 scale; cutoff depths are truncated cents
+        let program = AkaiProgram {
+            modulation: Some(ProgramModulation { filter_mod_1_source: 7, ..Default::default() }), // LFO1
+            keygroups: vec![Keygroup {
+                filter: Some(Filter { mod_input_1: 21, ..Default::default() }),
+                zones: vec![Zone { sample_name: "pad.wav".to_string(), pan: -25, ..Default::default() }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let sfz = program.to_sfz_string();
+        assert!(sfz.contains("\npan=-25\n"));
+        assert!(sfz.contains("\nfillfo_depth=2015\n"));
+    }
+
+    #[test]
+    fn test_sfz_velocity_crossfade() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup { zone_crossfade: 1, ..Default::default() };
+        keygroup.zones.push(Zone { sample_name: "soft.wav".to_string(), low_vel: 0, high_vel: 63, ..Default::default() });
+        keygroup.zones.push(Zone { sample_name: "hard.wav".to_string(), low_vel: 64, high_vel: 127, ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        let sfz = program.to_sfz_string();
+        let regions: Vec<&str> = sfz.split("<region>").skip(1).collect();
+        assert!(regions[0].contains("lovel=0\nhivel=95\nxfout_lovel=31\nxfout_hivel=95\nxf_velcurve=power\n"));
+        assert!(regions[1].contains("lovel=31\nhivel=127\nxfin_lovel=31\nxfin_hivel=95\nxf_velcurve=power\n"));
+
+        // Crossfade off keeps hard splits
+        program.keygroups[0].zone_crossfade = 0;
+        let sfz = program.to_sfz_string();
+        assert!(sfz.contains("lovel=0\nhivel=63\n"));
+        assert!(!sfz.contains("xf"));
+    }
+
+    fn detuned_program() -> AkaiProgram {
+        let mut tuning = ProgramTuning { semitone: 2, fine: -5, ..Default::default() };
+        tuning.detune[4] = -14; // E
+        let mut program = AkaiProgram { tuning: Some(tuning), ..Default::default() };
+        let mut keygroup = Keygroup { low_key: 60, high_key: 67, ..Default::default() };
+        keygroup.zones.push(Zone { sample_name: "oud.wav".to_string(), ..Default::default() });
+        keygroup.zones.push(Zone { sample_name: "oud_up.wav".to_string(), semitone_tune: 1, ..Default::default() });
+        program.keygroups.push(keygroup);
+        program
+    }
+
+    #[test]
+    fn test_sfz_program_tuning_in_global() {
+        let sfz = detuned_program().to_sfz_string();
+        let global = sfz.split("<global>").nth(1).unwrap().split("<region>").next().unwrap();
+        assert!(global.contains("transpose=2\ntune=-5\n"));
+
+        // Region tuning replaces the global opcodes, so it carries the program tuning
+        let regions: Vec<&str> = sfz.split("<region>").skip(1).collect();
+        assert!(regions[1].contains("transpose=3\n"));
+        assert!(!regions[0].contains("transpose="));
+    }
+
+    #[test]
+    fn test_sfz_detune_split_regions() {
+        let options = ConvertOptions { detune: DetuneMode::SplitRegions, scala_file: Some("oud.scl".to_string()), ..Default::default() };
+        let sfz = detuned_program().to_sfz_string_with(&options);
+        assert!(!sfz.contains("<control>"));
+
+        // 60-63, 64, 65-67 for each of the two zones
+        assert_eq!(sfz.matches("<region>").count(), 6);
+        assert!(sfz.contains("lokey=60\nhikey=63\n"));
+        assert!(sfz.contains("lokey=64\nhikey=64\n"));
+        assert!(sfz.contains("lokey=65\nhikey=67\n"));
+        assert!(sfz.contains("tune=-19\n")); // E: -5 (program) - 14 (detune)
+
+        // Default options have no Scala file to reference, so they split too
+        assert_eq!(detuned_program().to_sfz_string(), sfz);
+    }
+
+    #[test]
+    fn test_sfz_detune_scala_reference() {
+        let options = ConvertOptions { detune: DetuneMode::Scala, scala_file: Some("oud.scl".to_string()), ..Default::default() };
+        let sfz = detuned_program().to_sfz_string_with(&options);
+        assert!(sfz.contains("<control>\nscala_name=oud.scl\nscala_root_key=60\n"));
+        assert!(!sfz.contains("scala_root_freq")); // C is not detuned
+        assert_eq!(sfz.matches("<region>").count(), 2);
+        assert!(sfz.contains("lokey=60\nhikey=67\n"));
+    }
+
+    #[test]
+    fn test_sfz_z8_filters() {
+        let mut program = AkaiProgram::default();
+        let mut keygroup = Keygroup {
+            filter: Some(Filter { filter_type: 1, cutoff: 80, ..Default::default() }),
+            filter2: Some(Filter { filter_type: 7, cutoff: 20, keyboard_track: 12, ..Default::default() }),
+            filter3: Some(Filter { filter_type: 18, cutoff: 60, ..Default::default() }),
+            filter_routing: FilterRouting::Parallel,
+            ..Default::default()
+        };
+        keygroup.zones.push(Zone { sample_name: "pad.wav".to_string(), ..Default::default() });
+        program.keygroups.push(keygroup);
+
+        let sfz = program.to_sfz_string();
+        assert!(sfz.contains("fil_type=lpf_2p\n"));
+        assert!(sfz.contains("fil2_type=hpf_2p\ncutoff2=79.6\nresonance2=0.0\nfil2_keytrack=1200\n"));
+        assert!(sfz.contains("// AKP filters 1 and 2 run in parallel"));
+        assert!(sfz.contains("// AKP filter 3: pkf_2p"));
+        assert!(!sfz.contains("fil3_type"));
+    }
+
+    fn mpc_kit() -> AkaiProgram {
+        let mut pads = PadAssignments::default();
+        pads.notes[0] = Some(38);
+        pads.notes[1] = Some(36);
+        let mut program = AkaiProgram { pads: Some(pads), model: ProgramModel::Mpc4000, ..Default::default() };
+        for (key, sample) in [(36, "kick.wav"), (38, "snare.wav")] {
+            let mut keygroup = Keygroup { low_key: key, high_key: key, ..Default::default() };
+            keygroup.zones.push(Zone { sample_name: sample.to_string(), ..Default::default() });
+            program.keygroups.push(keygroup);
+        }
+        program
+    }
+
+    #[test]
+    fn test_sfz_drum_kit_layout_by_pad() {
+        let options = ConvertOptions { drum_kit_layout: true, ..Default::default() };
+        let sfz = mpc_kit().to_sfz_string_with(&options);
+        let regions: Vec<&str> = sfz.split("<region>").skip(1).collect();
+        assert_eq!(regions.len(), 2);
+        assert!(regions[0].starts_with("\nregion_label=Pad A01\nsample=snare.wav\nlokey=38\nhikey=38\n"));
+        assert!(regions[1].starts_with("\nregion_label=Pad A02\nsample=kick.wav\n"));
+
+        // Default layout keeps keygroup order and has no labels
+        let sfz = mpc_kit().to_sfz_string();
+        assert!(!sfz.contains("region_label"));
+        assert!(sfz.find("kick.wav").unwrap() < sfz.find("snare.wav").unwrap());
+    }
+
     #[test]
     fn test_sfz_filter_type_zero_is_active() {
         let mut program = AkaiProgram::default();
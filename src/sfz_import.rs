@@ -0,0 +1,1015 @@
+//! SFZ import: builds an `AkaiProgram` from an SFZ instrument so it can be
+//! written as AKP for the S5000/S6000.
+//!
+//! Each `<region>` becomes a zone. Regions with the same key range and the
+//! same keygroup-level settings (envelopes, filter, mute group, crossfade)
+//! share a keygroup, up to the AKP limit of four zones; past that, another
+//! keygroup with the same range is started. Envelope, filter and LFO
+//! opcodes are converted back to the AKP scales by inverting the helpers in
+//! `instrument.rs`, so SFZ written by this crate imports to the program it
+//! came from.
+//!
+//! Every opcode that has no AKP equivalent, falls outside the AKP range, or
+//! disagrees with a program-wide value set by an earlier region is listed
+//! in `SfzImport::skipped`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::akp::SampleFile;
+use crate::instrument::{envelope_time_to_raw, release_time_to_raw};
+use crate::types::{
+    AkaiProgram, SkippedOpcode, Envelope, Filter, FilterEnvelope,
+    Keygroup, Lfo, ProgramHeader, ProgramModulation, ProgramOutput, Zone,
+};
+
+/// Mute groups the S5000/S6000 offers (0 = off).
+const MAX_MUTE_GROUP: f32 = 32.0;
+/// Modulation source number for velocity (see `instrument::ModSource`).
+const VELOCITY_SOURCE: u8 = 5;
+/// SFZ plays a sample at this key when `pitch_keycenter` isn't given.
+const DEFAULT_ROOT_KEY: u8 = 60;
+
+/// Result of importing an SFZ file.
+#[derive(Debug, Clone)]
+pub struct SfzImport {
+    /// Zones play their samples by path, relative to the SFZ. AKP output
+    /// renames them (see `AkaiProgram::write_akp_with_samples`).
+    pub program: AkaiProgram,
+    /// Unique samples with their AKP names, in the order zones first use
+    /// them. Paths that would share a name get a numbered one (see
+    /// `AkaiProgram::use_akp_sample_names`).
+    pub samples: Vec<SampleFile>,
+    /// Opcodes that couldn't be represented, in line order.
+    pub skipped: Vec<SkippedOpcode>,
+}
+
+/// Read and import an SFZ file. Sample root notes aren't checked; see
+/// `AkaiProgram::match_sample_roots`.
+pub fn read_sfz(path: &Path) -> Result<SfzImport> {
+    let text = fs::read_to_string(path)?;
+    Ok(parse_sfz(&text))
+}
+
+/// Import SFZ text. Anything that can't be represented is reported in
+/// `SfzImport::skipped` rather than failing the import.
+pub fn parse_sfz(text: &str) -> SfzImport {
+    let mut importer = Importer::default();
+    let (regions, control) = importer.read_headers(text);
+
+    let mut default_path = String::new();
+    for opcode in control.into_values() {
+        if opcode.name == "default_path" {
+            default_path = opcode.value.replace('\\', "/");
+        } else {
+            importer.skip(&opcode, "control opcode not supported");
+        }
+    }
+
+    for mut region in regions {
+        let Some((keygroup, zone)) = importer.region(&mut region, &default_path) else {
+            continue;
+        };
+        for opcode in region.opcodes.into_values() {
+            importer.skip(&opcode, "no AKP equivalent");
+        }
+        importer.program.add_zone(&keygroup, zone);
+    }
+
+    importer.finish()
+}
+
+/// An opcode with the line it came from.
+#[derive(Debug, Clone)]
+struct Opcode {
+    name: String,
+    value: String,
+    line: usize,
+}
+
+/// Opcodes in effect for one region, with `<global>`, `<master>` and
+/// `<group>` values merged in and overridden by the region's own.
+struct Region {
+    line: usize,
+    opcodes: BTreeMap<String, Opcode>,
+}
+
+impl Region {
+    fn take(&mut self, name: &str) -> Option<Opcode> {
+        self.opcodes.remove(name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+    /// A header this importer doesn't read (`<curve>`, `<effect>`, `<midi>`, …).
+    Other,
+}
+
+#[derive(Default)]
+struct Importer {
+    program: AkaiProgram,
+    skipped: Vec<SkippedOpcode>,
+    /// Program-wide opcodes: first value seen and its line.
+    program_values: HashMap<String, (String, usize)>,
+    filter_velocity: bool,
+}
+
+impl Importer {
+    fn skip(&mut self, opcode: &Opcode, reason: impl Into<String>) {
+        let skipped = SkippedOpcode {
+            line: opcode.line,
+            opcode: opcode.name.clone(),
+            value: opcode.value.clone(),
+            reason: reason.into(),
+        };
+        // Group and global opcodes are seen once per region; report them once
+        if !self.skipped.contains(&skipped) {
+            self.skipped.push(skipped);
+        }
+    }
+
+    /// Split the text into regions (with inherited opcodes merged) and the
+    /// `<control>` opcodes.
+    fn read_headers(&mut self, text: &str) -> (Vec<Region>, BTreeMap<String, Opcode>) {
+        let mut control = BTreeMap::new();
+        let mut global = BTreeMap::new();
+        let mut master = BTreeMap::new();
+        let mut group: BTreeMap<String, Opcode> = BTreeMap::new();
+        let mut regions: Vec<Region> = Vec::new();
+        // Opcodes before the first header apply like <global> ones
+        let mut scope = Scope::Global;
+        let mut defines: Vec<(String, String)> = Vec::new();
+
+        for (index, raw_line) in strip_comments(text).lines().enumerate() {
+            let line = index + 1;
+            let mut content = raw_line.to_string();
+            for (name, value) in &defines {
+                content = content.replace(name.as_str(), value);
+            }
+            let trimmed = content.trim();
+
+            if let Some(rest) = trimmed.strip_prefix("#define") {
+                let mut parts = rest.split_whitespace();
+                if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                    defines.push((name.to_string(), value.to_string()));
+                    // Longest first, so $NOTE doesn't clobber $NOTE_HI
+                    defines.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+                }
+                continue;
+            }
+            if trimmed.starts_with('#') {
+                let opcode = Opcode { name: trimmed.to_string(), value: String::new(), line };
+                self.skip(&opcode, "directive not supported");
+                continue;
+            }
+
+            for token in tokenize(trimmed) {
+                match token {
+                    Token::Header(name) => {
+                        scope = match name {
+                            "control" => Scope::Control,
+                            "global" => {
+                                global.clear();
+                                master.clear();
+                                group.clear();
+                                Scope::Global
+                            }
+                            "master" => {
+                                master.clear();
+                                group.clear();
+                                Scope::Master
+                            }
+                            "group" => {
+                                group.clear();
+                                Scope::Group
+                            }
+                            "region" => {
+                                let mut opcodes = global.clone();
+                                opcodes.extend(master.clone());
+                                opcodes.extend(group.clone());
+                                regions.push(Region { line, opcodes });
+                                Scope::Region
+                            }
+                            other => {
+                                let opcode = Opcode { name: format!("<{other}>"), value: String::new(), line };
+                                self.skip(&opcode, "header not supported");
+                                Scope::Other
+                            }
+                        };
+                    }
+                    Token::Opcode(name, value) => {
+                        let opcode = Opcode { name: name.to_string(), value: value.to_string(), line };
+                        let target = match scope {
+                            Scope::Control => &mut control,
+                            Scope::Global => &mut global,
+                            Scope::Master => &mut master,
+                            Scope::Group => &mut group,
+                            Scope::Region => match regions.last_mut() {
+                                Some(region) => &mut region.opcodes,
+                                None => continue,
+                            },
+                            Scope::Other => continue,
+                        };
+                        target.insert(opcode.name.clone(), opcode);
+                    }
+                }
+            }
+        }
+
+        (regions, control)
+    }
+
+    /// Parse a number, reporting it if it isn't one.
+    fn number(&mut self, opcode: &Opcode) -> Option<f32> {
+        match opcode.value.parse::<f32>() {
+            Ok(value) if value.is_finite() => Some(value),
+            _ => {
+                self.skip(opcode, "not a number");
+                None
+            }
+        }
+    }
+
+    /// Round a converted value into an AKP range, reporting it if it had to be clamped.
+    fn fit(&mut self, opcode: &Opcode, value: f32, min: i32, max: i32) -> i32 {
+        let rounded = if value.is_nan() { min } else { value.round().clamp(min as f32, max as f32) as i32 };
+        if value.is_nan() || value.round() < min as f32 || value.round() > max as f32 {
+            self.skip(opcode, format!("outside the AKP range, clamped to {rounded}"));
+        }
+        rounded
+    }
+
+    /// Take a number and convert it into an AKP range in one go.
+    fn take_fit(&mut self, region: &mut Region, name: &str, min: i32, max: i32, convert: impl Fn(f32) -> f32) -> Option<i32> {
+        let opcode = region.take(name)?;
+        let value = self.number(&opcode)?;
+        Some(self.fit(&opcode, convert(value), min, max))
+    }
+
+    /// Take a program-wide opcode. Only the first region that sets it
+    /// applies; later regions with a different value are reported.
+    fn take_program(&mut self, region: &mut Region, name: &str) -> Option<Opcode> {
+        let opcode = region.take(name)?;
+        match self.program_values.get(name) {
+            None => {
+                self.program_values.insert(name.to_string(), (opcode.value.clone(), opcode.line));
+                Some(opcode)
+            }
+            Some((value, _)) if *value == opcode.value => None,
+            Some((value, line)) => {
+                let reason = format!("AKP stores one value per program; using {value} from line {line}");
+                self.skip(&opcode, reason);
+                None
+            }
+        }
+    }
+
+    fn take_program_fit(&mut self, region: &mut Region, name: &str, min: i32, max: i32, convert: impl Fn(f32) -> f32) -> Option<i32> {
+        let opcode = self.take_program(region, name)?;
+        let value = self.number(&opcode)?;
+        Some(self.fit(&opcode, convert(value), min, max))
+    }
+
+    /// Convert one region into its keygroup settings (no zones) and its zone.
+    fn region(&mut self, region: &mut Region, default_path: &str) -> Option<(Keygroup, Zone)> {
+        let Some(sample) = region.take("sample") else {
+            let opcode = Opcode { name: "<region>".to_string(), value: String::new(), line: region.line };
+            self.skip(&opcode, "region has no sample, skipped");
+            return None;
+        };
+        if sample.value.starts_with('*') {
+            self.skip(&sample, "generated waveforms aren't supported, region skipped");
+            return None;
+        }
+
+        let path = format!("{default_path}{}", sample.value.replace('\\', "/"));
+        if !path.rsplit_once('.').is_some_and(|(_, ext)| ext.eq_ignore_ascii_case("wav")) {
+            self.skip(&sample, "the S5000/S6000 only loads WAV samples");
+        }
+
+        let mut keygroup = Keygroup { low_key: 0, high_key: 127, ..Default::default() };
+        let mut zone = Zone { sample_name: path, root_key: Some(DEFAULT_ROOT_KEY), ..Default::default() };
+
+        self.program_settings(region);
+        self.keys(region, &mut keygroup, &mut zone);
+        self.zone_settings(region, &mut zone);
+        self.velocity(region, &mut keygroup, &mut zone);
+        self.mute_group(region, &mut keygroup);
+        self.amp_envelope(region, &mut keygroup);
+        self.filter(region, &mut keygroup);
+        self.filter_envelope(region, &mut keygroup);
+
+        Some((keygroup, zone))
+    }
+
+    /// Opcodes that live in the AKP `out`, `tune` and `lfo` chunks.
+    fn program_settings(&mut self, region: &mut Region) {
+        let output = self.program.output.get_or_insert_with(|| ProgramOutput {
+            // SFZ default amp_veltrack
+            velocity_sensitivity: 100,
+            ..Default::default()
+        });
+        let (mut loudness, mut velocity_sensitivity) = (output.loudness, output.velocity_sensitivity);
+        if let Some(value) = self.take_program_fit(region, "amplitude", 0, 100, |v| v) {
+            loudness = value as u8;
+        }
+        if let Some(value) = self.take_program_fit(region, "amp_veltrack", -100, 100, |v| v) {
+            velocity_sensitivity = value as i8;
+        }
+        if let Some(output) = &mut self.program.output {
+            output.loudness = loudness;
+            output.velocity_sensitivity = velocity_sensitivity;
+        }
+
+        let mut tuning = self.program.tuning.clone().unwrap_or_default();
+        if let Some(value) = self.take_program_fit(region, "bend_up", 0, 24, |cents| cents / 100.0) {
+            tuning.pitchbend_up = value as u8;
+        }
+        if let Some(value) = self.take_program_fit(region, "bend_down", 0, 24, |cents| -cents / 100.0) {
+            tuning.pitchbend_down = value as u8;
+        }
+        if let Some(value) = self.take_program_fit(region, "pitch_chanaft", -12, 12, |cents| cents / 100.0) {
+            tuning.aftertouch = value as i8;
+        }
+        self.program.tuning = Some(tuning);
+
+        // SFZ v1 pitch LFO is the same LFO our writer calls lfo1
+        for (prefix, freq, pitch, delay) in [
+            ("lfo1", "lfo1_freq", "lfo1_pitch", "lfo1_delay"),
+            ("pitchlfo", "pitchlfo_freq", "pitchlfo_depth", "pitchlfo_delay"),
+            ("lfo2", "lfo2_freq", "lfo2_pitch", "lfo2_delay"),
+        ] {
+            let mut lfo = match prefix {
+                "lfo2" => self.program.lfo2.clone(),
+                _ => self.program.lfo1.clone(),
+            };
+            if let Some(rate) = self.take_program_fit(region, freq, 0, 100, Lfo::rate_from_hz) {
+                lfo.get_or_insert_with(Lfo::default).rate = rate as u8;
+            }
+            if let Some(depth) = self.take_program_fit(region, pitch, 0, 100, |cents| cents) {
+                lfo.get_or_insert_with(Lfo::default).depth = depth as u8;
+            }
+            if let Some(delay) = self.take_program_fit(region, delay, 0, 100, |seconds| seconds / 10.0 * 100.0) {
+                lfo.get_or_insert_with(Lfo::default).delay = delay as u8;
+            }
+            if prefix != "pitchlfo" {
+                if let Some(opcode) = self.take_program(region, &format!("{prefix}_wave")) {
+                    match lfo_waveform(&opcode.value) {
+                        Some(waveform) => lfo.get_or_insert_with(Lfo::default).waveform = waveform,
+                        None => self.skip(&opcode, "no matching AKP LFO waveform"),
+                    }
+                }
+            } else {
+                if let Some(value) = self.take_program_fit(region, "pitchlfo_depthcc1", 0, 100, |cents| cents) {
+                    lfo.get_or_insert_with(Lfo::default).modwheel = value as u8;
+                }
+                if let Some(value) = self.take_program_fit(region, "pitchlfo_depthchanaft", 0, 100, |cents| cents) {
+                    lfo.get_or_insert_with(Lfo::default).aftertouch = value as u8;
+                }
+            }
+            match prefix {
+                "lfo2" => self.program.lfo2 = lfo,
+                _ => self.program.lfo1 = lfo,
+            }
+        }
+    }
+
+    fn keys(&mut self, region: &mut Region, keygroup: &mut Keygroup, zone: &mut Zone) {
+        if let Some(opcode) = region.take("key") {
+            match note_number(&opcode.value) {
+                Some(key) => {
+                    keygroup.low_key = key;
+                    keygroup.high_key = key;
+                    zone.root_key = Some(key);
+                }
+                None => self.skip(&opcode, "not a MIDI note"),
+            }
+        }
+        for name in ["lokey", "hikey", "pitch_keycenter"] {
+            let Some(opcode) = region.take(name) else { continue };
+            if name == "pitch_keycenter" && opcode.value.eq_ignore_ascii_case("sample") {
+                // Play at the WAV's own root note, which is what the S5000 does anyway
+                zone.root_key = None;
+                continue;
+            }
+            match note_number(&opcode.value) {
+                Some(key) if name == "lokey" => keygroup.low_key = key,
+                Some(key) if name == "hikey" => keygroup.high_key = key,
+                Some(key) => zone.root_key = Some(key),
+                None => self.skip(&opcode, "not a MIDI note"),
+            }
+        }
+    }
+
+    /// Tuning, pan, level, playback mode and key tracking.
+    fn zone_settings(&mut self, region: &mut Region, zone: &mut Zone) {
+        // transpose and tune share the zone's semitone/fine pair; fine is ±50 cents
+        let semitones = region.take("transpose").and_then(|o| self.number(&o).map(|v| (o, v)));
+        let cents = region.take("tune").and_then(|o| self.number(&o).map(|v| (o, v)));
+        if semitones.is_some() || cents.is_some() {
+            let total = semitones.as_ref().map_or(0.0, |(_, v)| v * 100.0) + cents.as_ref().map_or(0.0, |(_, v)| *v);
+            let semitone = (total / 100.0).round();
+            let opcode = semitones.map_or_else(|| cents.unwrap().0, |(o, _)| o);
+            zone.semitone_tune = self.fit(&opcode, semitone, -36, 36) as i8;
+            zone.fine_tune = (total - semitone * 100.0).round().clamp(-50.0, 50.0) as i8;
+        }
+
+        if let Some(pan) = self.take_fit(region, "pan", -50, 50, |v| v) {
+            zone.pan = pan as i8;
+        }
+        if let Some(level) = self.take_fit(region, "volume", -50, 50, |v| v) {
+            zone.level = level as i8;
+        }
+
+        if let Some(opcode) = region.take("loop_mode") {
+            zone.playback = match opcode.value.as_str() {
+                "no_loop" => 0,
+                "one_shot" => 1,
+                "loop_continuous" => 2,
+                "loop_sustain" => 3,
+                _ => {
+                    self.skip(&opcode, "unknown loop mode");
+                    zone.playback
+                }
+            };
+        }
+
+        if let Some(opcode) = region.take("pitch_keytrack") {
+            match self.number(&opcode) {
+                Some(0.0) => zone.keyboard_track = 0,
+                Some(100.0) => zone.keyboard_track = 1,
+                Some(_) => self.skip(&opcode, "AKP key tracking is on (100) or off (0)"),
+                None => {}
+            }
+        }
+    }
+
+    /// Velocity range and crossfades. Our SFZ writer widens crossfading
+    /// layers to overlap by the fade; the AKP range boundary is the middle
+    /// of the fade.
+    fn velocity(&mut self, region: &mut Region, keygroup: &mut Keygroup, zone: &mut Zone) {
+        if let Some(low) = self.take_fit(region, "lovel", 0, 127, |v| v) {
+            zone.low_vel = low as u8;
+        }
+        if let Some(high) = self.take_fit(region, "hivel", 0, 127, |v| v) {
+            zone.high_vel = high as u8;
+        }
+
+        let mut fade = |importer: &mut Self, low: &str, high: &str| -> Option<u8> {
+            let low = importer.take_fit(region, low, 0, 127, |v| v);
+            let high = importer.take_fit(region, high, 0, 127, |v| v);
+            match (low, high) {
+                (None, None) => None,
+                (low, high) => Some(((low.unwrap_or(0) + high.unwrap_or(127)) / 2) as u8),
+            }
+        };
+        let fade_in = fade(self, "xfin_lovel", "xfin_hivel");
+        let fade_out = fade(self, "xfout_lovel", "xfout_hivel");
+        if let Some(middle) = fade_in {
+            zone.low_vel = middle.saturating_add(1).min(zone.high_vel);
+        }
+        if let Some(middle) = fade_out {
+            zone.high_vel = middle.max(zone.low_vel);
+        }
+        if fade_in.is_some() || fade_out.is_some() {
+            keygroup.zone_crossfade = 1;
+        }
+
+        if let Some(opcode) = region.take("xf_velcurve") {
+            if opcode.value != "power" {
+                self.skip(&opcode, "AKP crossfades are equal-power");
+            }
+        }
+    }
+
+    /// `group` + `off_by` pointing at itself is an AKP mute group.
+    fn mute_group(&mut self, region: &mut Region, keygroup: &mut Keygroup) {
+        let group = region.take("group");
+        let off_by = region.take("off_by");
+        let off_mode = region.take("off_mode");
+        let Some(off_by) = off_by else {
+            // A group nothing turns off has no effect
+            return;
+        };
+        if group.as_ref().map(|g| &g.value) != Some(&off_by.value) {
+            self.skip(&off_by, "AKP mute groups only choke their own group");
+            return;
+        }
+        if let Some(value) = self.number(&off_by) {
+            keygroup.mute_group = self.fit(&off_by, value, 0, MAX_MUTE_GROUP as i32) as u8;
+        }
+        if let Some(opcode) = off_mode.filter(|o| o.value != "fast") {
+            self.skip(&opcode, "AKP mute groups cut notes off immediately");
+        }
+    }
+
+    fn amp_envelope(&mut self, region: &mut Region, keygroup: &mut Keygroup) {
+        // SFZ defaults: instant attack, full sustain, shortest release
+        let mut env = Envelope { sustain: 100, ..Default::default() };
+        if let Some(v) = self.take_fit(region, "ampeg_attack", 0, 100, envelope_time_to_raw) {
+            env.attack = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "ampeg_decay", 0, 100, envelope_time_to_raw) {
+            env.decay = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "ampeg_sustain", 0, 100, |v| v) {
+            env.sustain = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "ampeg_release", 0, 100, release_time_to_raw) {
+            env.release = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "ampeg_vel2attack", -100, 100, |v| v) {
+            env.velocity_attack = v as i8;
+        }
+        if let Some(v) = self.take_fit(region, "ampeg_vel2release", -100, 100, |v| v) {
+            env.on_vel_release = v as i8;
+        }
+        keygroup.amp_env = Some(env);
+    }
+
+    fn filter(&mut self, region: &mut Region, keygroup: &mut Keygroup) {
+        let mut filter = Filter::default();
+        let mut present = false;
+
+        if let Some(opcode) = region.take("fil_type") {
+            present = true;
+            match filter_type(&opcode.value) {
+                Some((filter_type, exact)) => {
+                    filter.filter_type = filter_type;
+                    if !exact {
+                        let name = Filter { filter_type, ..Default::default() }.sfz_filter_type();
+                        self.skip(&opcode, format!("approximated as {name}"));
+                    }
+                }
+                None => self.skip(&opcode, "no matching AKP filter, using lpf_2p"),
+            }
+        }
+        if let Some(v) = self.take_fit(region, "cutoff", 0, 100, Filter::cutoff_from_hz) {
+            present = true;
+            filter.cutoff = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "resonance", 0, 12, Filter::resonance_from_db) {
+            present = true;
+            filter.resonance = v as u8;
+        }
+        if let Some(v) = self.take_fit(region, "fil_keytrack", -36, 36, |cents| cents / 100.0) {
+            present = true;
+            filter.keyboard_track = v as i8;
+        }
+        // Velocity → cutoff goes through the first filter modulation input
+        if let Some(v) = self.take_fit(region, "fil_veltrack", -100, 100, |cents| cents / 9600.0 * 100.0) {
+            present = true;
+            filter.mod_input_1 = v as i8;
+            self.filter_velocity = true;
+        }
+
+        if present {
+            keygroup.filter = Some(filter);
+        }
+    }
+
+    fn filter_envelope(&mut self, region: &mut Region, keygroup: &mut Keygroup) {
+        let mut env = FilterEnvelope::default();
+        let mut present = false;
+        for name in ["fileg_attack", "fileg_decay", "fileg_sustain", "fileg_release", "fileg_depth", "fileg_vel2attack", "fileg_vel2release"] {
+            let (min, max, convert): (i32, i32, fn(f32) -> f32) = match name {
+                "fileg_attack" | "fileg_decay" => (0, 100, envelope_time_to_raw),
+                "fileg_release" => (0, 100, release_time_to_raw),
+                "fileg_sustain" => (0, 100, |v| v),
+                "fileg_depth" => (-100, 100, |cents| cents / 9600.0 * 100.0),
+                _ => (-100, 100, |v| v),
+            };
+            let Some(v) = self.take_fit(region, name, min, max, convert) else { continue };
+            present = true;
+            match name {
+                "fileg_attack" => env.attack = v as u8,
+                "fileg_decay" => env.decay = v as u8,
+                "fileg_sustain" => env.sustain = v as u8,
+                "fileg_release" => env.release = v as u8,
+                "fileg_depth" => env.depth = v as i8,
+                "fileg_vel2attack" => env.velocity_attack = v as i8,
+                _ => env.on_vel_release = v as i8,
+            }
+        }
+        if present {
+            keygroup.filter_env = Some(env);
+        }
+    }
+
+    fn finish(mut self) -> SfzImport {
+        self.program.header = Some(ProgramHeader {
+            midi_program_number: 0,
+            number_of_keygroups: self.program.keygroups.len().min(u8::MAX as usize) as u8,
+        });
+        if self.filter_velocity {
+            self.program.modulation = Some(ProgramModulation {
+                filter_mod_1_source: VELOCITY_SOURCE,
+                ..Default::default()
+            });
+        }
+        self.skipped.sort_by_key(|s| s.line);
+        let samples = self.program.clone().use_akp_sample_names();
+        SfzImport { program: self.program, samples, skipped: self.skipped }
+    }
+}
+
+/// Remove `//` line comments and `/* */` block comments. A comment only
+/// starts at the beginning of a line, after whitespace or after a header's
+/// `>`, so a `//` inside a sample path is kept. Line breaks inside block
+/// comments are kept too, so line numbers still match the file.
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut in_block = false;
+    let mut prev = None;
+    while let Some(c) = rest.chars().next() {
+        if in_block {
+            if let Some(after) = rest.strip_prefix("*/") {
+                // Keep the text either side of the comment apart
+                out.push(' ');
+                prev = Some(' ');
+                in_block = false;
+                rest = after;
+                continue;
+            }
+            if c == '\n' {
+                out.push('\n');
+            }
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let comment_can_start = prev.is_none_or(|p: char| p.is_whitespace() || p == '>');
+        if comment_can_start && rest.starts_with("/*") {
+            in_block = true;
+            rest = &rest[2..];
+            continue;
+        }
+        if comment_can_start && rest.starts_with("//") {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+            continue;
+        }
+        out.push(c);
+        prev = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+enum Token<'a> {
+    Header(&'a str),
+    Opcode(&'a str, &'a str),
+}
+
+/// Split a comment-free line into headers and `name=value` opcodes. A value
+/// runs to the next header or opcode, so sample paths may contain spaces.
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if let Some(header) = rest.strip_prefix('<') {
+            let Some(end) = header.find('>') else { break };
+            tokens.push(Token::Header(header[..end].trim()));
+            rest = header[end + 1..].trim_start();
+            continue;
+        }
+        let Some(eq) = rest.find('=') else { break };
+        let name = rest[..eq].trim();
+        let value_start = eq + 1;
+        let value_end = next_token(rest, value_start);
+        tokens.push(Token::Opcode(name, rest[value_start..value_end].trim()));
+        rest = rest[value_end..].trim_start();
+    }
+    tokens
+}
+
+/// Where the value starting at `from` ends: the next `<` or the next
+/// whitespace-separated `word=`.
+fn next_token(line: &str, from: usize) -> usize {
+    let bytes = line.as_bytes();
+    let mut i = from;
+    while i < bytes.len() {
+        if bytes[i] == b'<' {
+            return i;
+        }
+        if bytes[i].is_ascii_whitespace() {
+            let word_start = i + 1;
+            let word_len = bytes[word_start..].iter()
+                .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+                .count();
+            if word_len > 0 && bytes.get(word_start + word_len) == Some(&b'=') {
+                return i;
+            }
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// A MIDI note number or an SFZ note name (`c4` = 60, `f#3`, `eb2`).
+fn note_number(value: &str) -> Option<u8> {
+    if let Ok(number) = value.parse::<u8>() {
+        return (number <= 127).then_some(number);
+    }
+    let lower = value.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    let base: i32 = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' if rest.len() > 1 => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let note = (octave.parse::<i32>().ok()? + 1) * 12 + base + accidental;
+    u8::try_from(note).ok().filter(|&n| n <= 127)
+}
+
+/// AKP filter type for an SFZ `fil_type`, and whether it's an exact match.
+fn filter_type(value: &str) -> Option<(u8, bool)> {
+    Some(match value {
+        "lpf_2p" => (0, true),
+        "lpf_4p" => (1, true),
+        "bpf_2p" => (3, true),
+        "bpf_4p" => (4, true),
+        "hpf_1p" => (6, true),
+        "hpf_2p" => (7, true),
+        "brf_2p" => (12, true),
+        "pkf_2p" => (17, true),
+        "lpf_1p" | "lpf_2p_sv" => (0, false),
+        "lpf_6p" | "lpf_4p_sv" => (1, false),
+        "bpf_1p" | "bpf_2p_sv" => (3, false),
+        "bpf_6p" | "bpf_4p_sv" => (4, false),
+        "hpf_4p" | "hpf_6p" | "hpf_2p_sv" | "hpf_4p_sv" => (7, false),
+        "brf_1p" | "brf_2p_sv" => (12, false),
+        _ => return None,
+    })
+}
+
+/// AKP LFO waveform for an SFZ `lfoN_wave`: the names our writer uses, or
+/// the SFZ v2 numbers that have an AKP equivalent.
+fn lfo_waveform(value: &str) -> Option<u8> {
+    match value {
+        "sine" | "1" => Some(0),
+        "triangle" | "0" => Some(1),
+        "square" | "3" => Some(2),
+        "saw" | "6" => Some(6),
+        "7" => Some(7),
+        "random" | "12" => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::EnvelopeTiming;
+    use crate::types::{ProgramModel, ProgramTuning};
+
+    fn reasons(import: &SfzImport) -> Vec<String> {
+        import.skipped.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tokenize_values_with_spaces() {
+        let tokens = tokenize("<region> sample=Piano C3 soft.wav lokey=60 <region>key=c4");
+        let summary: Vec<String> = tokens.iter().map(|t| match t {
+            Token::Header(h) => format!("<{h}>"),
+            Token::Opcode(n, v) => format!("{n}={v}"),
+        }).collect();
+        assert_eq!(summary, ["<region>", "sample=Piano C3 soft.wav", "lokey=60", "<region>", "key=c4"]);
+    }
+
+    #[test]
+    fn test_line_comments() {
+        let sfz = "\
+// Felt piano
+<region> sample=samples//Piano C3.wav // soft layer
+<region>// no sample yet
+<region> sample=http://example/b.wav lokey=60 //hikey=72
+";
+        let import = parse_sfz(sfz);
+        let paths: Vec<&str> = import.samples.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["samples//Piano C3.wav", "http://example/b.wav"]);
+        assert_eq!(import.program.keygroups[1].high_key, 127);
+        assert_eq!(reasons(&import), ["line 3: <region> (region has no sample, skipped)"]);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let sfz = "\
+/* Felt piano
+   <region> sample=commented.wav */
+<region> sample=a.wav /* soft */ lokey=60
+<region> /* a */ sample=dir/*b.wav ampeg_hold=1
+";
+        let import = parse_sfz(sfz);
+        let paths: Vec<&str> = import.samples.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, ["a.wav", "dir/*b.wav"]);
+        assert_eq!(import.program.keygroups[0].low_key, 60);
+        // Line numbers still count the lines inside the comment
+        assert_eq!(reasons(&import), ["line 4: ampeg_hold=1 (no AKP equivalent)"]);
+    }
+
+    #[test]
+    fn test_note_names() {
+        assert_eq!(note_number("60"), Some(60));
+        assert_eq!(note_number("c4"), Some(60));
+        assert_eq!(note_number("C#4"), Some(61));
+        assert_eq!(note_number("eb2"), Some(39));
+        assert_eq!(note_number("c-1"), Some(0));
+        assert_eq!(note_number("b"), None);
+        assert_eq!(note_number("200"), None);
+    }
+
+    #[test]
+    fn test_regions_grouped_by_key_range() {
+        let sfz = "\
+<group> lokey=48 hikey=59 pitch_keycenter=53
+<region> sample=samples/Str C3 pp.wav hivel=63
+<region> sample=samples/Str C3 ff.wav lovel=64
+<group> lokey=60 hikey=71
+<region> sample=Str C4.wav
+";
+        let import = parse_sfz(sfz);
+        let keygroups = &import.program.keygroups;
+        assert_eq!(keygroups.len(), 2);
+        assert_eq!((keygroups[0].low_key, keygroups[0].high_key), (48, 59));
+        assert_eq!(keygroups[0].zones.len(), 2);
+        assert_eq!(keygroups[0].zones[0].sample_name, "samples/Str C3 pp.wav");
+        assert_eq!((keygroups[0].zones[0].low_vel, keygroups[0].zones[0].high_vel), (0, 63));
+        assert_eq!((keygroups[0].zones[1].low_vel, keygroups[0].zones[1].high_vel), (64, 127));
+        assert_eq!(keygroups[0].zones[0].root_key, Some(53));
+        assert_eq!(keygroups[1].zones[0].root_key, Some(60));
+        assert_eq!(import.samples[0].path, "samples/Str C3 pp.wav");
+        assert_eq!(import.samples.len(), 3);
+        assert!(import.skipped.is_empty(), "{:?}", reasons(&import));
+    }
+
+    #[test]
+    fn test_more_than_four_zones_splits_keygroup() {
+        let mut sfz = String::from("<group> lokey=36 hikey=36\n");
+        for i in 0..6 {
+            sfz.push_str(&format!("<region> sample=kick{i}.wav lovel={} hivel={}\n", i * 20, i * 20 + 19));
+        }
+        let import = parse_sfz(&sfz);
+        let zones: Vec<usize> = import.program.keygroups.iter().map(|k| k.zones.len()).collect();
+        assert_eq!(zones, [4, 2]);
+        assert!(import.program.keygroups.iter().all(|k| (k.low_key, k.high_key) == (36, 36)));
+    }
+
+    #[test]
+    fn test_different_envelopes_split_keygroup() {
+        let sfz = "\
+<region> sample=a.wav key=60 ampeg_release=0.1
+<region> sample=b.wav key=60 ampeg_release=0.01
+";
+        let import = parse_sfz(sfz);
+        assert_eq!(import.program.keygroups.len(), 2);
+    }
+
+    #[test]
+    fn test_parameters_inverted() {
+        let sfz = "\
+<global> bend_up=1200 bend_down=-700 amplitude=70 amp_veltrack=50
+<region> sample=a.wav lokey=c3 hikey=b3 transpose=-1 tune=75 pan=-20 volume=-6 loop_mode=one_shot
+group=3 off_by=3 off_mode=fast
+ampeg_attack=0.010 ampeg_decay=0.020 ampeg_sustain=80 ampeg_release=0.100
+fil_type=hpf_2p cutoff=2000 resonance=10 fil_keytrack=1200 fil_veltrack=4800
+fileg_attack=0.005 fileg_sustain=40 fileg_depth=2400
+lfo1_freq=5 lfo1_wave=triangle lfo1_pitch=30 lfo1_delay=1.5
+";
+        let import = parse_sfz(sfz);
+        assert!(import.skipped.is_empty(), "{:?}", reasons(&import));
+        let program = &import.program;
+
+        let tuning = program.tuning.as_ref().unwrap();
+        assert_eq!((tuning.pitchbend_up, tuning.pitchbend_down), (12, 7));
+        let output = program.output.as_ref().unwrap();
+        assert_eq!((output.loudness, output.velocity_sensitivity), (70, 50));
+
+        let keygroup = &program.keygroups[0];
+        assert_eq!((keygroup.low_key, keygroup.high_key, keygroup.mute_group), (48, 59, 3));
+        let zone = &keygroup.zones[0];
+        assert_eq!((zone.semitone_tune, zone.fine_tune), (0, -25));
+        assert_eq!((zone.pan, zone.level, zone.playback), (-20, -6, 1));
+
+        let amp = keygroup.amp_env.as_ref().unwrap();
+        assert!((amp.attack_time() - 0.010).abs() < 0.001);
+        assert!((amp.decay_time() - 0.020).abs() < 0.001);
+        assert!((amp.release_time() - 0.100).abs() < 0.005);
+        assert_eq!(amp.sustain, 80);
+
+        let filter = keygroup.filter.as_ref().unwrap();
+        assert_eq!(filter.sfz_filter_type(), "hpf_2p");
+        assert!((filter.cutoff_hz() - 2000.0).abs() < 100.0);
+        assert_eq!((filter.resonance, filter.keyboard_track, filter.mod_input_1), (3, 12, 50));
+        assert_eq!(program.modulation.as_ref().unwrap().filter_mod_1_source, VELOCITY_SOURCE);
+
+        let filter_env = keygroup.filter_env.as_ref().unwrap();
+        assert_eq!((filter_env.sustain, filter_env.depth), (40, 25));
+
+        let lfo = program.lfo1.as_ref().unwrap();
+        assert!((lfo.rate_hz() - 5.0).abs() < 0.3);
+        assert_eq!((lfo.waveform, lfo.depth, lfo.delay), (1, 30, 15));
+    }
+
+    #[test]
+    fn test_unrepresentable_opcodes_reported() {
+        let sfz = "\
+#include \"common.sfz\"
+<control> default_path=Samples/ label_cc1=Vibrato
+<effect> type=reverb
+<region> sample=a.wav ampeg_hold=0.5 loop_start=100 ampeg_release=10
+<region> sample=b.wav amplitude=50
+<region> sample=c.wav amplitude=60 fil_type=lpf_1p
+<region> sample=*sine
+";
+        let import = parse_sfz(sfz);
+        assert_eq!(import.samples[0].path, "Samples/a.wav");
+        assert_eq!(import.program.keygroups.iter().map(|k| k.zones.len()).sum::<usize>(), 3);
+        assert_eq!(reasons(&import), [
+            "line 1: #include \"common.sfz\" (directive not supported)",
+            "line 2: label_cc1=Vibrato (control opcode not supported)",
+            "line 3: <effect> (header not supported)",
+            "line 4: ampeg_release=10 (outside the AKP range, clamped to 100)",
+            "line 4: ampeg_hold=0.5 (no AKP equivalent)",
+            "line 4: loop_start=100 (no AKP equivalent)",
+            "line 6: amplitude=60 (AKP stores one value per program; using 50 from line 5)",
+            "line 6: fil_type=lpf_1p (approximated as lpf_2p)",
+            "line 7: sample=*sine (generated waveforms aren't supported, region skipped)",
+        ]);
+    }
+
+    #[test]
+    fn test_group_opcodes_reported_once() {
+        let sfz = "<group> ampeg_hold=1\n<region> sample=a.wav\n<region> sample=b.wav\n";
+        assert_eq!(parse_sfz(sfz).skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_defines_substituted() {
+        let sfz = "#define $KEY 62\n#define $KEYS 64\n<region> sample=a.wav key=$KEY\n<region> sample=b.wav key=$KEYS\n";
+        let import = parse_sfz(sfz);
+        assert_eq!(import.program.keygroups[0].low_key, 62);
+        assert_eq!(import.program.keygroups[1].low_key, 64);
+    }
+
+    #[test]
+    fn test_round_trip_through_sfz_writer() {
+        let program = AkaiProgram {
+            model: ProgramModel::S5000,
+            output: Some(ProgramOutput { loudness: 80, velocity_sensitivity: 40, ..Default::default() }),
+            tuning: Some(ProgramTuning { pitchbend_up: 12, pitchbend_down: 2, ..Default::default() }),
+            lfo1: Some(Lfo { waveform: 1, rate: 40, depth: 20, delay: 10, ..Default::default() }),
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                mute_group: 2,
+                zone_crossfade: 1,
+                amp_env: Some(Envelope { attack: 60, decay: 80, sustain: 70, release: 60, ..Default::default() }),
+                filter: Some(Filter { filter_type: 7, cutoff: 65, resonance: 4, ..Default::default() }),
+                filter_env: Some(FilterEnvelope { attack: 90, decay: 100, sustain: 50, release: 80, depth: 25, ..Default::default() }),
+                zones: vec![
+                    Zone { sample_name: "PNO C3 P.WAV".to_string(), high_vel: 63, root_key: Some(48), ..Default::default() },
+                    Zone { sample_name: "PNO C3 F.WAV".to_string(), low_vel: 64, root_key: Some(48), pan: 10, ..Default::default() },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let import = parse_sfz(&program.to_sfz_string());
+        assert!(import.skipped.is_empty(), "{:?}", reasons(&import));
+        let imported = &import.program;
+        assert_eq!(imported.output, program.output);
+        assert_eq!(imported.tuning, program.tuning);
+        assert_eq!(imported.lfo1, program.lfo1);
+        assert_eq!(imported.keygroups.len(), 1);
+
+        let (original, keygroup) = (&program.keygroups[0], &imported.keygroups[0]);
+        assert_eq!((keygroup.low_key, keygroup.high_key), (36, 59));
+        assert_eq!(keygroup.mute_group, original.mute_group);
+        assert_eq!(keygroup.zone_crossfade, 1);
+        assert_eq!(keygroup.amp_env, original.amp_env);
+        assert_eq!(keygroup.filter, original.filter);
+        assert_eq!(keygroup.filter_env, original.filter_env);
+        assert_eq!(keygroup.zones, original.zones);
+    }
+}
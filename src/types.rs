@@ -1,10 +1,83 @@
-// ---- Output format enum (unchanged) ----
+use std::fmt;
 
+// ---- Built-in output formats ----
+
+/// The built-in writers, from before formats were registered by id. Only
+/// covers the formats shipped with the crate; use a writer id with
+/// `Registry::writer` or `convert_file` instead.
+#[deprecated(note = "use a writer id such as \"sfz\" with `Registry` or `convert_file`")]
+#[allow(deprecated)]
 #[derive(Clone, Copy, Default, PartialEq)]
 pub enum OutputFormat {
     #[default]
     Sfz,
     DecentSampler,
+    /// Binary AKP, written with `AkaiProgram::write_akp` rather than as text.
+    Akp,
+    /// SoundFont 2 with the samples embedded, written with `AkaiProgram::write_sf2`.
+    Sf2,
+    /// Bitwig Studio `.multisample` zip with the samples bundled, written with
+    /// `AkaiProgram::write_multisample`.
+    BitwigMultisample,
+    /// Ableton Live Sampler preset (`.adv`), written with `AkaiProgram::write_adv`.
+    AbletonSampler,
+    /// Akai MPC keygroup or drum program (`.xpm`).
+    MpcXpm,
+    /// Logic EXS24 instrument, written with `AkaiProgram::write_exs`.
+    Exs24,
+    /// Renoise instrument (`.xrni`) zip with the samples bundled, written with
+    /// `AkaiProgram::write_xrni`.
+    RenoiseXrni,
+    /// Synthstrom Deluge synth preset, written into the card layout with
+    /// `AkaiProgram::write_deluge`.
+    Deluge,
+    /// 1010music Blackbox multisample preset, written into the card layout
+    /// with `AkaiProgram::write_blackbox`.
+    Blackbox,
+}
+
+#[allow(deprecated)]
+impl OutputFormat {
+    /// Id of the built-in writer for this format in `registry::Registry`,
+    /// which has its extension, name and capabilities.
+    pub fn id(self) -> &'static str {
+        match self {
+            OutputFormat::Sfz => "sfz",
+            OutputFormat::DecentSampler => "ds",
+            OutputFormat::Akp => "akp",
+            OutputFormat::Sf2 => "sf2",
+            OutputFormat::BitwigMultisample => "bitwig",
+            OutputFormat::AbletonSampler => "ableton",
+            OutputFormat::MpcXpm => "mpc",
+            OutputFormat::Exs24 => "exs",
+            OutputFormat::RenoiseXrni => "renoise",
+            OutputFormat::Deluge => "deluge",
+            OutputFormat::Blackbox => "blackbox",
+        }
+    }
+}
+
+/// How the per-pitch-class detune table from the `tune` chunk is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DetuneMode {
+    /// Reference a Scala `.scl` tuning file (SFZ `scala_name`).
+    #[default]
+    Scala,
+    /// Split each region per key and bake the offset into `tune`.
+    /// Larger output, but works in every SFZ player.
+    SplitRegions,
+}
+
+/// Options for the output writers. `Default` gives the same output as the
+/// plain `to_sfz_string()` / `to_dspreset_string()` calls.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub detune: DetuneMode,
+    /// File name of the Scala tuning written next to the preset. Scala mode
+    /// needs this; without it the detune table falls back to split regions.
+    pub scala_file: Option<String>,
+    /// Lay out MPC4000 programs as a drum kit by pad (see `AkaiProgram::key_layout`).
+    pub drum_kit_layout: bool,
 }
 
 // ---- RIFF chunk header (unchanged) ----
@@ -17,18 +90,66 @@ pub struct RiffChunkHeader {
 
 // ---- Top-level program ----
 
-#[derive(Debug, Default)]
+/// Which Akai model family wrote the program. The AKP models share the
+/// S5000/S6000 chunk layout; later models add chunks, which is how they're
+/// detected. S1000/S3000 programs are a separate block format (see `s1000`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ProgramModel {
+    #[default]
+    S5000,
+    /// Z4/Z8: up to three `filt` chunks per keygroup.
+    ZSeries,
+    /// MPC4000: pad assignment chunk before `prg `.
+    Mpc4000,
+    /// S1000/S3000 program file (`.p`/`.p3`).
+    S1000,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AkaiProgram {
+    pub model: ProgramModel,
     pub header: Option<ProgramHeader>,
     pub output: Option<ProgramOutput>,
     pub tuning: Option<ProgramTuning>,
     pub lfo1: Option<Lfo>,
     pub lfo2: Option<Lfo>,
     pub modulation: Option<ProgramModulation>,
+    pub pads: Option<PadAssignments>,
     pub keygroups: Vec<Keygroup>,
 }
 
-#[derive(Debug, Default)]
+// ---- PadAssignments (MPC4000 pad chunk) ----
+
+pub const PAD_BANKS: usize = 4;
+pub const PADS_PER_BANK: usize = 16;
+
+/// MPC4000 pad → note mapping, 4 banks (A-D) of 16 pads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PadAssignments {
+    /// Indexed bank-major: A01..A16, B01..B16, ...; `None` = unassigned.
+    pub notes: [Option<u8>; PAD_BANKS * PADS_PER_BANK],
+}
+
+impl Default for PadAssignments {
+    fn default() -> Self {
+        Self { notes: [None; PAD_BANKS * PADS_PER_BANK] }
+    }
+}
+
+impl PadAssignments {
+    /// Display label for a pad index, e.g. 0 → "Pad A01", 17 → "Pad B02".
+    pub fn label(index: usize) -> String {
+        let bank = (b'A' + (index / PADS_PER_BANK) as u8) as char;
+        format!("Pad {bank}{:02}", index % PADS_PER_BANK + 1)
+    }
+
+    /// Assigned pads in pad order, as (pad index, note).
+    pub fn assigned(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.notes.iter().enumerate().filter_map(|(index, note)| note.map(|n| (index, n)))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct ProgramHeader {
     pub midi_program_number: u8,
     pub number_of_keygroups: u8,
@@ -36,7 +157,7 @@ pub struct ProgramHeader {
 
 // ---- ProgramOutput (out chunk, 8 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProgramOutput {
     pub loudness: u8,
     pub amp_mod_1: u8,
@@ -63,7 +184,7 @@ impl Default for ProgramOutput {
 
 // ---- ProgramTuning (tune chunk, 22 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProgramTuning {
     pub semitone: i8,
     pub fine: i8,
@@ -88,9 +209,35 @@ impl Default for ProgramTuning {
     }
 }
 
+impl ProgramTuning {
+    /// Whether any pitch class in the detune table is offset.
+    pub fn has_detune(&self) -> bool {
+        self.detune.iter().any(|&d| d != 0)
+    }
+
+    /// Detune offset in cents for a MIDI key (index 0 = C).
+    pub fn detune_for_key(&self, key: u8) -> i8 {
+        self.detune[key as usize % 12]
+    }
+
+    /// Split a key range into runs of consecutive keys sharing the same
+    /// detune offset, as (low key, high key, cents).
+    pub fn detune_splits(&self, low_key: u8, high_key: u8) -> Vec<(u8, u8, i8)> {
+        let mut splits: Vec<(u8, u8, i8)> = Vec::new();
+        for key in low_key..=high_key {
+            let cents = self.detune_for_key(key);
+            match splits.last_mut() {
+                Some(last) if last.2 == cents => last.1 = key,
+                _ => splits.push((key, key, cents)),
+            }
+        }
+        splits
+    }
+}
+
 // ---- Lfo (lfo chunk, 12 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[derive(Default)]
 pub struct Lfo {
     pub waveform: u8,
@@ -109,7 +256,7 @@ pub struct Lfo {
 
 // ---- ProgramModulation (mods chunk, 38 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ProgramModulation {
     pub amp_mod_1_source: u8,
     pub amp_mod_2_source: u8,
@@ -156,7 +303,10 @@ impl Default for ProgramModulation {
 
 // ---- Keygroup ----
 
-#[derive(Debug)]
+/// Zones per keygroup.
+pub const MAX_ZONES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Keygroup {
     // From kloc (16 bytes)
     pub low_key: u8,
@@ -176,8 +326,19 @@ pub struct Keygroup {
     pub amp_env: Option<Envelope>,
     pub filter_env: Option<FilterEnvelope>,
     pub aux_env: Option<AuxEnvelope>,
-    // Filter
+    // Filter (Z4/Z8 add filter 2 and 3)
     pub filter: Option<Filter>,
+    pub filter2: Option<Filter>,
+    pub filter3: Option<Filter>,
+    pub filter_routing: FilterRouting,
+}
+
+/// How the Z4/Z8 filters are connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FilterRouting {
+    #[default]
+    Series,
+    Parallel,
 }
 
 impl Default for Keygroup {
@@ -199,13 +360,36 @@ impl Default for Keygroup {
             filter_env: None,
             aux_env: None,
             filter: None,
+            filter2: None,
+            filter3: None,
+            filter_routing: FilterRouting::Series,
+        }
+    }
+}
+
+impl AkaiProgram {
+    /// Add a zone to the first keygroup with the same settings as
+    /// `keygroup` (ignoring zones) that still has room, or to a new copy of
+    /// `keygroup`. Used by importers, where each region/sample arrives with
+    /// its own key range and keygroup-level settings.
+    pub fn add_zone(&mut self, keygroup: &Keygroup, zone: Zone) {
+        let existing = self.keygroups.iter_mut().find(|k| {
+            k.zones.len() < MAX_ZONES && Keygroup { zones: Vec::new(), ..(*k).clone() } == *keygroup
+        });
+        match existing {
+            Some(existing) => existing.zones.push(zone),
+            None => {
+                let mut new = keygroup.clone();
+                new.zones = vec![zone];
+                self.keygroups.push(new);
+            }
         }
     }
 }
 
 // ---- Zone (zone chunk, 46-48 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Zone {
     pub sample_name: String,
     pub low_vel: u8,
@@ -219,6 +403,10 @@ pub struct Zone {
     pub level: i8,
     pub keyboard_track: u8,
     pub vel_to_start: i16,
+    // Resolved from the sample file, not stored in the AKP (see `samples::apply_sample_info`)
+    pub root_key: Option<u8>,
+    pub root_tune: i8,
+    pub sample_loop: Option<SampleLoop>,
 }
 
 impl Default for Zone {
@@ -236,13 +424,39 @@ impl Default for Zone {
             level: 0,
             keyboard_track: 1, // ON
             vel_to_start: 0,
+            root_key: None,
+            root_tune: 0,
+            sample_loop: None,
+        }
+    }
+}
+
+impl Zone {
+    /// Whether the zone should loop. LOOP and LOOP UNTIL RELEASE always do;
+    /// AS SAMPLE defers to the sample, so it loops only if the WAV has a loop.
+    pub fn loop_enabled(&self) -> bool {
+        match self.playback {
+            2 | 3 => true,
+            4 => self.sample_loop.is_some(),
+            _ => false,
         }
     }
 }
 
+/// Loop points in sample frames, read from the WAV `smpl` chunk.
+/// `end` is inclusive, matching both `smpl` and SFZ `loop_end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SampleLoop {
+    pub start: u32,
+    pub end: u32,
+    /// Crossfade length in frames. `smpl` has no crossfade field, so this
+    /// is 0 unless the source format stores one.
+    pub crossfade: u32,
+}
+
 // ---- Envelope (amp env, 18 bytes) ----
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Envelope {
     pub attack: u8,
     pub decay: u8,
@@ -256,7 +470,7 @@ pub struct Envelope {
 
 // ---- FilterEnvelope (filter env, 18 bytes) ----
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct FilterEnvelope {
     pub attack: u8,
     pub decay: u8,
@@ -271,7 +485,7 @@ pub struct FilterEnvelope {
 
 // ---- AuxEnvelope (aux env, 18 bytes) ----
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AuxEnvelope {
     pub rate_1: u8,
     pub rate_2: u8,
@@ -290,7 +504,7 @@ pub struct AuxEnvelope {
 
 // ---- Filter (filt chunk, 10 bytes) ----
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Filter {
     pub filter_type: u8,
     pub cutoff: u8,
@@ -317,7 +531,67 @@ impl Default for Filter {
     }
 }
 
+/// One key range of a keygroup as laid out by the writers.
+#[derive(Debug)]
+pub struct KeyRange<'a> {
+    pub keygroup: &'a Keygroup,
+    pub low_key: u8,
+    pub high_key: u8,
+    /// Detune table offset in cents baked into this range (split mode only).
+    pub detune: i8,
+    /// Pad label in drum-kit layout, e.g. "Pad A01".
+    pub label: Option<String>,
+}
+
 impl AkaiProgram {
+    /// Key ranges to emit, in output order.
+    ///
+    /// Normally this is every keygroup in file order (split per key when
+    /// `split_detune` applies). With `by_pad` and MPC4000 pad assignments, the
+    /// program is laid out as a drum kit instead: one single-key range per
+    /// assigned pad, in pad order, for each keygroup covering the pad's note.
+    pub fn key_layout(&self, split_detune: bool, by_pad: bool) -> Vec<KeyRange<'_>> {
+        if let (true, Some(pads)) = (by_pad, &self.pads) {
+            let detune_for = |note: u8| match &self.tuning {
+                Some(tuning) if split_detune => tuning.detune_for_key(note),
+                _ => 0,
+            };
+            return pads.assigned()
+                .flat_map(|(pad, note)| {
+                    self.keygroups.iter()
+                        .filter(move |kg| kg.low_key <= note && note <= kg.high_key)
+                        .map(move |keygroup| KeyRange {
+                            keygroup,
+                            low_key: note,
+                            high_key: note,
+                            detune: detune_for(note),
+                            label: Some(PadAssignments::label(pad)),
+                        })
+                })
+                .collect();
+        }
+
+        self.keygroups.iter()
+            .flat_map(|keygroup| {
+                self.key_splits(keygroup, split_detune).into_iter().map(move |(low_key, high_key, detune)| {
+                    KeyRange { keygroup, low_key, high_key, detune, label: None }
+                })
+            })
+            .collect()
+    }
+
+    /// Key ranges to emit for a keygroup, as (low key, high key, detune cents).
+    /// Without a detune table, or when `split` is false, this is the
+    /// keygroup's own range with no offset.
+    pub fn key_splits(&self, keygroup: &Keygroup, split: bool) -> Vec<(u8, u8, i8)> {
+        match &self.tuning {
+            Some(tuning) if split && tuning.has_detune() => {
+                tuning.detune_splits(keygroup.low_key, keygroup.high_key)
+            }
+            _ => vec![(keygroup.low_key, keygroup.high_key, 0)],
+        }
+    }
+
     /// Collect unique sample paths from all zones across all keygroups.
     pub fn sample_paths(&self) -> Vec<&str> {
         let mut seen = std::collections::HashSet::new();
@@ -333,171 +607,87 @@ impl AkaiProgram {
     }
 }
 
-// ---- Conversion helpers ----
-
-/// Shared envelope timing conversions for amp and filter envelopes.
-/// Both use identical exponential curves: attack/decay use exp(x*4)*0.001,
-/// release uses exp(x*5)*0.001 with 0.001s minimum to avoid clicks.
-pub trait EnvelopeTiming {
-    fn attack_raw(&self) -> u8;
-    fn decay_raw(&self) -> u8;
-    fn release_raw(&self) -> u8;
-    fn sustain_raw(&self) -> u8;
-
-    /// Convert AKP attack (0-100) to seconds (exponential curve).
-    fn attack_time(&self) -> f32 {
-        let v = self.attack_raw();
-        if v == 0 { 0.0 } else { (v as f32 / 100.0 * 4.0).exp() * 0.001 }
-    }
-
-    /// Convert AKP decay (0-100) to seconds (exponential curve).
-    fn decay_time(&self) -> f32 {
-        let v = self.decay_raw();
-        if v == 0 { 0.0 } else { (v as f32 / 100.0 * 4.0).exp() * 0.001 }
-    }
-
-    /// Convert AKP release (0-100) to seconds. Minimum 0.001s to avoid clicks.
-    fn release_time(&self) -> f32 {
-        let v = self.release_raw();
-        if v == 0 { 0.001 } else { (v as f32 / 100.0 * 5.0).exp() * 0.001 }
-    }
-
-    /// Convert AKP sustain (0-100) to normalized 0.0-1.0.
-    fn sustain_normalized(&self) -> f32 {
-        self.sustain_raw() as f32 / 100.0
-    }
+/// Velocity range and crossfade points for one zone. Fade ranges are
+/// (low velocity, high velocity) pairs over which the zone fades in or out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityCrossfade {
+    pub low_vel: u8,
+    pub high_vel: u8,
+    pub fade_in: Option<(u8, u8)>,
+    pub fade_out: Option<(u8, u8)>,
 }
 
-impl EnvelopeTiming for Envelope {
-    fn attack_raw(&self) -> u8 { self.attack }
-    fn decay_raw(&self) -> u8 { self.decay }
-    fn release_raw(&self) -> u8 { self.release }
-    fn sustain_raw(&self) -> u8 { self.sustain }
-}
-
-impl EnvelopeTiming for FilterEnvelope {
-    fn attack_raw(&self) -> u8 { self.attack }
-    fn decay_raw(&self) -> u8 { self.decay }
-    fn release_raw(&self) -> u8 { self.release }
-    fn sustain_raw(&self) -> u8 { self.sustain }
-}
-
-impl Lfo {
-    /// Convert AKP waveform byte to name. Spec: 0=SINE,1=TRI,2=SQ,3=SQ+,4=SQ-,5=SAW_BI,6=SAW_UP,7=SAW_DN,8=RANDOM
-    pub fn waveform_name(&self) -> &'static str {
-        match self.waveform {
-            0 => "sine",
-            1 => "triangle",
-            2 => "square",
-            3 => "square",    // SQ+ (positive phase)
-            4 => "square",    // SQ- (negative phase)
-            5 => "saw",       // SAW BI (bipolar)
-            6 => "saw",       // SAW UP
-            7 => "saw",       // SAW DOWN (ramp)
-            8 => "random",
-            _ => "sine",
-        }
-    }
+impl Keygroup {
+    /// Velocity layout for each zone, in zone order.
+    ///
+    /// With `zone_crossfade` off this is just each zone's velocity range. With
+    /// it on, every pair of adjacent velocity layers crossfades between the two
+    /// layers' range centers, and the ranges are widened to overlap across the
+    /// fade. Zones stacked on the same range are treated as one layer; a zone
+    /// whose range contains another's (e.g. a full-velocity layer) doesn't fade.
+    pub fn velocity_crossfades(&self) -> Vec<VelocityCrossfade> {
+        let center = |z: &Zone| ((z.low_vel as u16 + z.high_vel as u16) / 2) as u8;
+
+        self.zones.iter().map(|zone| {
+            let mut xf = VelocityCrossfade {
+                low_vel: zone.low_vel,
+                high_vel: zone.high_vel,
+                fade_in: None,
+                fade_out: None,
+            };
+            if self.zone_crossfade == 0 {
+                return xf;
+            }
 
-    /// Convert AKP LFO rate (0-100) to Hz (0.1-30 Hz, logarithmic).
-    pub fn rate_hz(&self) -> f32 {
-        0.1 * (300.0f32).powf(self.rate as f32 / 100.0)
-    }
+            // Nearest layer below that ends where this one starts
+            let below = self.zones.iter()
+                .filter(|z| z.low_vel < zone.low_vel && z.high_vel < zone.high_vel)
+                .filter(|z| z.high_vel as u16 + 1 >= zone.low_vel as u16)
+                .max_by_key(|z| z.low_vel);
+            if let Some(below) = below {
+                let (from, to) = (center(below), center(zone));
+                if to > from {
+                    xf.low_vel = xf.low_vel.min(from);
+                    xf.fade_in = Some((from, to));
+                }
+            }
 
-    /// Convert AKP LFO depth (0-100) to normalized 0.0-1.0.
-    /// Used for pitch modulation: depth * 100 gives cents (0-100 cent range).
-    /// depth=1 → 1 cent (subtle vibrato), depth=99 → 99 cents (dramatic wobble).
-    pub fn depth_normalized(&self) -> f32 {
-        self.depth as f32 / 100.0
-    }
-}
+            // Nearest layer above that starts where this one ends
+            let above = self.zones.iter()
+                .filter(|z| z.low_vel > zone.low_vel && z.high_vel > zone.high_vel)
+                .filter(|z| z.low_vel as u16 <= zone.high_vel as u16 + 1)
+                .min_by_key(|z| z.low_vel);
+            if let Some(above) = above {
+                let (from, to) = (center(zone), center(above));
+                if to > from {
+                    xf.high_vel = xf.high_vel.max(to);
+                    xf.fade_out = Some((from, to));
+                }
+            }
 
-impl ProgramOutput {
-    /// Convert AKP loudness (0-100) to dB. Loudness is a linear gain percentage,
-    /// so dB = 20 * log10(loudness / 100). Loudness=100 → 0dB, 85 → -1.4dB, 50 → -6dB.
-    pub fn volume_db(&self) -> f32 {
-        if self.loudness == 0 {
-            return -60.0;
-        }
-        20.0 * (self.loudness as f32 / 100.0).log10()
+            xf
+        }).collect()
     }
 }
 
-/// Map AKP modulation source (0-14) to SFZ opcode suffix.
-/// Returns the full suffix including connector: "_oncc1", "_chanaft", etc.
-/// This avoids invalid opcodes like "pitch_onbend" — bend and aftertouch
-/// use dedicated suffixes without the "on" prefix.
-/// Returns None for sources that don't map to a CC/controller.
-pub fn mod_source_sfz_suffix(source: u8) -> Option<&'static str> {
-    match source {
-        1 => Some("_oncc1"),    // MODWHEEL
-        2 => Some("_bend"),     // BEND (not "_onbend")
-        3 => Some("_chanaft"),  // AFTERTOUCH (not "_onchanaft")
-        4 => Some("_oncc16"),   // EXTERNAL (typically general purpose controller)
-        _ => None,              // NO_SOURCE, VELOCITY, KEYBOARD, LFOs, envelopes, deltas
-    }
-}
+// ---- Import reports ----
 
-/// Classify AKP modulation source (0-14) into a type category.
-pub fn mod_source_type(source: u8) -> &'static str {
-    match source {
-        0 => "none",
-        1..=4 => "cc",
-        5 => "velocity",
-        6 => "keyboard",
-        7 => "lfo1",
-        8 => "lfo2",
-        9 => "amp_env",
-        10 => "filt_env",
-        11 => "aux_env",
-        12..=14 => "delta",
-        _ => "unknown",
-    }
+/// An opcode, attribute or element an importer couldn't represent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedOpcode {
+    /// 1-based line the opcode (or header/element) is on.
+    pub line: usize,
+    pub opcode: String,
+    pub value: String,
+    pub reason: String,
 }
 
-/// Human-readable name for an AKP modulation source.
-pub fn mod_source_name(source: u8) -> &'static str {
-    match source {
-        0 => "NO_SOURCE",
-        1 => "MODWHEEL",
-        2 => "BEND",
-        3 => "AFTERTOUCH",
-        4 => "EXTERNAL",
-        5 => "VELOCITY",
-        6 => "KEYBOARD",
-        7 => "LFO1",
-        8 => "LFO2",
-        9 => "AMP_ENV",
-        10 => "FILT_ENV",
-        11 => "AUX_ENV",
-        12 => "MIDI_NOTE",
-        13 => "MIDI_VELOCITY",
-        14 => "MIDI_RANDOM",
-        _ => "UNKNOWN",
-    }
-}
-
-impl Filter {
-    /// Convert AKP cutoff (0-100) to Hz (20-20000, logarithmic).
-    pub fn cutoff_hz(&self) -> f32 {
-        20.0 * (1000.0f32).powf(self.cutoff as f32 / 100.0)
-    }
-
-    /// Convert AKP resonance (0-12) to dB (0-40 range, linear).
-    pub fn resonance_db(&self) -> f32 {
-        (self.resonance as f32 / 12.0) * 40.0
-    }
-
-    /// Map Akai filter type (0-25) to SFZ fil_type opcode.
-    pub fn sfz_filter_type(&self) -> &'static str {
-        match self.filter_type {
-            0..=2 => "lpf_2p",           // 2-pole LP, 4-pole LP, 2-pole LP+
-            3..=5 => "bpf_2p",           // 2-pole BP, 4-pole BP, 2-pole BP+
-            6 | 8 => "hpf_1p",               // 1-pole HP, 1-pole HP+
-            7 => "hpf_2p",                    // 2-pole HP
-            12..=16 => "brf_2p", // Notch variants
-            17..=21 => "pkf_2p", // Peak variants
-            _ => "lpf_2p",                    // Morphing, phaser, voweliser -> fallback
+impl fmt::Display for SkippedOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.value.is_empty() {
+            write!(f, "line {}: {} ({})", self.line, self.opcode, self.reason)
+        } else {
+            write!(f, "line {}: {}={} ({})", self.line, self.opcode, self.value, self.reason)
         }
     }
 }
@@ -507,29 +697,93 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_lfo_depth_normalized_boundaries() {
-        assert_eq!(Lfo { depth: 0, ..Default::default() }.depth_normalized(), 0.0);
-        assert!((Lfo { depth: 50, ..Default::default() }.depth_normalized() - 0.5).abs() < f32::EPSILON);
-        assert!((Lfo { depth: 100, ..Default::default() }.depth_normalized() - 1.0).abs() < f32::EPSILON);
+    fn test_velocity_crossfades() {
+        let layer = |low_vel, high_vel| Zone { low_vel, high_vel, ..Default::default() };
+        let mut keygroup = Keygroup {
+            zones: vec![layer(0, 31), layer(32, 63), layer(64, 95), layer(96, 127)],
+            ..Default::default()
+        };
+
+        // Off: plain velocity splits
+        let xf = keygroup.velocity_crossfades();
+        assert_eq!((xf[1].low_vel, xf[1].high_vel, xf[1].fade_in, xf[1].fade_out), (32, 63, None, None));
+
+        // On: fades between adjacent layer centers (15, 47, 79, 111)
+        keygroup.zone_crossfade = 1;
+        let xf = keygroup.velocity_crossfades();
+        assert_eq!(xf[0], VelocityCrossfade { low_vel: 0, high_vel: 47, fade_in: None, fade_out: Some((15, 47)) });
+        assert_eq!(xf[1], VelocityCrossfade { low_vel: 15, high_vel: 79, fade_in: Some((15, 47)), fade_out: Some((47, 79)) });
+        assert_eq!(xf[3], VelocityCrossfade { low_vel: 79, high_vel: 127, fade_in: Some((79, 111)), fade_out: None });
+    }
+
+    #[test]
+    fn test_velocity_crossfades_stacked_and_gapped_zones() {
+        let layer = |low_vel, high_vel| Zone { low_vel, high_vel, ..Default::default() };
+        let keygroup = Keygroup {
+            zone_crossfade: 1,
+            zones: vec![layer(0, 63), layer(0, 63), layer(64, 127), layer(0, 127)],
+            ..Default::default()
+        };
+
+        let xf = keygroup.velocity_crossfades();
+        // Stacked layers both fade into the upper layer
+        assert_eq!(xf[0].fade_out, Some((31, 95)));
+        assert_eq!(xf[1].fade_out, Some((31, 95)));
+        assert_eq!(xf[2].fade_in, Some((31, 95)));
+        // Full-range zone has no neighbouring layer
+        assert_eq!((xf[3].fade_in, xf[3].fade_out), (None, None));
+    }
+
+    #[test]
+    fn test_detune_splits() {
+        let mut tuning = ProgramTuning::default();
+        assert!(!tuning.has_detune());
+        assert_eq!(tuning.detune_splits(48, 71), vec![(48, 71, 0)]);
+
+        // Only E (4) and B (11) detuned
+        tuning.detune[4] = -14;
+        tuning.detune[11] = -12;
+        assert!(tuning.has_detune());
+        assert_eq!(tuning.detune_for_key(64), -14);
+        assert_eq!(
+            tuning.detune_splits(60, 72),
+            vec![(60, 63, 0), (64, 64, -14), (65, 70, 0), (71, 71, -12), (72, 72, 0)]
+        );
     }
 
     #[test]
-    fn test_envelope_timing_trait_shared_behavior() {
-        let amp = Envelope { attack: 50, decay: 50, sustain: 80, release: 50, ..Default::default() };
-        let filt = FilterEnvelope { attack: 50, decay: 50, sustain: 80, release: 50, ..Default::default() };
-        // Same raw values produce identical timing
-        assert_eq!(amp.attack_time(), filt.attack_time());
-        assert_eq!(amp.decay_time(), filt.decay_time());
-        assert_eq!(amp.release_time(), filt.release_time());
-        assert_eq!(amp.sustain_normalized(), filt.sustain_normalized());
+    fn test_pad_labels() {
+        assert_eq!(PadAssignments::label(0), "Pad A01");
+        assert_eq!(PadAssignments::label(15), "Pad A16");
+        assert_eq!(PadAssignments::label(17), "Pad B02");
+        assert_eq!(PadAssignments::label(63), "Pad D16");
     }
 
     #[test]
-    fn test_envelope_timing_zero_values() {
-        let env = Envelope { attack: 0, decay: 0, sustain: 0, release: 0, ..Default::default() };
-        assert_eq!(env.attack_time(), 0.0);
-        assert_eq!(env.decay_time(), 0.0);
-        assert_eq!(env.sustain_normalized(), 0.0);
-        assert_eq!(env.release_time(), 0.001); // minimum to avoid clicks
+    fn test_key_layout_by_pad() {
+        let mut pads = PadAssignments::default();
+        pads.notes[0] = Some(38);   // A01 snare
+        pads.notes[1] = Some(36);   // A02 kick
+        pads.notes[16] = Some(50);  // B01, no keygroup
+        let program = AkaiProgram {
+            pads: Some(pads),
+            keygroups: vec![
+                Keygroup { low_key: 36, high_key: 36, ..Default::default() },
+                Keygroup { low_key: 37, high_key: 40, ..Default::default() },
+            ],
+            ..Default::default()
+        };
+
+        let layout = program.key_layout(false, true);
+        let summary: Vec<(u8, u8, Option<&str>)> = layout.iter()
+            .map(|r| (r.low_key, r.high_key, r.label.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![(38, 38, Some("Pad A01")), (36, 36, Some("Pad A02"))]);
+        assert!(std::ptr::eq(layout[0].keygroup, &program.keygroups[1]));
+
+        // Without by_pad: keygroups in file order
+        let layout = program.key_layout(false, false);
+        assert_eq!(layout.len(), 2);
+        assert_eq!((layout[1].low_key, layout[1].high_key, layout[1].label.clone()), (37, 40, None));
     }
 }
@@ -0,0 +1,347 @@
+//! Akai MPC program (`.xpm`) output for the MPC Live/One/X/Force and MPC
+//! Software. Programs are written as keygroup programs (an instrument per
+//! key range with the AKP zones as its up to 4 layers), or, for MPC4000
+//! kits laid out by pad, as drum programs with an instrument per pad.
+//!
+//! Envelope times and levels and the filter cutoff are on Akai's 0-100
+//! scale in both formats, so they're written as the same fraction of the
+//! range. Samples are referenced by name, which the MPC looks up next to
+//! the program.
+
+use std::path::Path;
+
+use crate::dspreset::xml_escape;
+use crate::samples::DEFAULT_ROOT_KEY;
+use crate::types::{AkaiProgram, ConvertOptions, Keygroup, PadAssignments, Zone, MAX_ZONES};
+
+/// Instruments in an MPC keygroup program.
+pub const MAX_KEYGROUPS: usize = 128;
+
+/// MPC filter types for the AKP ones it has.
+const FILTER_OFF: u8 = 0;
+const FILTER_LOW_2: u8 = 2;
+const FILTER_LOW_4: u8 = 3;
+const FILTER_HIGH_1: u8 = 6;
+const FILTER_HIGH_2: u8 = 7;
+const FILTER_BAND_2: u8 = 11;
+const FILTER_BAND_4: u8 = 12;
+
+impl AkaiProgram {
+    pub fn to_xpm_string(&self, name: &str) -> String {
+        self.to_xpm_string_with(name, &ConvertOptions::default())
+    }
+
+    /// An MPC program named `name`. With `drum_kit_layout` and a pad table
+    /// this is a drum program, otherwise a keygroup program.
+    pub fn to_xpm_string_with(&self, name: &str, options: &ConvertOptions) -> String {
+        let drum_pads = self.pads.as_ref().filter(|_| options.drum_kit_layout);
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<MPCVObject>\n");
+        xml.push_str("  <Version>\n");
+        xml.push_str("    <File_Version>2.1</File_Version>\n");
+        xml.push_str("    <Application>MPC-V</Application>\n");
+        xml.push_str("    <Application_Version>2.10.0.0</Application_Version>\n");
+        xml.push_str("    <Platform>Linux</Platform>\n");
+        xml.push_str("  </Version>\n");
+        let program_type = if drum_pads.is_some() { "Drum" } else { "Keygroup" };
+        xml.push_str(&format!("  <Program type=\"{program_type}\">\n"));
+        element(&mut xml, 4, "Name", xml_escape(name));
+
+        let loudness = self.output.as_ref().map_or(100, |o| o.loudness);
+        element(&mut xml, 4, "Volume", format!("{:.6}", loudness as f32 / 100.0));
+        element(&mut xml, 4, "Pan", "0.500000");
+        element(&mut xml, 4, "TuneCoarse", self.tuning.as_ref().map_or(0, |t| t.semitone));
+        element(&mut xml, 4, "TuneFine", self.tuning.as_ref().map_or(0, |t| t.fine));
+        element(&mut xml, 4, "Mono", "False");
+
+        match drum_pads {
+            Some(pads) => self.write_drum_instruments(&mut xml, pads),
+            None => self.write_keygroup_instruments(&mut xml, options),
+        }
+
+        xml.push_str("  </Program>\n");
+        xml.push_str("</MPCVObject>\n");
+        xml
+    }
+
+    /// An instrument per key range. Per-key detune splits keygroups as for
+    /// SFZ, unless that would go past the MPC's 128 keygroups.
+    fn write_keygroup_instruments(&self, xml: &mut String, options: &ConvertOptions) {
+        let mut layout = self.key_layout(true, false);
+        if layout.len() > MAX_KEYGROUPS {
+            layout = self.key_layout(false, false);
+        }
+        layout.retain(|range| range.keygroup.zones.iter().any(|z| !z.sample_name.is_empty()));
+        if options.drum_kit_layout {
+            xml.push_str("    <!-- No pad table, so written as a keygroup program -->\n");
+        }
+        if layout.len() > MAX_KEYGROUPS {
+            xml.push_str(&format!(
+                "    <!-- {} keygroups past the MPC's {MAX_KEYGROUPS} left out -->\n",
+                layout.len() - MAX_KEYGROUPS,
+            ));
+            layout.truncate(MAX_KEYGROUPS);
+        }
+
+        element(xml, 4, "KeygroupNumKeygroups", layout.len());
+        xml.push_str("    <Instruments>\n");
+        for (index, range) in layout.iter().enumerate() {
+            let layers: Vec<(&Keygroup, &Zone)> = range.keygroup.zones.iter()
+                .filter(|z| !z.sample_name.is_empty())
+                .map(|z| (range.keygroup, z))
+                .collect();
+            write_instrument(xml, index + 1, (range.low_key, range.high_key), &layers, range.detune);
+        }
+        xml.push_str("    </Instruments>\n");
+    }
+
+    /// An instrument per assigned pad, layering the zones of every keygroup
+    /// covering its note. The MPC4000's banks A-D are the MPC's first four.
+    fn write_drum_instruments(&self, xml: &mut String, pads: &PadAssignments) {
+        xml.push_str("    <PadNoteMap>\n");
+        for (pad, note) in pads.assigned() {
+            xml.push_str(&format!("      <PadNote number=\"{}\">\n", pad + 1));
+            element(xml, 8, "Note", note);
+            xml.push_str("      </PadNote>\n");
+        }
+        xml.push_str("    </PadNoteMap>\n");
+
+        xml.push_str("    <Instruments>\n");
+        for (pad, note) in pads.assigned() {
+            let mut layers: Vec<(&Keygroup, &Zone)> = self.keygroups.iter()
+                .filter(|kg| kg.low_key <= note && note <= kg.high_key)
+                .flat_map(|kg| kg.zones.iter().filter(|z| !z.sample_name.is_empty()).map(move |z| (kg, z)))
+                .collect();
+            if layers.len() > MAX_ZONES {
+                xml.push_str(&format!(
+                    "      <!-- {}: {} layers past the MPC's {MAX_ZONES} left out -->\n",
+                    PadAssignments::label(pad), layers.len() - MAX_ZONES,
+                ));
+                layers.truncate(MAX_ZONES);
+            }
+            let detune = self.tuning.as_ref().map_or(0, |t| t.detune_for_key(note));
+            write_instrument(xml, pad + 1, (note, note), &layers, detune);
+        }
+        xml.push_str("    </Instruments>\n");
+    }
+}
+
+/// One instrument, taking its envelopes, filter and mute group from the
+/// first layer's keygroup.
+fn write_instrument(xml: &mut String, number: usize, (low_key, high_key): (u8, u8), layers: &[(&Keygroup, &Zone)], detune: i8) {
+    xml.push_str(&format!("      <Instrument number=\"{number}\">\n"));
+    let keygroup = layers.first().map(|(kg, _)| *kg);
+
+    // Peak, notch, morphing, phaser and voweliser types have no MPC filter
+    let filter = keygroup.and_then(|kg| kg.filter.as_ref());
+    let filter_type = filter.map_or(FILTER_OFF, |f| match f.filter_type {
+        0 | 2 => FILTER_LOW_2,
+        1 => FILTER_LOW_4,
+        3 | 5 => FILTER_BAND_2,
+        4 => FILTER_BAND_4,
+        6 | 8 => FILTER_HIGH_1,
+        7 => FILTER_HIGH_2,
+        _ => FILTER_OFF,
+    });
+    element(xml, 8, "FilterType", filter_type);
+    if let Some(filter) = filter.filter(|_| filter_type != FILTER_OFF) {
+        element(xml, 8, "Cutoff", fraction(filter.cutoff, 100));
+        element(xml, 8, "Resonance", fraction(filter.resonance, 12));
+    }
+    // The MPC's envelope amount is positive only
+    if let Some(env) = keygroup.and_then(|kg| kg.filter_env.as_ref()).filter(|_| filter_type != FILTER_OFF) {
+        element(xml, 8, "FilterAttack", fraction(env.attack, 100));
+        element(xml, 8, "FilterDecay", fraction(env.decay, 100));
+        element(xml, 8, "FilterSustain", fraction(env.sustain, 100));
+        element(xml, 8, "FilterRelease", fraction(env.release, 100));
+        element(xml, 8, "FilterEnvAmt", fraction(env.depth.max(0) as u8, 100));
+    }
+    if let Some(env) = keygroup.and_then(|kg| kg.amp_env.as_ref()) {
+        element(xml, 8, "VolumeAttack", fraction(env.attack, 100));
+        element(xml, 8, "VolumeDecay", fraction(env.decay, 100));
+        element(xml, 8, "VolumeSustain", fraction(env.sustain, 100));
+        element(xml, 8, "VolumeRelease", fraction(env.release, 100));
+    }
+    element(xml, 8, "LowNote", low_key);
+    element(xml, 8, "HighNote", high_key);
+    element(xml, 8, "MuteGroup", keygroup.map_or(0, |kg| kg.mute_group));
+
+    xml.push_str("        <Layers>\n");
+    for (index, (keygroup, zone)) in layers.iter().enumerate() {
+        write_layer(xml, index + 1, keygroup, zone, detune);
+    }
+    xml.push_str("        </Layers>\n");
+    xml.push_str("      </Instrument>\n");
+}
+
+fn write_layer(xml: &mut String, number: usize, keygroup: &Keygroup, zone: &Zone, detune: i8) {
+    // Program tuning is on the program; the rest splits into semitones and cents
+    let cents = (keygroup.semitone_tune as i32 + zone.semitone_tune as i32) * 100
+        + keygroup.fine_tune as i32 + zone.fine_tune as i32 + zone.root_tune as i32 + detune as i32;
+    let coarse = (cents as f32 / 100.0).round() as i32;
+    let sample_name = Path::new(&zone.sample_name.replace('\\', "/"))
+        .file_stem().unwrap_or_default().to_string_lossy().into_owned();
+
+    xml.push_str(&format!("          <Layer number=\"{number}\">\n"));
+    element(xml, 12, "Active", "True");
+    element(xml, 12, "Volume", format!("{:.6}", 10.0f32.powf(zone.level as f32 / 20.0)));
+    element(xml, 12, "Pan", format!("{:.6}", (0.5 + zone.pan as f32 / 100.0).clamp(0.0, 1.0)));
+    element(xml, 12, "TuneCoarse", coarse);
+    element(xml, 12, "TuneFine", cents - coarse * 100);
+    element(xml, 12, "VelStart", zone.low_vel);
+    element(xml, 12, "VelEnd", zone.high_vel);
+    element(xml, 12, "RootNote", zone.root_key.unwrap_or(DEFAULT_ROOT_KEY));
+    element(xml, 12, "KeyTrack", if zone.keyboard_track == 0 { "False" } else { "True" });
+    match zone.sample_loop.filter(|_| zone.loop_enabled()) {
+        Some(sample_loop) => {
+            element(xml, 12, "Loop", "True");
+            element(xml, 12, "LoopStart", sample_loop.start);
+            element(xml, 12, "LoopEnd", sample_loop.end);
+            element(xml, 12, "LoopCrossfadeLength", sample_loop.crossfade);
+        }
+        None => element(xml, 12, "Loop", "False"),
+    }
+    element(xml, 12, "SampleName", xml_escape(&sample_name));
+    xml.push_str("          </Layer>\n");
+}
+
+/// `value` out of `range`, as the 0-1 value the MPC stores.
+fn fraction(value: u8, range: u8) -> String {
+    format!("{:.6}", (value as f32 / range as f32).min(1.0))
+}
+
+fn element(xml: &mut String, indent: usize, tag: &str, value: impl std::fmt::Display) {
+    xml.push_str(&format!("{:indent$}<{tag}>{value}</{tag}>\n", ""));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Envelope, Filter, FilterEnvelope, ProgramTuning, SampleLoop};
+
+    /// The text between `<tag>` and `</tag>` for each occurrence.
+    fn values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+        xml.split(&format!("<{tag}>")).skip(1)
+            .map(|rest| &rest[..rest.find(&format!("</{tag}>")).unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn test_keygroup_program() {
+        let program = AkaiProgram {
+            keygroups: vec![Keygroup {
+                low_key: 36,
+                high_key: 59,
+                semitone_tune: 1,
+                mute_group: 2,
+                amp_env: Some(Envelope { attack: 10, decay: 50, sustain: 80, release: 25, ..Default::default() }),
+                filter: Some(Filter { filter_type: 1, cutoff: 60, resonance: 3, ..Default::default() }),
+                filter_env: Some(FilterEnvelope { depth: -40, ..Default::default() }),
+                zones: vec![
+                    Zone { sample_name: "Samples\\Piano Soft.wav".to_string(), high_vel: 63, fine_tune: 70, pan: -25, root_key: Some(48), ..Default::default() },
+                    Zone {
+                        sample_name: "Piano Hard".to_string(),
+                        low_vel: 64,
+                        level: -6,
+                        playback: 2,
+                        sample_loop: Some(SampleLoop { start: 100, end: 900, crossfade: 20 }),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let xml = program.to_xpm_string("Piano & Co");
+        assert!(xml.contains("<Program type=\"Keygroup\">"));
+        assert_eq!(values(&xml, "Name"), ["Piano &amp; Co"]);
+        assert_eq!(values(&xml, "KeygroupNumKeygroups"), ["1"]);
+        assert_eq!((values(&xml, "LowNote"), values(&xml, "HighNote")), (vec!["36"], vec!["59"]));
+        assert_eq!(values(&xml, "MuteGroup"), ["2"]);
+        assert_eq!(values(&xml, "FilterType"), ["3"]);
+        assert_eq!(values(&xml, "Cutoff"), ["0.600000"]);
+        assert_eq!(values(&xml, "Resonance"), ["0.250000"]);
+        assert_eq!(values(&xml, "FilterEnvAmt"), ["0.000000"]);
+        assert_eq!(values(&xml, "VolumeAttack"), ["0.100000"]);
+        assert_eq!(values(&xml, "VolumeSustain"), ["0.800000"]);
+
+        assert_eq!(values(&xml, "SampleName"), ["Piano Soft", "Piano Hard"]);
+        assert_eq!(values(&xml, "VelStart"), ["0", "64"]);
+        assert_eq!(values(&xml, "VelEnd"), ["63", "127"]);
+        // 1 semitone + 70 cents rounds to 2 semitones - 30 cents
+        assert_eq!(values(&xml, "TuneCoarse"), ["0", "2", "1"]);
+        assert_eq!(values(&xml, "TuneFine"), ["0", "-30", "0"]);
+        assert_eq!(values(&xml, "Pan"), ["0.500000", "0.250000", "0.500000"]);
+        assert_eq!(values(&xml, "Volume"), ["1.000000", "1.000000", "0.501187"]);
+        assert_eq!(values(&xml, "RootNote"), ["48", "60"]);
+        assert_eq!(values(&xml, "Loop"), ["False", "True"]);
+        assert_eq!(values(&xml, "LoopStart"), ["100"]);
+        assert_eq!(values(&xml, "LoopEnd"), ["900"]);
+    }
+
+    #[test]
+    fn test_detune_splits_only_within_128_keygroups() {
+        let tuning = ProgramTuning { detune: [0, 20, 0, 5, 0, 0, 5, 0, 5, 0, 5, 0], ..Default::default() };
+        let zones = vec![Zone { sample_name: "pad".to_string(), ..Default::default() }];
+        let mut program = AkaiProgram {
+            tuning: Some(tuning),
+            keygroups: vec![Keygroup { low_key: 60, high_key: 62, zones: zones.clone(), ..Default::default() }],
+            ..Default::default()
+        };
+        let xml = program.to_xpm_string("Pad");
+        assert_eq!(values(&xml, "KeygroupNumKeygroups"), ["3"]);
+        assert_eq!(values(&xml, "TuneFine"), ["0", "0", "20", "0"]);
+
+        // Full-range keygroups would split into 128 each, so they stay whole
+        program.keygroups = vec![Keygroup { low_key: 0, high_key: 127, zones, ..Default::default() }; 2];
+        let xml = program.to_xpm_string("Pad");
+        assert_eq!(values(&xml, "KeygroupNumKeygroups"), ["2"]);
+    }
+
+    #[test]
+    fn test_drum_program_by_pad() {
+        let mut pads = PadAssignments::default();
+        pads.notes[0] = Some(36);
+        pads.notes[17] = Some(38);
+        let layer = |name: &str| Zone { sample_name: name.to_string(), ..Default::default() };
+        let program = AkaiProgram {
+            pads: Some(pads),
+            keygroups: vec![
+                Keygroup { low_key: 36, high_key: 36, zones: vec![layer("kick 1"), layer("kick 2"), layer("kick 3")], ..Default::default() },
+                Keygroup { low_key: 30, high_key: 40, zones: vec![layer("room 1"), layer("room 2")], ..Default::default() },
+            ],
+            ..Default::default()
+        };
+
+        // Without --drum-kit it's still a keygroup program
+        assert!(program.to_xpm_string("Kit").contains("<Program type=\"Keygroup\">"));
+
+        let options = ConvertOptions { drum_kit_layout: true, ..Default::default() };
+        let xml = program.to_xpm_string_with("Kit", &options);
+        assert!(xml.contains("<Program type=\"Drum\">"));
+        assert!(xml.contains("<PadNote number=\"1\">\n        <Note>36</Note>"));
+        assert!(xml.contains("<PadNote number=\"18\">\n        <Note>38</Note>"));
+        assert!(xml.contains("<Instrument number=\"1\">"));
+        assert!(xml.contains("<Instrument number=\"18\">"));
+        assert!(xml.contains("<!-- Pad A01: 1 layers past the MPC's 4 left out -->"));
+        assert_eq!(values(&xml, "SampleName"), ["kick 1", "kick 2", "kick 3", "room 1", "room 1", "room 2"]);
+        assert_eq!((values(&xml, "LowNote"), values(&xml, "HighNote")), (vec!["36", "38"], vec!["36", "38"]));
+    }
+
+    #[test]
+    fn test_parsed_mpc4000_kit_to_drum_program() {
+        let program = crate::parser::parse_akp_bytes(&crate::fixtures::mpc4000_kit()).unwrap();
+        let options = ConvertOptions { drum_kit_layout: true, ..Default::default() };
+        let xml = program.to_xpm_string_with("Kit", &options);
+        assert!(xml.contains("<Program type=\"Drum\">"));
+        assert!(!xml.contains("No pad table"));
+        assert_eq!(values(&xml, "Note"), ["36", "38", "36"]);
+        assert_eq!(values(&xml, "SampleName"), ["KICK", "SNARE", "KICK"]);
+        for number in [1, 2, 17] {
+            assert!(xml.contains(&format!("<Instrument number=\"{number}\">")));
+        }
+    }
+}
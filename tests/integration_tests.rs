@@ -96,3 +96,19 @@ fn test_valid_riff_but_empty_program() {
     assert!(stderr.contains("Missing required 'keygroup' chunk"));
     assert!(!output.status.success());
 }
+
+#[test]
+fn test_unrecognised_disk_image() {
+    let temp_dir = TempDir::new().unwrap();
+    let image_path = temp_dir.path().join("library.iso");
+    File::create(&image_path).unwrap().write_all(&[0u8; 64 * 1024]).unwrap();
+
+    let output = Command::new(cli_binary())
+        .arg(&image_path)
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid disk image: no ISO 9660, FAT or Akai S1000/S3000 filesystem found"));
+    assert!(!output.status.success());
+}